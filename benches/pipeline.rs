@@ -0,0 +1,54 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use chara::bench::{deep_quotations, long_literal_list, many_definitions};
+use chara::Engine;
+
+fn corpora() -> Vec<(&'static str, String)> {
+    vec![
+        ("deep quotations", deep_quotations(1000, 100)),
+        ("many definitions", many_definitions(5000)),
+        ("long literal list", long_literal_list(100_000)),
+    ]
+}
+
+fn scan(c: &mut Criterion) {
+    let mut group = c.benchmark_group("scan");
+    for (name, source) in corpora() {
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_function(name, |b| b.iter(|| chara::scanner::scan_borrowed(&source).unwrap()));
+    }
+    group.finish();
+}
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for (name, source) in corpora() {
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_function(name, |b| b.iter(|| chara::compile(&source).unwrap()));
+    }
+    group.finish();
+}
+
+fn check(c: &mut Criterion) {
+    let mut group = c.benchmark_group("check");
+    for (name, source) in corpora() {
+        let program = chara::compile(&source).unwrap();
+        group.bench_function(name, |b| b.iter(|| program.check().unwrap()));
+    }
+    group.finish();
+}
+
+fn eval(c: &mut Criterion) {
+    let mut group = c.benchmark_group("eval");
+    for (name, source) in corpora() {
+        let program = chara::compile(&source).unwrap();
+        group.bench_function(name, |b| b.iter_batched(Engine::new, |mut engine| program.run(&mut engine).unwrap(), BatchSize::SmallInput));
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(20);
+    targets = scan, parse, check, eval
+}
+criterion_main!(benches);
@@ -0,0 +1,26 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+/// A source of about `size` bytes, repeating definitions, terms, strings and comments like a real program has.
+fn source(size: usize) -> String {
+    let chunk = "## Squares an integer.\ndef square: (Int -> Int) = dup *;\n\
+        # Some arithmetic, quoted and called.\n[1 2 +] call square \"a string, with \\\"escapes\\\"\" drop\n\
+        test \"squares\" { 3 square expect 9 }\n";
+    chunk.repeat(size / chunk.len() + 1)
+}
+
+fn scan(c: &mut Criterion) {
+    let mut group = c.benchmark_group("scan");
+    for megabytes in [1, 4] {
+        let source = source(megabytes << 20);
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_function(format!("{}MB", megabytes), |b| b.iter(|| chara::scanner::scan_borrowed(&source).unwrap()));
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(20);
+    targets = scan
+}
+criterion_main!(benches);
@@ -0,0 +1,13 @@
+#![no_std]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+/// Compile and typecheck a program, returning how many errors it has.
+pub fn errors(source: &str) -> usize {
+    match chara::compile(source) {
+        Ok(program) => program.check().err().map_or(0, |errors: Vec<_>| errors.len()),
+        Err(errors) => errors.len(),
+    }
+}
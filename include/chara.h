@@ -0,0 +1,56 @@
+/* The C interface to chara, from building chara into a shared library with
+ * `cargo rustc --lib --crate-type cdylib --release --features ffi`. See src/ffi.rs for details. */
+#ifndef CHARA_H
+#define CHARA_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct CharaEngine CharaEngine;
+
+typedef enum CharaTag {
+    CHARA_INT,
+    CHARA_BOOL,
+    CHARA_STRING,
+    /* A quotation, given as its source in the `string` field. */
+    CHARA_QUOTATION,
+    /* Bytes, which can hold NULs, so they're given as their source, like `x"00ff"`, in the `string` field. */
+    CHARA_BYTES,
+    /* A set, given as the source that builds it in the `string` field. */
+    CHARA_SET,
+} CharaTag;
+
+typedef struct CharaValue {
+    CharaTag tag;
+    union {
+        int64_t int_;
+        bool boolean;
+        char *string;
+    } payload;
+} CharaValue;
+
+/* A new engine, with nothing defined. Free it with chara_engine_free. */
+CharaEngine *chara_engine_new(void);
+void chara_engine_free(CharaEngine *engine);
+
+/* Check and evaluate source, returning 0 if it ran. Otherwise returns 1, and if error isn't null, points it
+ * at the error messages, one per line, to be freed with chara_string_free. */
+int32_t chara_eval(CharaEngine *engine, const char *source, char **error);
+
+size_t chara_stack_len(const CharaEngine *engine);
+/* A copy of the value index places from the bottom of the stack. Free it with chara_value_free. */
+CharaValue chara_stack_get(const CharaEngine *engine, size_t index);
+void chara_value_free(CharaValue value);
+
+void chara_string_free(char *string);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -1,25 +1,170 @@
-use std::collections::HashMap;
+use alloc::{boxed::Box, collections::BTreeMap, format, string::{String, ToString}, sync::Arc, vec, vec::Vec};
 use crate::error::Error;
-use crate::parser::{Factor, parse, Parser};
+use crate::parser::{Cycle, Factor};
 use crate::scanner::Token;
-use crate::typechecker::Type;
+use crate::symbol::Symbol;
+use crate::typechecker::{generalizes, instantiate, is_retain_word, normalize, Environment, Type, TypeChecker};
+
+/// How deep the stack gets while a term runs, relative to its depth when the term starts.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct StackDepth {
+    /// The lowest it gets, which is how many values the term takes from below where it started, negated.
+    pub lowest: isize,
+    /// The highest it gets.
+    pub highest: isize,
+    /// The factor that first takes the stack down to `lowest`, if it goes below where it started.
+    pub deepest: Option<Token>,
+}
+
+/// A value on the stack, as far as depth analysis cares: a quotation that might be called, or anything else.
+#[derive(Clone)]
+enum Slot<'a> {
+    Quotation(&'a [Factor]),
+    Other,
+}
 
-enum Rule {
-    IsFunction
+/// Follows only how many values each factor takes and leaves, which doesn't need their types to agree.
+#[derive(Clone)]
+struct DepthWalker<'a> {
+    environment: &'a Environment,
+    /// The values pushed since the term started that are still on the stack, top last.
+    slots: Vec<Slot<'a>>,
+    depth: isize,
+    extent: StackDepth,
 }
 
-struct AbstractInterpreter {
+impl<'a> DepthWalker<'a> {
+    fn pop(&mut self, factor: &Factor) -> Slot<'a> {
+        self.depth -= 1;
+        if self.depth < self.extent.lowest {
+            self.extent.lowest = self.depth;
+            self.extent.deepest = Some(factor.token());
+        }
+        self.slots.pop().unwrap_or(Slot::Other)
+    }
+
+    fn push(&mut self, slot: Slot<'a>) {
+        self.depth += 1;
+        self.extent.highest = self.extent.highest.max(self.depth);
+        self.slots.push(slot);
+    }
+
+    fn take(&mut self, count: usize, factor: &Factor) {
+        for _ in 0..count {
+            self.pop(factor);
+        }
+    }
+
+    fn leave(&mut self, count: usize) {
+        for _ in 0..count {
+            self.push(Slot::Other);
+        }
+    }
+
+    /// Follow `factors`, or return `None` at the first one whose effect on the depth can't be known, like
+    /// calling a quotation that wasn't pushed in the same term.
+    fn walk(&mut self, factors: &'a [Factor]) -> Option<()> {
+        for factor in factors {
+            match factor {
+                Factor::Dup(_) => {
+                    let a = self.pop(factor);
+                    self.push(a.clone());
+                    self.push(a);
+                }
+                Factor::Drop(_) => self.take(1, factor),
+                Factor::Swap(_) => {
+                    let a = self.pop(factor);
+                    let b = self.pop(factor);
+                    self.push(a);
+                    self.push(b);
+                }
+                Factor::Quote(_) => {
+                    self.take(1, factor);
+                    self.leave(1);
+                }
+                Factor::Cat(_) => {
+                    self.take(2, factor);
+                    self.leave(1);
+                }
+                Factor::Call(_) => match self.pop(factor) {
+                    Slot::Quotation(body) => self.walk(body)?,
+                    Slot::Other => return None,
+                },
+                Factor::Ifte(_) => {
+                    let (Slot::Quotation(otherwise), Slot::Quotation(then), Slot::Quotation(condition)) =
+                        (self.pop(factor), self.pop(factor), self.pop(factor)) else {
+                        return None;
+                    };
+                    // The condition runs on a copy of the stack, so only how deep it goes matters. Each part
+                    // starts from the extent of the one before, so the last covers all three.
+                    let mut copy = self.clone();
+                    copy.walk(condition)?;
+                    let mut branch = self.clone();
+                    branch.extent = copy.extent;
+                    branch.walk(then)?;
+                    self.extent = branch.extent;
+                    self.walk(otherwise)?;
+                    if self.depth != branch.depth {
+                        return None;
+                    }
+                    // Which quotations are on the stack depends on the branch taken, so none are known.
+                    let kept = self.slots.len().min(branch.slots.len());
+                    self.slots.truncate(kept);
+                    self.slots.fill(Slot::Other);
+                }
+                Factor::Cond(_) => return None,
+                Factor::Let(names, _) => {
+                    let Slot::Quotation(body) = self.pop(factor) else {
+                        return None;
+                    };
+                    self.take(names.len(), factor);
+                    self.walk(body)?;
+                }
+                Factor::Local(_, _) | Factor::Int(_, _) | Factor::Bool(_, _) | Factor::String(_, _) | Factor::Bytes(_, _)
+                    | Factor::Set(_, _) => self.push(Slot::Other),
+                Factor::Quotation(body, _) => self.push(Slot::Quotation(body)),
+                Factor::Identifier(name, _) if is_retain_word(name) => {
+                    if name.as_str() == ">r" {
+                        self.take(1, factor);
+                    } else {
+                        self.leave(1);
+                    }
+                }
+                Factor::Identifier(name, _) => match &**self.environment.get(name)? {
+                    Type::Function(t_in, t_out) => {
+                        self.take(t_in.len(), factor);
+                        self.leave(t_out.len());
+                    }
+                    _ => self.leave(1),
+                },
+                Factor::Assertion(_) | Factor::Ascription(..) => {}
+            }
+        }
+        Some(())
+    }
+}
+
+pub struct AbstractInterpreter {
     in_stack: Vec<Type>,
     out_stack: Vec<Type>,
+    /// The values moved onto the retain stack by `>r`.
+    retained: Vec<Type>,
     param_count: usize,
+    environment: Environment,
 }
 
 impl AbstractInterpreter {
     pub fn new() -> AbstractInterpreter {
+        Self::with_environment(Environment::new_sync())
+    }
+
+    pub fn with_environment(environment: Environment) -> AbstractInterpreter {
         AbstractInterpreter {
             in_stack: Vec::new(),
             out_stack: Vec::new(),
+            retained: Vec::new(),
             param_count: 0,
+            environment,
         }
     }
 
@@ -30,25 +175,109 @@ impl AbstractInterpreter {
     }
 
     fn pop(&mut self) -> Type {
-        let popped = if self.out_stack.is_empty() {
+        if self.out_stack.is_empty() {
             let p = self.new_param();
-            self.in_stack.push(p.clone());
+            self.in_stack.insert(0, p.clone());
             p
         } else {
             self.out_stack.pop().unwrap()
-        };
-        popped
+        }
+    }
+
+    /// Pop a value that must have the type `expected`. If the stack has run dry, the expected type is
+    /// recorded as an input of the term rather than a fresh parameter.
+    fn pop_expecting(&mut self, expected: &Type) -> Type {
+        if self.out_stack.is_empty() {
+            self.in_stack.insert(0, expected.clone());
+            expected.clone()
+        } else {
+            self.out_stack.pop().unwrap()
+        }
     }
 
     fn push(&mut self, t: Type) {
         self.out_stack.push(t);
     }
 
-    pub fn interpret(mut self, factors: &Vec<Factor>) -> Result<Type, Error> {
+    pub fn interpret(mut self, factors: &[Factor]) -> Result<Type, Error> {
+        self.interpret_term(factors)?;
+        Ok(normalize(&Type::Function(self.in_stack, self.out_stack)))
+    }
+
+    /// Follow `factors`, with the body of each `let` followed where it's bound rather than pushed.
+    fn interpret_term(&mut self, factors: &[Factor]) -> Result<(), Error> {
+        let mut factors = factors.iter().peekable();
+        while let Some(factor) = factors.next() {
+            match (factor, factors.peek()) {
+                (Factor::Quotation(body, _), Some(Factor::Let(names, _))) => {
+                    factors.next();
+                    self.interpret_let(body, names)?;
+                }
+                _ => self.interpret_factor(factor)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Follow a `let` binding `names` around `body`. The bound values are popped like any others, so a term that
+    /// binds values from below it takes them as inputs.
+    fn interpret_let(&mut self, body: &[Factor], names: &[Symbol]) -> Result<(), Error> {
+        let outside = self.environment.clone();
+        for name in names.iter().rev() {
+            let t = self.pop();
+            self.environment.insert_mut(*name, Arc::new(t));
+        }
+        let result = self.interpret_term(body);
+        self.environment = outside;
+        result
+    }
+
+    /// The type of each term in `cycles`, in order. Each definition has the type it's annotated with, which is in
+    /// scope in its own body so it can call itself, and which the type inferred from its body has to be at least
+    /// as general as. It's used for the definitions and terms after it. Annotations are read by `checker`, which
+    /// knows the types they can name. Tests and macros are skipped.
+    pub fn interpret_cycles(mut self, cycles: &[Cycle], checker: &TypeChecker) -> Result<Vec<Type>, Error> {
+        let mut types = Vec::new();
+        for cycle in cycles {
+            match cycle {
+                Cycle::Definition(Token { value: name, .. }, annotation, body, _, _) => {
+                    let annotated = checker.type_from_annotation(annotation)?;
+                    self.environment.insert_mut(Symbol::intern(name), Arc::new(annotated.clone()));
+                    let inferred = AbstractInterpreter::with_environment(self.environment.clone()).interpret(body)?;
+                    if !generalizes(&inferred, &normalize(&annotated), &mut BTreeMap::new()) {
+                        let message = format!("`{}` is annotated {}, but its body has the type {}", name, normalize(&annotated), inferred);
+                        return Err(Error::TypeError(message, annotation.first_token().clone()));
+                    }
+                }
+                Cycle::Term(factors, _) => {
+                    types.push(AbstractInterpreter::with_environment(self.environment.clone()).interpret(factors)?);
+                }
+                Cycle::Test(..) | Cycle::Macro(..) => {}
+            }
+        }
+        Ok(types)
+    }
+
+    /// How deep the stack gets while `factors` run, following only how many values each factor takes and
+    /// leaves, so it can be worked out for terms whose types don't check. `None` if it depends on something
+    /// that can't be known beforehand, like which quotation is called, or on which branch of an `ifte` runs.
+    pub fn stack_depth(&self, factors: &[Factor]) -> Option<StackDepth> {
+        let mut walker = DepthWalker { environment: &self.environment, slots: Vec::new(), depth: 0, extent: StackDepth::default() };
+        walker.walk(factors)?;
+        Some(walker.extent)
+    }
+
+    /// The stack after each factor, with values taken from below the term shown only once they are pushed
+    /// back. Stops early at the first factor the interpreter can't handle.
+    pub fn trace_stack(mut self, factors: &[Factor]) -> Vec<Vec<Type>> {
+        let mut states = Vec::new();
         for factor in factors {
-            self.interpret_factor(factor)?;
+            if self.interpret_factor(factor).is_err() {
+                break;
+            }
+            states.push(self.out_stack.clone());
         }
-        Ok(Type::Function(self.in_stack, self.out_stack))
+        states
     }
 
     fn interpret_factor(&mut self, factor: &Factor) -> Result<(), Error> {
@@ -70,86 +299,233 @@ impl AbstractInterpreter {
                 self.push(wrapped);
                 Ok(())
             }
-            Factor::Call(_) => {
-                self.call()
+            Factor::Call(token) => {
+                self.call(token)
+            }
+            Factor::Cat(token) => {
+                let (second, first) = (self.pop(), self.pop());
+                let (Type::Function(first_in, first_out), Type::Function(second_in, second_out)) = (&first, &second) else {
+                    return Err(Error::TypeError(format!("`cat` joins two quotations, but got {} and {}", first, second), token.clone()));
+                };
+                let mut joined = AbstractInterpreter::with_environment(self.environment.clone());
+                joined.param_count = self.param_count;
+                joined.apply(first_in, first_out, token)?;
+                joined.apply(second_in, second_out, token)?;
+                self.param_count = joined.param_count;
+                self.push(Type::Function(joined.in_stack, joined.out_stack));
+                Ok(())
+            }
+            Factor::Swap(_) => {
+                let a = self.pop();
+                let b = self.pop();
+                self.push(a);
+                self.push(b);
+                Ok(())
+            }
+            Factor::Ifte(token) => self.ifte(token),
+            Factor::Cond(token) => {
+                Err(Error::TypeError("cond is not supported by the abstract interpreter".to_string(), token.clone()))
+            }
+            Factor::Let(_, token) => {
+                Err(Error::TypeError("`let` can only be followed with its body just before it".to_string(), token.clone()))
+            }
+            Factor::Local(name, token) => {
+                let t = self.environment.get(name)
+                    .ok_or_else(|| Error::TypeError(format!("Unknown identifier {}", name), token.clone()))?;
+                self.push((**t).clone());
+                Ok(())
             }
-            Factor::Cat(_) => { unimplemented!() }
-            Factor::Swap(_) => { unimplemented!() }
-            Factor::Ifte(_) => { unimplemented!() }
             Factor::Int(_, _) => { self.push(Type::Int); Ok(()) }
             Factor::Bool(_, _) => { self.push(Type::Bool); Ok(()) }
             Factor::String(_, _) => { self.push(Type::String); Ok(()) }
-            Factor::Identifier(_, _) => { unimplemented!() }
-            Factor::Quotation(factors) => {
-                let interpreter = AbstractInterpreter::new();
+            Factor::Bytes(_, _) => { self.push(Type::Bytes); Ok(()) }
+            Factor::Set(_, token) => {
+                Err(Error::TypeError("sets are not supported by the abstract interpreter".to_string(), token.clone()))
+            }
+            Factor::Identifier(name, token) if is_retain_word(name) => {
+                if name.as_str() == ">r" {
+                    let a = self.pop();
+                    self.retained.push(a);
+                    return Ok(());
+                }
+                let a = self.retained.last().cloned()
+                    .ok_or_else(|| Error::TypeError(format!("`{}` has no value on the retain stack to take here", name), token.clone()))?;
+                if name.as_str() == "r>" {
+                    self.retained.pop();
+                }
+                self.push(a);
+                Ok(())
+            }
+            Factor::Identifier(name, token) => {
+                let t = Arc::clone(self.environment.get(name)
+                    .ok_or_else(|| Error::TypeError(format!("Unknown identifier {}", name), token.clone()))?);
+                match &*instantiate(&t, &mut self.param_count) {
+                    Type::Function(t_in, t_out) => self.apply(t_in, t_out, token),
+                    t => {
+                        self.push(t.clone());
+                        Ok(())
+                    }
+                }
+            }
+            Factor::Quotation(factors, _) => {
+                let interpreter = AbstractInterpreter::with_environment(self.environment.clone());
                 let t = interpreter.interpret(factors)?;
                 self.push(t);
                 Ok(())
             }
+            Factor::Assertion(_) | Factor::Ascription(..) => Ok(()),
         }
     }
 
-    fn call(&mut self) -> Result<(), Error> {
+    fn call(&mut self, token: &Token) -> Result<(), Error> {
         let a= self.pop();
         match a {
-            Type::Function(t_in, t_out) => {
-                let mut learned: HashMap<usize, Type> = HashMap::new();
-                for t_expected in t_in.iter().rev() {
-                    let t_actual = self.pop();
-                    if let Type::Param(in_p) = t_expected {
+            Type::Function(t_in, t_out) => self.apply(&t_in, &t_out, token),
+            // A value taken from below the term has to be a function if it's called. What it takes and leaves could
+            // only be described in general by variables standing for the rest of the stack, which types don't have,
+            // so it's learned as taking the value under it and leaving one in its place, everywhere it's been used.
+            Type::Param(param) if self.in_stack.iter().any(|t| mentions(t, param)) => {
+                let t_arg = self.pop();
+                if mentions(&t_arg, param) {
+                    return Err(Error::TypeError("Can't call a function with itself as its input".to_string(), token.clone()));
+                }
+                let t_result = self.new_param();
+                self.learn(param, Type::Function(vec![t_arg], vec![t_result.clone()]));
+                self.push(t_result);
+                Ok(())
+            }
+            a => Err(Error::TypeError(format!("Expected a function to call, but got {}", a), token.clone())),
+        }
+    }
+
+    /// Applies a function taking `t_in` and leaving `t_out` to the stack.
+    fn apply(&mut self, t_in: &[Type], t_out: &[Type], token: &Token) -> Result<(), Error> {
+        let mut learned: BTreeMap<usize, Type> = BTreeMap::new();
+        for t_expected in t_in.iter().rev() {
+            let t_actual = match t_expected {
+                Type::Int | Type::Bool | Type::String | Type::Bytes => self.pop_expecting(t_expected),
+                _ => self.pop(),
+            };
+            if let Type::Param(in_p) = t_expected {
+                // Every input a parameter stands for has the same type, so what's learned from one
+                // is learned about the others.
+                match (learned.get(in_p).cloned(), &t_actual) {
+                    (None, _) => {
                         learned.insert(*in_p, t_actual);
-                    } else if let Type::Function(t_in, t_out) = t_expected {
-                        if let [Type::Param(in_p)] = t_in[..] {
-                            learned.insert(in_p, t_actual);
-                        }
-                    } else {
-                        if t_expected != &t_actual {
-                            panic!("Expected {:?} but got {:?}", t_expected, t_actual)
+                    }
+                    (Some(Type::Param(earlier)), _) if !mentions(&t_actual, earlier) => {
+                        self.learn(earlier, t_actual.clone());
+                        learned.insert(*in_p, t_actual);
+                    }
+                    (Some(earlier), Type::Param(actual_p)) if !mentions(&earlier, *actual_p) => self.learn(*actual_p, earlier),
+                    (Some(earlier), _) => {
+                        if earlier != t_actual && earlier != Type::Dyn && t_actual != Type::Dyn {
+                            return Err(Error::TypeError(format!("Expected {:?} but got {:?}", earlier, t_actual), token.clone()));
                         }
                     }
                 }
-                for out in t_out.into_iter() {
-                    match out {
-                        Type::Param(param) => {
-                            if let Some(t) = learned.get(&param) {
-                                self.push(t.clone());
-                            }
-                        }
-                        Type::Function(t_in, t_out) => {
-                            let new_in = Self::substitute_learned(&mut learned, t_in);
-                            let new_out = Self::substitute_learned(&mut learned, t_out);
-                            self.push(Type::Function(new_in, new_out));
-                        }
-                        _ => {
-                            self.push(out);
-                        }
+            } else if let Type::Function(t_in, _t_out) = t_expected {
+                if let [Type::Param(in_p)] = t_in[..] {
+                    learned.insert(in_p, t_actual);
+                }
+            } else if let (Type::Param(actual_p), Type::Int | Type::Bool | Type::String | Type::Bytes) = (&t_actual, t_expected) {
+                self.learn(*actual_p, t_expected.clone());
+            } else {
+                // `Dyn` values are only checked when they're run.
+                if t_expected != &t_actual && *t_expected != Type::Dyn && t_actual != Type::Dyn {
+                    return Err(Error::TypeError(format!("Expected {:?} but got {:?}", t_expected, t_actual), token.clone()));
+                }
+            }
+        }
+        for out in t_out {
+            match out {
+                Type::Param(param) => {
+                    if let Some(t) = learned.get(param) {
+                        self.push(t.clone());
                     }
                 }
-                Ok(())
+                Type::Function(t_in, t_out) => {
+                    let new_in = Self::substitute_learned(&mut learned, t_in);
+                    let new_out = Self::substitute_learned(&mut learned, t_out);
+                    self.push(Type::Function(new_in, new_out));
+                }
+                _ => {
+                    self.push(out.clone());
+                }
             }
-            Type::Param(param) => {
-                let t_in = self.in_stack.pop().unwrap();
-                let t_out = self.new_param();
-                self.in_stack.push(t_in.clone());
-                self.in_stack.push(Type::Function(vec![t_in], vec![t_out.clone()]));
-                self.out_stack.push(t_out);
-                Ok(())
+        }
+        Ok(())
+    }
+
+    /// An `ifte`, which has the effect of whichever branch is more specific. The condition runs on a copy of the
+    /// stack, so all it has to do is leave a `Bool`. A branch that reaches less far down the stack leaves what's
+    /// further down as it is, so the branches' effects are compared as if they reached equally far.
+    fn ifte(&mut self, token: &Token) -> Result<(), Error> {
+        let (else_branch, then_branch, condition) = (self.pop(), self.pop(), self.pop());
+        let error = |message: String| Err(Error::TypeError(message, token.clone()));
+        match &condition {
+            Type::Function(_, t_out) if matches!(t_out.last(), Some(Type::Bool | Type::Param(_) | Type::Dyn)) => {}
+            Type::Function(..) => return error(format!("The condition of an `ifte` has to leave a Bool, but it's {}", condition)),
+            Type::Param(_) | Type::Dyn => {}
+            t => return error(format!("Expected a quotation for the condition of an `ifte`, but got {}", t)),
+        }
+        let (Type::Function(mut then_in, mut then_out), Type::Function(mut else_in, mut else_out)) = (then_branch.clone(), else_branch.clone()) else {
+            // Branches that aren't known to be quotations yet can't be compared.
+            self.push(then_branch);
+            return self.call(token);
+        };
+        // Inputs are listed from the bottom of the stack up, so values from further down go at their start.
+        let mut next = then_in.iter().chain(&then_out).chain(&else_in).chain(&else_out)
+            .filter_map(|t| match t {
+                Type::Param(n) => Some(n + 1),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+        let depth = then_in.len().max(else_in.len());
+        for (t_in, t_out) in [(&mut then_in, &mut then_out), (&mut else_in, &mut else_out)] {
+            while t_in.len() < depth {
+                t_in.insert(0, Type::Param(next));
+                t_out.insert(0, Type::Param(next));
+                next += 1;
             }
-            _ => panic!("Expected function"),
         }
+        let (then_branch, else_branch) = (Type::Function(then_in, then_out), Type::Function(else_in, else_out));
+        let specific = if generalizes(&then_branch, &else_branch, &mut BTreeMap::new()) {
+            else_branch
+        } else if generalizes(&else_branch, &then_branch, &mut BTreeMap::new()) {
+            then_branch
+        } else {
+            return error(format!("The branches of an `ifte` have to have the same stack effect, but they're {} and {}",
+                normalize(&then_branch), normalize(&else_branch)));
+        };
+        self.push(specific);
+        self.call(token)
+    }
+
+    /// Record that `param` has the type `t`, replacing it everywhere it's already been used.
+    fn learn(&mut self, param: usize, t: Type) {
+        let replace = |types: &mut Vec<Type>| {
+            for slot in types.iter_mut() {
+                *slot = substitute(slot, param, &t);
+            }
+        };
+        replace(&mut self.in_stack);
+        replace(&mut self.out_stack);
+        replace(&mut self.retained);
     }
 
-    fn substitute_learned(learned: &mut HashMap<usize, Type>, t: Vec<Type>) -> Vec<Type> {
+    fn substitute_learned(learned: &mut BTreeMap<usize, Type>, t: &[Type]) -> Vec<Type> {
         let mut new = Vec::new();
-        for t in t.into_iter() {
+        for t in t {
             match t {
                 Type::Param(param) => {
-                    if let Some(t) = learned.get(&param) {
+                    if let Some(t) = learned.get(param) {
                         new.push(t.clone());
                     }
                 }
                 t => {
-                    new.push(t);
+                    new.push(t.clone());
                 }
             }
         }
@@ -157,12 +533,41 @@ impl AbstractInterpreter {
     }
 }
 
+/// Whether `Param(param)` is `t` or part of it.
+fn mentions(t: &Type, param: usize) -> bool {
+    match t {
+        Type::Param(n) => *n == param,
+        Type::Function(t_in, t_out) => t_in.iter().chain(t_out).any(|t| mentions(t, param)),
+        Type::Named(_, args) => args.iter().any(|t| mentions(t, param)),
+        Type::Set(element) => mentions(element, param),
+        _ => false,
+    }
+}
+
+/// `t` with `Param(param)` replaced by `with`, including inside the types it's made of.
+fn substitute(t: &Type, param: usize, with: &Type) -> Type {
+    let all = |types: &[Type]| types.iter().map(|t| substitute(t, param, with)).collect();
+    match t {
+        Type::Param(n) if *n == param => with.clone(),
+        Type::Function(t_in, t_out) => Type::Function(all(t_in), all(t_out)),
+        Type::Named(name, args) => Type::Named(name.clone(), all(args)),
+        Type::Set(element) => Type::Set(Box::new(substitute(element, param, with))),
+        t => t.clone(),
+    }
+}
+
+impl Default for AbstractInterpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::abstract_interpreter::AbstractInterpreter;
     use crate::error::Error;
     use crate::parser::{Cycle, parse};
-    use crate::typechecker::Type;
+    use crate::typechecker::{Type, TypeChecker};
 
     #[test]
     fn literal_int() {
@@ -398,18 +803,118 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
-    fn interpret(input: &str) -> Result<Type, Error> {
-        let cycles = parse(input)?;
-        let interpreter = AbstractInterpreter::new();
-        // TODO: This isn't right.
-        for cycle in cycles {
-            match cycle {
-                Cycle::Term(factors) => {
-                    return Ok(interpreter.interpret(&factors)?);
+    #[test]
+    fn retain_stack_moves_values_out_of_the_way() {
+        let input = "true 1 >r dup r@ r>";
+        let actual = interpret(input).unwrap();
+        let expected = Type::Function(vec![], vec![Type::Bool, Type::Bool, Type::Int, Type::Int]);
+        assert_eq!(actual, expected);
+        assert!(interpret("1 r>").is_err());
+    }
+
+    #[test]
+    fn follows_stack_depth_through_calls_and_branches() {
+        let depth = |input: &str| {
+            let Cycle::Term(factors, _) = &parse(input).unwrap()[0] else {
+                panic!("Expected Term");
+            };
+            let environment = crate::typechecker::TypeChecker::new().environment().clone();
+            AbstractInterpreter::with_environment(environment).stack_depth(factors).map(|depth| (depth.lowest, depth.highest))
+        };
+        assert_eq!(depth("1 2 + drop"), Some((0, 2)));
+        assert_eq!(depth("+ 1"), Some((-2, 0)));
+        assert_eq!(depth("[drop drop 1] call"), Some((-2, 1)));
+        assert_eq!(depth("[true] [drop] [1 2 + drop drop] ifte"), Some((-1, 3)));
+        assert_eq!(depth("[true] [drop] [] ifte"), None);
+        assert_eq!(depth("call"), None);
+        assert_eq!(depth("\"x\" 1 +"), Some((0, 2)));
+    }
+
+    #[test]
+    fn uses_the_definitions_before_each_term() {
+        let input = "def sq: (Int -> Int) = dup *; 3 sq def quad: (Int -> Int) = sq sq; quad true";
+        let checker = TypeChecker::new();
+        let interpret_cycles = |input| AbstractInterpreter::with_environment(checker.environment().clone()).interpret_cycles(&parse(input).unwrap(), &checker);
+        let expected = vec![
+            Type::Function(vec![], vec![Type::Int]),
+            Type::Function(vec![Type::Int], vec![Type::Int, Type::Bool]),
+        ];
+        assert_eq!(interpret_cycles(input).unwrap(), expected);
+        assert!(interpret_cycles("quad def quad: (Int -> Int) = dup * dup *;").is_err());
+    }
+
+    #[test]
+    fn definitions_can_call_themselves() {
+        let input = "def fact: (Int -> Int) = [dup 0 =] [drop 1] [dup 1 - fact *] ifte; 5 fact";
+        let checker = TypeChecker::new();
+        let actual = AbstractInterpreter::with_environment(checker.environment().clone()).interpret_cycles(&parse(input).unwrap(), &checker);
+        assert_eq!(actual.unwrap(), vec![Type::Function(vec![], vec![Type::Int])]);
+        let input = "def wrong: (Int -> String) = 1 +;";
+        match AbstractInterpreter::with_environment(checker.environment().clone()).interpret_cycles(&parse(input).unwrap(), &checker) {
+            Err(Error::TypeError(message, token)) => {
+                assert_eq!(message, "`wrong` is annotated (Int -> String), but its body has the type (Int -> Int)");
+                assert_eq!(token.value, "(");
+            }
+            result => panic!("Expected TypeError, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn takes_the_effect_of_the_more_specific_branch_of_an_ifte() {
+        assert_eq!(interpret("[0 <] [0 swap -] [] ifte").unwrap(), Type::Function(vec![Type::Int], vec![Type::Int]));
+        match interpret("[true] [1] [\"no\"] ifte") {
+            Err(Error::TypeError(message, _)) => {
+                assert_eq!(message, "The branches of an `ifte` have to have the same stack effect, but they're ( -> Int) and ( -> String)");
+            }
+            result => panic!("Expected TypeError, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn call_learns_that_what_it_calls_is_a_function() {
+        let input = "1 swap call";
+        let actual = interpret(input).unwrap();
+        let expected = Type::Function(vec![Type::Function(vec![Type::Int], vec![Type::Param(0)])], vec![Type::Param(0)]);
+        assert_eq!(actual, expected);
+        let input = "dup >r call r>";
+        let actual = interpret(input).unwrap();
+        let function = Type::Function(vec![Type::Param(0)], vec![Type::Param(1)]);
+        let expected = Type::Function(vec![Type::Param(0), function.clone()], vec![Type::Param(1), function]);
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn joins_quotations_with_cat() {
+        let joined = Type::Function(vec![Type::Int], vec![Type::Int]);
+        assert_eq!(interpret("[1 +] [2 *] cat").unwrap(), Type::Function(vec![], vec![joined]));
+        assert_eq!(interpret("[1] [2 3] cat call").unwrap(), Type::Function(vec![], vec![Type::Int, Type::Int, Type::Int]));
+    }
+
+    #[test]
+    fn reports_calling_what_isnt_a_function() {
+        let mut typechecker = TypeChecker::new();
+        typechecker.check(&parse("def anything: a = 1;").unwrap()).unwrap();
+        for (input, message) in [("1 call", "Expected a function to call, but got Int"), ("anything call", "Expected a function to call, but got a"),
+            ("dup call", "Can't call a function with itself as its input")] {
+            let Cycle::Term(factors, _) = &parse(input).unwrap()[0] else {
+                panic!("Expected Term");
+            };
+            let interpreter = AbstractInterpreter::with_environment(typechecker.environment().clone());
+            match interpreter.interpret(factors) {
+                Err(Error::TypeError(actual, token)) => {
+                    assert_eq!(actual, message);
+                    assert_eq!(token.value, "call");
                 }
-                _ => {}
+                result => panic!("Expected TypeError, got {:?}", result),
             }
         }
-        Err(Error::UnexpectedEndOfFile("".to_string()))
+    }
+
+    /// The type of the last term in `input`.
+    fn interpret(input: &str) -> Result<Type, Error> {
+        let cycles = parse(input)?;
+        let checker = TypeChecker::new();
+        AbstractInterpreter::with_environment(checker.environment().clone()).interpret_cycles(&cycles, &checker)?.pop()
+            .ok_or_else(|| Error::UnexpectedEndOfFile(vec!["term".to_string()], "program".to_string()))
     }
 }
\ No newline at end of file
@@ -0,0 +1,106 @@
+use std::time::{Duration, Instant};
+use crate::edition::declared;
+use crate::error::Error;
+use crate::parser::Parser;
+use crate::profiler::format_duration;
+use crate::scanner::scan_borrowed;
+use crate::{Engine, Program};
+
+// Generated programs for the benchmarks in `benches/`, each stressing a different part of the pipeline. They
+// are all well typed, and run without error.
+
+/// `count` terms, each a quotation nested `depth` deep, for the recursive parts of parsing and checking.
+pub fn deep_quotations(count: usize, depth: usize) -> String {
+    format!("{}1{} drop\n", "[".repeat(depth), "]".repeat(depth)).repeat(count)
+}
+
+/// `count` small definitions, each calling the one before it, and a term calling the last, so running it goes
+/// through every one of them.
+pub fn many_definitions(count: usize) -> String {
+    let mut source = String::new();
+    for i in 0..count {
+        let body = if i == 0 { "1 +".to_string() } else { format!("word{} 1 +", i - 1) };
+        source.push_str(&format!("def word{}: (Int -> Int) = {};\n", i, body));
+    }
+    if count > 0 {
+        source.push_str(&format!("0 word{}\n", count - 1));
+    }
+    source
+}
+
+/// A quotation of `length` integers.
+pub fn long_literal_list(length: usize) -> String {
+    let literals: Vec<String> = (0..length).map(|i| i.to_string()).collect();
+    format!("[{}] drop\n", literals.join(" "))
+}
+
+/// How long each phase of handling a program took, once for each time it was measured.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct Timings {
+    pub scan: Vec<Duration>,
+    pub parse: Vec<Duration>,
+    pub check: Vec<Duration>,
+    pub run: Vec<Duration>,
+}
+
+/// Scan, parse, check and run `source` `iterations` times, timing each phase by itself. It's run the way
+/// `Program::run_main` runs it, with a new engine each time.
+pub fn measure(source: &str, main: &str, iterations: usize) -> Result<Timings, Vec<Error>> {
+    let mut timings = Timings::default();
+    let edition = declared(source).map_err(|error| vec![error])?.unwrap_or_default();
+    for _ in 0..iterations {
+        let start = Instant::now();
+        let tokens = scan_borrowed(source).map_err(|error| vec![error])?;
+        timings.scan.push(start.elapsed());
+
+        let start = Instant::now();
+        let cycles = Parser::new(tokens).parse().map_err(|error| vec![error])?;
+        timings.parse.push(start.elapsed());
+        let program = Program { cycles, files: Vec::new(), edition };
+
+        let start = Instant::now();
+        program.check()?;
+        timings.check.push(start.elapsed());
+
+        let mut engine = Engine::new();
+        let start = Instant::now();
+        program.run_main(&mut engine, main).map_err(|error| vec![error])?;
+        timings.run.push(start.elapsed());
+    }
+    Ok(timings)
+}
+
+impl Timings {
+    /// A table of the fastest, median and slowest time of each phase.
+    pub fn report(&self) -> String {
+        let mut report = format!("{:<8} {:>12} {:>12} {:>12}\n", "phase", "min", "median", "max");
+        for (phase, times) in [("scan", &self.scan), ("parse", &self.parse), ("check", &self.check), ("run", &self.run)] {
+            let mut times = times.clone();
+            times.sort();
+            if let (Some(min), Some(max)) = (times.first(), times.last()) {
+                report.push_str(&format!(
+                    "{:<8} {:>12} {:>12} {:>12}\n",
+                    phase, format_duration(*min), format_duration(times[times.len() / 2]), format_duration(*max),
+                ));
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{deep_quotations, long_literal_list, many_definitions, measure};
+
+    #[test]
+    fn measures_each_phase_of_the_corpora() {
+        for source in [deep_quotations(3, 10), many_definitions(20), long_literal_list(100)] {
+            let timings = measure(&source, "main", 2).unwrap();
+            assert_eq!([timings.scan.len(), timings.parse.len(), timings.check.len(), timings.run.len()], [2; 4]);
+        }
+        let report = measure("1 2 +", "main", 3).unwrap().report();
+        assert_eq!(report.lines().map(|line| line.split_whitespace().next().unwrap()).collect::<Vec<_>>(),
+            vec!["phase", "scan", "parse", "check", "run"]);
+        assert!(measure("1 nope", "main", 1).is_err());
+    }
+}
@@ -0,0 +1,241 @@
+use alloc::{boxed::Box, string::{String, ToString}, sync::Arc, vec, vec::Vec};
+use rpds::RedBlackTreeMapSync;
+use crate::edition::Edition;
+use crate::evaluator::{NativeWord, Value};
+use crate::symbol::Symbol;
+use crate::typechecker::{Effects, Environment, Type};
+
+/// A word that's there without being defined in chara: its type, what it does besides change the stack, and
+/// what it's for.
+pub struct Builtin {
+    pub name: String,
+    pub t: Type,
+    pub effects: Effects,
+    pub doc: String,
+    /// The Rust that runs the word, or `None` for the words the evaluator runs itself. Chara's own words are all
+    /// run by the evaluator, which an engine only lets run the ones in its registry.
+    pub native: Option<NativeWord>,
+}
+
+impl Builtin {
+    /// A pure word the evaluator runs itself.
+    pub fn new(name: &str, t: Type, doc: &str) -> Builtin {
+        Builtin { name: name.to_string(), t, effects: Effects::PURE, doc: doc.to_string(), native: None }
+    }
+
+    /// A word implemented in Rust, which should pop the inputs and push the outputs `t` says it has. There's no
+    /// telling what Rust does, so it's taken to do IO unless it's given other effects.
+    pub fn native(name: &str, t: Type, doc: &str, word: impl FnMut(&mut Vec<Value>) -> Result<(), String> + 'static) -> Builtin {
+        Builtin { name: name.to_string(), t, effects: Effects::IO, doc: doc.to_string(), native: Some(Box::new(word)) }
+    }
+
+    pub fn with_effects(mut self, effects: Effects) -> Builtin {
+        self.effects = effects;
+        self
+    }
+}
+
+/// A set of builtins, for checking and running programs with, and for tooling to list.
+#[derive(Default)]
+pub struct Registry {
+    builtins: Vec<Builtin>,
+}
+
+impl Registry {
+    /// A registry with no builtins in it.
+    pub fn new() -> Registry {
+        Self::default()
+    }
+
+    /// Every word built into chara.
+    pub fn standard() -> Registry {
+        Self::for_edition(Edition::LATEST)
+    }
+
+    /// Every word built into chara for programs written for `edition`, which has what was built in as of it.
+    pub fn for_edition(edition: Edition) -> Registry {
+        // Every edition so far has the same builtins.
+        match edition {
+            Edition::E2024 => {}
+        }
+        let mut registry = Registry::new();
+        let mut word = |name: &str, t: Type, doc: &str| registry.add(Builtin::new(name, t, doc));
+        let f = Type::Function;
+        let (a, b, c, d) = (Type::Param(0), Type::Param(1), Type::Param(2), Type::Param(3));
+        let (int, bool, string, bytes) = (Type::Int, Type::Bool, Type::String, Type::Bytes);
+        word("+", f(vec![int.clone(), int.clone()], vec![int.clone()]), "Add two numbers, wrapping around on overflow.");
+        word("-", f(vec![int.clone(), int.clone()], vec![int.clone()]), "Subtract the top number from the one under it.");
+        word("*", f(vec![int.clone(), int.clone()], vec![int.clone()]), "Multiply two numbers, wrapping around on overflow.");
+        word("/", f(vec![int.clone(), int.clone()], vec![int.clone()]), "Divide the number under the top one by it, rounding toward zero.");
+        word("<", f(vec![a.clone(), a.clone()], vec![bool.clone()]), "Whether the value under the top one orders before it.");
+        word(">", f(vec![a.clone(), a.clone()], vec![bool.clone()]), "Whether the value under the top one orders after it.");
+        word("<=", f(vec![a.clone(), a.clone()], vec![bool.clone()]), "Whether the value under the top one orders before or equal to it.");
+        word(">=", f(vec![a.clone(), a.clone()], vec![bool.clone()]), "Whether the value under the top one orders after or equal to it.");
+        word("compare", f(vec![a.clone(), a.clone()], vec![int.clone()]), "-1, 0 or 1 as the value under the top one orders before, equal to or after it. Numbers order by value, and strings and bytes lexicographically.");
+        word("=", f(vec![a.clone(), a.clone()], vec![bool.clone()]), "Whether two values are equal.");
+        word("!=", f(vec![a.clone(), a.clone()], vec![bool.clone()]), "Whether two values aren't equal.");
+        word("hash", f(vec![a.clone()], vec![int.clone()]), "A number that's the same for equal values.");
+        word("not", f(vec![bool.clone()], vec![bool.clone()]), "Negate a boolean.");
+        word("and", f(vec![bool.clone(), bool.clone()], vec![bool.clone()]), "Whether both booleans are true.");
+        word("or", f(vec![bool.clone(), bool.clone()], vec![bool.clone()]), "Whether either boolean is true.");
+        word("and*", f(vec![f(vec![a.clone()], vec![bool.clone()]), f(vec![a.clone()], vec![bool.clone()])], vec![bool.clone()]),
+            "Whether both predicates hold, each run on a copy of the stack. The second only runs if the first holds.");
+        word("or*", f(vec![f(vec![a.clone()], vec![bool.clone()]), f(vec![a.clone()], vec![bool.clone()])], vec![bool.clone()]),
+            "Whether either predicate holds, each run on a copy of the stack. The second only runs if the first doesn't.");
+        word("if", f(vec![bool.clone(), f(vec![a.clone()], vec![a.clone()])], vec![]), "Run a quotation if given true. It can't change how many values are on the stack, so it means the same either way.");
+        word("unless", f(vec![bool.clone(), f(vec![a.clone()], vec![a.clone()])], vec![]), "Run a quotation if given false. It can't change how many values are on the stack, so it means the same either way.");
+        // How many definitions there are is only known when it runs, so the list is `Dyn`.
+        word("words", f(vec![], vec![Type::Dyn]), "A list of the name and signature of every definition, as pairs, sorted by name.");
+        word("see", f(vec![string.clone()], vec![string.clone()]), "The source of the definition with the given name.");
+        // Which definition it finds is only known when it runs, so what it finds is `Dyn`.
+        #[cfg(feature = "reflection")]
+        {
+            word("lookup", f(vec![string.clone()], vec![Type::Dyn, bool.clone()]),
+                "A quotation that calls the definition with the given name, and whether there is one. It's empty if there isn't.");
+        }
+        word("assert", f(vec![bool.clone()], vec![]), "Stop with an error unless given true.");
+        word("assert-eq", f(vec![a.clone(), a.clone()], vec![]), "Stop with an error unless the top value equals the one under it.");
+        let apply = |t_out: &Type| f(vec![a.clone()], vec![t_out.clone()]);
+        word("bi", f(vec![a.clone(), apply(&b), apply(&c)], vec![b.clone(), c.clone()]), "Run two quotations, each on its own copy of a value.");
+        word("tri", f(vec![a.clone(), apply(&b), apply(&c), apply(&d)], vec![b.clone(), c.clone(), d]), "Run three quotations, each on its own copy of a value.");
+        word("keep", f(vec![a.clone(), apply(&b)], vec![b.clone(), a.clone()]), "Run a quotation on a value, then push the value again.");
+        // Quotations only have a single parameter for what's below their inputs, so capturing a value leaves one
+        // with none.
+        word("curry", f(vec![a.clone(), apply(&b)], vec![f(vec![], vec![b.clone()])]), "Put a value at the start of a quotation.");
+        // What they leave depends on the quotations they're given, so `check_term` checks them against a list whose
+        // quotations' types are known and a count written out, and these only give their inputs.
+        word("compose", f(vec![f(vec![], vec![])], vec![apply(&b)]), "Join a list of quotations into one that runs each of them in turn.");
+        word("apply-n", f(vec![apply(&b), int.clone()], vec![]), "Run a quotation on each of the given number of values.");
+        word("both", f(vec![a.clone(), a.clone(), apply(&b)], vec![b.clone(), b.clone()]), "Run a quotation on each of two values.");
+        // What the retain stack holds is tracked by `check_term`, so these only give each word's effect on the stack.
+        word(">r", f(vec![a.clone()], vec![]), "Move the top value onto the retain stack.");
+        word("r>", f(vec![], vec![a.clone()]), "Move the top value of the retain stack back onto the stack.");
+        word("r@", f(vec![], vec![a.clone()]), "Copy the top value of the retain stack onto the stack.");
+        word("throw", f(vec![a.clone()], vec![]), "Stop running, to the handler of the nearest `try` if there is one.");
+        word("try", f(vec![a.clone(), apply(&b), f(vec![a.clone(), c.clone()], vec![b.clone()])], vec![b.clone()]),
+            "Run a quotation, and if it throws, run the handler on the stack from before it and the thrown value.");
+        word("dynamic", f(vec![a.clone()], vec![Type::Dyn]), "Make a value `Dyn`, so it's only checked when it's used.");
+        word("show", f(vec![a.clone()], vec![string.clone()]), "A value as it's printed.");
+        word("type-of", f(vec![a.clone()], vec![string.clone()]), "The name of the kind of value on top of the stack.");
+        word("bytes-length", f(vec![bytes.clone()], vec![int.clone()]), "How many bytes there are.");
+        word("bytes-at", f(vec![bytes.clone(), int.clone()], vec![int.clone()]), "The byte at an index.");
+        word("bytes-slice", f(vec![bytes.clone(), int.clone(), int.clone()], vec![bytes.clone()]), "The bytes from a start index up to but not including an end one.");
+        word("bytes-cat", f(vec![bytes.clone(), bytes.clone()], vec![bytes.clone()]), "Join two byte strings.");
+        word("string->bytes", f(vec![string.clone()], vec![bytes.clone()]), "A string's UTF-8 encoding.");
+        word("bytes->string", f(vec![bytes.clone()], vec![string.clone()]), "Decode UTF-8 bytes.");
+        // Lists are quotations of their values, which have no type of their own to give the elements.
+        let (list, less) = (f(vec![], vec![]), f(vec![a.clone(), a.clone()], vec![bool.clone()]));
+        word("range", f(vec![int.clone(), int.clone()], vec![list.clone()]), "A list of the numbers from a start up to but not including an end.");
+        word("each", f(vec![list.clone(), f(vec![a.clone()], vec![])], vec![]), "Run a quotation on each value of a list.");
+        word("enumerate", f(vec![list.clone()], vec![list.clone()]), "A list of each index and value of a list, as pairs.");
+        word("sort", f(vec![list.clone(), less.clone()], vec![list.clone()]), "Sort a list by a quotation saying whether one value goes before another.");
+        word("binary-search", f(vec![list.clone(), a.clone(), less.clone()], vec![int.clone()]), "The index of a value in a sorted list, or -1 if it isn't there.");
+        word("min-by", f(vec![list.clone(), less.clone()], vec![a.clone()]), "The least value of a list, by a quotation saying whether one is less than another.");
+        word("max-by", f(vec![list, less], vec![a.clone()]), "The greatest value of a list, by a quotation saying whether one is less than another.");
+        // Lazy sequences are lists at runtime, of a seed, quotations for ending and stepping it, and where it stops once
+        // `take` or `take-while` have cut it short. Only `force` produces their values.
+        let seq = Type::Named("Seq".to_string(), vec![a.clone()]);
+        word("iterate", f(vec![a.clone(), f(vec![a.clone()], vec![a.clone()])], vec![seq.clone()]), "An endless sequence of a seed and each value stepped from the one before.");
+        let (done, step) = (f(vec![b.clone()], vec![bool.clone()]), f(vec![b.clone()], vec![a.clone(), b.clone()]));
+        word("unfold", f(vec![b.clone(), done, step], vec![seq.clone()]), "A sequence of the values stepped out of a seed, until it's done.");
+        word("take", f(vec![seq.clone(), int.clone()], vec![seq.clone()]), "The sequence of the first values of a sequence.");
+        word("take-while", f(vec![seq.clone(), f(vec![a.clone()], vec![bool.clone()])], vec![seq.clone()]), "The sequence of the values of a sequence up to the first a quotation rejects.");
+        word("force", f(vec![seq], vec![f(vec![], vec![])]), "A list of every value of a sequence, which never finishes for an endless one.");
+        // Coroutines are lists at runtime too, of their stack and the rest of their body. What `resume` passes in
+        // is what the `yield` it picks up from leaves, which isn't tracked, so `yield` can leave anything.
+        let coroutine = Type::Named("Coroutine".to_string(), vec![a.clone(), b.clone()]);
+        word("coroutine", f(vec![apply(&b)], vec![coroutine.clone()]), "A coroutine that runs a quotation a bit at a time.");
+        word("resume", f(vec![coroutine.clone(), a.clone()], vec![coroutine, b.clone(), bool.clone()]),
+            "Run a coroutine with a value until it yields or finishes, pushing what it left and whether it finished.");
+        word("yield", f(vec![b.clone()], vec![a.clone()]), "Pause the running coroutine, handing a value to what resumed it.");
+        // A continuation is a quotation of the rest of the `reset` body, from the value `shift` leaves to what the
+        // body leaves.
+        word("reset", f(vec![f(vec![], vec![a.clone()])], vec![a.clone()]), "Run a quotation, marking how far a `shift` inside it captures.");
+        word("shift", f(vec![f(vec![apply(&b)], vec![b.clone()])], vec![a.clone()]), "Run a quotation on the rest of the `reset` it's in, as a quotation.");
+        let set = Type::Set(Box::new(a.clone()));
+        word("set-new", f(vec![], vec![set.clone()]), "An empty set.");
+        word("set-add", f(vec![set.clone(), a.clone()], vec![set.clone()]), "Add a value to a set.");
+        word("set-contains", f(vec![set.clone(), a.clone()], vec![bool.clone()]), "Whether a set has a value in it.");
+        word("set-union", f(vec![set.clone(), set.clone()], vec![set.clone()]), "The values in either of two sets.");
+        word("set-intersect", f(vec![set.clone(), set.clone()], vec![set.clone()]), "The values in both of two sets.");
+        word("set->list", f(vec![set], vec![f(vec![], vec![])]), "A list of the values in a set, in order.");
+        word("random-int", f(vec![int.clone(), int.clone()], vec![int]), "A random number from a low bound up to a high one, inclusive.");
+        word("random-bool", f(vec![], vec![bool.clone()]), "A random boolean.");
+        #[cfg(feature = "regex")]
+        {
+            word("regex-match", f(vec![string.clone(), string.clone()], vec![bool.clone()]), "Whether a string matches a regex anywhere.");
+            word("regex-find-all", f(vec![string.clone(), string.clone()], vec![f(vec![], vec![])]), "A list of every match of a regex in a string.");
+            word("regex-replace", f(vec![string.clone(), string.clone(), string], vec![Type::String]), "Replace every match of a regex in a string.");
+        }
+        word("breakpoint", f(vec![], vec![]), "Pause here when running in the REPL or debugger.");
+        // The list has to hold whatever is below it on the stack, which types can't say, so it's `Dyn`.
+        word("assert-stack", f(vec![Type::Dyn], vec![]), "Stop with an error unless the stack holds the values of a list.");
+        // Builtins are pure but for the random words and `force`, which can be given an endless sequence.
+        for (name, effects) in [("random-int", Effects::NONDETERMINISM), ("random-bool", Effects::NONDETERMINISM), ("force", Effects::DIVERGENCE)] {
+            if let Some(builtin) = registry.builtins.iter_mut().find(|builtin| builtin.name == name) {
+                builtin.effects = effects;
+            }
+        }
+        registry
+    }
+
+    /// Add a builtin, replacing any already there with the same name.
+    pub fn add(&mut self, builtin: Builtin) {
+        self.builtins.retain(|other| other.name != builtin.name);
+        self.builtins.push(builtin);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Builtin> {
+        self.builtins.iter().find(|builtin| builtin.name == name)
+    }
+
+    /// The builtins, in the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = &Builtin> {
+        self.builtins.iter()
+    }
+
+    /// The builtins, taking their implementations along with them.
+    pub fn into_builtins(self) -> Vec<Builtin> {
+        self.builtins
+    }
+
+    /// The types of the builtins, for programs to be checked against.
+    pub fn environment(&self) -> Environment {
+        self.builtins.iter().map(|builtin| (Symbol::intern(&builtin.name), Arc::new(builtin.t.clone()))).collect()
+    }
+
+    /// The effects of the builtins that have any.
+    pub fn effects(&self) -> RedBlackTreeMapSync<Symbol, Effects> {
+        self.builtins.iter()
+            .filter(|builtin| builtin.effects != Effects::PURE)
+            .map(|builtin| (Symbol::intern(&builtin.name), builtin.effects))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::typechecker::{Effects, Type};
+    use super::{Builtin, Registry};
+
+    #[test]
+    fn lists_every_builtin_with_its_type_and_docs() {
+        let registry = Registry::standard();
+        let keep = registry.get("keep").unwrap();
+        assert_eq!(keep.t.to_string(), "(a, (a -> b) -> b, a)");
+        assert!(registry.iter().all(|builtin| !builtin.doc.is_empty() && builtin.native.is_none()));
+        assert_eq!(registry.get("random-int").unwrap().effects, Effects::NONDETERMINISM);
+        assert_eq!(registry.environment().size(), registry.iter().count());
+        assert_eq!(registry.effects().size(), 3);
+    }
+
+    #[test]
+    fn adding_a_builtin_replaces_one_with_the_same_name() {
+        let mut registry = Registry::standard();
+        let count = registry.iter().count();
+        registry.add(Builtin::native("+", Type::Function(vec![], vec![]), "Nothing.", |_| Ok(())));
+        assert_eq!(registry.iter().count(), count);
+        assert_eq!(registry.get("+").unwrap().effects, Effects::IO);
+        assert!(registry.get("+").unwrap().native.is_some());
+    }
+}
@@ -0,0 +1,218 @@
+use crate::error::Error;
+use crate::evaluator::Value;
+use alloc::{format, string::{String, ToString}, vec::Vec};
+
+/// Types that can be pushed onto a chara stack. Tuples push each element in order, so the last element
+/// ends up on top.
+pub trait IntoChara {
+    fn push_onto(self, stack: &mut Vec<Value>);
+}
+
+/// Types that can be popped off a chara stack. Tuples pop from the top, so the last element is taken
+/// from the top of the stack.
+pub trait FromChara: Sized {
+    /// How many values `pop_from` takes off the stack.
+    const VALUES: usize = 1;
+
+    fn pop_from(stack: &mut Vec<Value>) -> Result<Self, Error>;
+}
+
+impl From<i64> for Value {
+    fn from(i: i64) -> Self {
+        Value::Int(i)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_string())
+    }
+}
+
+/// Lists are quotations that push each of their elements.
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(values: Vec<T>) -> Self {
+        Value::Quotation(values.into_iter().map(|v| v.into().to_factor()).collect())
+    }
+}
+
+impl TryFrom<Value> for i64 {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Int(i) => Ok(i),
+            v => Err(Error::ConversionError(format!("Expected Int, got {}", v))),
+        }
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Bool(b) => Ok(b),
+            v => Err(Error::ConversionError(format!("Expected Bool, got {}", v))),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::String(s) => Ok(s),
+            v => Err(Error::ConversionError(format!("Expected String, got {}", v))),
+        }
+    }
+}
+
+/// Only quotations made up entirely of literals can be converted to lists.
+impl<T: TryFrom<Value, Error = Error>> TryFrom<Value> for Vec<T> {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Quotation(factors) => factors.iter()
+                .map(|factor| {
+                    let value = Value::from_factor(factor).ok_or_else(|| {
+                        Error::ConversionError(format!("Expected a literal in list, got {:?}", factor))
+                    })?;
+                    T::try_from(value)
+                })
+                .collect(),
+            v => Err(Error::ConversionError(format!("Expected list, got {}", v))),
+        }
+    }
+}
+
+macro_rules! single_value_conversions {
+    ($($t:ty),*) => {
+        $(
+            impl IntoChara for $t {
+                fn push_onto(self, stack: &mut Vec<Value>) {
+                    stack.push(self.into());
+                }
+            }
+
+            impl FromChara for $t {
+                fn pop_from(stack: &mut Vec<Value>) -> Result<Self, Error> {
+                    let value = stack.pop()
+                        .ok_or_else(|| Error::ConversionError("Stack underflow".to_string()))?;
+                    <$t>::try_from(value)
+                }
+            }
+        )*
+    };
+}
+
+single_value_conversions!(i64, bool, String);
+
+impl IntoChara for Value {
+    fn push_onto(self, stack: &mut Vec<Value>) {
+        stack.push(self);
+    }
+}
+
+impl FromChara for Value {
+    fn pop_from(stack: &mut Vec<Value>) -> Result<Self, Error> {
+        stack.pop().ok_or_else(|| Error::ConversionError("Stack underflow".to_string()))
+    }
+}
+
+impl IntoChara for &str {
+    fn push_onto(self, stack: &mut Vec<Value>) {
+        stack.push(self.into());
+    }
+}
+
+impl<T: Into<Value>> IntoChara for Vec<T> {
+    fn push_onto(self, stack: &mut Vec<Value>) {
+        stack.push(self.into());
+    }
+}
+
+impl<T: TryFrom<Value, Error = Error>> FromChara for Vec<T> {
+    fn pop_from(stack: &mut Vec<Value>) -> Result<Self, Error> {
+        let value = stack.pop().ok_or_else(|| Error::ConversionError("Stack underflow".to_string()))?;
+        Vec::<T>::try_from(value)
+    }
+}
+
+macro_rules! tuple_conversions {
+    ($($name:ident),*) => {
+        impl<$($name: IntoChara),*> IntoChara for ($($name,)*) {
+            #[allow(non_snake_case)]
+            fn push_onto(self, stack: &mut Vec<Value>) {
+                let ($($name,)*) = self;
+                $($name.push_onto(stack);)*
+            }
+        }
+
+        impl<$($name: FromChara),*> FromChara for ($($name,)*) {
+            const VALUES: usize = 0 $(+ $name::VALUES)*;
+
+            #[allow(non_snake_case)]
+            fn pop_from(stack: &mut Vec<Value>) -> Result<Self, Error> {
+                tuple_conversions!(@pop stack; $($name),*);
+                Ok(($($name,)*))
+            }
+        }
+    };
+    // Pop in reverse order so the last element of the tuple comes from the top of the stack.
+    (@pop $stack:ident; $first:ident $(, $rest:ident)*) => {
+        tuple_conversions!(@pop $stack; $($rest),*);
+        let $first = $first::pop_from($stack)?;
+    };
+    (@pop $stack:ident;) => {};
+}
+
+tuple_conversions!(A, B);
+tuple_conversions!(A, B, C);
+tuple_conversions!(A, B, C, D);
+
+#[cfg(test)]
+mod tests {
+    use crate::evaluator::Value;
+    use super::{FromChara, IntoChara};
+
+    #[test]
+    fn converts_scalars() {
+        assert_eq!(Value::from(1), Value::Int(1));
+        assert_eq!(i64::try_from(Value::Int(1)).unwrap(), 1);
+        assert!(bool::try_from(Value::Bool(true)).unwrap());
+        assert_eq!(String::try_from(Value::from("a")).unwrap(), "a");
+        assert!(i64::try_from(Value::Bool(true)).is_err());
+    }
+
+    #[test]
+    fn converts_lists_through_quotations() {
+        let value = Value::from(vec![1, 2, 3]);
+        assert!(matches!(value, Value::Quotation(ref factors) if factors.len() == 3));
+        assert_eq!(Vec::<i64>::try_from(value).unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn tuples_keep_their_last_element_on_top() {
+        let mut stack = Vec::new();
+        (1, true, "x").push_onto(&mut stack);
+        assert_eq!(stack, vec![Value::Int(1), Value::Bool(true), Value::from("x")]);
+        let (i, b, s) = <(i64, bool, String)>::pop_from(&mut stack).unwrap();
+        assert_eq!((i, b, s.as_str()), (1, true, "x"));
+        assert!(stack.is_empty());
+    }
+}
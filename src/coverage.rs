@@ -0,0 +1,124 @@
+use std::collections::{BTreeMap, HashMap};
+use crate::evaluator::{Observer, Value};
+use crate::parser::{Cycle, Factor};
+use crate::scanner::Span;
+
+/// Records how many times each factor ran, keyed by its span.
+///
+/// Quotation literals aren't counted themselves: pushing one says nothing about whether its body ran,
+/// so only the factors inside them are. That way the untaken branch of an `ifte` shows up as uncovered.
+#[derive(Default)]
+pub struct Coverage {
+    hits: HashMap<Span, u64>,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Self::default()
+    }
+
+    pub fn hits(&self, factor: &Factor) -> u64 {
+        self.hits.get(&factor.span()).copied().unwrap_or(0)
+    }
+
+    /// Every factor in the program that never ran, in source order.
+    pub fn uncovered<'a>(&self, cycles: &'a [Cycle]) -> Vec<&'a Factor> {
+        coverable(cycles).into_iter().filter(|factor| self.hits(factor) == 0).collect()
+    }
+
+    /// The number of factors that ran, and the number that could have.
+    pub fn summary(&self, cycles: &[Cycle]) -> (usize, usize) {
+        let factors = coverable(cycles);
+        let covered = factors.iter().filter(|factor| self.hits(factor) > 0).count();
+        (covered, factors.len())
+    }
+
+    /// An lcov tracefile for a single source file. A line's hit count is the lowest of the factors on
+    /// it, so a line is only reported as covered once everything on it has run.
+    pub fn lcov(&self, path: &str, cycles: &[Cycle]) -> String {
+        let mut lines: BTreeMap<usize, u64> = BTreeMap::new();
+        for factor in coverable(cycles) {
+            let hits = self.hits(factor);
+            lines.entry(factor.token().line)
+                .and_modify(|line| *line = (*line).min(hits))
+                .or_insert(hits);
+        }
+        let mut report = format!("TN:\nSF:{}\n", path);
+        for (line, hits) in &lines {
+            report.push_str(&format!("DA:{},{}\n", line, hits));
+        }
+        let covered = lines.values().filter(|hits| **hits > 0).count();
+        report.push_str(&format!("LF:{}\nLH:{}\nend_of_record\n", lines.len(), covered));
+        report
+    }
+}
+
+impl Observer for Coverage {
+    fn before_factor(&mut self, factor: &Factor, _stack: &[Value], _depth: usize) {
+        if !matches!(factor, Factor::Quotation(_, _)) {
+            *self.hits.entry(factor.span()).or_default() += 1;
+        }
+    }
+}
+
+fn coverable(cycles: &[Cycle]) -> Vec<&Factor> {
+    fn collect<'a>(factors: &'a [Factor], into: &mut Vec<&'a Factor>) {
+        for factor in factors {
+            match factor {
+                Factor::Quotation(inner, _) => collect(inner, into),
+                factor => into.push(factor),
+            }
+        }
+    }
+    let mut factors = Vec::new();
+    for cycle in cycles {
+        match cycle {
+            Cycle::Definition(_, _, body, _, _) => collect(body, &mut factors),
+            Cycle::Term(term, _) => collect(term, &mut factors),
+            Cycle::Test(_, body, expected, _) => {
+                collect(body, &mut factors);
+                collect(expected.as_deref().unwrap_or_default(), &mut factors);
+            }
+            // Macros only run while the program is expanded, so they can't be covered by running it.
+            Cycle::Macro(_, _, _) => {}
+        }
+    }
+    factors
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::evaluator::Evaluator;
+    use crate::format::factor_to_source;
+    use crate::parser::{parse, Cycle};
+    use super::Coverage;
+
+    fn cover(input: &str) -> (Vec<Cycle>, Rc<RefCell<Coverage>>) {
+        let cycles = parse(input).unwrap();
+        let coverage = Rc::new(RefCell::new(Coverage::new()));
+        let mut evaluator = Evaluator::new();
+        evaluator.add_observer(Box::new(coverage.clone()));
+        for cycle in &cycles {
+            evaluator.eval_cycle(cycle).unwrap();
+        }
+        (cycles, coverage)
+    }
+
+    #[test]
+    fn finds_untaken_branches() {
+        let (cycles, coverage) = cover("def abs: (Int -> Int) = [0 <] [0 swap -] [] ifte;\n5 abs");
+        let coverage = coverage.borrow();
+        let uncovered: Vec<String> = coverage.uncovered(&cycles).into_iter().map(factor_to_source).collect();
+        assert_eq!(uncovered, vec!["0", "swap", "-"]);
+        assert_eq!(coverage.summary(&cycles), (5, 8));
+    }
+
+    #[test]
+    fn writes_lcov_line_records() {
+        let (cycles, coverage) = cover("def unused: (Int -> Int) = 1 +;\n2 dup\n[3] drop");
+        let lcov = coverage.borrow().lcov("main.chara", &cycles);
+        assert_eq!(lcov, "TN:\nSF:main.chara\nDA:1,0\nDA:2,1\nDA:3,0\nLF:3\nLH:1\nend_of_record\n");
+    }
+}
@@ -0,0 +1,314 @@
+use alloc::{collections::{BTreeSet, VecDeque}, string::{String, ToString}, vec::Vec};
+use rpds::ListSync;
+use crate::error::Error;
+use crate::evaluator::{Checkpoint, Evaluator, Value};
+use crate::parser::Factor;
+
+/// How many steps a debugger can go back by default.
+pub const HISTORY_LIMIT: usize = 1000;
+
+/// Why the debugger stopped running.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Pause {
+    /// A single step finished.
+    Stepped,
+    /// Execution is about to enter a definition with a breakpoint on it.
+    Breakpoint(String),
+    /// A `breakpoint` word just ran.
+    BreakpointWord,
+    /// There is nothing left to evaluate.
+    Finished,
+}
+
+/// Drives an evaluator one factor at a time, for interactive debugging.
+pub struct Debugger {
+    evaluator: Evaluator,
+    breakpoints: BTreeSet<String>,
+    history: History,
+}
+
+impl Debugger {
+    pub fn new(mut evaluator: Evaluator) -> Debugger {
+        let mut history = History::new(HISTORY_LIMIT);
+        history.start(&mut evaluator);
+        Debugger {
+            evaluator,
+            breakpoints: BTreeSet::new(),
+            history,
+        }
+    }
+
+    /// The steps taken so far, as far back as they can be undone.
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// Undo the last step, going back to where evaluation was before it. Returns `false` if there's no step
+    /// left to undo.
+    pub fn back(&mut self) -> bool {
+        self.history.back(&mut self.evaluator).is_some()
+    }
+
+    pub fn evaluator(&self) -> &Evaluator {
+        &self.evaluator
+    }
+
+    pub fn into_evaluator(self) -> Evaluator {
+        self.evaluator
+    }
+
+    /// Schedule a term to be debugged.
+    pub fn start(&mut self, factors: Vec<Factor>) {
+        self.evaluator.take_breakpoint();
+        self.evaluator.push_term(factors, None);
+    }
+
+    pub fn stack(&self) -> &[Value] {
+        self.evaluator.stack()
+    }
+
+    pub fn current_factor(&self) -> Option<&Factor> {
+        self.evaluator.current_factor()
+    }
+
+    pub fn add_breakpoint(&mut self, name: &str) {
+        self.breakpoints.insert(name.to_string());
+    }
+
+    pub fn remove_breakpoint(&mut self, name: &str) {
+        self.breakpoints.remove(name);
+    }
+
+    /// Evaluate the next factor. If it calls a quotation or definition, stop at the first factor inside it.
+    pub fn step(&mut self) -> Result<Pause, Error> {
+        if !self.history.step(&mut self.evaluator, 0)? {
+            Ok(Pause::Finished)
+        } else if self.evaluator.take_breakpoint() {
+            Ok(Pause::BreakpointWord)
+        } else {
+            Ok(Pause::Stepped)
+        }
+    }
+
+    /// An alias for `step`, to pair with `step_over`.
+    pub fn step_into(&mut self) -> Result<Pause, Error> {
+        self.step()
+    }
+
+    /// Evaluate the next factor, including everything it calls, unless a breakpoint is hit on the way.
+    pub fn step_over(&mut self) -> Result<Pause, Error> {
+        let depth = self.evaluator.depth();
+        match self.step()? {
+            Pause::Stepped => {}
+            pause => return Ok(pause),
+        }
+        if let Some(name) = self.breakpoint_hit() {
+            return Ok(Pause::Breakpoint(name));
+        }
+        self.run_while(|evaluator| evaluator.depth() > depth)
+    }
+
+    /// Run until the end of evaluation, the next breakpoint, or the next `breakpoint` word.
+    pub fn continue_(&mut self) -> Result<Pause, Error> {
+        self.run_while(|_| true)
+    }
+
+    /// The name of the definition being entered, if it has a breakpoint on it.
+    fn breakpoint_hit(&self) -> Option<String> {
+        match self.evaluator.current_definition() {
+            Some((name, true)) if self.breakpoints.contains(name) => Some(name.to_string()),
+            _ => None,
+        }
+    }
+
+    fn run_while(&mut self, condition: impl Fn(&Evaluator) -> bool) -> Result<Pause, Error> {
+        while condition(&self.evaluator) {
+            match self.step()? {
+                Pause::Stepped => {}
+                pause => return Ok(pause),
+            }
+            if let Some(name) = self.breakpoint_hit() {
+                return Ok(Pause::Breakpoint(name));
+            }
+        }
+        if self.evaluator.depth() == 0 {
+            Ok(Pause::Finished)
+        } else {
+            Ok(Pause::Stepped)
+        }
+    }
+}
+
+/// A record of the last steps an evaluator took, each with where it was before it, so they can be looked back
+/// on and undone. Stacks are kept as persistent lists, top first, and a step only replaces the values it
+/// reached, so each shares what's below them with the one before it.
+pub struct History {
+    moments: VecDeque<Moment>,
+    limit: usize,
+    /// The evaluator's stack as of the last step.
+    stack: ListSync<Value>,
+}
+
+struct Moment {
+    /// The factor the step evaluated, if it was known beforehand.
+    factor: Option<Factor>,
+    stack: ListSync<Value>,
+    checkpoint: Checkpoint,
+    /// Where whatever drove the step was, apart from the evaluator.
+    position: usize,
+}
+
+impl History {
+    /// A history that goes back `limit` steps at most.
+    pub fn new(limit: usize) -> History {
+        History { moments: VecDeque::new(), limit, stack: ListSync::new_sync() }
+    }
+
+    /// Forget every step, and start recording from where `evaluator` is.
+    pub fn start(&mut self, evaluator: &mut Evaluator) {
+        self.moments.clear();
+        evaluator.take_reached();
+        self.stack = evaluator.stack().iter().fold(ListSync::new_sync(), |stack, value| stack.push_front(value.clone()));
+    }
+
+    /// Step `evaluator`, recording where it was before, with `position` for whatever is driving it to keep
+    /// track of anything else it needs to go back to.
+    pub fn step(&mut self, evaluator: &mut Evaluator, position: usize) -> Result<bool, Error> {
+        if self.limit > 0 && evaluator.depth() > 0 {
+            if self.moments.len() == self.limit {
+                self.moments.pop_front();
+            }
+            let factor = evaluator.current_factor().cloned();
+            self.moments.push_back(Moment { factor, stack: self.stack.clone(), checkpoint: evaluator.checkpoint(), position });
+        }
+        let result = evaluator.step();
+        let reached = evaluator.take_reached();
+        while self.stack.len() > reached {
+            self.stack.drop_first_mut();
+        }
+        for value in &evaluator.stack()[reached..] {
+            self.stack.push_front_mut(value.clone());
+        }
+        result
+    }
+
+    /// Undo the last step recorded, returning the position recorded with it, or `None` if there's none.
+    pub fn back(&mut self, evaluator: &mut Evaluator) -> Option<usize> {
+        let moment = self.moments.pop_back()?;
+        let mut stack: Vec<Value> = moment.stack.iter().cloned().collect();
+        stack.reverse();
+        evaluator.rewind(moment.checkpoint, stack);
+        evaluator.take_reached();
+        self.stack = moment.stack;
+        Some(moment.position)
+    }
+
+    /// Each step recorded, oldest first, with the factor it evaluated if that was known, and the stack from
+    /// before it, bottom first.
+    pub fn steps(&self) -> impl Iterator<Item = (Option<&Factor>, Vec<Value>)> {
+        self.moments.iter().map(|moment| {
+            let mut stack: Vec<Value> = moment.stack.iter().cloned().collect();
+            stack.reverse();
+            (moment.factor.as_ref(), stack)
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.moments.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.moments.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::evaluator::{Evaluator, Value};
+    use crate::parser::{parse, Cycle, Factor};
+    use super::{Debugger, Pause};
+
+    fn debug(input: &str) -> Debugger {
+        let mut evaluator = Evaluator::new();
+        let mut term = Vec::new();
+        for cycle in parse(input).unwrap() {
+            match cycle {
+                Cycle::Term(factors, _) => term.extend(factors),
+                definition => evaluator.eval_cycle(&definition).unwrap(),
+            }
+        }
+        let mut debugger = Debugger::new(evaluator);
+        debugger.start(term);
+        debugger
+    }
+
+    #[test]
+    fn steps_through_factors() {
+        let mut debugger = debug("1 2 +");
+        assert!(matches!(debugger.current_factor(), Some(Factor::Int(_, _))));
+        assert_eq!(debugger.step().unwrap(), Pause::Stepped);
+        assert_eq!(debugger.stack(), &[Value::Int(1)]);
+        debugger.step().unwrap();
+        assert!(matches!(debugger.current_factor(), Some(Factor::Identifier(name, _)) if name == "+"));
+        debugger.step().unwrap();
+        assert_eq!(debugger.stack(), &[Value::Int(3)]);
+        assert_eq!(debugger.step().unwrap(), Pause::Finished);
+    }
+
+    #[test]
+    fn steps_into_and_over_quotation_calls() {
+        let mut debugger = debug("[1 2] call 3");
+        debugger.step().unwrap();
+        debugger.step_into().unwrap();
+        assert!(matches!(debugger.current_factor(), Some(Factor::Int(_, token)) if token.value == "1"));
+
+        let mut debugger = debug("[1 2] call 3");
+        debugger.step().unwrap();
+        debugger.step_over().unwrap();
+        assert_eq!(debugger.stack(), &[Value::Int(1), Value::Int(2)]);
+        assert!(matches!(debugger.current_factor(), Some(Factor::Int(_, token)) if token.value == "3"));
+    }
+
+    #[test]
+    fn continues_to_breakpoints() {
+        let mut debugger = debug("def inc: (Int -> Int) = 1 +; 1 inc inc");
+        debugger.add_breakpoint("inc");
+        assert_eq!(debugger.continue_().unwrap(), Pause::Breakpoint("inc".to_string()));
+        assert_eq!(debugger.stack(), &[Value::Int(1)]);
+        assert_eq!(debugger.continue_().unwrap(), Pause::Breakpoint("inc".to_string()));
+        assert_eq!(debugger.stack(), &[Value::Int(2)]);
+        assert_eq!(debugger.continue_().unwrap(), Pause::Finished);
+        assert_eq!(debugger.stack(), &[Value::Int(3)]);
+    }
+
+    #[test]
+    fn stops_after_breakpoint_words() {
+        let mut debugger = debug("def inc: (Int -> Int) = breakpoint 1 +; 1 inc 2 breakpoint");
+        assert_eq!(debugger.continue_().unwrap(), Pause::BreakpointWord);
+        assert!(matches!(debugger.current_factor(), Some(Factor::Int(_, token)) if token.value == "1"));
+        assert_eq!(debugger.step_over().unwrap(), Pause::Stepped);
+        assert_eq!(debugger.continue_().unwrap(), Pause::BreakpointWord);
+        assert_eq!(debugger.stack(), &[Value::Int(2), Value::Int(2)]);
+        assert_eq!(debugger.continue_().unwrap(), Pause::Finished);
+    }
+
+    #[test]
+    fn goes_back_through_history() {
+        let mut debugger = debug("def inc: (Int -> Int) = 1 +; 5 [inc] call 2 *");
+        assert_eq!(debugger.continue_().unwrap(), Pause::Finished);
+        assert_eq!(debugger.stack(), &[Value::Int(12)]);
+        let stacks: Vec<Vec<Value>> = debugger.history().steps().map(|(_, stack)| stack).collect();
+        assert_eq!(stacks.first(), Some(&vec![]));
+        assert!(stacks.contains(&vec![Value::Int(5), Value::Int(1)]));
+        while debugger.stack() != [Value::Int(6)] {
+            assert!(debugger.back());
+        }
+        assert!(matches!(debugger.current_factor(), Some(Factor::Int(_, token)) if token.value == "2"));
+        assert_eq!(debugger.continue_().unwrap(), Pause::Finished);
+        assert_eq!(debugger.stack(), &[Value::Int(12)]);
+        while debugger.back() {}
+        assert!(debugger.history().is_empty());
+        assert_eq!(debugger.stack(), &[]);
+        assert!(matches!(debugger.current_factor(), Some(Factor::Int(_, token)) if token.value == "5"));
+    }
+}
@@ -0,0 +1,77 @@
+use crate::format::annotation_to_source;
+use crate::parser::Cycle;
+use crate::scanner::Token;
+use alloc::{format, string::String};
+
+/// A Markdown reference listing each definition with its signature and doc comment, in source order.
+pub fn to_markdown(title: &str, cycles: &[Cycle]) -> String {
+    let mut out = format!("# {}\n", title);
+    for cycle in cycles {
+        if let Cycle::Definition(Token { value: name, .. }, annotation, _, _, docs) = cycle {
+            out.push_str(&format!("\n## `{}`\n\n`{} : {}`\n", name, name, annotation_to_source(annotation)));
+            if let Some(docs) = docs {
+                out.push_str(&format!("\n{}\n", docs));
+            }
+        }
+    }
+    out
+}
+
+/// The same reference as `to_markdown`, as a standalone HTML page.
+pub fn to_html(title: &str, cycles: &[Cycle]) -> String {
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{}</title></head>\n<body>\n<h1>{}</h1>\n",
+        escape(title), escape(title),
+    );
+    for cycle in cycles {
+        if let Cycle::Definition(Token { value: name, .. }, annotation, _, _, docs) = cycle {
+            out.push_str(&format!(
+                "<h2 id=\"{}\"><code>{}</code></h2>\n<pre><code>{} : {}</code></pre>\n",
+                escape(name), escape(name), escape(name), escape(&annotation_to_source(annotation)),
+            ));
+            if let Some(docs) = docs {
+                out.push_str(&format!("<p>{}</p>\n", escape(docs).replace("\n\n", "</p>\n<p>")));
+            }
+        }
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::parse;
+
+    const SOURCE: &str = "## Squares a number.\ndef sq: (Int -> Int) = dup *;\ndef lt: (Int, Int -> Bool) = <;\n2 sq";
+
+    #[test]
+    fn lists_definitions_in_markdown() {
+        let cycles = parse(SOURCE).unwrap();
+        assert_eq!(super::to_markdown("math", &cycles), "\
+# math
+
+## `sq`
+
+`sq : (Int -> Int)`
+
+Squares a number.
+
+## `lt`
+
+`lt : (Int, Int -> Bool)`
+");
+    }
+
+    #[test]
+    fn escapes_html() {
+        let cycles = parse(SOURCE).unwrap();
+        let html = super::to_html("<math>", &cycles);
+        assert!(html.contains("<title>&lt;math&gt;</title>"));
+        assert!(html.contains("<pre><code>lt : (Int, Int -&gt; Bool)</code></pre>"));
+        assert!(html.contains("<p>Squares a number.</p>"));
+    }
+}
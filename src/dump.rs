@@ -0,0 +1,98 @@
+use crate::parser::{Cycle, Factor, TypeAnnotation, Value};
+use crate::scanner::Token;
+use alloc::{format, string::{String, ToString}, vec::Vec};
+
+/// Render cycles as S-expressions, one cycle per line. This is meant for debugging the parser, so it
+/// shows the structure of the AST rather than reproducing the source.
+pub fn to_sexpr(cycles: &[Cycle]) -> String {
+    cycles.iter().map(cycle_to_sexpr).collect::<Vec<_>>().join("\n")
+}
+
+/// Render cycles as pretty-printed JSON.
+#[cfg(feature = "serde")]
+pub fn to_json(cycles: &[Cycle]) -> String {
+    serde_json::to_string_pretty(cycles).expect("the AST is always serializable")
+}
+
+fn cycle_to_sexpr(cycle: &Cycle) -> String {
+    match cycle {
+        Cycle::Definition(Token { value: name, .. }, annotation, factors, _, _) => {
+            format!("(def {} {} {})", name, annotation_to_sexpr(annotation), term_to_sexpr(factors))
+        }
+        Cycle::Term(factors, _) => format!("(term {})", term_to_sexpr(factors)),
+        Cycle::Test(name, body, Some(expected), _) => {
+            format!("(test \"{}\" {} (expect {}))", name, term_to_sexpr(body), term_to_sexpr(expected))
+        }
+        Cycle::Test(name, body, None, _) => format!("(test \"{}\" {})", name, term_to_sexpr(body)),
+        Cycle::Macro(name, body, _) => format!("(macro {} {})", name, term_to_sexpr(body)),
+    }
+}
+
+fn term_to_sexpr(factors: &[Factor]) -> String {
+    format!("({})", factors.iter().map(factor_to_sexpr).collect::<Vec<_>>().join(" "))
+}
+
+fn factor_to_sexpr(factor: &Factor) -> String {
+    match factor {
+        Factor::Dup(_) => "dup".to_string(),
+        Factor::Drop(_) => "drop".to_string(),
+        Factor::Quote(_) => "quote".to_string(),
+        Factor::Call(_) => "call".to_string(),
+        Factor::Cat(_) => "cat".to_string(),
+        Factor::Swap(_) => "swap".to_string(),
+        Factor::Ifte(_) => "ifte".to_string(),
+        Factor::Cond(_) => "cond".to_string(),
+        Factor::Let(names, _) => format!("(let {})", names.iter().map(|name| name.as_str()).collect::<Vec<_>>().join(" ")),
+        Factor::Local(name, _) => format!("(local {})", name),
+        Factor::Int(Value::Integer(i), _) => format!("(int {})", i),
+        Factor::Bool(Value::Boolean(b), _) => format!("(bool {})", b),
+        Factor::String(Value::String(s), _) => format!("(string \"{}\")", s),
+        Factor::Bytes(Value::Bytes(bytes), _) => format!("(bytes {})", bytes.iter().map(u8::to_string).collect::<Vec<_>>().join(" ")),
+        Factor::Int(value, _) | Factor::Bool(value, _) | Factor::String(value, _) | Factor::Bytes(value, _) => format!("(literal {:?})", value),
+        Factor::Set(elements, _) => format!("(set {})", term_to_sexpr(elements)),
+        Factor::Identifier(name, _) => format!("(word {})", name),
+        Factor::Quotation(factors, _) => format!("(quotation {})", term_to_sexpr(factors)),
+        Factor::Assertion(annotation) => format!("(assert {})", annotation_to_sexpr(annotation)),
+        Factor::Ascription(annotation, _) => format!("(ascribe {})", annotation_to_sexpr(annotation)),
+    }
+}
+
+fn annotation_to_sexpr(annotation: &TypeAnnotation) -> String {
+    match annotation {
+        TypeAnnotation::Function(t_in, t_out, _, _) => {
+            let render = |types: &[TypeAnnotation]| types.iter().map(annotation_to_sexpr).collect::<Vec<_>>().join(" ");
+            format!("(-> ({}) ({}))", render(t_in), render(t_out))
+        }
+        TypeAnnotation::Identifier(name, _) => name.clone(),
+        TypeAnnotation::Apply(name, args, _, _) => {
+            format!("({} {})", name, args.iter().map(annotation_to_sexpr).collect::<Vec<_>>().join(" "))
+        }
+        TypeAnnotation::Effectful(t, _, effects) => {
+            let effects = effects.iter().map(|effect| format!(" {}", effect.value)).collect::<String>();
+            format!("(! {}{})", annotation_to_sexpr(t), effects)
+        }
+        TypeAnnotation::Total(t, _) => format!("(total {})", annotation_to_sexpr(t)),
+        TypeAnnotation::Memo(t, _) => format!("(memo {})", annotation_to_sexpr(t)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::parse;
+
+    #[test]
+    fn renders_definitions_and_terms() {
+        let cycles = parse("def twice: (Int -> Int, Int) = dup; 1 [\"a\" true] twice").unwrap();
+        assert_eq!(super::to_sexpr(&cycles), "\
+(def twice (-> (Int) (Int Int)) (dup))
+(term ((int 1) (quotation ((string \"a\") (bool true))) (word twice)))");
+    }
+
+    #[test]
+    fn renders_tests() {
+        let cycles = parse("test \"one\" { 1 expect 1 } test \"two\" { 2 2 assert-eq }").unwrap();
+        assert_eq!(super::to_sexpr(&cycles), "\
+(test \"one\" ((int 1)) (expect ((int 1))))
+(test \"two\" ((int 2) (int 2) (word assert-eq)))");
+    }
+}
@@ -0,0 +1,97 @@
+use alloc::{format, string::ToString, vec::Vec};
+use core::fmt;
+use crate::error::Error;
+use crate::scanner::{Span, Token};
+
+/// A version of the language. Changes that would break existing programs only apply to programs written for the
+/// edition that makes them, so a program keeps meaning what it did. So far an edition only decides which builtins
+/// a program is checked and run with, by way of `Registry::for_edition`; scanning and parsing are the same in
+/// every edition.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Edition {
+    /// The first edition, which programs that don't say are written for.
+    #[default]
+    E2024,
+}
+
+impl Edition {
+    /// The newest edition this version of chara knows.
+    pub const LATEST: Edition = Edition::E2024;
+    pub const ALL: [Edition; 1] = [Edition::E2024];
+
+    /// The edition written as `name`, like `2024`.
+    pub fn parse(name: &str) -> Option<Edition> {
+        Self::ALL.into_iter().find(|edition| edition.as_str() == name)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Edition::E2024 => "2024",
+        }
+    }
+}
+
+impl fmt::Display for Edition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// The edition `source` says it's written for, with a `#lang chara/2024` line before any code, if it says. The
+/// line is a comment to the scanner, so it's found before scanning, and older versions of chara skip it. One that
+/// doesn't name an edition this version knows is an error at its `#lang`.
+pub fn declared(source: &str) -> Result<Option<Edition>, Error> {
+    let mut offset = 0;
+    let Some((number, line)) = source.split_inclusive('\n').enumerate().find(|(_, line)| {
+        offset += line.len();
+        !line.trim().is_empty()
+    }) else {
+        return Ok(None);
+    };
+    offset -= line.len();
+    let Some(language) = line.trim().strip_prefix("#lang").filter(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace)) else {
+        return Ok(None);
+    };
+    let indent = line.len() - line.trim_start().len();
+    let start = offset + indent;
+    let token = Token::new("#lang", number + 1, line[..indent].chars().count() + 1, Span::new(start, start + "#lang".len()));
+    let language = language.trim();
+    let name = language.strip_prefix("chara/").ok_or_else(|| {
+        let message = match language {
+            "" => format!("`#lang` has to say which edition the source is written for, like `#lang chara/{}`", Edition::LATEST),
+            language => format!("`#lang {}` isn't chara, which is written `#lang chara/{}`", language, Edition::LATEST),
+        };
+        Error::ParseError(message, token.clone())
+    })?;
+    Edition::parse(name).map(Some).ok_or_else(|| {
+        let known = Edition::ALL.iter().map(Edition::to_string).collect::<Vec<_>>().join(", ");
+        Error::ParseError(format!("unknown edition `{}`; this version of chara knows {}", name, known), token)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{declared, Edition};
+
+    #[test]
+    fn reads_the_edition_a_source_declares() {
+        assert_eq!(declared("\n#lang chara/2024\n1 2 +"), Ok(Some(Edition::E2024)));
+        assert_eq!(declared("1 2 +\n#lang chara/2024"), Ok(None));
+        assert_eq!(declared("# just a comment"), Ok(None));
+        assert_eq!(declared(""), Ok(None));
+        assert_eq!(declared("#lang chara/2031").unwrap_err().to_string(), "1:1: unknown edition `2031`; this version of chara knows 2024");
+        assert!(declared("#lang racket").is_err());
+        assert_eq!(declared("#language").unwrap(), None);
+        for source in ["#lang", "\n  #lang \n1", "#lang chara/"] {
+            assert!(matches!(declared(source), Err(super::Error::ParseError(_, token)) if token.value == "#lang"), "{}", source);
+        }
+        match declared("\n  #lang \n1").unwrap_err() {
+            super::Error::ParseError(message, token) => {
+                assert_eq!(message, "`#lang` has to say which edition the source is written for, like `#lang chara/2024`");
+                assert_eq!((token.line, token.col, token.span.start), (2, 3, 3));
+            }
+            error => panic!("Expected ParseError, got {:?}", error),
+        }
+    }
+}
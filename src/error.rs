@@ -1,11 +1,174 @@
+use core::fmt;
 use crate::scanner::Token;
+use crate::typechecker::Type;
+use alloc::{boxed::Box, format, string::{String, ToString}, vec::Vec};
 
+#[allow(clippy::enum_variant_names)]
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub enum Error {
     ParseError(String, Token),
     TypeError(String, Token),
-    UnexpectedEndOfFile(String),
-    UnexpectedToken(String, Token),
+    /// The input ended while one of the listed tokens was expected, inside the given construct.
+    UnexpectedEndOfFile(Vec<String>, String),
+    /// A token other than the listed ones was found, inside the given construct.
+    UnexpectedToken(Vec<String>, String, Token),
+    /// A failure while evaluating, with the names of the definitions being evaluated, outermost first.
+    RuntimeError(String, Token, Vec<String>),
+    /// A macro couldn't be expanded where it was used, at the given token.
+    ExpansionError(String, Token),
+    /// A value couldn't be converted between its chara and Rust representations.
+    ConversionError(String),
+    /// A refactoring like a rename couldn't be done, e.g. because the new name is taken.
+    RefactorError(String),
+    /// A configuration file or an in-source attribute couldn't be understood.
+    ConfigError(String),
+    /// A word was defined again where that isn't allowed, with where it was defined first and where again.
+    DuplicateDefinition(String, Token, Token),
+    /// A program run for its entry point has no word of the given name, nor any top-level terms to run instead.
+    NoEntryPoint(String),
+    /// Bytes given to `Engine::restore` aren't a snapshot, or are one from an incompatible version.
+    SnapshotError(String),
+    /// An error in one of the files of a package, with the path of the file.
+    InFile(String, Box<Error>),
+    /// Reading or writing a file failed. Only the message is kept so that errors stay comparable.
+    IoError(String),
     EndOfTerm,
     UnknownError,
 }
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ParseError(message, token) => write!(f, "{}:{}: {}", token.line, token.col, message),
+            Error::TypeError(message, token) => write!(f, "{}:{}: type error: {}", token.line, token.col, message),
+            Error::UnexpectedEndOfFile(expected, context) => {
+                write!(f, "unexpected end of file: {} while parsing {}", describe_expected(expected), context)
+            }
+            Error::UnexpectedToken(expected, context, token) => write!(
+                f, "{}:{}: {} while parsing {}, found `{}`",
+                token.line, token.col, describe_expected(expected), context, token.value,
+            ),
+            Error::RuntimeError(message, token, call_stack) if call_stack.is_empty() => {
+                write!(f, "{}:{}: runtime error: {}", token.line, token.col, message)
+            }
+            Error::RuntimeError(message, token, call_stack) => write!(
+                f, "{}:{}: runtime error: {} (in {} -> {})",
+                token.line, token.col, message, call_stack.join(" -> "), token.value,
+            ),
+            Error::ExpansionError(message, token) => write!(f, "{}:{}: expansion error: {}", token.line, token.col, message),
+            Error::ConversionError(message) => write!(f, "conversion error: {}", message),
+            Error::RefactorError(message) => write!(f, "refactor error: {}", message),
+            Error::ConfigError(message) => write!(f, "config error: {}", message),
+            Error::DuplicateDefinition(name, first, again) => write!(
+                f, "{}:{}: `{}` is already defined, at {}:{}",
+                again.line, again.col, name, first.line, first.col,
+            ),
+            Error::NoEntryPoint(name) => write!(f, "nothing to run: define a `{}` word, or add top-level terms", name),
+            Error::SnapshotError(message) => write!(f, "snapshot error: {}", message),
+            Error::InFile(path, error) => write!(f, "{}: {}", path, error),
+            Error::IoError(message) => write!(f, "io error: {}", message),
+            Error::EndOfTerm => write!(f, "unexpected end of term"),
+            Error::UnknownError => write!(f, "unknown error"),
+        }
+    }
+}
+
+impl Error {
+    /// The token the error is about, for errors that have a place in the source.
+    pub fn token(&self) -> Option<&Token> {
+        match self {
+            Error::ParseError(_, token) | Error::TypeError(_, token) | Error::UnexpectedToken(_, _, token)
+            | Error::RuntimeError(_, token, _) | Error::ExpansionError(_, token) | Error::DuplicateDefinition(_, _, token) => Some(token),
+            Error::InFile(_, error) => error.token(),
+            _ => None,
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::IoError(error.to_string())
+    }
+}
+
+/// Render a set of acceptable tokens, e.g. "expected `]`" or "expected one of `,`, `->`, `)`".
+pub fn describe_expected(expected: &[String]) -> String {
+    match expected {
+        [] => "expected nothing".to_string(),
+        [single] => format!("expected {}", single),
+        many => format!("expected one of {}", many.join(", ")),
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Warning {
+    /// The term consumes values it is never given, so it would underflow at runtime.
+    StackUnderflow(Vec<Type>, Token),
+    /// The term leaves more values on the stack than expected.
+    LeftoverValues(Vec<Type>, Token),
+    /// The body of a definition can take more values from the stack than it's declared to take, by the factor
+    /// the token is from.
+    DefinitionUnderflow(String, usize, usize, Token),
+}
+
+impl Warning {
+    pub fn token(&self) -> &Token {
+        match self {
+            Warning::StackUnderflow(_, token) | Warning::LeftoverValues(_, token) | Warning::DefinitionUnderflow(_, _, _, token) => token,
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let render = |types: &[Type]| types.iter().map(Type::to_string).collect::<Vec<_>>().join(", ");
+        match self {
+            Warning::StackUnderflow(types, token) => write!(
+                f, "{}:{}: warning: term would underflow the stack, it needs {}",
+                token.line, token.col, render(types),
+            ),
+            Warning::LeftoverValues(types, token) => write!(
+                f, "{}:{}: warning: term leaves {} value(s) on the stack: {}",
+                token.line, token.col, types.len(), render(types),
+            ),
+            Warning::DefinitionUnderflow(name, declared, needed, token) => write!(
+                f, "{}:{}: warning: `{}` is declared to take {} value(s), but can take {} from the stack by here",
+                token.line, token.col, name, declared, needed,
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::parse;
+    use crate::typechecker::Type;
+    use super::Warning;
+
+    #[test]
+    fn displays_errors_with_their_position() {
+        let error = parse("def a: (Int x").unwrap_err();
+        assert_eq!(error.to_string(), "1:13: expected one of `,`, `->` while parsing type annotation of `def a`, found `x`");
+        let error = parse("def a: Int = 1").unwrap_err();
+        assert_eq!(error.to_string(), "unexpected end of file: expected `;` while parsing definition of `a`");
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn converts_io_errors() {
+        fn read() -> Result<String, Box<dyn std::error::Error>> {
+            Ok(std::fs::read_to_string("/this/file/does/not/exist").map_err(super::Error::from)?)
+        }
+        assert!(read().unwrap_err().to_string().starts_with("io error: "));
+    }
+
+    #[test]
+    fn displays_warnings_with_types() {
+        let token = crate::scanner::Token::unknown();
+        let warning = Warning::LeftoverValues(vec![Type::Int, Type::Function(vec![Type::Param(0)], vec![Type::Bool])], token);
+        assert_eq!(warning.to_string(), "0:0: warning: term leaves 2 value(s) on the stack: Int, (a -> Bool)");
+    }
+}
@@ -0,0 +1,2126 @@
+use alloc::{boxed::Box, collections::{BTreeMap, BTreeSet, VecDeque}, format, rc::Rc, string::{String, ToString}, sync::Arc, vec, vec::Vec};
+use core::{cell::RefCell, cmp::Ordering, fmt, hash::{Hash, Hasher}, iter, mem};
+use rpds::RedBlackTreeMapSync;
+use crate::error::Error;
+use crate::format::{annotation_to_source, factor_to_source, term_to_source, value_to_source};
+use crate::parser::{self, Cycle, Factor, TypeAnnotation};
+use crate::random::Rng;
+use crate::scanner::{Span, Token};
+use crate::symbol::Symbol;
+
+/// A value on the runtime stack. Values are equal when they have the same structure, which for quotations
+/// means the same source, wherever they came from, and they hash the same way, so they can be keys.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Value {
+    Int(i64),
+    Bool(bool),
+    String(String),
+    Bytes(Vec<u8>),
+    /// A set, with its elements in the order `compare` puts them in, so sets that are equal are the same
+    /// value and are always gone through in the same order.
+    Set(Vec<Value>),
+    Quotation(Vec<Factor>),
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        self.compare(other).is_eq()
+    }
+}
+
+impl Eq for Value {}
+
+/// Values are ordered the way `compare` orders them.
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Value) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Value {
+    fn cmp(&self, other: &Value) -> Ordering {
+        self.compare(other)
+    }
+}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Value::Int(i) => (0u8, i).hash(state),
+            Value::Bool(b) => (1u8, b).hash(state),
+            Value::String(s) => (2u8, s).hash(state),
+            Value::Bytes(bytes) => (3u8, bytes).hash(state),
+            Value::Set(elements) => (4u8, elements).hash(state),
+            Value::Quotation(factors) => (5u8, term_to_source(factors)).hash(state),
+        }
+    }
+}
+
+/// Values are displayed as the source that would push them, with control characters in strings escaped so
+/// each value fits on a line, and sets in order, so what's shown is the same on every run and platform.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            // Strings keep the escape sequences of their source, so only characters that aren't part of one are
+            // escaped.
+            Value::String(s) => {
+                write!(f, "\"")?;
+                let mut escaped = false;
+                for c in s.chars() {
+                    match c {
+                        '\n' => write!(f, "\\n")?,
+                        '\t' => write!(f, "\\t")?,
+                        '\r' => write!(f, "\\r")?,
+                        c if c.is_control() => write!(f, "\\u{{{:x}}}", c as u32)?,
+                        '"' if !escaped => write!(f, "\\\"")?,
+                        c => write!(f, "{}", c)?,
+                    }
+                    escaped = c == '\\' && !escaped;
+                }
+                write!(f, "\"")
+            }
+            Value::Set(elements) => {
+                write!(f, "set-new")?;
+                elements.iter().try_for_each(|element| write!(f, " {} set-add", element))
+            }
+            Value::Quotation(factors) => {
+                let rendered = factors.iter()
+                    .map(|factor| Value::from_factor(factor).map_or_else(|| factor_to_source(factor), |value| value.to_string()))
+                    .collect::<Vec<_>>();
+                write!(f, "[{}]", rendered.join(" "))
+            }
+            value => write!(f, "{}", value_to_source(value)),
+        }
+    }
+}
+
+/// The 64-bit FNV-1a hash, which `hash` uses so a value hashes the same in every run and on every target.
+struct Fnv(u64);
+
+impl Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x100_0000_01b3);
+        }
+    }
+}
+
+impl Value {
+    /// A hash of the value's structure, the same for values that are equal.
+    pub fn hash_code(&self) -> i64 {
+        let mut hasher = Fnv(0xcbf2_9ce4_8422_2325);
+        self.hash(&mut hasher);
+        hasher.finish() as i64
+    }
+
+    /// The order of set elements, and what `<` and `compare` use: by type, in the order below, then ints by value, strings and bytes
+    /// lexicographically, sets by their elements and quotations by their source.
+    pub fn compare(&self, other: &Value) -> Ordering {
+        let rank = |value: &Value| match value {
+            Value::Int(_) => 0,
+            Value::Bool(_) => 1,
+            Value::String(_) => 2,
+            Value::Bytes(_) => 3,
+            Value::Set(_) => 4,
+            Value::Quotation(_) => 5,
+        };
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a.cmp(b),
+            (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+            (Value::String(a), Value::String(b)) => a.cmp(b),
+            (Value::Bytes(a), Value::Bytes(b)) => a.cmp(b),
+            (Value::Set(a), Value::Set(b)) => a.iter().zip(b)
+                .map(|(a, b)| a.compare(b))
+                .find(|order| order.is_ne())
+                .unwrap_or_else(|| a.len().cmp(&b.len())),
+            (Value::Quotation(a), Value::Quotation(b)) => term_to_source(a).cmp(&term_to_source(b)),
+            (a, b) => rank(a).cmp(&rank(b)),
+        }
+    }
+
+    /// The factor that pushes this value when evaluated, used when quoting values.
+    pub fn to_factor(&self) -> Factor {
+        match self {
+            Value::Int(i) => Factor::Int(parser::Value::Integer(*i), Token::unknown()),
+            Value::Bool(b) => Factor::Bool(parser::Value::Boolean(*b), Token::unknown()),
+            Value::String(s) => Factor::String(parser::Value::String(s.clone()), Token::unknown()),
+            Value::Bytes(bytes) => Factor::Bytes(parser::Value::Bytes(bytes.clone()), Token::unknown()),
+            Value::Set(elements) => Factor::Set(elements.iter().map(Value::to_factor).collect(), Token::unknown()),
+            Value::Quotation(factors) => Factor::Quotation(factors.clone(), Span::default()),
+        }
+    }
+
+    /// The value pushed by a literal factor or quotation, or `None` for any other factor.
+    pub fn from_factor(factor: &Factor) -> Option<Value> {
+        match factor {
+            Factor::Int(parser::Value::Integer(i), _) => Some(Value::Int(*i)),
+            Factor::Bool(parser::Value::Boolean(b), _) => Some(Value::Bool(*b)),
+            Factor::String(parser::Value::String(s), _) => Some(Value::String(s.clone())),
+            Factor::Bytes(parser::Value::Bytes(bytes), _) => Some(Value::Bytes(bytes.clone())),
+            Factor::Set(elements, _) => elements.iter().map(Value::from_factor).collect::<Option<_>>().map(Value::Set),
+            Factor::Quotation(factors, _) => Some(Value::Quotation(factors.clone())),
+            _ => None,
+        }
+    }
+}
+
+/// Hooks called by the evaluator as it runs, for tools like tracers and profilers. `depth` is the number of
+/// frames on the control stack while the factor runs.
+pub trait Observer {
+    fn before_factor(&mut self, _factor: &Factor, _stack: &[Value], _depth: usize) {}
+    fn after_factor(&mut self, _factor: &Factor, _stack: &[Value], _depth: usize) {}
+    /// Called when the body of a definition starts being evaluated. This happens while the identifier
+    /// that called it is being evaluated, between `before_factor` and `after_factor`.
+    fn enter_definition(&mut self, _name: &str, _depth: usize) {}
+    /// Called when the body of a definition has finished, or a `try` has caught a failure inside it. Not called
+    /// if evaluation fails partway otherwise.
+    fn exit_definition(&mut self, _name: &str, _depth: usize) {}
+}
+
+/// Lets an observer be shared, so its results can still be read after it is handed to an evaluator.
+impl<T: Observer> Observer for Rc<RefCell<T>> {
+    fn before_factor(&mut self, factor: &Factor, stack: &[Value], depth: usize) {
+        self.borrow_mut().before_factor(factor, stack, depth);
+    }
+
+    fn after_factor(&mut self, factor: &Factor, stack: &[Value], depth: usize) {
+        self.borrow_mut().after_factor(factor, stack, depth);
+    }
+
+    fn enter_definition(&mut self, name: &str, depth: usize) {
+        self.borrow_mut().enter_definition(name, depth);
+    }
+
+    fn exit_definition(&mut self, name: &str, depth: usize) {
+        self.borrow_mut().exit_definition(name, depth);
+    }
+}
+
+/// A unit of pending work on the evaluator's control stack.
+#[derive(Debug, Clone)]
+enum Frame {
+    /// A term being evaluated, with the index of its next factor and the definition it belongs to, if any.
+    Term { factors: Arc<Vec<Factor>>, pc: usize, name: Option<Symbol> },
+    /// Waiting for the condition of an `ifte` to finish, after which the stack is restored and a branch runs.
+    Branch { saved: Vec<Value>, then_branch: Vec<Factor>, else_branch: Vec<Factor>, token: Token },
+    /// Waiting for a predicate of a `cond` to finish, with the quotations after it still to go, last first.
+    Cond { saved: Vec<Value>, clauses: Vec<Vec<Factor>>, token: Token },
+    /// The body of a `try` is running above this. If it fails, the stack goes back to `saved`, the retain stack
+    /// to its first `retained` values, and the handler runs.
+    Try { saved: Vec<Value>, retained: usize, handler: Vec<Factor> },
+    /// Waiting for a comparison a sorting or searching word asked for, which runs `less` on the two values it
+    /// pushed onto the stack when it was `depth` deep. It has to leave just a `Bool` in their place, after which
+    /// the search goes on.
+    Compare { depth: usize, less: Vec<Factor>, search: Search, token: Token },
+    /// Waiting for one of the quotations of a lazy sequence being forced, run on a copy of the stack, after
+    /// which the stack is restored and the sequence goes on.
+    Produce { saved: Vec<Value>, production: Production, awaiting: Awaiting, token: Token },
+    /// A coroutine `resume` started is running above this, on a stack of its own. When it yields or finishes, the
+    /// stack goes back to `saved`, with what it handed out on top.
+    Resume { saved: Vec<Value>, token: Token },
+    /// The body of a `reset` is running above this, and a `shift` inside it captures up to here.
+    Reset,
+    /// A memoized definition is running above this, on the `key` values it was given, which start at `height` on
+    /// the stack. What it leaves from there is its result for them.
+    Memo { name: Symbol, key: Vec<Value>, height: usize },
+}
+
+/// A lazy sequence being forced by `take`, `take-while` or `force`. A sequence is a list of its seed, a
+/// quotation saying whether a seed ends the sequence, and a step that takes a seed to a value and the next
+/// seed. Nothing runs until it's forced, so `iterate` can make an infinite one.
+#[derive(Debug, Clone)]
+struct Production {
+    seed: Value,
+    done: Vec<Factor>,
+    step: Vec<Factor>,
+    /// How many more values to take, for `take`.
+    limit: Option<usize>,
+    /// What values have to satisfy to be taken, for `take-while`. The first that doesn't ends the sequence.
+    keep: Option<Vec<Factor>>,
+    /// A value waiting for `keep` to say whether it's taken.
+    pending: Option<Value>,
+    values: Vec<Value>,
+}
+
+/// Which quotation of a `Production` a frame is waiting for.
+#[derive(Debug, Clone, Copy)]
+enum Awaiting {
+    Done,
+    Step,
+    Keep,
+}
+
+/// A sorting or searching word partway through. Its comparisons run one at a time on the control stack, like
+/// `ifte` conditions, so they can be stepped through and can fail like any other code. The comparator says
+/// whether its first value goes before its second.
+#[derive(Debug, Clone)]
+enum Search {
+    /// A bottom-up merge sort. Each pass merges the sorted `runs` in pairs into `merged`, which are the runs of
+    /// the next pass. `merging` is the pair being merged, each reversed so its first value is last, and what's
+    /// been merged from them so far. It's stable, and compares and moves values O(n log n) times.
+    Sort { runs: VecDeque<Vec<Value>>, merged: Vec<Vec<Value>>, merging: Option<(Vec<Value>, Vec<Value>, Vec<Value>)> },
+    /// Narrowing `low` and `high` down to the first of `items` that doesn't go before `target`, then finding
+    /// whether it's equal to `target`, which it is if `target` doesn't go before it either.
+    Bisect { items: Vec<Value>, target: Value, low: usize, high: usize, found: Option<bool> },
+    /// The value that goes first, or last if `max`, of those seen so far, with the rest still to see.
+    Extreme { best: Value, rest: Vec<Value>, max: bool },
+}
+
+/// What a search needs next.
+enum SearchStep {
+    /// To know whether the first value goes before the second.
+    Compare(Value, Value),
+    Done(Value),
+}
+
+impl Search {
+    fn next_step(&mut self) -> SearchStep {
+        match self {
+            Search::Sort { runs, merged, merging } => loop {
+                match merging {
+                    // A value from the second run only goes first if it goes before the one from the first.
+                    Some((first, second, _)) if !first.is_empty() && !second.is_empty() => {
+                        return SearchStep::Compare(second.last().unwrap().clone(), first.last().unwrap().clone());
+                    }
+                    Some(_) => {
+                        let (first, second, mut run) = merging.take().unwrap();
+                        run.extend(first.into_iter().rev().chain(second.into_iter().rev()));
+                        merged.push(run);
+                    }
+                    None => match (runs.pop_front(), runs.pop_front()) {
+                        (Some(mut first), Some(mut second)) => {
+                            first.reverse();
+                            second.reverse();
+                            let run = Vec::with_capacity(first.len() + second.len());
+                            *merging = Some((first, second, run));
+                        }
+                        (Some(run), None) => merged.push(run),
+                        (None, _) if merged.len() > 1 => *runs = mem::take(merged).into(),
+                        (None, _) => {
+                            let sorted = merged.pop().unwrap_or_default();
+                            return SearchStep::Done(Value::Quotation(sorted.iter().map(Value::to_factor).collect()));
+                        }
+                    },
+                }
+            },
+            Search::Bisect { items, target, low, high, .. } if *low < *high => {
+                SearchStep::Compare(items[(*low + *high) / 2].clone(), target.clone())
+            }
+            Search::Bisect { items, target, low, found, .. } => match (found, items.get(*low)) {
+                (None, Some(item)) => SearchStep::Compare(target.clone(), item.clone()),
+                (Some(true), _) => SearchStep::Done(Value::Int(*low as i64)),
+                _ => SearchStep::Done(Value::Int(-1)),
+            },
+            Search::Extreme { best, rest, max } => match rest.last() {
+                Some(value) if *max => SearchStep::Compare(best.clone(), value.clone()),
+                Some(value) => SearchStep::Compare(value.clone(), best.clone()),
+                None => SearchStep::Done(best.clone()),
+            },
+        }
+    }
+
+    fn record(&mut self, before: bool) {
+        match self {
+            Search::Sort { merging, .. } => {
+                let (first, second, run) = merging.as_mut().expect("sorts only compare while merging");
+                run.extend(if before { second.pop() } else { first.pop() });
+            }
+            Search::Bisect { low, high, found, .. } if *low == *high => *found = Some(!before),
+            Search::Bisect { low, high, .. } if before => *low = (*low + *high) / 2 + 1,
+            Search::Bisect { low, high, .. } => *high = (*low + *high) / 2,
+            Search::Extreme { best, rest, .. } => {
+                let value = rest.pop().unwrap();
+                if before {
+                    *best = value;
+                }
+            }
+        }
+    }
+}
+
+/// Evaluates terms using an explicit control stack, so evaluation can be paused between any two factors.
+pub struct Evaluator {
+    stack: Vec<Value>,
+    /// The auxiliary stack that `>r` moves values onto and `r>` moves them back from.
+    retain: Vec<Value>,
+    /// The value being thrown by `throw`, until a `try` catches it or the failure it raised leaves `step`.
+    thrown: Option<Value>,
+    dictionary: Dictionary,
+    /// The signatures of the words defined by running their definitions, as written, for `words` to list.
+    signatures: BTreeMap<Symbol, String>,
+    /// Words implemented by the embedding program rather than in chara.
+    natives: BTreeMap<Symbol, NativeWord>,
+    /// The builtin words it runs, when it's limited to some of them.
+    builtins: Option<BTreeSet<Symbol>>,
+    frames: Vec<Frame>,
+    observers: Vec<Box<dyn Observer>>,
+    /// Where `random-int` and `random-bool` get their numbers.
+    rng: Rng,
+    /// The memoized definitions, by name.
+    memos: BTreeMap<Symbol, MemoTable>,
+    /// How many results each memoized definition keeps before it starts over, if there's a limit.
+    memo_limit: Option<usize>,
+    /// How many values a `range` can make.
+    range_limit: usize,
+    /// Whether a `breakpoint` has run since whatever is stepping through evaluation last looked.
+    breakpoint: bool,
+    /// How far down the stack has been changed since `take_reached` was last called.
+    reached: usize,
+}
+
+/// Where evaluation is, apart from the stack, for going back to with `Evaluator::rewind`. Terms are shared
+/// with the evaluator rather than copied.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    frames: Vec<Frame>,
+    retain: Vec<Value>,
+    thrown: Option<Value>,
+    rng: Rng,
+    breakpoint: bool,
+}
+
+/// How many inputs a memoized definition takes, and the results it's given for them so far.
+struct MemoTable {
+    inputs: usize,
+    results: BTreeMap<Vec<Value>, Vec<Value>>,
+}
+
+/// How a new evaluator is set up.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct EvaluatorConfig {
+    /// What the random words start from, so a program using them does the same thing each time it's run, as
+    /// tests need. Without one they start from the time.
+    pub seed: Option<u64>,
+    /// How many results each `memo def` keeps. A definition whose cache is full forgets them all and starts over,
+    /// so a long-running program can't fill memory with them. Without a limit they're all kept.
+    pub memo_limit: Option<usize>,
+    /// How many values a `range` can make, so a program can't ask for more than fit in memory. Without a limit
+    /// it's `MAX_RANGE`.
+    pub range_limit: Option<usize>,
+}
+
+/// How many values a `range` can make by default.
+pub const MAX_RANGE: usize = 1 << 20;
+
+/// A word implemented in Rust. It takes its inputs from the stack and pushes its outputs onto it, and the error
+/// it can return becomes a runtime error at the place it was used.
+pub type NativeWord = Box<dyn FnMut(&mut Vec<Value>) -> Result<(), String>>;
+
+/// The bodies of the words defined in chara, by name. It's a persistent map, so starting an evaluator from a
+/// dictionary doesn't copy it, and neither does defining a word in one of several evaluators sharing it.
+pub type Dictionary = RedBlackTreeMapSync<Symbol, Arc<Vec<Factor>>>;
+
+impl Evaluator {
+    pub fn new() -> Evaluator {
+        Evaluator::with_config(EvaluatorConfig::default())
+    }
+
+    pub fn with_config(config: EvaluatorConfig) -> Evaluator {
+        Evaluator {
+            stack: Vec::new(),
+            retain: Vec::new(),
+            thrown: None,
+            dictionary: Dictionary::new_sync(),
+            signatures: BTreeMap::new(),
+            natives: BTreeMap::new(),
+            builtins: None,
+            frames: Vec::new(),
+            observers: Vec::new(),
+            rng: config.seed.map_or_else(Rng::unseeded, Rng::new),
+            memos: BTreeMap::new(),
+            memo_limit: config.memo_limit,
+            range_limit: config.range_limit.unwrap_or(MAX_RANGE),
+            breakpoint: false,
+            reached: 0,
+        }
+    }
+
+    /// An evaluator with an empty stack, and the words of `dictionary` defined.
+    pub fn with_dictionary(dictionary: Dictionary) -> Evaluator {
+        Evaluator { dictionary, ..Evaluator::new() }
+    }
+
+    /// Start the random words over from `seed`.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    /// The words defined so far, to start other evaluators from.
+    pub fn dictionary(&self) -> &Dictionary {
+        &self.dictionary
+    }
+
+    pub fn add_observer(&mut self, observer: Box<dyn Observer>) {
+        self.observers.push(observer);
+    }
+
+    pub fn stack(&self) -> &[Value] {
+        &self.stack
+    }
+
+    pub fn stack_mut(&mut self) -> &mut Vec<Value> {
+        self.reached = 0;
+        &mut self.stack
+    }
+
+    pub fn retain_stack(&self) -> &[Value] {
+        &self.retain
+    }
+
+    pub fn define(&mut self, name: &str, factors: Vec<Factor>) {
+        let name = Symbol::intern(name);
+        self.natives.remove(&name);
+        self.signatures.remove(&name);
+        self.memos.remove(&name);
+        self.dictionary.insert_mut(name, Arc::new(factors));
+    }
+
+    /// Keep the results of the definition of `name` by the `inputs` values it takes, so it only runs once for
+    /// each of them. It has to be pure for that to give the same results. Redefining it forgets them.
+    pub fn memoize(&mut self, name: &str, inputs: usize) {
+        self.memos.insert(Symbol::intern(name), MemoTable { inputs, results: BTreeMap::new() });
+    }
+
+    /// Define a word implemented in Rust, replacing any definition of it in chara.
+    pub fn define_native(&mut self, name: &str, word: NativeWord) {
+        let name = Symbol::intern(name);
+        self.dictionary.remove_mut(&name);
+        self.signatures.remove(&name);
+        self.natives.insert(name, word);
+    }
+
+    /// Only run the builtin words in `names`, as when they come from a registry without the rest. Words defined
+    /// in chara or Rust still run.
+    pub fn limit_builtins(&mut self, names: impl IntoIterator<Item = Symbol>) {
+        self.builtins = Some(names.into_iter().collect());
+    }
+
+    /// Run the builtin word `name` again after `limit_builtins` left it out.
+    pub fn allow_builtin(&mut self, name: &str) {
+        if let Some(builtins) = &mut self.builtins {
+            builtins.insert(Symbol::intern(name));
+        }
+    }
+
+    /// The body of a definition, as it was parsed.
+    pub fn definition(&self, name: &str) -> Option<&[Factor]> {
+        self.dictionary.get(&Symbol::get(name)?).map(|factors| factors.as_slice())
+    }
+
+    /// The names of every definition, sorted.
+    pub fn definitions(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.dictionary.keys().map(|name| name.as_str()).collect();
+        names.sort();
+        names
+    }
+
+    pub fn eval_cycle(&mut self, cycle: &Cycle) -> Result<(), Error> {
+        match cycle {
+            Cycle::Definition(Token { value: name, .. }, annotation, factors, _, _) => {
+                self.define(name, factors.clone());
+                self.signatures.insert(Symbol::intern(name), annotation_to_source(annotation.without_modifiers()));
+                if annotation.is_memo() {
+                    let inputs = match annotation.without_modifiers() {
+                        TypeAnnotation::Function(inputs, _, _, _) => inputs.len(),
+                        _ => 0,
+                    };
+                    self.memoize(name, inputs);
+                }
+                Ok(())
+            }
+            Cycle::Term(factors, _) => self.eval_term(factors),
+            // Tests only run when asked for, through `run_test`, and macros are expanded away before anything runs.
+            Cycle::Test(_, _, _, _) | Cycle::Macro(_, _, _) => Ok(()),
+        }
+    }
+
+    /// Run a test body on an empty stack, then check it left the same values as `expected` does. The stack
+    /// is restored afterwards, whether or not the test passed.
+    pub fn run_test(&mut self, body: &[Factor], expected: Option<&[Factor]>) -> Result<(), Error> {
+        let saved = self.set_stack(Vec::new());
+        let result = self.eval_term(body).and_then(|_| {
+            let Some(expected) = expected else {
+                return Ok(());
+            };
+            let actual = self.set_stack(Vec::new());
+            self.eval_term(expected)?;
+            if actual == self.stack {
+                Ok(())
+            } else {
+                let token = expected.first().or(body.first()).map(Factor::token).unwrap_or_else(Token::unknown);
+                Err(self.error(format!("Expected [{}], got [{}]", render(&self.stack), render(&actual)), &token))
+            }
+        });
+        self.set_stack(saved);
+        result
+    }
+
+    pub fn eval_term(&mut self, factors: &[Factor]) -> Result<(), Error> {
+        self.eval_shared(Arc::new(factors.to_vec()))
+    }
+
+    /// Evaluate a term without copying it, for terms that are run over and over.
+    pub fn eval_shared(&mut self, factors: Arc<Vec<Factor>>) -> Result<(), Error> {
+        let depth = self.frames.len();
+        self.frames.push(Frame::Term { factors, pc: 0, name: None });
+        while self.frames.len() > depth {
+            if let Err(error) = self.step() {
+                self.frames.truncate(depth);
+                return Err(error);
+            }
+        }
+        // Nothing pauses at breakpoints when evaluation runs to the end like this.
+        self.breakpoint = false;
+        Ok(())
+    }
+
+    /// Schedule `factors` to be evaluated by subsequent calls to `step`.
+    pub fn push_term(&mut self, factors: Vec<Factor>, name: Option<Symbol>) {
+        self.frames.push(Frame::Term { factors: Arc::new(factors), pc: 0, name });
+    }
+
+    /// Drop whatever evaluation is scheduled, leaving the stack as it is.
+    pub fn abort(&mut self) {
+        self.frames.clear();
+    }
+
+    /// Whether a `breakpoint` has run since this was last asked, for pausing there when stepping through
+    /// evaluation.
+    pub fn take_breakpoint(&mut self) -> bool {
+        core::mem::take(&mut self.breakpoint)
+    }
+
+    /// How far down the stack evaluation has reached since this was last asked: the values below that are the
+    /// same ones as then. History can be kept cheaply by only recording what's above it.
+    pub fn take_reached(&mut self) -> usize {
+        core::mem::replace(&mut self.reached, self.stack.len())
+    }
+
+    /// Where evaluation is now, apart from the stack.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            frames: self.frames.clone(),
+            retain: self.retain.clone(),
+            thrown: self.thrown.clone(),
+            rng: self.rng.clone(),
+            breakpoint: self.breakpoint,
+        }
+    }
+
+    /// Go back to where evaluation was at `checkpoint`, with `stack` as the stack. Observers aren't told about
+    /// the definitions this leaves or goes back into.
+    pub fn rewind(&mut self, checkpoint: Checkpoint, stack: Vec<Value>) {
+        self.frames = checkpoint.frames;
+        self.retain = checkpoint.retain;
+        self.thrown = checkpoint.thrown;
+        self.rng = checkpoint.rng;
+        self.breakpoint = checkpoint.breakpoint;
+        self.set_stack(stack);
+    }
+
+    /// How many frames are on the control stack. Evaluation is finished when this is zero.
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// The names of the definitions currently being evaluated, outermost first.
+    pub fn call_stack(&self) -> Vec<String> {
+        self.frames.iter()
+            .filter_map(|frame| match frame {
+                Frame::Term { name, .. } => name.map(|name| name.to_string()),
+                Frame::Branch { .. } | Frame::Cond { .. } | Frame::Try { .. } | Frame::Compare { .. } | Frame::Produce { .. } | Frame::Resume { .. } | Frame::Reset | Frame::Memo { .. } => None,
+            })
+            .collect()
+    }
+
+    /// The factor the next call to `step` will evaluate, if it is already known. It isn't known while the
+    /// condition of an `ifte` or a predicate of a `cond` is being resolved.
+    pub fn current_factor(&self) -> Option<&Factor> {
+        for frame in self.frames.iter().rev() {
+            match frame {
+                Frame::Term { factors, pc, .. } if *pc < factors.len() => return Some(&factors[*pc]),
+                Frame::Term { .. } | Frame::Try { .. } | Frame::Resume { .. } | Frame::Reset | Frame::Memo { .. } => continue,
+                Frame::Branch { .. } | Frame::Cond { .. } | Frame::Compare { .. } | Frame::Produce { .. } => return None,
+            }
+        }
+        None
+    }
+
+    /// The definition whose body the next factor belongs to, and whether that factor is the first of the body.
+    pub fn current_definition(&self) -> Option<(&str, bool)> {
+        match self.frames.last()? {
+            Frame::Term { name: Some(name), pc, .. } => Some((name.as_str(), *pc == 0)),
+            _ => None,
+        }
+    }
+
+    /// Evaluate a single factor. Returns `false` if there was nothing left to evaluate. A failure inside the
+    /// body of a `try` is caught here, and running its handler counts as the step.
+    pub fn step(&mut self) -> Result<bool, Error> {
+        match self.step_factor() {
+            Err(error) => self.catch(error).map(|_| true),
+            result => result,
+        }
+    }
+
+    fn step_factor(&mut self) -> Result<bool, Error> {
+        self.settle()?;
+        let Some(Frame::Term { factors, pc, .. }) = self.frames.last_mut() else {
+            return Ok(false);
+        };
+        let factors = Arc::clone(factors);
+        let factor = &factors[*pc];
+        *pc += 1;
+        let depth = self.frames.len();
+        for observer in &mut self.observers {
+            observer.before_factor(factor, &self.stack, depth);
+        }
+        self.eval_factor(factor)?;
+        for observer in &mut self.observers {
+            observer.after_factor(factor, &self.stack, depth);
+        }
+        self.settle()?;
+        Ok(true)
+    }
+
+    /// Pop finished terms and `try` bodies and resolve pending `ifte` branches and `cond` clauses, until the top frame has a factor to evaluate.
+    fn settle(&mut self) -> Result<(), Error> {
+        loop {
+            match self.frames.last() {
+                Some(Frame::Term { factors, pc, .. }) if *pc >= factors.len() => {
+                    if let Some(Frame::Term { name: Some(name), .. }) = self.frames.pop() {
+                        let depth = self.frames.len() + 1;
+                        for observer in &mut self.observers {
+                            observer.exit_definition(name.as_str(), depth);
+                        }
+                    }
+                }
+                Some(Frame::Branch { .. }) => {
+                    let Some(Frame::Branch { saved, then_branch, else_branch, token }) = self.frames.pop() else {
+                        unreachable!()
+                    };
+                    let result = self.pop_bool(&token)?;
+                    self.set_stack(saved);
+                    self.push_term(if result { then_branch } else { else_branch }, None);
+                }
+                Some(Frame::Cond { .. }) => {
+                    let Some(Frame::Cond { saved, mut clauses, token }) = self.frames.pop() else {
+                        unreachable!()
+                    };
+                    let result = self.pop_bool(&token)?;
+                    self.set_stack(saved);
+                    let body = clauses.pop().unwrap();
+                    if result {
+                        self.push_term(body, None);
+                    } else {
+                        self.next_clause(clauses, token);
+                    }
+                }
+                Some(Frame::Try { .. } | Frame::Reset) => {
+                    self.frames.pop();
+                }
+                Some(Frame::Memo { .. }) => {
+                    let Some(Frame::Memo { name, key, height }) = self.frames.pop() else {
+                        unreachable!()
+                    };
+                    // A definition that takes more than it says it does has no result to keep.
+                    if let (Some(result), Some(table)) = (self.stack.get(height..), self.memos.get_mut(&name)) {
+                        if self.memo_limit.is_some_and(|limit| table.results.len() >= limit) {
+                            table.results.clear();
+                        }
+                        if self.memo_limit != Some(0) {
+                            table.results.insert(key, result.to_vec());
+                        }
+                    }
+                }
+                Some(Frame::Produce { .. }) => {
+                    let Some(Frame::Produce { saved, mut production, awaiting, token }) = self.frames.pop() else {
+                        unreachable!()
+                    };
+                    match awaiting {
+                        Awaiting::Done => {
+                            let done = self.pop_bool(&token)?;
+                            self.set_stack(saved);
+                            if done {
+                                self.stack.push(Value::Quotation(production.values.iter().map(Value::to_factor).collect()));
+                            } else {
+                                let step = iter::once(production.seed.to_factor()).chain(production.step.iter().cloned()).collect();
+                                self.frames.push(Frame::Produce { saved: self.stack.clone(), production, awaiting: Awaiting::Step, token });
+                                self.push_term(step, None);
+                            }
+                        }
+                        Awaiting::Step => {
+                            production.seed = self.pop(&token)?;
+                            let value = self.pop(&token)?;
+                            self.set_stack(saved);
+                            match &production.keep {
+                                Some(keep) => {
+                                    let keep = iter::once(value.to_factor()).chain(keep.iter().cloned()).collect();
+                                    production.pending = Some(value);
+                                    self.frames.push(Frame::Produce { saved: self.stack.clone(), production, awaiting: Awaiting::Keep, token });
+                                    self.push_term(keep, None);
+                                }
+                                None => {
+                                    production.values.push(value);
+                                    self.produce(production, token);
+                                }
+                            }
+                        }
+                        Awaiting::Keep => {
+                            let kept = self.pop_bool(&token)?;
+                            self.set_stack(saved);
+                            let value = production.pending.take().unwrap();
+                            if kept {
+                                production.values.push(value);
+                                self.produce(production, token);
+                            } else {
+                                self.stack.push(Value::Quotation(production.values.iter().map(Value::to_factor).collect()));
+                            }
+                        }
+                    }
+                }
+                Some(Frame::Resume { .. }) => {
+                    let Some(Frame::Resume { saved, token }) = self.frames.pop() else {
+                        unreachable!()
+                    };
+                    let value = self.pop(&token)?;
+                    self.suspend(saved, Vec::new(), value, false);
+                }
+                Some(Frame::Compare { .. }) => {
+                    let Some(Frame::Compare { depth, less, mut search, token }) = self.frames.pop() else {
+                        unreachable!()
+                    };
+                    let before = self.pop_bool(&token)?;
+                    if self.stack.len() != depth {
+                        let message = format!("The comparison `[{}]` has to take the two values it's given and leave a Bool", term_to_source(&less));
+                        return Err(self.error(message, &token));
+                    }
+                    search.record(before);
+                    self.search(search, less, token);
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Unwind to the innermost `try` and run its handler on the stack the `try` started with, with the thrown
+    /// value on top of it. Failures raised by anything other than `throw` are caught as their message.
+    fn catch(&mut self, error: Error) -> Result<(), Error> {
+        let thrown = self.thrown.take();
+        let Some(index) = self.frames.iter().rposition(|frame| matches!(frame, Frame::Try { .. })) else {
+            return Err(error);
+        };
+        let unwound: Vec<(Symbol, usize)> = self.frames.drain(index + 1..).enumerate()
+            .filter_map(|(i, frame)| match frame {
+                Frame::Term { name: Some(name), .. } => Some((name, index + 2 + i)),
+                _ => None,
+            })
+            .collect();
+        for (name, depth) in unwound.into_iter().rev() {
+            for observer in &mut self.observers {
+                observer.exit_definition(name.as_str(), depth);
+            }
+        }
+        let Some(Frame::Try { saved, retained, handler }) = self.frames.pop() else {
+            unreachable!()
+        };
+        self.set_stack(saved);
+        self.retain.truncate(retained);
+        self.stack.push(thrown.unwrap_or_else(|| match error {
+            Error::RuntimeError(message, _, _) => Value::String(message),
+            error => Value::String(error.to_string()),
+        }));
+        self.push_term(handler, None);
+        Ok(())
+    }
+
+    /// Replace the inputs of the memoized definition `name` with the result it gave for them before, if it did.
+    /// Otherwise, if it's memoized, get ready to keep the result it's about to give.
+    fn recall(&mut self, name: Symbol) -> bool {
+        let Some(table) = self.memos.get(&name) else {
+            return false;
+        };
+        let Some(height) = self.stack.len().checked_sub(table.inputs) else {
+            return false;
+        };
+        let key = self.stack[height..].to_vec();
+        if let Some(result) = table.results.get(&key) {
+            self.stack.truncate(height);
+            self.reached = self.reached.min(height);
+            self.stack.extend(result.iter().cloned());
+            return true;
+        }
+        self.frames.push(Frame::Memo { name, key, height });
+        false
+    }
+
+    /// Run the next predicate of a `cond` against a copy of the stack, or its default if no predicates are left.
+    fn next_clause(&mut self, mut clauses: Vec<Vec<Factor>>, token: Token) {
+        let next = clauses.pop().unwrap();
+        if !clauses.is_empty() {
+            let saved = self.stack.clone();
+            self.frames.push(Frame::Cond { saved, clauses, token });
+        }
+        self.push_term(next, None);
+    }
+
+    /// Run the next comparison `search` needs, or push what it found if it's done.
+    fn search(&mut self, mut search: Search, less: Vec<Factor>, token: Token) {
+        match search.next_step() {
+            SearchStep::Compare(a, b) => {
+                let comparison = less.clone();
+                self.frames.push(Frame::Compare { depth: self.stack.len(), less, search, token });
+                self.stack.extend([a, b]);
+                self.push_term(comparison, None);
+            }
+            SearchStep::Done(value) => self.stack.push(value),
+        }
+    }
+
+    /// Ask whether the sequence `production` is forcing is done, unless it has taken as many values as it may.
+    fn produce(&mut self, mut production: Production, token: Token) {
+        if production.limit == Some(0) {
+            self.stack.push(Value::Quotation(production.values.iter().map(Value::to_factor).collect()));
+            return;
+        }
+        production.limit = production.limit.map(|limit| limit - 1);
+        let done = iter::once(production.seed.to_factor()).chain(production.done.iter().cloned()).collect();
+        self.frames.push(Frame::Produce { saved: self.stack.clone(), production, awaiting: Awaiting::Done, token });
+        self.push_term(done, None);
+    }
+
+    /// Hand `value` out of the coroutine running on the stack, back to the stack `resume` was given, along with
+    /// the coroutine as it is now, to go on with `rest`, and whether it yielded rather than finishing.
+    fn suspend(&mut self, saved: Vec<Value>, rest: Vec<Factor>, value: Value, yielded: bool) {
+        let stack = self.set_stack(saved).iter().map(Value::to_factor).collect();
+        let coroutine = vec![Factor::Quotation(stack, Span::default()), Factor::Quotation(rest, Span::default())];
+        self.stack.extend([Value::Quotation(coroutine), value, Value::Bool(yielded)]);
+    }
+
+    /// Suspend the innermost coroutine at a `yield`, with the rest of what it's running to go on with.
+    fn yield_value(&mut self, token: &Token) -> Result<(), Error> {
+        let Some(index) = self.frames.iter().rposition(|frame| matches!(frame, Frame::Resume { .. })) else {
+            return Err(self.error("yield has to be inside a coroutine".to_string(), token));
+        };
+        let message = "A coroutine can only yield from its body and the words it calls";
+        let value = self.pop(token)?;
+        let rest = self.capture(index, message, token)?;
+        let Some(Frame::Resume { saved, .. }) = self.frames.pop() else {
+            unreachable!()
+        };
+        self.suspend(saved, rest, value, true);
+        Ok(())
+    }
+
+    /// Take the frames above `index` off the control stack, as a quotation of the rest of each term in them, which
+    /// goes on from where they were when it's called. Anything else they're in the middle of, like an `ifte`
+    /// condition or a `try`, can't be picked up again from a quotation, so that fails with `message`.
+    fn capture(&mut self, index: usize, message: &str, token: &Token) -> Result<Vec<Factor>, Error> {
+        if !self.frames[index + 1..].iter().all(|frame| matches!(frame, Frame::Term { .. })) {
+            return Err(self.error(message.to_string(), token));
+        }
+        let mut rest = Vec::new();
+        let mut exited = Vec::new();
+        for (i, frame) in self.frames.drain(index + 1..).enumerate().rev() {
+            let Frame::Term { factors, pc, name } = frame else {
+                unreachable!()
+            };
+            rest.extend(factors[pc.min(factors.len())..].iter().cloned());
+            exited.extend(name.map(|name| (name, index + 2 + i)));
+        }
+        for (name, depth) in exited {
+            for observer in &mut self.observers {
+                observer.exit_definition(name.as_str(), depth);
+            }
+        }
+        Ok(rest)
+    }
+
+    /// The stack and the rest of the body of a coroutine, as `coroutine` and `resume` leave them.
+    fn pop_coroutine(&mut self, token: &Token) -> Result<(Vec<Value>, Vec<Factor>), Error> {
+        let factors = self.pop_quotation(token)?;
+        if let [Factor::Quotation(stack, _), Factor::Quotation(rest, _)] = factors.as_slice() {
+            if let Some(stack) = stack.iter().map(Value::from_factor).collect::<Option<Vec<_>>>() {
+                return Ok((stack, rest.clone()));
+            }
+        }
+        Err(self.error(format!("Expected a coroutine, got {}", Value::Quotation(factors)), token))
+    }
+
+    /// A lazy sequence, as `iterate` and `unfold` make it: a seed and quotations to end and step it. Once `take` or
+    /// `take-while` have cut it short, it has a quotation after those of where it stops, which holds a count for each
+    /// `take` and a quotation for each `take-while`.
+    fn pop_sequence(&mut self, token: &Token) -> Result<Vec<Factor>, Error> {
+        let factors = self.pop_quotation(token)?;
+        match factors.as_slice() {
+            [seed, Factor::Quotation(..), Factor::Quotation(..), stops @ ..] if Value::from_factor(seed).is_some() => match stops {
+                [] => return Ok(factors),
+                [Factor::Quotation(stops, _)] if stops.iter().all(|stop| matches!(stop, Factor::Int(parser::Value::Integer(0..), _) | Factor::Quotation(..))) => {
+                    return Ok(factors);
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+        Err(self.error(format!("Expected a sequence, got [{}]", term_to_source(&factors)), token))
+    }
+
+    /// Cut the sequence on the stack short at `stop`, without producing any of its values.
+    fn cut_short(&mut self, stop: Factor, token: &Token) -> Result<(), Error> {
+        let mut factors = self.pop_sequence(token)?;
+        match factors.get_mut(3) {
+            Some(Factor::Quotation(stops, _)) => stops.push(stop),
+            _ => factors.push(Factor::Quotation(vec![stop], Span::default())),
+        }
+        self.stack.push(Value::Quotation(factors));
+        Ok(())
+    }
+
+    /// How to produce the values of the sequence on the stack. Every value it takes has to satisfy each
+    /// `take-while` it's been cut short by, so their quotations are joined into one that runs each in turn.
+    fn pop_production(&mut self, token: &Token) -> Result<Production, Error> {
+        let mut factors = self.pop_sequence(token)?.into_iter();
+        let (Some(seed), Some(Factor::Quotation(done, _)), Some(Factor::Quotation(step, _))) = (factors.next(), factors.next(), factors.next()) else {
+            unreachable!()
+        };
+        let quote = |factors| Factor::Quotation(factors, Span::default());
+        let (mut limit, mut keep) = (None, None::<Vec<Factor>>);
+        if let Some(Factor::Quotation(stops, _)) = factors.next() {
+            for stop in stops {
+                match stop {
+                    Factor::Int(parser::Value::Integer(count), _) => {
+                        // A count too big for a `usize` is more values than could ever be held anyway.
+                        let count = usize::try_from(count).unwrap_or(usize::MAX);
+                        limit = Some(limit.map_or(count, |limit: usize| limit.min(count)));
+                    }
+                    Factor::Quotation(next, _) => keep = Some(match keep {
+                        None => next,
+                        Some(keep) => {
+                            let reject = vec![Factor::Drop(token.clone()), Value::Bool(false).to_factor()];
+                            vec![quote(keep), quote(next), quote(reject), Factor::Ifte(token.clone())]
+                        }
+                    }),
+                    _ => unreachable!(),
+                }
+            }
+        }
+        Ok(Production { seed: Value::from_factor(&seed).unwrap(), done, step, limit, keep, pending: None, values: Vec::new() })
+    }
+
+    /// The values of a list, which is a quotation of literals.
+    fn pop_list(&mut self, token: &Token) -> Result<Vec<Value>, Error> {
+        let factors = self.pop_quotation(token)?;
+        factors.iter()
+            .map(|factor| Value::from_factor(factor).ok_or_else(|| {
+                self.error(format!("Expected a list of values, got a quotation with {} in it", factor_to_source(factor)), token)
+            }))
+            .collect()
+    }
+
+    fn error(&self, message: String, token: &Token) -> Error {
+        Error::RuntimeError(message, token.clone(), self.call_stack())
+    }
+
+    fn pop(&mut self, token: &Token) -> Result<Value, Error> {
+        let value = self.stack.pop().ok_or_else(|| self.error("Stack underflow".to_string(), token))?;
+        self.reached = self.reached.min(self.stack.len());
+        Ok(value)
+    }
+
+    /// Replace the whole stack, returning what it was.
+    fn set_stack(&mut self, stack: Vec<Value>) -> Vec<Value> {
+        self.reached = 0;
+        core::mem::replace(&mut self.stack, stack)
+    }
+
+    fn pop_int(&mut self, token: &Token) -> Result<i64, Error> {
+        match self.pop(token)? {
+            Value::Int(i) => Ok(i),
+            v => Err(self.error(format!("Expected Int, got {}", v), token)),
+        }
+    }
+
+    fn pop_bool(&mut self, token: &Token) -> Result<bool, Error> {
+        match self.pop(token)? {
+            Value::Bool(b) => Ok(b),
+            v => Err(self.error(format!("Expected Bool, got {}", v), token)),
+        }
+    }
+
+    fn pop_string(&mut self, token: &Token) -> Result<String, Error> {
+        match self.pop(token)? {
+            Value::String(s) => Ok(s),
+            v => Err(self.error(format!("Expected String, got {}", v), token)),
+        }
+    }
+
+    fn pop_bytes(&mut self, token: &Token) -> Result<Vec<u8>, Error> {
+        match self.pop(token)? {
+            Value::Bytes(bytes) => Ok(bytes),
+            v => Err(self.error(format!("Expected Bytes, got {}", v), token)),
+        }
+    }
+
+    fn pop_set(&mut self, token: &Token) -> Result<Vec<Value>, Error> {
+        match self.pop(token)? {
+            Value::Set(elements) => Ok(elements),
+            v => Err(self.error(format!("Expected Set, got {}", v), token)),
+        }
+    }
+
+    fn pop_quotation(&mut self, token: &Token) -> Result<Vec<Factor>, Error> {
+        match self.pop(token)? {
+            Value::Quotation(factors) => Ok(factors),
+            v => Err(self.error(format!("Expected quotation, got {}", v), token)),
+        }
+    }
+
+    fn eval_factor(&mut self, factor: &Factor) -> Result<(), Error> {
+        match factor {
+            Factor::Dup(token) => {
+                let a = self.pop(token)?;
+                self.stack.push(a.clone());
+                self.stack.push(a);
+            }
+            Factor::Drop(token) => {
+                self.pop(token)?;
+            }
+            Factor::Quote(token) => {
+                let a = self.pop(token)?;
+                self.stack.push(Value::Quotation(vec![a.to_factor()]));
+            }
+            Factor::Call(token) => {
+                let factors = self.pop_quotation(token)?;
+                self.push_term(factors, None);
+            }
+            Factor::Cat(token) => {
+                let b = self.pop_quotation(token)?;
+                let mut a = self.pop_quotation(token)?;
+                a.extend(b);
+                self.stack.push(Value::Quotation(a));
+            }
+            Factor::Swap(token) => {
+                let b = self.pop(token)?;
+                let a = self.pop(token)?;
+                self.stack.push(b);
+                self.stack.push(a);
+            }
+            Factor::Ifte(token) => {
+                // The condition runs against a copy of the stack, which is restored before the branch runs.
+                let else_branch = self.pop_quotation(token)?;
+                let then_branch = self.pop_quotation(token)?;
+                let condition = self.pop_quotation(token)?;
+                let saved = self.stack.clone();
+                self.frames.push(Frame::Branch { saved, then_branch, else_branch, token: token.clone() });
+                self.push_term(condition, None);
+            }
+            Factor::Cond(token) => {
+                // Predicates are tried in order, each against a copy of the stack like an `ifte` condition.
+                let clauses = self.pop_quotation(token)?.into_iter()
+                    .rev()
+                    .map(|clause| match clause {
+                        Factor::Quotation(factors, _) => Some(factors),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<_>>>()
+                    .filter(|clauses| clauses.len() % 2 == 1);
+                let Some(clauses) = clauses else {
+                    let message = "Expected pairs of [predicate] [body] quotations and a [default] for cond".to_string();
+                    return Err(self.error(message, token));
+                };
+                self.next_clause(clauses, token.clone());
+            }
+            Factor::Let(names, token) => {
+                let body = self.pop_quotation(token)?;
+                let mut values = Vec::new();
+                for name in names.iter().rev() {
+                    values.push((*name, self.pop(token)?.to_factor()));
+                }
+                self.push_term(bind(&body, &values), None);
+            }
+            Factor::Local(name, token) => return Err(self.error(format!("{} isn't bound by a let here", name), token)),
+            Factor::Int(parser::Value::Integer(i), _) => self.stack.push(Value::Int(*i)),
+            Factor::Bool(parser::Value::Boolean(b), _) => self.stack.push(Value::Bool(*b)),
+            Factor::String(parser::Value::String(s), _) => self.stack.push(Value::String(s.clone())),
+            Factor::Bytes(parser::Value::Bytes(bytes), _) => self.stack.push(Value::Bytes(bytes.clone())),
+            Factor::Set(elements, token) => {
+                let value = Value::from_factor(factor).ok_or_else(|| self.error(format!("Malformed set of {}", term_to_source(elements)), token))?;
+                self.stack.push(value);
+            }
+            Factor::Int(_, token) | Factor::Bool(_, token) | Factor::String(_, token) | Factor::Bytes(_, token) => {
+                return Err(self.error("Malformed literal".to_string(), token));
+            }
+            Factor::Identifier(name, token) => self.eval_word(*name, token)?,
+            Factor::Quotation(factors, _) => self.stack.push(Value::Quotation(factors.clone())),
+            // Assertions are for the typechecker, but ascriptions can coerce out of `Dyn`, so they're checked.
+            Factor::Assertion(_) => {}
+            Factor::Ascription(annotation, token) => {
+                let value = self.stack.last().ok_or_else(|| self.error("Stack underflow".to_string(), token))?;
+                if !has_type(value, annotation) {
+                    let message = format!("Expected {}, got {}", annotation_to_source(annotation), value);
+                    return Err(self.error(message, token));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn eval_word(&mut self, symbol: Symbol, token: &Token) -> Result<(), Error> {
+        let name = symbol.as_str();
+        // A definition replaces a builtin of the same name, as it does when typechecking.
+        if let Some(factors) = self.dictionary.get(&symbol).map(Arc::clone) {
+            if self.recall(symbol) {
+                return Ok(());
+            }
+            self.frames.push(Frame::Term { factors, pc: 0, name: Some(symbol) });
+            let depth = self.frames.len();
+            for observer in &mut self.observers {
+                observer.enter_definition(name, depth);
+            }
+            return Ok(());
+        }
+        // So does a word implemented in Rust.
+        if let Some(word) = self.natives.get_mut(&symbol) {
+            self.reached = 0;
+            if let Err(message) = word(&mut self.stack) {
+                return Err(self.error(message, token));
+            }
+            return Ok(());
+        }
+        if self.builtins.as_ref().is_some_and(|builtins| !builtins.contains(&symbol)) {
+            return Err(self.error(format!("Unknown word {}", name), token));
+        }
+        match name {
+            "+" | "-" | "*" | "/" => {
+                let b = self.pop_int(token)?;
+                let a = self.pop_int(token)?;
+                let result = match name {
+                    "+" => a.wrapping_add(b),
+                    "-" => a.wrapping_sub(b),
+                    "*" => a.wrapping_mul(b),
+                    "/" if b == 0 => return Err(self.error("Division by zero".to_string(), token)),
+                    _ => a.wrapping_div(b),
+                };
+                self.stack.push(Value::Int(result));
+            }
+            "<" | ">" | "<=" | ">=" | "compare" | "=" | "!=" => {
+                let b = self.pop(token)?;
+                let a = self.pop(token)?;
+                let order = a.compare(&b);
+                self.stack.push(match name {
+                    "<" => Value::Bool(order.is_lt()),
+                    ">" => Value::Bool(order.is_gt()),
+                    "<=" => Value::Bool(order.is_le()),
+                    ">=" => Value::Bool(order.is_ge()),
+                    "compare" => Value::Int(order as i64),
+                    "=" => Value::Bool(order.is_eq()),
+                    _ => Value::Bool(order.is_ne()),
+                });
+            }
+            "hash" => {
+                let value = self.pop(token)?;
+                self.stack.push(Value::Int(value.hash_code()));
+            }
+            "not" => {
+                let a = self.pop_bool(token)?;
+                self.stack.push(Value::Bool(!a));
+            }
+            "and" | "or" => {
+                let b = self.pop_bool(token)?;
+                let a = self.pop_bool(token)?;
+                self.stack.push(Value::Bool(if name == "and" { a && b } else { a || b }));
+            }
+            "assert" => {
+                if !self.pop_bool(token)? {
+                    return Err(self.error("Assertion failed: expected true, got false".to_string(), token));
+                }
+            }
+            "assert-eq" => {
+                let expected = self.pop(token)?;
+                let actual = self.pop(token)?;
+                if expected != actual {
+                    let message = format!("Assertion failed: expected {}, got {}", expected, actual);
+                    return Err(self.error(message, token));
+                }
+            }
+            "assert-stack" => {
+                let expected = self.pop_quotation(token)?;
+                if Value::Quotation(expected.clone()) != Value::Quotation(self.stack.iter().map(Value::to_factor).collect()) {
+                    let message = format!(
+                        "Assertion failed: expected stack [{}], got [{}]", term_to_source(&expected), render(&self.stack),
+                    );
+                    return Err(self.error(message, token));
+                }
+            }
+            "and*" | "or*" => {
+                // The second predicate is an `ifte` of its own, run only by the branch the first one leaves undecided.
+                let second = self.pop_quotation(token)?;
+                let first = self.pop_quotation(token)?;
+                let [yes, no] = [true, false].map(|b| vec![Value::Bool(b).to_factor()]);
+                let quote = |factors| Factor::Quotation(factors, Span::default());
+                let second = vec![quote(second), quote(yes.clone()), quote(no.clone()), Factor::Ifte(token.clone())];
+                let (then_branch, else_branch) = if name == "and*" { (second, no) } else { (yes, second) };
+                let saved = self.stack.clone();
+                self.frames.push(Frame::Branch { saved, then_branch, else_branch, token: token.clone() });
+                self.push_term(first, None);
+            }
+            "if" | "unless" => {
+                let body = self.pop_quotation(token)?;
+                if self.pop_bool(token)? == (name == "if") {
+                    self.push_term(body, None);
+                }
+            }
+            "bi" | "tri" => {
+                // Each quotation runs on its own copy of the value, one after another.
+                let mut quotations = Vec::new();
+                for _ in 0..if name == "bi" { 2 } else { 3 } {
+                    quotations.insert(0, self.pop_quotation(token)?);
+                }
+                let value = self.pop(token)?.to_factor();
+                let factors = quotations.into_iter().flat_map(|quotation| iter::once(value.clone()).chain(quotation)).collect();
+                self.push_term(factors, None);
+            }
+            "keep" => {
+                let quotation = self.pop_quotation(token)?;
+                let value = self.pop(token)?.to_factor();
+                let factors = iter::once(value.clone()).chain(quotation).chain(iter::once(value)).collect();
+                self.push_term(factors, None);
+            }
+            ">r" => {
+                let value = self.pop(token)?;
+                self.retain.push(value);
+            }
+            "r>" | "r@" => {
+                let value = match name {
+                    "r>" => self.retain.pop(),
+                    _ => self.retain.last().cloned(),
+                };
+                let value = value.ok_or_else(|| self.error("Retain stack underflow".to_string(), token))?;
+                self.stack.push(value);
+            }
+            "throw" => {
+                let value = self.pop(token)?;
+                let message = format!("Uncaught throw: {}", value);
+                self.thrown = Some(value);
+                return Err(self.error(message, token));
+            }
+            "try" => {
+                let handler = self.pop_quotation(token)?;
+                let body = self.pop_quotation(token)?;
+                self.frames.push(Frame::Try { saved: self.stack.clone(), retained: self.retain.len(), handler });
+                self.push_term(body, None);
+            }
+            // A coroutine is a list of its own stack and what's left of its body, which starts as all of it.
+            "coroutine" => {
+                let body = self.pop_quotation(token)?;
+                self.stack.push(Value::Quotation(vec![Factor::Quotation(Vec::new(), Span::default()), Factor::Quotation(body, Span::default())]));
+            }
+            "resume" => {
+                let value = self.pop(token)?;
+                let (mut stack, rest) = self.pop_coroutine(token)?;
+                stack.push(value);
+                let saved = self.set_stack(stack);
+                self.frames.push(Frame::Resume { saved, token: token.clone() });
+                self.push_term(rest, None);
+            }
+            "yield" => self.yield_value(token)?,
+            "breakpoint" => self.breakpoint = true,
+            "reset" => {
+                let body = self.pop_quotation(token)?;
+                self.frames.push(Frame::Reset);
+                self.push_term(body, None);
+            }
+            // The rest of the `reset` body is taken as a quotation, which the quotation given to `shift` runs in its
+            // place, as many times as it likes.
+            "shift" => {
+                let body = self.pop_quotation(token)?;
+                let Some(index) = self.frames.iter().rposition(|frame| matches!(frame, Frame::Reset)) else {
+                    return Err(self.error("shift has to be inside a reset".to_string(), token));
+                };
+                let continuation = self.capture(index, "shift can only capture the body of its reset and the words it calls", token)?;
+                self.stack.push(Value::Quotation(continuation));
+                self.push_term(body, None);
+            }
+            "curry" => {
+                let quotation = self.pop_quotation(token)?;
+                let value = self.pop(token)?;
+                self.stack.push(Value::Quotation(iter::once(value.to_factor()).chain(quotation).collect()));
+            }
+            "compose" => {
+                let quotations = self.pop_list(token)?;
+                let mut composed = Vec::new();
+                for quotation in quotations {
+                    match quotation {
+                        Value::Quotation(factors) => composed.extend(factors),
+                        v => return Err(self.error(format!("Expected a list of quotations, got one with {} in it", v), token)),
+                    }
+                }
+                self.stack.push(Value::Quotation(composed));
+            }
+            "apply-n" => {
+                let count = self.pop_int(token)?;
+                let quotation = self.pop_quotation(token)?;
+                let count = usize::try_from(count).map_err(|_| self.error(format!("apply-n can't apply a quotation to {} values", count), token))?;
+                if count > self.stack.len() {
+                    return Err(self.error("Stack underflow".to_string(), token));
+                }
+                let values = self.stack.split_off(self.stack.len() - count);
+                self.reached = self.reached.min(self.stack.len());
+                let factors = values.into_iter().flat_map(|value| iter::once(value.to_factor()).chain(quotation.clone())).collect();
+                self.push_term(factors, None);
+            }
+            "both" => {
+                let quotation = self.pop_quotation(token)?;
+                let b = self.pop(token)?;
+                let a = self.pop(token)?;
+                let factors = [a, b].into_iter().flat_map(|value| iter::once(value.to_factor()).chain(quotation.clone())).collect();
+                self.push_term(factors, None);
+            }
+            // Values carry their types at runtime, so making one `Dyn` leaves it as it is.
+            "dynamic" => {
+                let value = self.pop(token)?;
+                self.stack.push(value);
+            }
+            "show" => {
+                let value = self.pop(token)?;
+                self.stack.push(Value::String(value.to_string()));
+            }
+            "type-of" => {
+                let value = self.pop(token)?;
+                self.stack.push(Value::String(type_tag(&value).to_string()));
+            }
+            // A word defined through `define` rather than by running its definition has no signature to list.
+            "words" => {
+                let words = self.definitions().into_iter()
+                    .map(|name| {
+                        let signature = self.signatures.get(&Symbol::intern(name)).cloned().unwrap_or_default();
+                        Value::from(vec![name, signature.as_str()]).to_factor()
+                    })
+                    .collect();
+                self.stack.push(Value::Quotation(words));
+            }
+            "see" => {
+                let name = self.pop_string(token)?;
+                let factors = self.definition(&name)
+                    .ok_or_else(|| self.error(format!("No definition named {}", name), token))?;
+                self.stack.push(Value::String(term_to_source(factors)));
+            }
+            #[cfg(feature = "reflection")]
+            "lookup" => {
+                let name = self.pop_string(token)?;
+                let found = self.definition(&name).is_some();
+                let quotation = if found { vec![Factor::Identifier(Symbol::intern(&name), token.clone())] } else { Vec::new() };
+                self.stack.push(Value::Quotation(quotation));
+                self.stack.push(Value::Bool(found));
+            }
+            "bytes-length" => {
+                let bytes = self.pop_bytes(token)?;
+                self.stack.push(Value::Int(bytes.len() as i64));
+            }
+            "bytes-at" => {
+                let index = self.pop_int(token)?;
+                let bytes = self.pop_bytes(token)?;
+                let byte = usize::try_from(index).ok().and_then(|index| bytes.get(index))
+                    .ok_or_else(|| self.error(format!("Index {} is out of bounds for {} bytes", index, bytes.len()), token))?;
+                self.stack.push(Value::Int(i64::from(*byte)));
+            }
+            "bytes-slice" => {
+                // The slice runs from `start` up to but not including `end`.
+                let end = self.pop_int(token)?;
+                let start = self.pop_int(token)?;
+                let bytes = self.pop_bytes(token)?;
+                let range = usize::try_from(start).ok().zip(usize::try_from(end).ok()).filter(|(start, end)| start <= end && *end <= bytes.len());
+                let (start, end) = range
+                    .ok_or_else(|| self.error(format!("Slice {} to {} is out of bounds for {} bytes", start, end, bytes.len()), token))?;
+                self.stack.push(Value::Bytes(bytes[start..end].to_vec()));
+            }
+            "bytes-cat" => {
+                let b = self.pop_bytes(token)?;
+                let mut a = self.pop_bytes(token)?;
+                a.extend(b);
+                self.stack.push(Value::Bytes(a));
+            }
+            "string->bytes" => {
+                let s = self.pop_string(token)?;
+                self.stack.push(Value::Bytes(s.into_bytes()));
+            }
+            "bytes->string" => {
+                let bytes = self.pop_bytes(token)?;
+                let s = String::from_utf8(bytes).map_err(|error| {
+                    self.error(format!("Invalid UTF-8 at byte {}", error.utf8_error().valid_up_to()), token)
+                })?;
+                self.stack.push(Value::String(s));
+            }
+            "range" => {
+                // Like a slice, the range runs up to but not including `end`.
+                let end = self.pop_int(token)?;
+                let start = self.pop_int(token)?;
+                let length = (i128::from(end) - i128::from(start)).max(0);
+                if length > self.range_limit as i128 {
+                    let message = format!("A range can have at most {} values, but {} up to {} has {}", self.range_limit, start, end, length);
+                    return Err(self.error(message, token));
+                }
+                self.stack.push(Value::Quotation((start..end).map(|i| Value::Int(i).to_factor()).collect()));
+            }
+            "each" => {
+                let quotation = self.pop_quotation(token)?;
+                let values = self.pop_list(token)?;
+                let factors = values.into_iter().flat_map(|value| iter::once(value.to_factor()).chain(quotation.clone())).collect();
+                self.push_term(factors, None);
+            }
+            "enumerate" => {
+                let values = self.pop_list(token)?;
+                let pairs = values.into_iter().enumerate()
+                    .map(|(i, value)| Factor::Quotation(vec![Value::Int(i as i64).to_factor(), value.to_factor()], Span::default()))
+                    .collect();
+                self.stack.push(Value::Quotation(pairs));
+            }
+            // A sequence starting from a seed, with a step that takes it to the next value.
+            "iterate" => {
+                let step = self.pop_quotation(token)?;
+                let seed = self.pop(token)?;
+                let done = vec![Factor::Drop(token.clone()), Factor::Bool(parser::Value::Boolean(false), token.clone())];
+                let step = iter::once(Factor::Dup(token.clone())).chain(step).collect();
+                self.stack.push(Value::Quotation(vec![seed.to_factor(), Factor::Quotation(done, Span::default()), Factor::Quotation(step, Span::default())]));
+            }
+            "unfold" => {
+                let step = self.pop_quotation(token)?;
+                let done = self.pop_quotation(token)?;
+                let seed = self.pop(token)?;
+                self.stack.push(Value::Quotation(vec![seed.to_factor(), Factor::Quotation(done, Span::default()), Factor::Quotation(step, Span::default())]));
+            }
+            // Sequences stay lazy until they're forced, so these only record where they stop.
+            "take" => {
+                let count = self.pop_int(token)?;
+                if count < 0 {
+                    return Err(self.error(format!("Can't take a negative number of values, {}", count), token));
+                }
+                self.cut_short(Value::Int(count).to_factor(), token)?;
+            }
+            "take-while" => {
+                let keep = self.pop_quotation(token)?;
+                self.cut_short(Factor::Quotation(keep, Span::default()), token)?;
+            }
+            "force" => {
+                let production = self.pop_production(token)?;
+                self.produce(production, token.clone());
+            }
+            "sort" => {
+                let less = self.pop_quotation(token)?;
+                let runs = self.pop_list(token)?.into_iter().map(|value| vec![value]).collect();
+                self.search(Search::Sort { runs, merged: Vec::new(), merging: None }, less, token.clone());
+            }
+            "binary-search" => {
+                let less = self.pop_quotation(token)?;
+                let target = self.pop(token)?;
+                let items = self.pop_list(token)?;
+                let high = items.len();
+                self.search(Search::Bisect { items, target, low: 0, high, found: None }, less, token.clone());
+            }
+            "min-by" | "max-by" => {
+                let less = self.pop_quotation(token)?;
+                let mut rest = self.pop_list(token)?;
+                rest.reverse();
+                let best = rest.pop().ok_or_else(|| self.error(format!("{} needs a list with something in it", name), token))?;
+                self.search(Search::Extreme { best, rest, max: name == "max-by" }, less, token.clone());
+            }
+            "set-new" => self.stack.push(Value::Set(Vec::new())),
+            "set-add" => {
+                let value = self.pop(token)?;
+                let mut elements = self.pop_set(token)?;
+                if let Err(index) = elements.binary_search_by(|element| element.compare(&value)) {
+                    elements.insert(index, value);
+                }
+                self.stack.push(Value::Set(elements));
+            }
+            "set-contains" => {
+                let value = self.pop(token)?;
+                let elements = self.pop_set(token)?;
+                self.stack.push(Value::Bool(elements.binary_search_by(|element| element.compare(&value)).is_ok()));
+            }
+            "set-union" | "set-intersect" => {
+                // Both sets are in order, so they're merged in a single pass over each.
+                let b = self.pop_set(token)?;
+                let a = self.pop_set(token)?;
+                let (mut a, mut b) = (a.into_iter().peekable(), b.into_iter().peekable());
+                let mut elements = Vec::new();
+                while let (Some(x), Some(y)) = (a.peek(), b.peek()) {
+                    match x.compare(y) {
+                        Ordering::Less => elements.extend(a.next().filter(|_| name == "set-union")),
+                        Ordering::Greater => elements.extend(b.next().filter(|_| name == "set-union")),
+                        Ordering::Equal => {
+                            b.next();
+                            elements.extend(a.next());
+                        }
+                    }
+                }
+                if name == "set-union" {
+                    elements.extend(a.chain(b));
+                }
+                self.stack.push(Value::Set(elements));
+            }
+            "set->list" => {
+                let elements = self.pop_set(token)?;
+                self.stack.push(Value::Quotation(elements.iter().map(Value::to_factor).collect()));
+            }
+            "random-int" => {
+                let high = self.pop_int(token)?;
+                let low = self.pop_int(token)?;
+                if low > high {
+                    return Err(self.error(format!("random-int needs a low bound no higher than its high one, got {} and {}", low, high), token));
+                }
+                let i = self.rng.between(low, high);
+                self.stack.push(Value::Int(i));
+            }
+            "random-bool" => {
+                let b = self.rng.below(2) == 1;
+                self.stack.push(Value::Bool(b));
+            }
+            #[cfg(feature = "regex")]
+            "regex-match" | "regex-find-all" | "regex-replace" => self.eval_regex(name, token)?,
+            _ => return Err(self.error(format!("Unknown word {}", name), token)),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "regex")]
+impl Evaluator {
+    /// The regex words, which take the text and then the pattern, in the syntax of the `regex` crate.
+    /// `regex-replace` replaces every match, and its replacement can use groups like `$1`.
+    fn eval_regex(&mut self, name: &str, token: &Token) -> Result<(), Error> {
+        let replacement = if name == "regex-replace" { Some(self.pop_string(token)?) } else { None };
+        let pattern = self.pop_string(token)?;
+        let text = self.pop_string(token)?;
+        let regex = regex::Regex::new(&pattern).map_err(|error| self.error(format!("Invalid regex {:?}: {}", pattern, error), token))?;
+        let result = match (name, replacement) {
+            ("regex-match", _) => Value::Bool(regex.is_match(&text)),
+            ("regex-find-all", _) => {
+                Value::Quotation(regex.find_iter(&text).map(|found| Value::String(found.as_str().to_string()).to_factor()).collect())
+            }
+            (_, replacement) => Value::String(regex.replace_all(&text, replacement.unwrap_or_default().as_str()).into_owned()),
+        };
+        self.stack.push(result);
+        Ok(())
+    }
+}
+
+/// The name of the type a value has at runtime. Quotations are all `Function`s, since their stack effects
+/// aren't known without checking them.
+fn type_tag(value: &Value) -> &'static str {
+    match value {
+        Value::Int(_) => "Int",
+        Value::Bool(_) => "Bool",
+        Value::String(_) => "String",
+        Value::Bytes(_) => "Bytes",
+        Value::Set(_) => "Set",
+        Value::Quotation(_) => "Function",
+    }
+}
+
+/// Whether `value` has the type `annotation` gives, as far as can be told at runtime. Declared types and type
+/// parameters can be held by any value.
+fn has_type(value: &Value, annotation: &TypeAnnotation) -> bool {
+    match annotation {
+        TypeAnnotation::Function(..) => matches!(value, Value::Quotation(_)),
+        TypeAnnotation::Identifier(name, _) if matches!(name.as_str(), "Int" | "Bool" | "String" | "Bytes") => name == type_tag(value),
+        TypeAnnotation::Apply(name, ..) if name == "Set" => matches!(value, Value::Set(_)),
+        TypeAnnotation::Identifier(..) | TypeAnnotation::Apply(..) => true,
+        TypeAnnotation::Effectful(t, _, _) | TypeAnnotation::Total(t, _) | TypeAnnotation::Memo(t, _) => has_type(value, t),
+    }
+}
+
+/// `factors` with the locals in `values` replaced by the literals of their values, in quotations too. Locals
+/// can't be shadowed, so every one with a bound name is bound to that value.
+fn bind(factors: &[Factor], values: &[(Symbol, Factor)]) -> Vec<Factor> {
+    factors.iter()
+        .map(|factor| match factor {
+            Factor::Local(name, _) => values.iter()
+                .find(|(local, _)| local == name)
+                .map_or_else(|| factor.clone(), |(_, value)| value.clone()),
+            Factor::Quotation(inner, span) => Factor::Quotation(bind(inner, values), *span),
+            factor => factor.clone(),
+        })
+        .collect()
+}
+
+/// Display values in a row, as a stack is shown.
+fn render(values: &[Value]) -> String {
+    values.iter().map(Value::to_string).collect::<Vec<_>>().join(" ")
+}
+
+impl Default for Evaluator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+    use crate::parser::{parse, Cycle};
+    use crate::symbol::Symbol;
+    use super::{Evaluator, Value};
+
+    fn eval(input: &str) -> Result<Vec<Value>, Error> {
+        let mut evaluator = Evaluator::new();
+        for cycle in parse(input)? {
+            evaluator.eval_cycle(&cycle)?;
+        }
+        Ok(evaluator.stack().to_vec())
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(eval("1 2 + 3 *").unwrap(), vec![Value::Int(9)]);
+    }
+
+    #[test]
+    fn only_runs_the_builtins_it_is_limited_to() {
+        let mut evaluator = Evaluator::new();
+        evaluator.limit_builtins([Symbol::intern("+")]);
+        for cycle in parse("1 2 +").unwrap() {
+            evaluator.eval_cycle(&cycle).unwrap();
+        }
+        assert_eq!(evaluator.stack(), &[Value::Int(3)]);
+        let error = parse("2 *").unwrap().iter().try_for_each(|cycle| evaluator.eval_cycle(cycle)).unwrap_err();
+        assert!(error.to_string().contains("Unknown word *"), "{}", error);
+        evaluator.allow_builtin("*");
+        for cycle in parse("*").unwrap() {
+            evaluator.eval_cycle(&cycle).unwrap();
+        }
+        assert_eq!(evaluator.stack(), &[Value::Int(6)]);
+    }
+
+    #[test]
+    fn stack_shuffling() {
+        assert_eq!(eval("1 2 swap dup drop").unwrap(), vec![Value::Int(2), Value::Int(1)]);
+    }
+
+    #[test]
+    fn quote_and_call() {
+        assert_eq!(eval("1 quote call [2 3] call").unwrap(), vec![Value::Int(1), Value::Int(2), Value::Int(3)]);
+    }
+
+    #[test]
+    fn cat_joins_quotations() {
+        assert_eq!(eval("[1] [2 +] cat call").unwrap(), vec![Value::Int(3)]);
+    }
+
+    #[test]
+    fn ifte_restores_the_stack_after_the_condition() {
+        assert_eq!(eval("5 [0 >] [1 +] [1 -] ifte").unwrap(), vec![Value::Int(6)]);
+        assert_eq!(eval("5 [0 <] [1 +] [1 -] ifte").unwrap(), vec![Value::Int(4)]);
+    }
+
+    #[test]
+    fn cond_runs_the_body_of_the_first_predicate_that_holds() {
+        let sign = "cond { [dup 0 <] [drop -1] [dup 0 =] [drop 0] [drop 1] }";
+        for (n, expected) in [(-5, -1), (0, 0), (7, 1)] {
+            assert_eq!(eval(&format!("{} {}", n, sign)).unwrap(), vec![Value::Int(expected)]);
+        }
+        assert_eq!(eval("1 [[false] [2] [3]] cond").unwrap(), vec![Value::Int(1), Value::Int(3)]);
+        assert!(eval("1 [[true] [2]] cond").is_err());
+    }
+
+    #[test]
+    fn let_binds_values_to_locals() {
+        assert_eq!(eval("1 2 let x y in y x end").unwrap(), vec![Value::Int(2), Value::Int(1)]);
+        let source = "def hyp2: (Int, Int -> Int) = let a b in a a * b b * + end; 3 4 hyp2";
+        assert_eq!(eval(source).unwrap(), vec![Value::Int(25)]);
+        assert_eq!(eval("5 let n in 1 [n +] end call").unwrap(), vec![Value::Int(6)]);
+    }
+
+    #[test]
+    fn checks_coercions_out_of_dyn_at_runtime() {
+        let values = eval("1 dynamic :: Int 1 + \"s\" :: Dyn type-of [] type-of").unwrap();
+        assert_eq!(values, vec![Value::Int(2), Value::String("String".to_string()), Value::String("Function".to_string())]);
+        match eval("\"x\" dynamic :: Int 1 +").unwrap_err() {
+            Error::RuntimeError(message, token, _) => {
+                assert_eq!(message, "Expected Int, got \"x\"");
+                assert_eq!(token.col, 13);
+            }
+            error => panic!("Expected RuntimeError, got {:?}", error),
+        }
+    }
+
+    #[test]
+    fn works_with_bytes() {
+        let values = eval("b\"hi\" x\"00ff\" bytes-cat dup bytes-length swap dup 3 bytes-at swap 1 3 bytes-slice").unwrap();
+        assert_eq!(values, vec![Value::Int(4), Value::Int(255), Value::Bytes(vec![b'i', 0])]);
+        assert_eq!(eval("\"é\" string->bytes bytes->string").unwrap(), vec![Value::String("é".to_string())]);
+        for (source, expected) in [
+            ("x\"61ff\" bytes->string", "Invalid UTF-8 at byte 1"),
+            ("b\"ab\" 2 bytes-at", "Index 2 is out of bounds for 2 bytes"),
+            ("b\"ab\" 1 3 bytes-slice", "Slice 1 to 3 is out of bounds for 2 bytes"),
+        ] {
+            match eval(source).unwrap_err() {
+                Error::RuntimeError(message, _, _) => assert_eq!(message, expected),
+                error => panic!("Expected RuntimeError, got {:?}", error),
+            }
+        }
+    }
+
+    #[test]
+    fn ranges_over_and_enumerates_lists() {
+        assert_eq!(eval("1 4 range 3 3 range").unwrap(), vec![Value::from(vec![1, 2, 3]), Value::Quotation(vec![])]);
+        match eval("0 9223372036854775807 range").unwrap_err() {
+            Error::RuntimeError(message, _, _) => assert_eq!(message, "A range can have at most 1048576 values, but 0 up to 9223372036854775807 has 9223372036854775807"),
+            error => panic!("Expected RuntimeError, got {:?}", error),
+        }
+        let mut evaluator = Evaluator::with_config(super::EvaluatorConfig { range_limit: Some(2), ..super::EvaluatorConfig::default() });
+        assert!(evaluator.eval_cycle(&parse("0 3 range").unwrap()[0]).is_err());
+        assert_eq!(eval("[-1 9223372036854775807 range] [drop \"too long\"] try").unwrap(), vec![Value::String("too long".into())]);
+        assert_eq!(eval("0 [1 2 3] [+] each").unwrap(), vec![Value::Int(6)]);
+        let pairs = eval("[\"a\" \"b\"] enumerate").unwrap();
+        assert_eq!(crate::format::value_to_source(&pairs[0]), "[[0 \"a\"] [1 \"b\"]]");
+    }
+
+    #[test]
+    fn orders_numbers_by_value_and_strings_lexicographically() {
+        let values = eval("1 2 < 2 2 <= 3 2 >= 1 2 != \"apple\" \"banana\" < \"b\" \"abc\" > \"\" \"a\" >=").unwrap();
+        assert_eq!(values, [true, true, true, true, true, true, false].map(Value::Bool));
+        let orders = eval("1 2 compare 2 2 compare \"b\" \"a\" compare").unwrap();
+        assert_eq!(orders, [-1, 0, 1].map(Value::Int));
+    }
+
+    #[test]
+    fn short_circuits_predicates() {
+        let values = eval("5 [0 >] [10 <] and* 5 [0 <] [10 <] and* 5 [0 <] [10 <] or* 5 [0 <] [10 >] or*").unwrap();
+        assert_eq!(values, vec![Value::Int(5), Value::Bool(true), Value::Int(5), Value::Bool(false), Value::Int(5), Value::Bool(true), Value::Int(5), Value::Bool(false)]);
+        // The second predicate would fail if it ran.
+        assert_eq!(eval("0 [0 =] [\"no\" throw] or* swap [1 =] [\"no\" throw] and*").unwrap(), vec![Value::Bool(true), Value::Int(0), Value::Bool(false)]);
+    }
+
+    #[test]
+    fn runs_single_branches_on_a_boolean() {
+        let values = eval("-3 dup 0 < [0 swap -] if 4 dup 0 < [0 swap -] if 1 true [1 +] unless 1 false [1 +] unless").unwrap();
+        assert_eq!(values, [3, 4, 1, 2].map(Value::Int));
+    }
+
+    #[test]
+    fn composes_and_applies_quotations() {
+        assert_eq!(eval("3 [[1 +] [2 *] [dup]] compose call").unwrap(), [8, 8].map(Value::Int));
+        assert_eq!(eval("[] compose").unwrap(), vec![Value::Quotation(vec![])]);
+        assert_eq!(eval("1 2 3 4 [10 *] 3 apply-n").unwrap(), [1, 20, 30, 40].map(Value::Int));
+        assert_eq!(eval("1 [drop] 0 apply-n").unwrap(), vec![Value::Int(1)]);
+        for (source, message) in [
+            ("[[1] 2] compose", "1:9: runtime error: Expected a list of quotations, got one with 2 in it"),
+            ("1 2 [1 +] 3 apply-n", "1:13: runtime error: Stack underflow"),
+            ("1 [1 +] -1 apply-n", "1:12: runtime error: apply-n can't apply a quotation to -1 values"),
+        ] {
+            assert_eq!(eval(source).unwrap_err().to_string(), message);
+        }
+    }
+
+    #[test]
+    fn compares_and_hashes_values_by_structure() {
+        let values = eval("1 1 = \"a\" \"a\" = [1 [2]] [1 [2]] = [1] [2] = 1 true =").unwrap();
+        assert_eq!(values, vec![Value::Bool(true), Value::Bool(true), Value::Bool(true), Value::Bool(false), Value::Bool(false)]);
+        let hashes = eval("[1 \"x\"] hash [1 \"x\"] hash 1 hash true hash").unwrap();
+        assert_eq!(hashes[0], hashes[1]);
+        assert_ne!(hashes[2], hashes[3]);
+        // Quotations from different places are the same value, so they're interchangeable as keys.
+        let keys: std::collections::HashSet<Value> = eval("[dup] 1 quote call [dup] 1").unwrap().into_iter().collect();
+        assert_eq!(keys.len(), 2);
+    }
+
+    #[test]
+    fn keeps_sets_in_order() {
+        let values = eval("set-new 3 set-add 1 set-add 3 set-add dup 1 set-contains swap 2 set-contains").unwrap();
+        assert_eq!(values, vec![Value::Bool(true), Value::Bool(false)]);
+        let source = "set-new 1 set-add 2 set-add 3 set-add set-new 3 set-add 4 set-add 2 set-add";
+        let values = eval(&format!("{} set-union set->list {} set-intersect set->list", source, source)).unwrap();
+        assert_eq!(values, vec![Value::from(vec![1, 2, 3, 4]), Value::from(vec![2, 3])]);
+        let set = eval("set-new \"b\" set-add 10 set-add 9 set-add true set-add [x] set-add").unwrap();
+        assert_eq!(crate::format::value_to_source(&set[0]), "set-new 9 set-add 10 set-add true set-add \"b\" set-add [x] set-add");
+        assert_eq!(eval("set-new 1 set-add quote call 2 set-add set->list").unwrap(), vec![Value::from(vec![1, 2])]);
+    }
+
+    #[test]
+    fn forces_lazy_sequences() {
+        assert_eq!(eval("1 [2 *] iterate 5 take force").unwrap(), vec![Value::from(vec![1, 2, 4, 8, 16])]);
+        assert_eq!(eval("0 [1 +] iterate [10 <] take-while force").unwrap(), vec![Value::from((0..10).collect::<Vec<i64>>())]);
+        // Counting down from 3, emitting each seed squared.
+        let source = "3 [0 =] [dup dup * swap 1 -] unfold";
+        assert_eq!(eval(&format!("{} force {} 2 take force", source, source)).unwrap(), vec![Value::from(vec![9, 4, 1]), Value::from(vec![9, 4])]);
+        assert_eq!(eval("1 [0 1 /] iterate 0 take force").unwrap(), vec![Value::Quotation(vec![])]);
+        // Cutting a sequence short more than once keeps the shortest prefix that satisfies all of them.
+        let source = "0 [1 +] iterate 8 take [small] take-while 5 take [10 <] take-while force";
+        assert_eq!(eval(&format!("def small: (Int -> Bool) = 4 < ;\n{}", source)).unwrap(), vec![Value::from(vec![0, 1, 2, 3])]);
+        for (source, expected) in [("[1 2] 3 take", "Expected a sequence, got [1 2]"), ("1 [2 *] iterate -1 take", "Can't take a negative number of values, -1")] {
+            match eval(source).unwrap_err() {
+                Error::RuntimeError(message, _, _) => assert_eq!(message, expected),
+                error => panic!("Expected RuntimeError, got {:?}", error),
+            }
+        }
+    }
+
+    #[test]
+    fn coroutines_exchange_values_at_each_yield() {
+        let program = "def count: (Int -> Int) = dup yield drop 1 + count;\n[count] coroutine 5 resume drop drop 0 resume";
+        let mut stack = eval(program).unwrap();
+        assert_eq!(stack.split_off(1), vec![Value::Int(6), Value::Bool(true)]);
+        let stack = eval("[1 + yield 10 *] coroutine 5 resume drop drop 2 resume").unwrap();
+        assert_eq!(super::render(&stack), "[[] []] 20 false");
+        for (source, message) in [
+            ("1 yield", "1:3: runtime error: yield has to be inside a coroutine"),
+            ("[[yield] [1] [2] ifte] coroutine true resume", "1:3: runtime error: A coroutine can only yield from its body and the words it calls"),
+            ("[1 2] 0 resume", "1:9: runtime error: Expected a coroutine, got [1 2]"),
+        ] {
+            assert_eq!(eval(source).unwrap_err().to_string(), message);
+        }
+    }
+
+    #[test]
+    fn shift_captures_the_rest_of_its_reset() {
+        assert_eq!(eval("1 [2 [drop 10] shift 3] reset 4").unwrap(), vec![Value::Int(1), Value::Int(2), Value::Int(10), Value::Int(4)]);
+        assert_eq!(eval("10 [[dup cat call] shift 1 +] reset").unwrap(), vec![Value::Int(12)]);
+        let program = "def inc: (Int -> Int) = [] shift 1 +;\n[5 inc 2 *] reset";
+        assert_eq!(super::render(&eval(program).unwrap()), "5 [1 + 2 *]");
+        for (source, message) in [
+            ("[] shift", "1:4: runtime error: shift has to be inside a reset"),
+            ("[[[] shift] [1] [2] ifte] reset", "1:6: runtime error: shift can only capture the body of its reset and the words it calls"),
+        ] {
+            assert_eq!(eval(source).unwrap_err().to_string(), message);
+        }
+    }
+
+    #[test]
+    fn sorts_and_searches_lists_with_comparators() {
+        assert_eq!(eval("[3 1 2 1] [<] sort").unwrap(), vec![Value::from(vec![1, 1, 2, 3])]);
+        assert_eq!(eval("[\"bb\" \"a\" \"ccc\"] [>] sort").unwrap(), vec![Value::from(vec!["ccc", "bb", "a"])]);
+        assert_eq!(eval("[1 2 3 4 5] [>] sort [] [<] sort").unwrap(), vec![Value::from(vec![5, 4, 3, 2, 1]), Value::Quotation(vec![])]);
+        // Values that compare equal keep their order.
+        let source = "[21 12 25 14 33 27 11] [10 / swap 10 / swap <] sort";
+        assert_eq!(eval(source).unwrap(), vec![Value::from(vec![12, 14, 11, 21, 25, 27, 33])]);
+        assert_eq!(eval("0 [2 1] [<] sort").unwrap(), vec![Value::Int(0), Value::from(vec![1, 2])]);
+        let source = "[1 3 5 7] 5 [<] binary-search [1 3 5 7] 4 [<] binary-search [1 3] 9 [<] binary-search [] 1 [<] binary-search";
+        assert_eq!(eval(source).unwrap(), vec![Value::Int(2), Value::Int(-1), Value::Int(-1), Value::Int(-1)]);
+        assert_eq!(eval("[4 9 2] [<] min-by [4 9 2] [<] max-by").unwrap(), vec![Value::Int(2), Value::Int(9)]);
+        for (source, message) in [
+            ("[] [<] max-by", "max-by needs a list with something in it"),
+            ("[1 dup] [<] sort", "Expected a list of values, got a quotation with dup in it"),
+            ("0 [2 1] [drop drop drop true] sort", "The comparison `[drop drop drop true]` has to take the two values it's given and leave a Bool"),
+        ] {
+            match eval(source).unwrap_err() {
+                Error::RuntimeError(actual, _, _) => assert_eq!(actual, message),
+                error => panic!("Expected RuntimeError, got {:?}", error),
+            }
+        }
+    }
+
+    #[test]
+    fn random_words_repeat_from_a_seed() {
+        use super::EvaluatorConfig;
+        let cycles = parse("1 6 random-int 1 6 random-int random-bool -3 -3 random-int").unwrap();
+        let run = |seed| {
+            let mut evaluator = Evaluator::with_config(EvaluatorConfig { seed: Some(seed), ..EvaluatorConfig::default() });
+            cycles.iter().try_for_each(|cycle| evaluator.eval_cycle(cycle)).unwrap();
+            evaluator.stack().to_vec()
+        };
+        let values = run(9);
+        assert_eq!(values, run(9));
+        assert!(values[..2].iter().all(|value| matches!(value, Value::Int(1..=6))));
+        assert!(matches!(values[2], Value::Bool(_)));
+        assert_eq!(values[3], Value::Int(-3));
+        match eval("2 1 random-int").unwrap_err() {
+            Error::RuntimeError(message, _, _) => assert_eq!(message, "random-int needs a low bound no higher than its high one, got 2 and 1"),
+            error => panic!("Expected RuntimeError, got {:?}", error),
+        }
+    }
+
+    #[test]
+    fn memoized_definitions_run_once_for_each_input() {
+        use super::EvaluatorConfig;
+        let fib = "memo def fib: (Int -> Int) = [dup 2 <] [] [dup 1 - fib swap 2 - fib +] ifte;";
+        assert_eq!(eval(&format!("{} 70 fib", fib)).unwrap(), vec![Value::Int(190392490709135)]);
+        // `pick` keeps giving its first result for 1, even after what it calls is redefined, unless it can't keep any.
+        let cycles = parse(&format!("{}\ndef flip: (Int -> Int) = 1 +;\nmemo def pick: (Int -> Int) = flip; 1 pick 20 fib", fib)).unwrap();
+        let again = parse("def flip: (Int -> Int) = 10 +; 1 pick").unwrap();
+        for (limit, picked) in [(None, 2), (Some(3), 2), (Some(0), 11)] {
+            let mut evaluator = Evaluator::with_config(EvaluatorConfig { memo_limit: limit, ..EvaluatorConfig::default() });
+            cycles.iter().chain(&again).try_for_each(|cycle| evaluator.eval_cycle(cycle)).unwrap();
+            assert_eq!(evaluator.stack(), &[Value::Int(2), Value::Int(6765), Value::Int(picked)]);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "regex")]
+    fn matches_finds_and_replaces_with_regexes() {
+        let source = "\"a1b22\" \"[0-9]+\" regex-match \"a1b22\" \"[0-9]+\" regex-find-all \"a1b22\" \"([a-z])([0-9])\" \"$2$1\" regex-replace";
+        let values = eval(source).unwrap();
+        assert_eq!(values, vec![Value::Bool(true), Value::from(vec!["1", "22"]), Value::String("1a2b2".to_string())]);
+        match eval("\"x\" \"(\" regex-match").unwrap_err() {
+            Error::RuntimeError(message, _, _) => assert!(message.starts_with("Invalid regex \"(\": "), "{}", message),
+            error => panic!("Expected RuntimeError, got {:?}", error),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "reflection")]
+    fn looks_up_definitions_by_name() {
+        let source = "def double: (Int -> Int) = 2 *; 4 \"double\" lookup drop call \"nope\" lookup";
+        assert_eq!(eval(source).unwrap(), vec![Value::Int(8), Value::Quotation(vec![]), Value::Bool(false)]);
+        assert_eq!(eval("def one: Int = 1; \"one\" lookup swap call").unwrap(), vec![Value::Bool(true), Value::Int(1)]);
+    }
+
+    #[test]
+    fn retain_stack_holds_values_aside() {
+        assert_eq!(eval("1 2 3 >r + r>").unwrap(), vec![Value::Int(3), Value::Int(3)]);
+        assert_eq!(eval("1 >r r@ r@ + r> drop").unwrap(), vec![Value::Int(2)]);
+        match eval("r>").unwrap_err() {
+            Error::RuntimeError(message, token, _) => {
+                assert_eq!(message, "Retain stack underflow");
+                assert_eq!(token.value, "r>");
+            }
+            error => panic!("Expected RuntimeError, got {:?}", error),
+        }
+    }
+
+    #[test]
+    fn try_restores_the_stack_and_runs_the_handler() {
+        assert_eq!(eval("[1 0 /] [drop 0] try").unwrap(), vec![Value::Int(0)]);
+        assert_eq!(eval("[1 0 /] [] try").unwrap(), vec![Value::String("Division by zero".to_string())]);
+        assert_eq!(eval("1 [2 >r 3 throw] [] try").unwrap(), vec![Value::Int(1), Value::Int(3)]);
+        assert_eq!(eval("[[1 throw] [2 +] try 10 throw] [] try").unwrap(), vec![Value::Int(10)]);
+        assert_eq!(eval("def boom: (Int -> ) = throw; [5 boom] [1 +] try [2] [] try").unwrap(), vec![Value::Int(6), Value::Int(2)]);
+        match eval("def boom: (Int -> ) = throw; 7 boom").unwrap_err() {
+            Error::RuntimeError(message, token, call_stack) => {
+                assert_eq!(message, "Uncaught throw: 7");
+                assert_eq!(token.value, "throw");
+                assert_eq!(call_stack, vec!["boom".to_string()]);
+            }
+            error => panic!("Expected RuntimeError, got {:?}", error),
+        }
+    }
+
+    #[test]
+    fn bi_tri_and_both_apply_quotations_to_copies_of_values() {
+        assert_eq!(eval("5 [1 +] [2 *] bi").unwrap(), vec![Value::Int(6), Value::Int(10)]);
+        assert_eq!(eval("5 [1 +] [2 *] [dup *] tri").unwrap(), vec![Value::Int(6), Value::Int(10), Value::Int(25)]);
+        assert_eq!(eval("3 4 [dup *] both").unwrap(), vec![Value::Int(9), Value::Int(16)]);
+        assert_eq!(eval("[1] [call] [call 1 +] bi").unwrap(), vec![Value::Int(1), Value::Int(2)]);
+        assert_eq!(eval("def both: (Int -> Int) = 1 +; 1 both").unwrap(), vec![Value::Int(2)]);
+    }
+
+    #[test]
+    fn keep_restores_the_value_after_the_quotation() {
+        assert_eq!(eval("5 [1 +] keep").unwrap(), vec![Value::Int(6), Value::Int(5)]);
+        assert_eq!(eval("1 2 [+] keep").unwrap(), vec![Value::Int(3), Value::Int(2)]);
+        assert!(eval("5 keep").is_err());
+    }
+
+    #[test]
+    fn curry_captures_a_value_into_a_quotation() {
+        assert_eq!(super::render(&eval("5 [1 +] curry").unwrap()), "[5 1 +]");
+        assert_eq!(eval("2 [*] curry 3 swap call").unwrap(), vec![Value::Int(6)]);
+        assert_eq!(eval("[1] [call] curry call").unwrap(), vec![Value::Int(1)]);
+    }
+
+    #[test]
+    fn calls_definitions() {
+        assert_eq!(eval("def inc: (Int -> Int) = 1 +; 1 inc inc").unwrap(), vec![Value::Int(3)]);
+    }
+
+    #[test]
+    fn shares_dictionaries_between_evaluators() {
+        let mut first = Evaluator::new();
+        first.define("one", vec![]);
+        let mut second = Evaluator::with_dictionary(first.dictionary().clone());
+        second.define("two", vec![]);
+        first.define_native("one", Box::new(|_| Ok(())));
+        assert_eq!((first.definitions(), second.definitions()), (vec![], vec!["one", "two"]));
+    }
+
+    #[test]
+    fn reports_division_by_zero() {
+        match eval("1 0 /").unwrap_err() {
+            Error::RuntimeError(message, token, call_stack) => {
+                assert_eq!(message, "Division by zero");
+                assert_eq!(token.value, "/");
+                assert!(call_stack.is_empty());
+            }
+            error => panic!("Expected RuntimeError, got {:?}", error),
+        }
+    }
+
+    #[test]
+    fn reports_stack_underflow() {
+        assert!(matches!(eval("drop"), Err(Error::RuntimeError(_, _, _))));
+    }
+
+    #[test]
+    fn records_the_definitions_a_runtime_error_happened_in() {
+        let error = eval("def helper: (Int -> Int) = 0 /; def main: (Int -> Int) = helper; 1 main").unwrap_err();
+        match &error {
+            Error::RuntimeError(_, _, call_stack) => assert_eq!(call_stack, &vec!["main".to_string(), "helper".to_string()]),
+            error => panic!("Expected RuntimeError, got {:?}", error),
+        }
+        assert_eq!(error.to_string(), "1:30: runtime error: Division by zero (in main -> helper -> /)");
+        let mut evaluator = Evaluator::new();
+        evaluator.eval_term(&[]).unwrap();
+        assert_eq!(evaluator.depth(), 0);
+    }
+
+    #[test]
+    fn asserts_on_values_and_the_stack() {
+        assert!(eval("1 1 + 2 assert-eq").unwrap().is_empty());
+        assert!(eval("[1 2] [1 3] assert-eq").is_err());
+        assert_eq!(eval("1 [2] [1 [2]] assert-stack").unwrap().len(), 2);
+        match eval("1 [2] assert-stack").unwrap_err() {
+            Error::RuntimeError(message, _, _) => assert_eq!(message, "Assertion failed: expected stack [2], got [1]"),
+            error => panic!("Expected RuntimeError, got {:?}", error),
+        }
+    }
+
+    #[test]
+    fn assertions_work_as_contracts() {
+        let program = "def positive: (Int -> Int) = dup 0 > assert; def check: (Int -> Int) = positive;";
+        assert_eq!(eval(&format!("{} 1 check", program)).unwrap(), vec![Value::Int(1)]);
+        let error = eval(&format!("{}\n-1 check", program)).unwrap_err();
+        assert_eq!(error.to_string(), "1:38: runtime error: Assertion failed: expected true, got false (in check -> positive -> assert)");
+        let error = eval("def f: (Int -> Int) = dup 2 assert-eq; 1 f").unwrap_err();
+        assert_eq!(error.to_string(), "1:29: runtime error: Assertion failed: expected 2, got 1 (in f -> assert-eq)");
+    }
+
+    #[test]
+    fn introspects_definitions() {
+        let program = "def sq: (Int -> Int) = dup *; def four: Int = 2 sq;";
+        let words = vec![Value::from(vec!["four", "Int"]).to_factor(), Value::from(vec!["sq", "(Int -> Int)"]).to_factor()];
+        assert_eq!(eval(&format!("{} words", program)).unwrap(), vec![Value::Quotation(words)]);
+        assert_eq!(eval(&format!("{} \"four\" see", program)).unwrap(), vec![Value::from("2 sq")]);
+        assert!(eval("\"+\" see").is_err());
+    }
+
+    #[test]
+    fn runs_tests_against_their_expectations() {
+        let mut evaluator = Evaluator::new();
+        for cycle in parse("def inc: (Int -> Int) = 1 +; 7").unwrap() {
+            evaluator.eval_cycle(&cycle).unwrap();
+        }
+        let cycles = parse("test \"inc\" { 1 inc expect 2 } test \"wrong\" { 1 inc expect 3 }").unwrap();
+        let results: Vec<_> = cycles.iter()
+            .map(|cycle| match cycle {
+                Cycle::Test(_, body, expected, _) => evaluator.run_test(body, expected.as_deref()),
+                cycle => panic!("Expected Test, got {:?}", cycle),
+            })
+            .collect();
+        assert!(results[0].is_ok());
+        assert_eq!(results[1].as_ref().unwrap_err().to_string(), "1:59: runtime error: Expected [3], got [2]");
+        assert_eq!(evaluator.stack(), &[Value::Int(7)]);
+    }
+
+    #[test]
+    fn displays_values_as_the_source_that_pushes_them() {
+        assert_eq!(Value::from("tab\there \"quoted\"").to_string(), "\"tab\\there \\\"quoted\\\"\"");
+        assert_eq!(eval("[1 [\"a\" true] [dup +]]").unwrap()[0].to_string(), "[1 [\"a\" true] [dup +]]");
+        assert_eq!(eval("set-new 2 set-add 1 set-add").unwrap()[0].to_string(), "set-new 1 set-add 2 set-add");
+        assert_eq!(eval("[1 \"a\\n\"] show").unwrap(), vec![Value::from("[1 \"a\\n\"]")]);
+    }
+}
@@ -0,0 +1,171 @@
+use alloc::{collections::BTreeMap, format, string::{String, ToString}, vec, vec::Vec};
+use crate::error::Error;
+use crate::evaluator::{Evaluator, Value};
+use crate::parser::{Cycle, Factor};
+use crate::scanner::{Span, Token};
+use crate::symbol::Symbol;
+
+/// How many macros deep an expansion can go, counting macros used by the output of others, before it's taken
+/// to be expanding forever.
+const MAX_DEPTH: usize = 64;
+
+/// Expand the macros in `cycles`, taking their definitions out of the program.
+///
+/// A macro runs where a term uses it, on a stack holding a quotation of the factors after it in the same term or
+/// quotation, and the quotation it leaves takes the place of the macro and those factors. What it leaves is
+/// expanded in turn, so macros can expand into uses of macros, but their own bodies aren't expanded. Macros can use
+/// the words defined before them.
+pub fn expand(cycles: Vec<Cycle>) -> Result<Vec<Cycle>, Error> {
+    let mut expander = Expander { macros: BTreeMap::new(), evaluator: Evaluator::new(), renamed: 0 };
+    let mut expanded = Vec::new();
+    for cycle in cycles {
+        match cycle {
+            Cycle::Macro(name, body, span) => {
+                expander.macros.insert(Symbol::intern(&name), (body, span));
+            }
+            Cycle::Definition(name, annotation, body, span, docs) => {
+                let body = expander.expand_term(body, 0)?;
+                expander.evaluator.define(&name.value, body.clone());
+                expanded.push(Cycle::Definition(name, annotation, body, span, docs));
+            }
+            Cycle::Term(term, span) => expanded.push(Cycle::Term(expander.expand_term(term, 0)?, span)),
+            Cycle::Test(name, body, expected, span) => {
+                let body = expander.expand_term(body, 0)?;
+                let expected = expected.map(|expected| expander.expand_term(expected, 0)).transpose()?;
+                expanded.push(Cycle::Test(name, body, expected, span));
+            }
+        }
+    }
+    Ok(expanded)
+}
+
+struct Expander {
+    /// The body of each macro defined so far, with the span of its definition, which tells the factors it
+    /// writes apart from the ones it was given.
+    macros: BTreeMap<Symbol, (Vec<Factor>, Span)>,
+    /// Runs the macros, with the words defined so far.
+    evaluator: Evaluator,
+    /// How many locals have been renamed, for naming the next one.
+    renamed: usize,
+}
+
+impl Expander {
+    fn expand_term(&mut self, factors: Vec<Factor>, depth: usize) -> Result<Vec<Factor>, Error> {
+        let mut expanded = Vec::new();
+        let mut factors = factors.into_iter();
+        while let Some(factor) = factors.next() {
+            match factor {
+                Factor::Identifier(name, token) if self.macros.contains_key(&name) => {
+                    let output = self.run(name, &token, factors.collect(), depth)?;
+                    expanded.extend(self.expand_term(output, depth + 1)?);
+                    break;
+                }
+                Factor::Quotation(inner, span) => expanded.push(Factor::Quotation(self.expand_term(inner, depth)?, span)),
+                factor => expanded.push(factor),
+            }
+        }
+        Ok(expanded)
+    }
+
+    /// Run the macro `name`, used at `token`, on the factors after it.
+    fn run(&mut self, name: Symbol, token: &Token, rest: Vec<Factor>, depth: usize) -> Result<Vec<Factor>, Error> {
+        let error = |message: String| Error::ExpansionError(message, token.clone());
+        if depth >= MAX_DEPTH {
+            return Err(error(format!("`{}` is still expanding after {} macros inside each other", name, MAX_DEPTH)));
+        }
+        let (body, span) = self.macros[&name].clone();
+        *self.evaluator.stack_mut() = vec![Value::Quotation(rest)];
+        self.evaluator.eval_term(&body).map_err(|failure| error(format!("`{}` failed while expanding: {}", name, failure)))?;
+        match core::mem::take(self.evaluator.stack_mut()).as_slice() {
+            [Value::Quotation(output)] => Ok(self.rename_locals(output.clone(), &span, &[])),
+            stack => {
+                let stack = stack.iter().map(ToString::to_string).collect::<Vec<_>>().join(" ");
+                Err(error(format!("`{}` has to leave a single quotation to expand into, but left [{}]", name, stack)))
+            }
+        }
+    }
+
+    /// Give the `let`s a macro wrote into its output, which are the ones from inside its definition at `span`,
+    /// locals of their own, so they can't capture the locals of the factors the macro was given.
+    fn rename_locals(&mut self, factors: Vec<Factor>, span: &Span, renames: &[(Symbol, Symbol)]) -> Vec<Factor> {
+        let written = |token: &Token| token.span.file == span.file && span.start <= token.span.start && token.span.end <= span.end;
+        let mut renamed = Vec::new();
+        let mut factors = factors.into_iter().peekable();
+        while let Some(factor) = factors.next() {
+            match factor {
+                Factor::Quotation(body, body_span) if matches!(factors.peek(), Some(Factor::Let(_, token)) if written(token)) => {
+                    let Some(Factor::Let(names, token)) = factors.next() else {
+                        unreachable!()
+                    };
+                    let mut inner = renames.to_vec();
+                    let names = names.into_iter()
+                        .map(|name| {
+                            self.renamed += 1;
+                            let fresh = Symbol::intern(&format!("{}#{}", name, self.renamed));
+                            inner.push((name, fresh));
+                            fresh
+                        })
+                        .collect();
+                    renamed.push(Factor::Quotation(self.rename_locals(body, span, &inner), body_span));
+                    renamed.push(Factor::Let(names, token));
+                }
+                Factor::Quotation(inner, inner_span) => {
+                    renamed.push(Factor::Quotation(self.rename_locals(inner, span, renames), inner_span));
+                }
+                Factor::Local(name, token) if written(&token) => {
+                    let name = renames.iter().rev().find(|(old, _)| *old == name).map_or(name, |(_, new)| *new);
+                    renamed.push(Factor::Local(name, token));
+                }
+                factor => renamed.push(factor),
+            }
+        }
+        renamed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+    use crate::evaluator::{Evaluator, Value};
+    use crate::parser::parse;
+    use super::expand;
+
+    fn run(source: &str) -> Result<Vec<Value>, Error> {
+        let mut evaluator = Evaluator::new();
+        for cycle in expand(parse(source)?)? {
+            evaluator.eval_cycle(&cycle)?;
+        }
+        Ok(evaluator.stack().to_vec())
+    }
+
+    #[test]
+    fn macros_rewrite_the_rest_of_their_term() {
+        // `twice` repeats the factors after it, and `skip` drops them, even inside a quotation.
+        let source = "def double: (( -> ) -> ( -> )) = dup cat;\nmacro twice = double;\n1 twice 2 *";
+        assert_eq!(run(source).unwrap(), vec![Value::Int(4)]);
+        assert_eq!(run("macro skip = drop [];\n[skip 5] call 3").unwrap(), vec![Value::Int(3)]);
+        let source = "macro twice = dup cat;\nmacro thrice = dup [twice] swap cat cat;\n1 thrice 1 +";
+        assert_eq!(run(source).unwrap(), vec![Value::Int(4)]);
+        let cycles = expand(parse("macro nothing = drop [];\ndef f: Int = 1; nothing 2").unwrap()).unwrap();
+        assert_eq!(cycles.len(), 2);
+    }
+
+    #[test]
+    fn macro_locals_dont_capture_the_factors_they_are_given() {
+        // `plus-ten` binds an `n` of its own, around factors with another `n` in them.
+        let source = "macro plus-ten = quote [10 let n in call n + end] cat;\n1 let n in plus-ten n end";
+        assert_eq!(run(source).unwrap(), vec![Value::Int(11)]);
+    }
+
+    #[test]
+    fn reports_macros_that_cannot_expand() {
+        for (source, message) in [
+            ("macro bad = drop 1;\nbad 2", "2:1: expansion error: `bad` has to leave a single quotation to expand into, but left [1]"),
+            ("macro forever = [forever] swap cat;\nforever", "1:18: expansion error: `forever` is still expanding after 64 macros inside each other"),
+        ] {
+            assert_eq!(run(source).unwrap_err().to_string(), message);
+        }
+        let error = run("macro fail = 1 0 /;\n1 fail").unwrap_err().to_string();
+        assert_eq!(error, "2:3: expansion error: `fail` failed while expanding: 1:18: runtime error: Division by zero");
+    }
+}
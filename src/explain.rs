@@ -0,0 +1,160 @@
+use alloc::{format, string::{String, ToString}, vec::Vec};
+use crate::doc::escape;
+use crate::evaluator::{Observer, Value};
+use crate::format::factor_to_source;
+use crate::parser::Factor;
+
+/// One word that ran, and the stack it left.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Row {
+    pub word: String,
+    /// How many calls deep the word ran, starting from 0 for the words of a term.
+    pub depth: usize,
+    /// The stack afterwards, bottom first.
+    pub stack: Vec<String>,
+}
+
+/// Records the stack after every word, for showing how a program works step by step:
+///
+/// ```text
+/// +------+-------+
+/// | word | stack |
+/// +------+-------+
+/// | 1    | 1     |
+/// | 2    | 1 2   |
+/// | +    | 3     |
+/// +------+-------+
+/// ```
+///
+/// Only the words of terms are recorded by default; raising `max_depth` records the words inside the
+/// definitions and quotations they call too, indented under them.
+pub struct Explainer {
+    rows: Vec<Row>,
+    /// The rows whose words might still be running, shallowest first. A word that calls something has
+    /// finished once the next word at its depth or shallower starts, and until then its row shows the
+    /// stack as of the last word inside it.
+    open: Vec<usize>,
+    pub max_depth: usize,
+}
+
+impl Explainer {
+    pub fn new() -> Explainer {
+        Explainer { rows: Vec::new(), open: Vec::new(), max_depth: 1 }
+    }
+
+    pub fn rows(&self) -> &[Row] {
+        &self.rows
+    }
+
+    /// The rows as an ASCII table.
+    pub fn to_text(&self) -> String {
+        let words: Vec<String> = self.rows.iter().map(|row| format!("{}{}", "  ".repeat(row.depth), row.word)).collect();
+        let stacks: Vec<String> = self.rows.iter().map(|row| row.stack.join(" ")).collect();
+        let width = |header: &str, cells: &[String]| cells.iter().map(|cell| cell.chars().count()).chain([header.len()]).max().unwrap_or(0);
+        let (word_width, stack_width) = (width("word", &words), width("stack", &stacks));
+        let rule = format!("+{}+{}+\n", "-".repeat(word_width + 2), "-".repeat(stack_width + 2));
+        let line = |word: &str, stack: &str| format!("| {:<word_width$} | {:<stack_width$} |\n", word, stack);
+        let mut out = format!("{}{}{}", rule, line("word", "stack"), rule);
+        for (word, stack) in words.iter().zip(&stacks) {
+            out.push_str(&line(word, stack));
+        }
+        out.push_str(&rule);
+        out
+    }
+
+    /// The rows as a standalone HTML page, with a cell for each value on the stack so it reads as a stack
+    /// growing to the right.
+    pub fn to_html(&self, title: &str) -> String {
+        let mut out = format!(
+            "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{}</title></head>\n<body>\n<h1>{}</h1>\n<table>\n<tr><th>word</th><th>stack</th></tr>\n",
+            escape(title), escape(title),
+        );
+        for row in &self.rows {
+            let values: String = row.stack.iter().map(|value| format!("<td><code>{}</code></td>", escape(value))).collect();
+            out.push_str(&format!(
+                "<tr><td style=\"padding-left: {}em\"><code>{}</code></td>{}</tr>\n",
+                row.depth * 2, escape(&row.word), values,
+            ));
+        }
+        out.push_str("</table>\n</body>\n</html>\n");
+        out
+    }
+}
+
+impl Default for Explainer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Observer for Explainer {
+    fn before_factor(&mut self, _factor: &Factor, stack: &[Value], depth: usize) {
+        let finished = self.open.iter().position(|&row| self.rows[row].depth + 1 >= depth).unwrap_or(self.open.len());
+        if finished < self.open.len() {
+            let stack = render(stack);
+            for row in self.open.split_off(finished) {
+                self.rows[row].stack = stack.clone();
+            }
+        }
+    }
+
+    fn after_factor(&mut self, factor: &Factor, stack: &[Value], depth: usize) {
+        if self.open.is_empty() && depth > self.max_depth {
+            return;
+        }
+        let stack = render(stack);
+        for &row in &self.open {
+            self.rows[row].stack = stack.clone();
+        }
+        if depth <= self.max_depth {
+            self.open.push(self.rows.len());
+            self.rows.push(Row { word: factor_to_source(factor), depth: depth.saturating_sub(1), stack });
+        }
+    }
+}
+
+fn render(stack: &[Value]) -> Vec<String> {
+    stack.iter().map(ToString::to_string).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::{compile, Engine};
+    use super::Explainer;
+
+    fn explain(input: &str, max_depth: usize) -> Explainer {
+        let explainer = Rc::new(RefCell::new(Explainer::new()));
+        explainer.borrow_mut().max_depth = max_depth;
+        let mut engine = Engine::new();
+        engine.add_observer(Box::new(explainer.clone()));
+        compile(input).unwrap().run(&mut engine).unwrap();
+        drop(engine);
+        Rc::try_unwrap(explainer).ok().unwrap().into_inner()
+    }
+
+    #[test]
+    fn tabulates_the_stack_after_every_word() {
+        assert_eq!(explain("def sq: (Int -> Int) = dup *; 3 sq \"done\"", 1).to_text(), "\
++--------+----------+
+| word   | stack    |
++--------+----------+
+| 3      | 3        |
+| sq     | 9        |
+| \"done\" | 9 \"done\" |
++--------+----------+
+");
+    }
+
+    #[test]
+    fn goes_inside_calls_to_the_depth_asked_for() {
+        let explainer = explain("def sq: (Int -> Int) = dup *; 3 sq", 2);
+        let words: Vec<(usize, &str)> = explainer.rows().iter().map(|row| (row.depth, row.word.as_str())).collect();
+        assert_eq!(words, vec![(0, "3"), (0, "sq"), (1, "dup"), (1, "*")]);
+        assert_eq!(explainer.rows()[1].stack, vec!["9"]);
+        let html = explainer.to_html("<sq>");
+        assert!(html.contains("<title>&lt;sq&gt;</title>"));
+        assert!(html.contains("<tr><td style=\"padding-left: 2em\"><code>dup</code></td><td><code>3</code></td><td><code>3</code></td></tr>"));
+    }
+}
@@ -0,0 +1,173 @@
+use std::ffi::{c_char, CStr, CString};
+use std::ptr;
+use crate::evaluator::Value;
+use crate::format::value_to_source;
+use crate::Engine;
+
+// A C interface to engines, declared for C and C++ in `include/chara.h`. Every pointer handed out is owned by
+// the caller, and has a matching function to free it. Null pointers are accepted wherever a pointer is freed.
+
+/// Which field of a `CharaValue`'s payload is set.
+#[repr(C)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum CharaTag {
+    Int,
+    Bool,
+    String,
+    /// A quotation, given as its source, like `[1 dup]`, in the `string` field.
+    Quotation,
+    /// Bytes, which can hold NULs, so they're given as their source, like `x"00ff"`, in the `string` field.
+    Bytes,
+    /// A set, given as the source that builds it, like `set-new 1 set-add`, in the `string` field.
+    Set,
+}
+
+#[repr(C)]
+pub union CharaPayload {
+    pub int: i64,
+    pub boolean: bool,
+    pub string: *mut c_char,
+}
+
+/// A value copied off an engine's stack. Free it with `chara_value_free` once done with it.
+#[repr(C)]
+pub struct CharaValue {
+    pub tag: CharaTag,
+    pub payload: CharaPayload,
+}
+
+/// A new engine, with nothing defined. Free it with `chara_engine_free`.
+#[no_mangle]
+pub extern "C" fn chara_engine_new() -> *mut Engine {
+    Box::into_raw(Box::new(Engine::new()))
+}
+
+/// # Safety
+/// `engine` must be null or have come from `chara_engine_new`, and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn chara_engine_free(engine: *mut Engine) {
+    if !engine.is_null() {
+        drop(Box::from_raw(engine));
+    }
+}
+
+/// Check and evaluate `source` against the engine, returning 0 if it ran. Otherwise returns 1, and if `error`
+/// isn't null, points it at the error messages, one per line, to be freed with `chara_string_free`.
+///
+/// # Safety
+/// `engine` must be a live engine from `chara_engine_new`, and `source` a NUL-terminated UTF-8 string.
+/// `error` must be null or valid to write a pointer to.
+#[no_mangle]
+pub unsafe extern "C" fn chara_eval(engine: *mut Engine, source: *const c_char, error: *mut *mut c_char) -> i32 {
+    let engine = &mut *engine;
+    let result = match CStr::from_ptr(source).to_str() {
+        Ok(source) => engine.eval_str(source).map_err(|errors| {
+            errors.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n")
+        }),
+        Err(_) => Err("source is not valid UTF-8".to_string()),
+    };
+    match result {
+        Ok(_) => 0,
+        Err(message) => {
+            if !error.is_null() {
+                *error = to_c_string(&message);
+            }
+            1
+        }
+    }
+}
+
+/// # Safety
+/// `engine` must be a live engine from `chara_engine_new`.
+#[no_mangle]
+pub unsafe extern "C" fn chara_stack_len(engine: *const Engine) -> usize {
+    (*engine).stack().len()
+}
+
+/// A copy of the value `index` places from the bottom of the stack, or an `Int` of 0 if there isn't one.
+///
+/// # Safety
+/// `engine` must be a live engine from `chara_engine_new`.
+#[no_mangle]
+pub unsafe extern "C" fn chara_stack_get(engine: *const Engine, index: usize) -> CharaValue {
+    match (*engine).stack().get(index) {
+        Some(Value::Int(i)) => CharaValue { tag: CharaTag::Int, payload: CharaPayload { int: *i } },
+        Some(Value::Bool(b)) => CharaValue { tag: CharaTag::Bool, payload: CharaPayload { boolean: *b } },
+        Some(Value::String(s)) => CharaValue { tag: CharaTag::String, payload: CharaPayload { string: to_c_string(s) } },
+        Some(value @ Value::Quotation(_)) => CharaValue {
+            tag: CharaTag::Quotation,
+            payload: CharaPayload { string: to_c_string(&value_to_source(value)) },
+        },
+        Some(value @ Value::Bytes(_)) => CharaValue {
+            tag: CharaTag::Bytes,
+            payload: CharaPayload { string: to_c_string(&value_to_source(value)) },
+        },
+        Some(value @ Value::Set(_)) => CharaValue {
+            tag: CharaTag::Set,
+            payload: CharaPayload { string: to_c_string(&value_to_source(value)) },
+        },
+        None => CharaValue { tag: CharaTag::Int, payload: CharaPayload { int: 0 } },
+    }
+}
+
+/// Free the string a `String`, `Quotation`, `Bytes` or `Set` value holds. Other values own nothing.
+///
+/// # Safety
+/// `value` must have come from `chara_stack_get`, and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn chara_value_free(value: CharaValue) {
+    if matches!(value.tag, CharaTag::String | CharaTag::Quotation | CharaTag::Bytes | CharaTag::Set) {
+        chara_string_free(value.payload.string);
+    }
+}
+
+/// # Safety
+/// `string` must be null or a string handed out by one of these functions, and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn chara_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+/// C strings end at the first NUL, so any inside chara strings are replaced rather than cutting them short.
+fn to_c_string(string: &str) -> *mut c_char {
+    CString::new(string.replace('\0', "\u{FFFD}")).map_or(ptr::null_mut(), CString::into_raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{CStr, CString};
+    use std::ptr;
+    use super::*;
+
+    #[test]
+    fn evaluates_and_reads_the_stack() {
+        unsafe {
+            let engine = chara_engine_new();
+            let source = CString::new("def sq: (Int -> Int) = dup *; 3 sq true \"hi\" [1 sq]").unwrap();
+            assert_eq!(chara_eval(engine, source.as_ptr(), ptr::null_mut()), 0);
+            assert_eq!(chara_stack_len(engine), 4);
+            let values: Vec<CharaValue> = (0..4).map(|i| chara_stack_get(engine, i)).collect();
+            assert_eq!((values[0].tag, values[0].payload.int), (CharaTag::Int, 9));
+            assert_eq!((values[1].tag, values[1].payload.boolean), (CharaTag::Bool, true));
+            assert_eq!(CStr::from_ptr(values[2].payload.string).to_str(), Ok("hi"));
+            assert_eq!((values[3].tag, CStr::from_ptr(values[3].payload.string).to_str()), (CharaTag::Quotation, Ok("[1 sq]")));
+            values.into_iter().for_each(|value| chara_value_free(value));
+            chara_engine_free(engine);
+        }
+    }
+
+    #[test]
+    fn reports_errors() {
+        unsafe {
+            let engine = chara_engine_new();
+            let source = CString::new("1 nope").unwrap();
+            let mut error = ptr::null_mut();
+            assert_eq!(chara_eval(engine, source.as_ptr(), &mut error), 1);
+            assert_eq!(CStr::from_ptr(error).to_str(), Ok("1:3: type error: Unknown identifier nope"));
+            chara_string_free(error);
+            chara_engine_free(engine);
+        }
+    }
+}
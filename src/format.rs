@@ -0,0 +1,171 @@
+use crate::evaluator::Value;
+use crate::parser::{self, Factor, TypeAnnotation};
+use crate::typechecker::Type;
+use core::iter;
+use alloc::{format, string::{String, ToString}, vec::Vec};
+
+/// Render a factor as chara source.
+pub fn factor_to_source(factor: &Factor) -> String {
+    match factor {
+        Factor::Dup(_) => "dup".to_string(),
+        Factor::Drop(_) => "drop".to_string(),
+        Factor::Quote(_) => "quote".to_string(),
+        Factor::Call(_) => "call".to_string(),
+        Factor::Cat(_) => "cat".to_string(),
+        Factor::Swap(_) => "swap".to_string(),
+        Factor::Ifte(_) => "ifte".to_string(),
+        Factor::Cond(_) => "cond".to_string(),
+        Factor::Let(names, _) => format!("let {}", names.iter().map(|name| name.as_str()).collect::<Vec<_>>().join(" ")),
+        Factor::Local(name, _) => name.to_string(),
+        Factor::Int(value, _) | Factor::Bool(value, _) | Factor::String(value, _) | Factor::Bytes(value, _) => literal_to_source(value),
+        Factor::Set(elements, _) => {
+            iter::once("set-new".to_string()).chain(elements.iter().map(|element| format!("{} set-add", factor_to_source(element)))).collect::<Vec<_>>().join(" ")
+        }
+        Factor::Identifier(name, _) => name.to_string(),
+        Factor::Quotation(factors, _) => format!("[{}]", term_to_source(factors)),
+        Factor::Assertion(annotation) => match &**annotation {
+            TypeAnnotation::Function(t_in, t_out, _, _) => {
+                let render = |types: &[TypeAnnotation]| types.iter().map(|t| format!("{} ", annotation_to_source(t))).collect::<String>();
+                format!("( {}-- {})", render(t_in), render(t_out))
+            }
+            annotation => annotation_to_source(annotation),
+        },
+        Factor::Ascription(annotation, _) => format!(":: {}", annotation_to_source(annotation)),
+    }
+}
+
+/// Render a term as chara source, with factors separated by single spaces. A `let` is written with its body
+/// between `in` and `end` again.
+pub fn term_to_source(factors: &[Factor]) -> String {
+    let mut sources = Vec::new();
+    let mut factors = factors.iter().peekable();
+    while let Some(factor) = factors.next() {
+        match (factor, factors.peek()) {
+            (Factor::Quotation(body, _), Some(Factor::Let(_, _))) => {
+                let binding = factor_to_source(factors.next().unwrap());
+                sources.push(format!("{} in {} end", binding, term_to_source(body)));
+            }
+            _ => sources.push(factor_to_source(factor)),
+        }
+    }
+    sources.join(" ")
+}
+
+/// Render a type annotation as chara source, e.g. `(Int, Int -> Bool)`.
+pub fn annotation_to_source(annotation: &TypeAnnotation) -> String {
+    match annotation {
+        TypeAnnotation::Function(t_in, t_out, _, _) => {
+            let render = |types: &[TypeAnnotation]| types.iter().map(annotation_to_source).collect::<Vec<_>>().join(", ");
+            format!("({} -> {})", render(t_in), render(t_out))
+        }
+        TypeAnnotation::Identifier(name, _) => name.clone(),
+        TypeAnnotation::Apply(name, args, _, _) => {
+            format!("{}({})", name, args.iter().map(annotation_to_source).collect::<Vec<_>>().join(", "))
+        }
+        TypeAnnotation::Effectful(t, _, effects) => {
+            let effects = effects.iter().map(|effect| format!(" {}", effect.value)).collect::<String>();
+            format!("{} !{}", annotation_to_source(t), effects)
+        }
+        TypeAnnotation::Total(t, _) => format!("total {}", annotation_to_source(t)),
+        TypeAnnotation::Memo(t, _) => format!("memo {}", annotation_to_source(t)),
+    }
+}
+
+/// Render a type in annotation syntax, as it would be written after `def name:`. Only some types can be
+/// written down: a term with no inputs is annotated with its single output, and parameters have no syntax.
+pub fn type_to_annotation(t: &Type) -> Option<String> {
+    match t {
+        Type::Int | Type::Bool | Type::String | Type::Bytes | Type::Dyn => Some(t.to_string()),
+        Type::Set(element) => Some(format!("Set({})", type_to_annotation(element)?)),
+        Type::Named(name, args) if args.is_empty() => Some(name.clone()),
+        Type::Named(name, args) => {
+            Some(format!("{}({})", name, args.iter().map(type_to_annotation).collect::<Option<Vec<_>>>()?.join(", ")))
+        }
+        Type::Param(_) => None,
+        Type::Function(t_in, t_out) if t_in.is_empty() && t_out.len() == 1 => type_to_annotation(&t_out[0]),
+        Type::Function(t_in, t_out) if !t_in.is_empty() && !t_out.is_empty() => {
+            let render = |types: &[Type]| types.iter().map(type_to_annotation).collect::<Option<Vec<_>>>().map(|types| types.join(", "));
+            Some(format!("({} -> {})", render(t_in)?, render(t_out)?))
+        }
+        Type::Function(_, _) => None,
+    }
+}
+
+/// Render a runtime value as the source of a literal that would push it.
+pub fn value_to_source(value: &Value) -> String {
+    factor_to_source(&value.to_factor())
+}
+
+fn literal_to_source(value: &parser::Value) -> String {
+    match value {
+        parser::Value::Integer(i) => i.to_string(),
+        parser::Value::Boolean(b) => b.to_string(),
+        // Strings keep their escape sequences from the source, so they only need their quotes back.
+        parser::Value::String(s) => format!("\"{}\"", s),
+        // Bytes that are text a string literal could hold are written as text, and any others in hex.
+        parser::Value::Bytes(bytes) => match core::str::from_utf8(bytes) {
+            Ok(text) if !text.contains(['"', '\\', '\n', '\r']) => format!("b\"{}\"", text),
+            _ => format!("x\"{}\"", bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<String>()),
+        },
+    }
+}
+
+/// Shorten `text` to at most `max_width` characters, marking the cut with an ellipsis.
+pub fn elide(text: &str, max_width: usize) -> String {
+    if text.chars().count() <= max_width {
+        text.to_string()
+    } else {
+        let kept: String = text.chars().take(max_width.saturating_sub(1)).collect();
+        format!("{}…", kept)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::evaluator::Value;
+    use crate::parser::parse;
+    use crate::parser::Cycle;
+
+    #[test]
+    fn renders_terms_as_source() {
+        let cycles = parse("1 [dup \"a\\n\" true] ifte +").unwrap();
+        match &cycles[0] {
+            Cycle::Term(factors, _) => assert_eq!(super::term_to_source(factors), "1 [dup \"a\\n\" true] ifte +"),
+            cycle => panic!("Expected Term, got {:?}", cycle),
+        }
+    }
+
+    #[test]
+    fn renders_annotations_as_source() {
+        match &parse("def a: (Int, (Bool -> String) -> Int) = drop drop 1;").unwrap()[0] {
+            Cycle::Definition(_, annotation, _, _, _) => {
+                assert_eq!(super::annotation_to_source(annotation), "(Int, (Bool -> String) -> Int)");
+            }
+            cycle => panic!("Expected Definition, got {:?}", cycle),
+        }
+    }
+
+    #[test]
+    fn renders_types_as_annotations() {
+        use crate::typechecker::Type;
+        let function = Type::Function(vec![Type::Int, Type::Function(vec![Type::Bool], vec![Type::Int])], vec![Type::String]);
+        assert_eq!(super::type_to_annotation(&function).unwrap(), "(Int, (Bool -> Int) -> String)");
+        assert_eq!(super::type_to_annotation(&Type::Function(vec![], vec![Type::Bool])).unwrap(), "Bool");
+        assert_eq!(super::type_to_annotation(&Type::Function(vec![Type::Int], vec![])), None);
+        assert_eq!(super::type_to_annotation(&Type::Function(vec![Type::Param(0)], vec![Type::Int])), None);
+    }
+
+    #[test]
+    fn renders_values_as_source() {
+        assert_eq!(super::value_to_source(&Value::from(vec![1, 2])), "[1 2]");
+        assert_eq!(super::value_to_source(&Value::from("s")), "\"s\"");
+        assert_eq!(super::value_to_source(&Value::Bytes(b"ok".to_vec())), "b\"ok\"");
+        assert_eq!(super::value_to_source(&Value::Bytes(vec![b'"', 0, 255])), "x\"2200ff\"");
+    }
+
+    #[test]
+    fn elides_long_text() {
+        assert_eq!(super::elide("abcdef", 4), "abc…");
+        assert_eq!(super::elide("abc", 4), "abc");
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use crate::error::Error;
+use crate::evaluator::Value;
+use crate::format::value_to_source;
+use crate::parser::Factor;
+pub use crate::random::Rng;
+use crate::scanner::Token;
+use crate::symbol::Symbol;
+use crate::typechecker::Type;
+use crate::Engine;
+
+/// Inputs that made a definition behave differently from its annotation.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Counterexample {
+    pub inputs: Vec<Value>,
+    pub problem: String,
+}
+
+/// Generates inputs for definitions from their annotations, and checks the outputs against them.
+pub struct Fuzzer {
+    rng: Rng,
+    pub runs: usize,
+}
+
+impl Fuzzer {
+    pub fn new(seed: u64) -> Fuzzer {
+        Fuzzer { rng: Rng::new(seed), runs: 100 }
+    }
+
+    /// Run the definition `name` on random inputs of its declared types, stopping at the first run whose
+    /// outputs don't match its declared output types, or that fails at runtime.
+    pub fn fuzz(&mut self, engine: &mut Engine, name: &str) -> Result<Option<Counterexample>, Error> {
+        let Some(Type::Function(t_in, t_out)) = engine.type_of(name).cloned() else {
+            return Err(Error::TypeError(format!("Unknown identifier {}", name), Token::unknown()));
+        };
+        let saved = std::mem::take(engine.evaluator.stack_mut());
+        let mut result = Ok(None);
+        for _ in 0..self.runs {
+            match self.run_once(engine, name, &t_in, &t_out) {
+                Ok(None) => {}
+                outcome => {
+                    result = outcome;
+                    break;
+                }
+            }
+        }
+        *engine.evaluator.stack_mut() = saved;
+        result
+    }
+
+    fn run_once(&mut self, engine: &mut Engine, name: &str, t_in: &[Type], t_out: &[Type]) -> Result<Option<Counterexample>, Error> {
+        let mut params = HashMap::new();
+        let inputs = t_in.iter()
+            .map(|t| self.generate(t, &mut params))
+            .collect::<Result<Vec<_>, _>>()?;
+        *engine.evaluator.stack_mut() = inputs.clone();
+        let word = Factor::Identifier(Symbol::intern(name), Token::unknown());
+        let problem = match engine.evaluator.eval_term(&[word]) {
+            Err(error) => Some(error.to_string()),
+            Ok(()) => check_outputs(engine.stack(), t_out, &mut params),
+        };
+        engine.evaluator.stack_mut().clear();
+        Ok(problem.map(|problem| Counterexample { inputs, problem }))
+    }
+
+    fn generate(&mut self, t: &Type, params: &mut HashMap<usize, Type>) -> Result<Value, Error> {
+        match t {
+            Type::Int => Ok(Value::Int(self.int())),
+            Type::Bool => Ok(Value::Bool(self.rng.below(2) == 1)),
+            Type::String => {
+                let length = self.rng.below(8);
+                Ok(Value::String((0..length).map(|_| (b'a' + self.rng.below(26) as u8) as char).collect()))
+            }
+            Type::Bytes => {
+                let length = self.rng.below(8);
+                Ok(Value::Bytes((0..length).map(|_| self.rng.below(256) as u8).collect()))
+            }
+            Type::Set(element) => {
+                let mut elements = (0..self.rng.below(4)).map(|_| self.generate(element, params)).collect::<Result<Vec<_>, _>>()?;
+                elements.sort_by(Value::compare);
+                elements.dedup_by(|a, b| a.compare(b).is_eq());
+                Ok(Value::Set(elements))
+            }
+            Type::Dyn => {
+                let chosen = [Type::Int, Type::Bool, Type::String][self.rng.below(3) as usize].clone();
+                self.generate(&chosen, params)
+            }
+            Type::Param(n) => {
+                let choices = [Type::Int, Type::Bool, Type::String];
+                let chosen = params.entry(*n).or_insert_with(|| choices[self.rng.below(3) as usize].clone()).clone();
+                self.generate(&chosen, params)
+            }
+            // A quotation that only pushes values is the one kind of function we can make up.
+            Type::Function(t_in, t_out) if t_in.is_empty() => {
+                let values = t_out.iter()
+                    .map(|t| self.generate(t, params))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Value::Quotation(values.iter().map(Value::to_factor).collect()))
+            }
+            t => Err(Error::TypeError(format!("Can't generate values of type {}", t), Token::unknown())),
+        }
+    }
+
+    /// Mostly small numbers, with the edges of the range thrown in, since that's where bugs tend to be.
+    fn int(&mut self) -> i64 {
+        match self.rng.below(10) {
+            0 => [0, 1, -1, i64::MIN, i64::MAX][self.rng.below(5) as usize],
+            1..=6 => self.rng.below(201) as i64 - 100,
+            _ => self.rng.next_u64() as i64,
+        }
+    }
+}
+
+fn check_outputs(outputs: &[Value], t_out: &[Type], params: &mut HashMap<usize, Type>) -> Option<String> {
+    let render = |values: &[Value]| values.iter().map(value_to_source).collect::<Vec<_>>().join(" ");
+    if outputs.len() != t_out.len() {
+        return Some(format!("expected {} output(s), got [{}]", t_out.len(), render(outputs)));
+    }
+    for (value, t) in outputs.iter().zip(t_out) {
+        if !matches_type(value, t, params) {
+            return Some(format!("expected {}, got {}", t, value_to_source(value)));
+        }
+    }
+    None
+}
+
+fn matches_type(value: &Value, t: &Type, params: &mut HashMap<usize, Type>) -> bool {
+    match (value, t) {
+        (Value::Int(_), Type::Int) | (Value::Bool(_), Type::Bool) | (Value::String(_), Type::String) => true,
+        (Value::Bytes(_), Type::Bytes) => true,
+        (Value::Set(elements), Type::Set(element)) => elements.iter().all(|value| matches_type(value, element, params)),
+        (Value::Quotation(_), Type::Function(_, _)) | (_, Type::Dyn) => true,
+        (value, Type::Param(n)) => match params.get(n).cloned() {
+            Some(bound) => matches_type(value, &bound, params),
+            None => true,
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::evaluator::Value;
+    use crate::Engine;
+    use super::Fuzzer;
+
+    fn load(source: &str) -> Engine {
+        let mut engine = Engine::new();
+        engine.eval_str(source).unwrap();
+        engine
+    }
+
+    #[test]
+    fn accepts_definitions_that_match_their_annotations() {
+        let mut engine = load("def sq: (Int -> Int) = dup *; def both: (Bool, Bool -> Bool) = and; 4");
+        assert_eq!(Fuzzer::new(1).fuzz(&mut engine, "sq").unwrap(), None);
+        assert_eq!(Fuzzer::new(1).fuzz(&mut engine, "both").unwrap(), None);
+        assert_eq!(engine.stack(), &[Value::Int(4)]);
+    }
+
+    #[test]
+    fn finds_outputs_that_dont_match() {
+        let mut engine = load("def wrong: (Int -> Int) = dup;");
+        let counterexample = Fuzzer::new(7).fuzz(&mut engine, "wrong").unwrap().unwrap();
+        assert_eq!(counterexample.inputs.len(), 1);
+        assert!(counterexample.problem.starts_with("expected 1 output(s), got"));
+
+        let mut engine = load("def lies: (Int -> Bool) = 1 +;");
+        let counterexample = Fuzzer::new(7).fuzz(&mut engine, "lies").unwrap().unwrap();
+        assert!(counterexample.problem.starts_with("expected Bool, got"));
+    }
+
+    #[test]
+    fn reports_runtime_failures() {
+        let mut engine = load("def inverse: (Int -> Int) = 1 swap /;");
+        let mut fuzzer = Fuzzer::new(3);
+        fuzzer.runs = 1000;
+        let counterexample = fuzzer.fuzz(&mut engine, "inverse").unwrap().unwrap();
+        assert_eq!(counterexample.inputs, vec![Value::Int(0)]);
+        assert!(counterexample.problem.contains("Division by zero"));
+    }
+}
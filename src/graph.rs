@@ -0,0 +1,143 @@
+use alloc::{collections::{BTreeMap, BTreeSet}, format, string::{String, ToString}, vec::Vec};
+use crate::parser::{Cycle, Factor};
+use crate::scanner::Token;
+
+/// Which of a program's definitions use which others, including in the quotations they push.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct CallGraph {
+    /// Every definition, in the order they're first defined.
+    pub definitions: Vec<String>,
+    /// The definitions each definition uses. Builtins aren't included.
+    pub calls: BTreeMap<String, BTreeSet<String>>,
+    /// The definitions used by the program's terms and tests, which is where running it starts.
+    pub entry: BTreeSet<String>,
+}
+
+/// The call graph of `cycles`. A word defined more than once gets the calls of all of its definitions.
+pub fn call_graph(cycles: &[Cycle]) -> CallGraph {
+    let mut graph = CallGraph::default();
+    for cycle in cycles {
+        if let Cycle::Definition(Token { value: name, .. }, _, _, _, _) = cycle {
+            if !graph.calls.contains_key(name) {
+                graph.definitions.push(name.clone());
+                graph.calls.insert(name.clone(), BTreeSet::new());
+            }
+        }
+    }
+    for cycle in cycles {
+        let mut used = BTreeSet::new();
+        match cycle {
+            Cycle::Definition(_, _, body, _, _) | Cycle::Term(body, _) | Cycle::Test(_, body, _, _) => words(body, &graph.calls, &mut used),
+            Cycle::Macro(_, _, _) => continue,
+        }
+        if let Cycle::Test(_, _, Some(expected), _) = cycle {
+            words(expected, &graph.calls, &mut used);
+        }
+        match cycle {
+            Cycle::Definition(Token { value: name, .. }, _, _, _, _) => graph.calls.get_mut(name).expect("every definition was added").extend(used),
+            _ => graph.entry.extend(used),
+        }
+    }
+    graph
+}
+
+fn words(factors: &[Factor], definitions: &BTreeMap<String, BTreeSet<String>>, used: &mut BTreeSet<String>) {
+    for factor in factors {
+        match factor {
+            Factor::Identifier(name, _) if definitions.contains_key(name.as_str()) => {
+                used.insert(name.to_string());
+            }
+            Factor::Quotation(inner, _) | Factor::Set(inner, _) => words(inner, definitions, used),
+            _ => {}
+        }
+    }
+}
+
+impl CallGraph {
+    /// The definitions nothing reachable from the entry points or from `main` uses, in the order they're
+    /// defined. Code only run through `dynamic` or `words` isn't seen, so these are candidates for removal
+    /// rather than certainly dead.
+    pub fn unreachable(&self, main: &str) -> Vec<&str> {
+        let mut reached: BTreeSet<&str> = BTreeSet::new();
+        let mut pending: Vec<&str> = self.entry.iter().map(String::as_str).collect();
+        if self.calls.contains_key(main) {
+            pending.push(main);
+        }
+        while let Some(name) = pending.pop() {
+            if reached.insert(name) {
+                pending.extend(self.calls[name].iter().map(String::as_str));
+            }
+        }
+        self.definitions.iter().map(String::as_str).filter(|name| !reached.contains(name)).collect()
+    }
+
+    /// The graph in Graphviz's DOT language, with the entry points in bold.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph calls {\n");
+        for name in &self.definitions {
+            if self.entry.contains(name) {
+                out.push_str(&format!("  {} [style=bold];\n", quote(name)));
+            } else {
+                out.push_str(&format!("  {};\n", quote(name)));
+            }
+        }
+        for name in &self.definitions {
+            for callee in &self.calls[name] {
+                out.push_str(&format!("  {} -> {};\n", quote(name), quote(callee)));
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// The graph as pretty-printed JSON.
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a call graph is always serializable")
+    }
+}
+
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::parse;
+    use super::call_graph;
+
+    const SOURCE: &str = "\
+def sq: (Int -> Int) = dup *;
+def quad: (Int -> Int) = [sq] keep drop sq;
+def unused: (Int -> Int) = sq 1 +;
+def main: Int = 2 quad;
+test \"sq\" { 3 sq expect 9 }";
+
+    #[test]
+    fn records_calls_between_definitions() {
+        let graph = call_graph(&parse(SOURCE).unwrap());
+        assert_eq!(graph.definitions, vec!["sq", "quad", "unused", "main"]);
+        assert_eq!(graph.calls["quad"].iter().collect::<Vec<_>>(), vec!["sq"]);
+        assert!(graph.calls["sq"].is_empty());
+        assert_eq!(graph.entry.iter().collect::<Vec<_>>(), vec!["sq"]);
+        assert_eq!(graph.unreachable("main"), vec!["unused"]);
+        assert_eq!(graph.unreachable("nope"), vec!["quad", "unused", "main"]);
+    }
+
+    #[test]
+    fn renders_dot() {
+        let graph = call_graph(&parse(SOURCE).unwrap());
+        assert_eq!(graph.to_dot(), "\
+digraph calls {
+  \"sq\" [style=bold];
+  \"quad\";
+  \"unused\";
+  \"main\";
+  \"quad\" -> \"sq\";
+  \"unused\" -> \"sq\";
+  \"main\" -> \"quad\";
+}
+");
+    }
+}
@@ -0,0 +1,496 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use crate::abstract_interpreter::AbstractInterpreter;
+use crate::error::{Error, Warning};
+use crate::format::type_to_annotation;
+use crate::parser::{let_names, parse, modified_def, Cycle, Factor};
+use crate::scanner::{columns, scan, utf16_position, Span, Token, TokenKind, COMBINATORS};
+use crate::symbol::Symbol;
+use crate::typechecker::{Environment, Type, TypeChecker};
+use crate::{compile, Engine};
+
+/// What a piece of source is, for highlighting.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum SemanticClass {
+    Keyword,
+    /// A core combinator or builtin word, like `dup` or `+`.
+    Builtin,
+    /// A word defined in the source or in the engine.
+    Word,
+    /// A word that isn't defined anywhere.
+    Unknown,
+    Literal,
+    /// A type name in an annotation.
+    Type,
+    Comment,
+    Punctuation,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct SemanticToken {
+    pub span: Span,
+    pub line: usize,
+    pub col: usize,
+    pub class: SemanticClass,
+}
+
+/// Classify every token and comment in `source`, in order. Words are looked up in the source's own
+/// definitions and in `engine`, so words from files loaded earlier count as defined.
+pub fn semantic_tokens(source: &str, engine: &Engine) -> Result<Vec<SemanticToken>, Error> {
+    let tokens = scan(source)?;
+    let defined = defined_names(&tokens);
+    let builtins = TypeChecker::new();
+    let mut result = Vec::new();
+    let mut offset = 0;
+    let (mut line, mut col) = (1, 1);
+    // Whether we're between the `:` and `=` of a definition.
+    let mut in_annotation = false;
+    // The names bound by each `let` we're inside, and how many of the innermost one's are still to come.
+    let mut locals: Vec<Vec<&str>> = Vec::new();
+    let mut naming = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        comments(source, offset, token.span.start, (line, col), &mut result);
+        offset = token.span.end;
+        (line, col) = (token.line, token.col + columns(&token.value));
+        let local = if token.kind != TokenKind::Identifier || in_annotation {
+            None
+        } else if let Some(count) = let_names(tokens[i..].iter().map(|token| (token.kind, token.value.as_str()))).filter(|_| naming == 0) {
+            locals.push(Vec::new());
+            naming = count + 1;
+            Some(SemanticClass::Keyword)
+        } else if modified_def(tokens[i..].iter().map(|token| (token.kind, token.value.as_str()))) {
+            Some(SemanticClass::Keyword)
+        } else if naming > 1 {
+            locals.last_mut().unwrap().push(&token.value);
+            naming -= 1;
+            Some(SemanticClass::Word)
+        } else if naming == 1 || (token.value == "end" && !locals.is_empty()) {
+            if naming == 0 {
+                locals.pop();
+            }
+            naming = 0;
+            Some(SemanticClass::Keyword)
+        } else {
+            locals.iter().flatten().any(|name| *name == token.value).then_some(SemanticClass::Word)
+        };
+        let class = local.unwrap_or_else(|| match token.kind {
+            TokenKind::Keyword => SemanticClass::Keyword,
+            TokenKind::Combinator => SemanticClass::Builtin,
+            TokenKind::Integer(_) | TokenKind::Boolean(_) | TokenKind::String | TokenKind::Bytes => SemanticClass::Literal,
+            TokenKind::DocComment => SemanticClass::Comment,
+            TokenKind::Punct | TokenKind::Unknown => {
+                let overriding = i >= 3 && tokens[i - 3].value == "def" && tokens[i - 2].value == "override";
+                if token.value == ":" && i >= 2 && (tokens[i - 2].value == "def" || overriding) {
+                    in_annotation = true;
+                }
+                SemanticClass::Punctuation
+            }
+            TokenKind::Identifier if in_annotation && token.value == "=" => {
+                in_annotation = false;
+                SemanticClass::Punctuation
+            }
+            TokenKind::Identifier if in_annotation && (token.value == "->" || token.value == "!") => SemanticClass::Punctuation,
+            TokenKind::Identifier if in_annotation => SemanticClass::Type,
+            TokenKind::Identifier if builtins.type_of(&token.value).is_some() => SemanticClass::Builtin,
+            TokenKind::Identifier if defined.contains(token.value.as_str()) || engine.type_of(&token.value).is_some() => {
+                SemanticClass::Word
+            }
+            TokenKind::Identifier => SemanticClass::Unknown,
+        });
+        result.push(SemanticToken { span: token.span, line: token.line, col: token.col, class });
+    }
+    comments(source, offset, source.len(), (line, col), &mut result);
+    Ok(result)
+}
+
+/// An occurrence of a word, in the file with the given index.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Reference {
+    pub file: usize,
+    pub span: Span,
+    /// Whether this is the name in the word's `def`, rather than a use of it.
+    pub definition: bool,
+}
+
+/// A replacement of the text at `span` in the file with the given index.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Edit {
+    pub file: usize,
+    pub span: Span,
+    pub text: String,
+}
+
+/// Every occurrence of the word `name` across `sources`, in order.
+pub fn references(sources: &[&str], name: &str) -> Result<Vec<Reference>, Error> {
+    let mut references = Vec::new();
+    for (file, source) in sources.iter().enumerate() {
+        for cycle in parse(source)? {
+            match cycle {
+                Cycle::Definition(defined, _, body, _, _) => {
+                    if defined.value == name {
+                        references.push(Reference { file, span: defined.span, definition: true });
+                    }
+                    uses(&body, name, file, &mut references);
+                }
+                Cycle::Term(term, _) | Cycle::Macro(_, term, _) => uses(&term, name, file, &mut references),
+                Cycle::Test(_, body, expected, _) => {
+                    uses(&body, name, file, &mut references);
+                    uses(expected.as_deref().unwrap_or_default(), name, file, &mut references);
+                }
+            }
+        }
+    }
+    Ok(references)
+}
+
+fn uses(factors: &[Factor], name: &str, file: usize, into: &mut Vec<Reference>) {
+    for factor in factors {
+        match factor {
+            Factor::Identifier(word, token) if word == name => {
+                into.push(Reference { file, span: token.span, definition: false });
+            }
+            Factor::Quotation(inner, _) => uses(inner, name, file, into),
+            _ => {}
+        }
+    }
+}
+
+/// The edits that rename the word `old` to `new` across `sources`. Fails if `new` isn't a valid name, or
+/// would collide with a builtin or another definition, either in the sources or in `engine`.
+pub fn rename(sources: &[&str], engine: &Engine, old: &str, new: &str) -> Result<Vec<Edit>, Error> {
+    if TokenKind::classify(new) != TokenKind::Identifier || matches!(new, "Int" | "Bool" | "String") {
+        return Err(Error::RefactorError(format!("`{}` isn't a valid name for a word", new)));
+    }
+    if TypeChecker::new().type_of(new).is_some() {
+        return Err(Error::RefactorError(format!("`{}` is a builtin word", new)));
+    }
+    let taken = engine.type_of(new).is_some() || sources.iter().any(|source| {
+        scan(source).is_ok_and(|tokens| defined_names(&tokens).contains(new))
+    });
+    if taken {
+        return Err(Error::RefactorError(format!("`{}` is already defined", new)));
+    }
+    let references = references(sources, old)?;
+    if references.is_empty() {
+        return Err(Error::RefactorError(format!("`{}` isn't used or defined anywhere", old)));
+    }
+    Ok(references.into_iter()
+        .map(|reference| Edit { file: reference.file, span: reference.span, text: new.to_string() })
+        .collect())
+}
+
+/// Apply the edits for one file to its source. Edits must not overlap.
+pub fn apply_edits(source: &str, edits: &[&Edit]) -> String {
+    let mut edits = edits.to_vec();
+    edits.sort_by_key(|edit| edit.span.start);
+    let mut result = String::new();
+    let mut offset = 0;
+    for edit in edits {
+        result.push_str(&source[offset..edit.span.start]);
+        result.push_str(&edit.text);
+        offset = edit.span.end;
+    }
+    result.push_str(&source[offset..]);
+    result
+}
+
+/// The stack after a factor, to be shown just after it.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct InlayHint {
+    /// The span of the factor the hint follows.
+    pub span: Span,
+    pub stack: Vec<Type>,
+}
+
+impl InlayHint {
+    /// The hint as it would be shown, Forth-style, e.g. `-- Int Int`.
+    pub fn label(&self) -> String {
+        let mut label = "--".to_string();
+        for t in &self.stack {
+            label.push(' ');
+            label.push_str(&t.to_string());
+        }
+        label
+    }
+}
+
+/// Hints with the abstract stack after every top-level factor of each term and definition body in `source`.
+/// Words are typed from the source's own annotations and from `engine`. A term stops getting hints at the
+/// first factor the abstract interpreter doesn't support.
+pub fn inlay_hints(source: &str, engine: &Engine) -> Result<Vec<InlayHint>, Error> {
+    let cycles = parse(source)?;
+    let environment = environment(&cycles, engine)?;
+    let mut hints = Vec::new();
+    for cycle in &cycles {
+        let factors = match cycle {
+            Cycle::Definition(_, _, body, _, _) => body,
+            Cycle::Term(term, _) | Cycle::Macro(_, term, _) => term,
+            Cycle::Test(_, body, _, _) => body,
+        };
+        let states = AbstractInterpreter::with_environment(environment.clone()).trace_stack(factors);
+        hints.extend(factors.iter().zip(states).map(|(factor, stack)| InlayHint { span: factor.span(), stack }));
+    }
+    Ok(hints)
+}
+
+/// A place in a source as editors count it: the line from 0, and the character from 0 in UTF-16 code units.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Position {
+    pub line: usize,
+    pub character: usize,
+}
+
+impl Position {
+    /// The position of byte `offset` of `source`.
+    pub fn at(source: &str, offset: usize) -> Position {
+        let (line, character) = utf16_position(source, offset);
+        Position { line, character }
+    }
+
+    /// The positions of the start and end of `span` in `source`.
+    pub fn range(source: &str, span: Span) -> (Position, Position) {
+        (Position::at(source, span.start), Position::at(source, span.end))
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem with a source, to be shown where it is.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// The start and end of the token the problem is at, if it's known.
+    pub range: Option<(Position, Position)>,
+}
+
+/// The problems with `source`: what stops it compiling, or checking against the words in `engine`, or the
+/// warnings about it if it checks.
+pub fn diagnostics(source: &str, engine: &Engine) -> Vec<Diagnostic> {
+    let range = |token: &Token| Position::range(source, token.span);
+    let error = |error: Error| Diagnostic { severity: Severity::Error, range: error.token().map(range), message: error.to_string() };
+    let warning = |warning: &Warning| Diagnostic { severity: Severity::Warning, range: Some(range(warning.token())), message: warning.to_string() };
+    let program = match compile(source) {
+        Ok(program) => program,
+        Err(errors) => return errors.into_iter().map(error).collect(),
+    };
+    let mut checker = engine.typechecker.clone();
+    match checker.check(program.cycles()) {
+        Ok(_) => checker.warnings().iter().map(warning).collect(),
+        Err(e) => vec![error(e)],
+    }
+}
+
+/// The type of a cycle, to be shown while hovering over it.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Hover {
+    /// The span of the cycle.
+    pub span: Span,
+    /// Where the cycle starts and ends.
+    pub range: (Position, Position),
+    /// The cycle's name or source, then its type, like `sq : (Int -> Int)`.
+    pub text: String,
+}
+
+/// The type of the cycle at byte `offset` of `source`, if there's one there. The cycles up to it are checked in
+/// order against the words in `engine`, and its type is the one the typechecker gives it.
+pub fn hover(source: &str, engine: &Engine, offset: usize) -> Result<Option<Hover>, Error> {
+    let cycles = parse(source)?;
+    let Some(index) = cycles.iter().position(|cycle| cycle.span().start <= offset && offset < cycle.span().end) else {
+        return Ok(None);
+    };
+    let types = engine.typechecker.clone().check(&cycles[..=index])?;
+    let cycle = &cycles[index];
+    Ok(types.last().map(|t| Hover {
+        span: cycle.span(),
+        range: Position::range(source, cycle.span()),
+        text: format!("{} : {}", cycle.label(), t),
+    }))
+}
+
+/// An edit replacing the annotation of the definition `name` with the type inferred from its body, if the two
+/// differ. There's no edit if they already agree, or if the inferred type can't be written as an annotation.
+pub fn annotation_fix(source: &str, engine: &Engine, name: &str) -> Result<Option<Edit>, Error> {
+    let cycles = parse(source)?;
+    let environment = environment(&cycles, engine)?;
+    let Some((annotation, body)) = cycles.iter().find_map(|cycle| match cycle {
+        Cycle::Definition(Token { value: defined, .. }, annotation, body, _, _) if defined == name => Some((annotation, body)),
+        _ => None,
+    }) else {
+        return Err(Error::RefactorError(format!("`{}` isn't defined", name)));
+    };
+    let inferred = AbstractInterpreter::with_environment(environment.clone()).interpret(body)?;
+    // An annotation like `Int` is shorthand for a term that takes nothing and pushes an Int.
+    let declared = match &*environment[&Symbol::intern(name)] {
+        Type::Function(t_in, t_out) => Type::Function(t_in.clone(), t_out.clone()),
+        t => Type::Function(vec![], vec![t.clone()]),
+    };
+    if inferred == declared {
+        return Ok(None);
+    }
+    Ok(type_to_annotation(&inferred).map(|text| Edit { file: 0, span: annotation.span(), text }))
+}
+
+/// The engine's environment with the declared types of the definitions in `cycles` added.
+fn environment(cycles: &[Cycle], engine: &Engine) -> Result<Environment, Error> {
+    let mut environment = engine.typechecker.environment().clone();
+    for cycle in cycles {
+        if let Cycle::Definition(Token { value: name, .. }, annotation, _, _, _) = cycle {
+            let t = engine.typechecker.type_from_annotation(annotation)?;
+            environment.insert_mut(Symbol::intern(name), Arc::new(t));
+        }
+    }
+    Ok(environment)
+}
+
+/// The names defined in a token stream, found by looking for `def` followed by a name, or by `override` and a name.
+fn defined_names(tokens: &[Token]) -> HashSet<&str> {
+    tokens.windows(3)
+        .filter(|window| window[0].kind == TokenKind::Keyword && window[0].value == "def")
+        .map(|window| if window[1].value == "override" && window[2].value != ":" { &window[2] } else { &window[1] })
+        .map(|name| name.value.as_str())
+        .filter(|name| !COMBINATORS.contains(name))
+        .collect()
+}
+
+/// Find plain comments between two tokens. The scanner drops them, but the gaps between tokens can only hold
+/// whitespace and comments, so any `#` in a gap starts one.
+fn comments(source: &str, start: usize, end: usize, (mut line, mut col): (usize, usize), into: &mut Vec<SemanticToken>) {
+    let mut comment: Option<(usize, usize, usize)> = None;
+    for (i, c) in source[start..end].char_indices() {
+        let index = start + i;
+        match c {
+            '\n' => {
+                if let Some((begin, begin_line, begin_col)) = comment.take() {
+                    into.push(SemanticToken { span: Span::new(begin, index), line: begin_line, col: begin_col, class: SemanticClass::Comment });
+                }
+                line += 1;
+                col = 1;
+                continue;
+            }
+            '#' if comment.is_none() => comment = Some((index, line, col)),
+            _ => {}
+        }
+        col += 1;
+    }
+    if let Some((begin, begin_line, begin_col)) = comment {
+        into.push(SemanticToken { span: Span::new(begin, end), line: begin_line, col: begin_col, class: SemanticClass::Comment });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Engine;
+    use super::{annotation_fix, apply_edits, diagnostics, hover, inlay_hints, references, rename, semantic_tokens, Position, SemanticClass, Severity};
+
+    fn classes(source: &str, engine: &Engine) -> Vec<(String, SemanticClass)> {
+        semantic_tokens(source, engine).unwrap().into_iter()
+            .map(|token| (source[token.span.start..token.span.end].to_string(), token.class))
+            .collect()
+    }
+
+    #[test]
+    fn classifies_tokens() {
+        use SemanticClass::*;
+        let source = "# squares\ndef sq: (Int -> Int) = dup * ; # done\n2 sq nope \"s\"";
+        let expected = vec![
+            ("# squares", Comment), ("def", Keyword), ("sq", Word), (":", Punctuation), ("(", Punctuation),
+            ("Int", Type), ("->", Punctuation), ("Int", Type), (")", Punctuation), ("=", Punctuation),
+            ("dup", Builtin), ("*", Builtin), (";", Punctuation), ("# done", Comment), ("2", Literal), ("sq", Word),
+            ("nope", Unknown), ("\"s\"", Literal),
+        ];
+        let expected: Vec<_> = expected.into_iter().map(|(text, class)| (text.to_string(), class)).collect();
+        assert_eq!(classes(source, &Engine::new()), expected);
+    }
+
+    #[test]
+    fn knows_words_from_the_engine() {
+        let mut engine = Engine::new();
+        engine.eval_str("def double: (Int -> Int) = 2 *;").unwrap();
+        assert_eq!(classes("1 double", &engine)[1], ("double".to_string(), SemanticClass::Word));
+        let tokens = semantic_tokens("1\n  # note", &engine).unwrap();
+        assert_eq!((tokens[1].line, tokens[1].col, tokens[1].class), (2, 3, SemanticClass::Comment));
+    }
+
+    #[test]
+    fn finds_references_across_files() {
+        let sources = ["def sq: (Int -> Int) = dup *;\n2 sq", "3 [sq] call test \"t\" { 1 sq expect 1 }"];
+        let found: Vec<_> = references(&sources, "sq").unwrap().into_iter()
+            .map(|reference| (reference.file, reference.span.start, reference.definition))
+            .collect();
+        assert_eq!(found, vec![(0, 4, true), (0, 32, false), (1, 3, false), (1, 25, false)]);
+    }
+
+    #[test]
+    fn renames_words() {
+        let sources = ["def sq: (Int -> Int) = dup *;\n2 sq", "3 [sq] call"];
+        let edits = rename(&sources, &Engine::new(), "sq", "square").unwrap();
+        let renamed: Vec<String> = sources.iter().enumerate()
+            .map(|(file, source)| apply_edits(source, &edits.iter().filter(|edit| edit.file == file).collect::<Vec<_>>()))
+            .collect();
+        assert_eq!(renamed, vec!["def square: (Int -> Int) = dup *;\n2 square", "3 [square] call"]);
+    }
+
+    #[test]
+    fn refuses_colliding_renames() {
+        let sources = ["def sq: (Int -> Int) = dup *; def cube: (Int -> Int) = dup sq *;"];
+        let error = |new| rename(&sources, &Engine::new(), "sq", new).unwrap_err().to_string();
+        assert_eq!(error("cube"), "refactor error: `cube` is already defined");
+        assert_eq!(error("dup"), "refactor error: `dup` isn't a valid name for a word");
+        assert_eq!(error("+"), "refactor error: `+` is a builtin word");
+        assert_eq!(error("a]"), "refactor error: `a]` isn't a valid name for a word");
+    }
+
+    #[test]
+    fn hovers_with_the_type_of_the_cycle() {
+        let source = "def inc: (Int -> Int) = 1 +;\n2 inc 3 +\n";
+        let shown = |offset| hover(source, &Engine::new(), offset).unwrap().map(|hover| hover.text);
+        assert_eq!(shown(5), Some("inc : (Int -> Int)".to_string()));
+        assert_eq!(shown(31), Some("2 inc 3 + : ( -> Int)".to_string()));
+        assert_eq!(shown(source.len()), None);
+        let source = "\"🙂\" drop 1";
+        let range = hover(source, &Engine::new(), 8).unwrap().unwrap().range;
+        assert_eq!(range, (Position { line: 0, character: 0 }, Position { line: 0, character: 11 }));
+    }
+
+    #[test]
+    fn places_diagnostics_in_utf16_code_units() {
+        let found = diagnostics("\"é🙂\" nope", &Engine::new());
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].severity, Severity::Error);
+        assert_eq!(found[0].message, "1:6: type error: Unknown identifier nope");
+        assert_eq!(found[0].range, Some((Position { line: 0, character: 6 }, Position { line: 0, character: 10 })));
+        let found = diagnostics("def one: Int = 1;\n\"é\" one", &Engine::new());
+        assert_eq!((found[0].severity, found[0].range.unwrap().0), (Severity::Warning, Position { line: 1, character: 0 }));
+        assert!(diagnostics("1 drop", &Engine::new()).is_empty());
+    }
+
+    #[test]
+    fn hints_the_stack_after_each_factor() {
+        let source = "def inc: (Int -> Int) = 1 +;\n2 inc \"s\" swap [1] 1 2 ifte";
+        let hints: Vec<(String, String)> = inlay_hints(source, &Engine::new()).unwrap().into_iter()
+            .map(|hint| (source[hint.span.start..hint.span.end].to_string(), hint.label()))
+            .collect();
+        let expected = vec![
+            ("1", "-- Int"), ("+", "-- Int"),
+            ("2", "-- Int"), ("inc", "-- Int"), ("\"s\"", "-- Int String"), ("swap", "-- String Int"),
+            ("[1]", "-- String Int ( -> Int)"), ("1", "-- String Int ( -> Int) Int"), ("2", "-- String Int ( -> Int) Int Int"),
+        ];
+        assert_eq!(hints, expected.into_iter().map(|(a, b)| (a.to_string(), b.to_string())).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn fixes_wrong_annotations() {
+        let source = "def pair: (Int -> Int) = 1 + \"s\"; def one: Int = 1; def cmp: (Int -> Int) = 1 <;";
+        let fix = |name| annotation_fix(source, &Engine::new(), name).unwrap()
+            .map(|edit| apply_edits(source, &[&edit]));
+        assert_eq!(fix("pair").unwrap(), "def pair: (Int -> Int, String) = 1 + \"s\"; def one: Int = 1; def cmp: (Int -> Int) = 1 <;");
+        assert_eq!(fix("one"), None);
+        assert_eq!(fix("cmp").unwrap(), "def pair: (Int -> Int) = 1 + \"s\"; def one: Int = 1; def cmp: (Int -> Bool) = 1 <;");
+        assert!(annotation_fix(source, &Engine::new(), "nope").is_err());
+    }
+}
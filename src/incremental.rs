@@ -0,0 +1,287 @@
+use std::collections::HashSet;
+use std::ops::Range;
+use crate::error::{Error, Warning};
+use crate::format::annotation_to_source;
+use crate::parser::{parse, Cycle, Factor};
+use crate::scanner::{columns, Span, Token};
+use crate::typechecker::TypeChecker;
+
+/// The cycles after an edit, which of them were reparsed, how many old cycles those replaced, and the words whose
+/// annotations changed.
+type Reparse = (Result<Vec<Cycle>, Error>, Range<usize>, usize, HashSet<String>);
+
+/// A source file kept parsed and checked as it is edited, for editors. Edits only reparse the cycles they touch,
+/// and only recheck those cycles and the ones that use a word whose annotation changed.
+pub struct Document {
+    source: String,
+    parsed: Result<Vec<Cycle>, Error>,
+    /// The result of checking each cycle on its own, in the same order as the cycles.
+    results: Vec<Result<Vec<Warning>, Error>>,
+    reparsed: usize,
+    rechecked: usize,
+}
+
+impl Document {
+    pub fn new(source: &str) -> Document {
+        let mut document = Document {
+            source: source.to_string(),
+            parsed: parse(source),
+            results: Vec::new(),
+            reparsed: 0,
+            rechecked: 0,
+        };
+        document.reparsed = document.cycles().len();
+        document.recheck(&(0..document.reparsed), 0, &HashSet::new());
+        document
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The parsed cycles, or the error that stopped the source from parsing.
+    pub fn parsed(&self) -> &Result<Vec<Cycle>, Error> {
+        &self.parsed
+    }
+
+    /// The parsed cycles, or none if the source doesn't parse.
+    pub fn cycles(&self) -> &[Cycle] {
+        self.parsed.as_deref().unwrap_or_default()
+    }
+
+    pub fn results(&self) -> &[Result<Vec<Warning>, Error>] {
+        &self.results
+    }
+
+    /// How many cycles the last edit reparsed and rechecked, to see how much work it took.
+    pub fn last_edit_cost(&self) -> (usize, usize) {
+        (self.reparsed, self.rechecked)
+    }
+
+    /// Replace the bytes in `range` with `text`.
+    pub fn edit(&mut self, range: Range<usize>, text: &str) {
+        let old_source = self.source.clone();
+        self.source.replace_range(range.clone(), text);
+        let old = std::mem::replace(&mut self.parsed, Ok(Vec::new()));
+        let (parsed, reparsed, replaced, changed) = match old {
+            Ok(cycles) => match self.reparse(&old_source, &cycles, &range, text) {
+                Some(result) => result,
+                None => self.full_reparse(cycles.len()),
+            },
+            Err(_) => self.full_reparse(0),
+        };
+        self.parsed = parsed;
+        self.reparsed = reparsed.len();
+        self.recheck(&reparsed, replaced, &changed);
+    }
+
+    fn full_reparse(&self, replaced: usize) -> Reparse {
+        let parsed = parse(&self.source);
+        let count = parsed.as_ref().map_or(0, Vec::len);
+        (parsed, 0..count, replaced, HashSet::new())
+    }
+
+    /// Reparse just the region of the source around the edit, keeping the cycles before and after it. Returns
+    /// `None` if a full reparse is needed to be sure of getting the same result.
+    fn reparse(&mut self, old_source: &str, cycles: &[Cycle], range: &Range<usize>, text: &str) -> Option<Reparse> {
+        let first = cycles.iter().position(|cycle| cycle.span().end >= range.start).unwrap_or(cycles.len());
+        let mut last = cycles.iter().rposition(|cycle| cycle.span().start <= range.end).map_or(0, |i| i + 1);
+        // An edit between cycles can still change the doc comment of the next one.
+        last = last.max((first + 1).min(cycles.len()));
+        // Cycles on the same line as the end of the edit have their columns moved, so they're reparsed too.
+        let end_line = line_col(old_source, range.end).0;
+        while last < cycles.len() && line_col(old_source, cycles[last].span().start).0 == end_line {
+            last += 1;
+        }
+
+        let start = if first > 0 { cycles[first - 1].span().end } else { 0 };
+        let old_end = cycles.get(last).map_or(old_source.len(), |cycle| cycle.span().start);
+        let bytes = text.len() as isize - range.len() as isize;
+        let end = (old_end as isize + bytes) as usize;
+        let region = &self.source[start..end];
+        let mut reparsed = parse(region).ok()?;
+        let (line, col) = line_col(&self.source, start);
+        for cycle in &mut reparsed {
+            shift_cycle(cycle, &|token| {
+                if token.line == 1 {
+                    token.col += col - 1;
+                }
+                token.line += line - 1;
+                shift_span(&mut token.span, start as isize);
+            }, start as isize);
+        }
+
+        // Terms next to each other would have been parsed as one, and doc comments attach to the definition
+        // after them, so in those cases the region doesn't stand on its own.
+        let is_term = |cycle: Option<&Cycle>| matches!(cycle, Some(Cycle::Term(_, _)));
+        let before = first.checked_sub(1).and_then(|i| cycles.get(i));
+        let after = cycles.get(last);
+        let joins_before = is_term(before) && (is_term(reparsed.first()) || (reparsed.is_empty() && is_term(after)));
+        let joins_after = is_term(after) && is_term(reparsed.last());
+        let documents_after = matches!(after, Some(Cycle::Definition(_, _, _, _, _))) && region.contains("##");
+        if joins_before || joins_after || documents_after {
+            return None;
+        }
+
+        let lines = text.matches('\n').count() as isize - old_source[range.clone()].matches('\n').count() as isize;
+        let shift = |token: &mut Token| {
+            token.line = (token.line as isize + lines) as usize;
+            shift_span(&mut token.span, bytes);
+        };
+        let mut cycles = cycles.to_vec();
+        for cycle in &mut cycles[last..] {
+            shift_cycle(cycle, &shift, bytes);
+        }
+        for result in &mut self.results[last..] {
+            shift_result(result, &shift);
+        }
+        let changed = changed_declarations(&cycles[first..last], &reparsed);
+        let count = reparsed.len();
+        cycles.splice(first..last, reparsed);
+        Some((Ok(cycles), first..first + count, last - first, changed))
+    }
+
+    /// Check the reparsed cycles, which took the place of `replaced` old ones, and whichever others use a word
+    /// in `changed`, keeping the results of the rest.
+    fn recheck(&mut self, reparsed: &Range<usize>, replaced: usize, changed: &HashSet<String>) {
+        let mut results: Vec<Option<Result<Vec<Warning>, Error>>> = std::mem::take(&mut self.results).into_iter().map(Some).collect();
+        results.splice(reparsed.start..reparsed.start + replaced, reparsed.clone().map(|_| None));
+        let cycles = self.cycles();
+        let mut rechecked = 0;
+        self.results = results.into_iter().zip(cycles).enumerate()
+            .map(|(i, (result, cycle))| match result {
+                Some(result) if !uses_any(cycle, changed) => result,
+                _ => {
+                    rechecked += 1;
+                    check_cycle(&cycles[..i], cycle)
+                }
+            })
+            .collect();
+        self.rechecked = rechecked;
+    }
+}
+
+/// The 1-based line and column of a byte offset.
+fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (line, columns(&before[line_start..]) + 1)
+}
+
+fn shift_span(span: &mut Span, bytes: isize) {
+    span.start = (span.start as isize + bytes) as usize;
+    span.end = (span.end as isize + bytes) as usize;
+}
+
+/// Apply `shift` to every token in a cycle, and move every span that isn't a token's by `bytes`.
+fn shift_cycle(cycle: &mut Cycle, shift: &dyn Fn(&mut Token), bytes: isize) {
+    cycle.visit_positions_mut(&mut |token| shift(token), &mut |span| shift_span(span, bytes));
+}
+
+/// Move the positions in a cycle's diagnostics along with the cycle.
+fn shift_result(result: &mut Result<Vec<Warning>, Error>, shift: &dyn Fn(&mut Token)) {
+    match result {
+        Ok(warnings) => {
+            for warning in warnings {
+                match warning {
+                    Warning::StackUnderflow(_, token) | Warning::LeftoverValues(_, token)
+                        | Warning::DefinitionUnderflow(_, _, _, token) => shift(token),
+                }
+            }
+        }
+        Err(Error::ParseError(_, token) | Error::TypeError(_, token) | Error::UnexpectedToken(_, _, token)
+            | Error::RuntimeError(_, token, _)) => shift(token),
+        Err(_) => {}
+    }
+}
+
+/// The names whose declarations differ between two runs of cycles, including ones only declared in one of them.
+fn changed_declarations(old: &[Cycle], new: &[Cycle]) -> HashSet<String> {
+    let declarations = |cycles: &[Cycle]| -> HashSet<(String, String)> {
+        cycles.iter()
+            .filter_map(|cycle| match cycle {
+                Cycle::Definition(Token { value: name, .. }, annotation, _, _, _) => Some((name.clone(), annotation_to_source(annotation))),
+                _ => None,
+            })
+            .collect()
+    };
+    let (old, new) = (declarations(old), declarations(new));
+    old.symmetric_difference(&new).map(|(name, _)| name.clone()).collect()
+}
+
+fn uses_any(cycle: &Cycle, names: &HashSet<String>) -> bool {
+    fn uses(factors: &[Factor], names: &HashSet<String>) -> bool {
+        factors.iter().any(|factor| match factor {
+            Factor::Identifier(name, _) => names.contains(name.as_str()),
+            Factor::Quotation(inner, _) => uses(inner, names),
+            _ => false,
+        })
+    }
+    !names.is_empty() && match cycle {
+        Cycle::Definition(_, _, body, _, _) => uses(body, names),
+        Cycle::Term(term, _) | Cycle::Macro(_, term, _) => uses(term, names),
+        Cycle::Test(_, body, expected, _) => uses(body, names) || uses(expected.as_deref().unwrap_or_default(), names),
+    }
+}
+
+/// Check a cycle with the declarations of the cycles before it in scope, without checking their bodies.
+fn check_cycle(before: &[Cycle], cycle: &Cycle) -> Result<Vec<Warning>, Error> {
+    let mut checker = TypeChecker::new();
+    for previous in before {
+        if let Cycle::Definition(Token { value: name, .. }, annotation, _, _, _) = previous {
+            // A bad annotation is reported when its own definition is checked.
+            if let Ok(t) = checker.type_from_annotation(annotation) {
+                checker.declare(name, t);
+            }
+        }
+    }
+    checker.check_cycle(cycle)?;
+    Ok(checker.take_warnings())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::parse;
+    use super::Document;
+
+    /// Apply an edit, then check the document matches one made from scratch from the edited source.
+    fn edit(document: &mut Document, find: &str, replace: &str) {
+        let start = document.source().find(find).unwrap();
+        document.edit(start..start + find.len(), replace);
+        assert_eq!(document.parsed(), &parse(document.source()));
+        assert_eq!(document.results(), Document::new(document.source()).results());
+    }
+
+    const SOURCE: &str = "def inc: (Int -> Int) = 1 +;\ndef twice: (Int -> Int) = inc inc;\n\ndef s: String = \"s\";\n1 twice s drop\n";
+
+    #[test]
+    fn reparses_only_the_edited_cycle() {
+        let mut document = Document::new(SOURCE);
+        edit(&mut document, "1 +", "2 +");
+        assert_eq!(document.last_edit_cost(), (1, 1));
+        edit(&mut document, "\"s\"", "\"a longer string\" drop\n\"t\"");
+        assert_eq!(document.last_edit_cost(), (1, 1));
+    }
+
+    #[test]
+    fn rechecks_users_of_changed_annotations() {
+        let mut document = Document::new(SOURCE);
+        edit(&mut document, "def inc: (Int -> Int)", "def inc: (Int -> Bool)");
+        // The term only sees `twice`'s annotation, so it isn't rechecked along with `twice`.
+        assert_eq!(document.last_edit_cost(), (1, 2));
+    }
+
+    #[test]
+    fn falls_back_to_a_full_reparse() {
+        let mut document = Document::new(SOURCE);
+        edit(&mut document, "inc inc;", "inc inc");
+        assert!(document.parsed().is_err());
+        edit(&mut document, "inc inc", "inc inc;");
+        assert!(document.parsed().is_ok());
+        // Joining two lines of terms makes them one term.
+        edit(&mut document, ";\n1 twice", "; 1 twice");
+        edit(&mut document, "1 twice s drop", "1 twice\n## docs\ndef x: Int = 1; s drop");
+        edit(&mut document, "\n## docs\n", " ");
+    }
+}
@@ -0,0 +1,874 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+#![allow(clippy::result_large_err)]
+
+extern crate alloc;
+
+pub mod abstract_interpreter;
+#[cfg(feature = "std")]
+pub mod bench;
+pub mod builtins;
+pub mod convert;
+#[cfg(feature = "std")]
+pub mod coverage;
+pub mod debugger;
+pub mod doc;
+pub mod dump;
+pub mod edition;
+pub mod error;
+pub mod explain;
+pub mod evaluator;
+pub mod expand;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod format;
+pub mod graph;
+#[cfg(feature = "std")]
+pub mod fuzz;
+#[cfg(feature = "std")]
+pub mod ide;
+#[cfg(feature = "std")]
+pub mod incremental;
+#[cfg(feature = "std")]
+pub mod lint;
+#[cfg(feature = "std")]
+pub mod package;
+pub mod parser;
+#[cfg(feature = "std")]
+pub mod profiler;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod random;
+#[cfg(feature = "std")]
+pub mod repl;
+pub mod scanner;
+pub mod symbol;
+#[cfg(feature = "std")]
+pub mod trace;
+#[cfg(feature = "std")]
+pub mod tutorial;
+pub mod typechecker;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use convert::{FromChara, IntoChara};
+pub use error::{Error, Warning};
+pub use evaluator::Value;
+pub use parser::Cycle;
+pub use symbol::Symbol;
+pub use typechecker::Type;
+
+use alloc::{boxed::Box, collections::BTreeMap, format, string::{String, ToString}, sync::Arc, vec, vec::Vec};
+use builtins::{Builtin, Registry};
+use debugger::History;
+use edition::Edition;
+use evaluator::Evaluator;
+use typechecker::{CheckConfig, Effects, Redefinition, TypeChecker};
+
+/// A parsed chara program, ready to be checked and run.
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Program {
+    cycles: Vec<Cycle>,
+    /// The paths of the files a package was built from, which the `file` of each span indexes into. Programs
+    /// compiled from a single source have none.
+    files: Vec<String>,
+    /// The edition the program is written for, which decides the builtins it's checked against.
+    #[cfg_attr(feature = "serde", serde(default))]
+    edition: Edition,
+}
+
+/// Scan and parse `source` into a program, and expand its macros.
+pub fn compile(source: &str) -> Result<Program, Vec<Error>> {
+    let edition = edition::declared(source).map_err(|error| vec![error])?.unwrap_or_default();
+    let cycles = parser::parse(source).and_then(expand::expand).map_err(|error| vec![error])?;
+    Ok(Program { cycles, files: Vec::new(), edition })
+}
+
+/// Scan and parse the source `reader` reads into a program as it's read, for input that's piped in or too big
+/// to read into memory first. Only the lines up to the first that isn't blank are read ahead, for a `#lang`
+/// line, and then parsed with the rest.
+#[cfg(feature = "std")]
+pub fn compile_reader(mut reader: impl std::io::BufRead) -> Result<Program, Vec<Error>> {
+    use std::io::Read;
+    let mut head = String::new();
+    loop {
+        let read = reader.read_line(&mut head).map_err(|error| vec![Error::from(error)])?;
+        if read == 0 || !head.trim().is_empty() {
+            break;
+        }
+    }
+    let edition = edition::declared(&head).map_err(|error| vec![error])?.unwrap_or_default();
+    let cycles = parser::parse_reader(std::io::Cursor::new(head).chain(reader))
+        .collect::<Result<_, _>>()
+        .and_then(expand::expand)
+        .map_err(|error| vec![error])?;
+    Ok(Program { cycles, files: Vec::new(), edition })
+}
+
+impl Program {
+    pub fn cycles(&self) -> &[Cycle] {
+        &self.cycles
+    }
+
+    pub fn edition(&self) -> Edition {
+        self.edition
+    }
+
+    /// A program with only this program's definitions, for using a file as a library.
+    pub fn definitions(&self) -> Program {
+        let cycles = self.cycles.iter()
+            .filter(|cycle| matches!(cycle, Cycle::Definition(_, _, _, _, _)))
+            .cloned()
+            .collect();
+        Program { cycles, files: self.files.clone(), edition: self.edition }
+    }
+
+    /// Typecheck the program, returning any warnings if it is well typed.
+    pub fn check(&self) -> Result<Vec<Warning>, Vec<Error>> {
+        self.check_types().map(|(_, warnings)| warnings)
+    }
+
+    /// Typecheck the program, returning the type inferred for each of its cycles, in order, and any warnings if
+    /// it is well typed.
+    pub fn check_types(&self) -> Result<(Vec<Type>, Vec<Warning>), Vec<Error>> {
+        self.check_with(CheckConfig::default())
+    }
+
+    /// Typecheck the program as `check_types` does, as strictly as `config` says.
+    pub fn check_with(&self, config: CheckConfig) -> Result<(Vec<Type>, Vec<Warning>), Vec<Error>> {
+        let mut typechecker = TypeChecker::with_registry(&Registry::for_edition(self.edition));
+        typechecker.set_config(config);
+        let types = typechecker.check(&self.cycles).map_err(|error| vec![self.locate(error)])?;
+        Ok((types, typechecker.warnings().to_vec()))
+    }
+
+    /// Run every cycle of the program against `engine`, leaving the results on its stack.
+    pub fn run(&self, engine: &mut Engine) -> Result<(), Error> {
+        for cycle in &self.cycles {
+            engine.evaluator.eval_cycle(cycle).map_err(|error| self.locate(error))?;
+            if let Cycle::Definition(scanner::Token { value: name, .. }, _, _, _, Some(docs)) = cycle {
+                engine.docs.insert(name.clone(), docs.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the program, then the word `main` if it defines one, on whatever the program left on the stack.
+    /// `main` can't take inputs, since nothing calls it with any. A program with neither a `main` nor
+    /// any top-level terms would do nothing, so that's an error.
+    pub fn run_main(&self, engine: &mut Engine, main: &str) -> Result<(), Error> {
+        let definition = self.cycles.iter().rev().find_map(|cycle| match cycle {
+            Cycle::Definition(scanner::Token { value: name, .. }, annotation, _, _, _) if name == main => Some(annotation),
+            _ => None,
+        });
+        if definition.is_none() && !self.cycles.iter().any(|cycle| matches!(cycle, Cycle::Term(_, _))) {
+            return Err(Error::NoEntryPoint(main.to_string()));
+        }
+        if let Some(parser::TypeAnnotation::Function(t_in, _, first, _)) = definition.map(|t| t.without_modifiers()) {
+            if !t_in.is_empty() {
+                let types = t_in.iter().map(format::annotation_to_source).collect::<Vec<_>>().join(", ");
+                let message = format!("`{}` is run with nothing to take as inputs, but takes {}", main, types);
+                return Err(self.locate(Error::TypeError(message, first.clone())));
+            }
+        }
+        self.run(engine)?;
+        if definition.is_some() {
+            let word = parser::Factor::Identifier(Symbol::intern(main), scanner::Token::unknown());
+            engine.evaluator.eval_term(&[word]).map_err(|error| self.locate(error))?;
+        }
+        Ok(())
+    }
+
+    /// Typecheck the program once, for running it many times over, possibly from several threads at once.
+    pub fn into_checked(self) -> Result<CheckedProgram, Vec<Error>> {
+        let warnings = self.check()?;
+        let mut evaluator = Evaluator::new();
+        let mut terms = Vec::new();
+        for cycle in &self.cycles {
+            match cycle {
+                Cycle::Definition(scanner::Token { value: name, .. }, _, factors, _, _) => evaluator.define(name, factors.clone()),
+                Cycle::Term(factors, _) => terms.push((evaluator.dictionary().clone(), Arc::new(factors.clone()))),
+                Cycle::Test(_, _, _, _) | Cycle::Macro(_, _, _) => {}
+            }
+        }
+        Ok(CheckedProgram { program: self, warnings, terms })
+    }
+
+    /// Say which file an error is in, for programs built from several.
+    fn locate(&self, error: Error) -> Error {
+        let file = error.token().and_then(|token| token.span.file).and_then(|file| self.files.get(file));
+        match file {
+            Some(path) => Error::InFile(path.clone(), Box::new(error)),
+            None => error,
+        }
+    }
+
+    /// Run each of the program's tests against `engine`, which should already have its definitions.
+    pub fn test(&self, engine: &mut Engine) -> Vec<TestOutcome> {
+        self.cycles.iter()
+            .filter_map(|cycle| match cycle {
+                Cycle::Test(name, body, expected, span) => Some(TestOutcome {
+                    name: name.clone(),
+                    span: *span,
+                    result: engine.evaluator.run_test(body, expected.as_deref()),
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// A program that has been typechecked, with its definitions ready to run. It is `Send + Sync`, and running it
+/// only needs a stack of its own, so a server can check a script once and run it on many threads.
+#[derive(Debug, Clone)]
+pub struct CheckedProgram {
+    program: Program,
+    warnings: Vec<Warning>,
+    /// Each top-level term, with the words defined by the time it runs.
+    terms: Vec<(evaluator::Dictionary, Arc<Vec<parser::Factor>>)>,
+}
+
+impl CheckedProgram {
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// Run the program's terms on an empty stack, returning what they leave on it.
+    pub fn run(&self) -> Result<Vec<Value>, Error> {
+        let mut stack = Vec::new();
+        for (dictionary, factors) in &self.terms {
+            let mut evaluator = Evaluator::with_dictionary(dictionary.clone());
+            *evaluator.stack_mut() = stack;
+            evaluator.eval_shared(Arc::clone(factors)).map_err(|error| self.program.locate(error))?;
+            stack = core::mem::take(evaluator.stack_mut());
+        }
+        Ok(stack)
+    }
+}
+
+/// The result of running one of a program's tests.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct TestOutcome {
+    pub name: String,
+    pub span: scanner::Span,
+    pub result: Result<(), Error>,
+}
+
+/// The typing environment and runtime state programs are run against. Definitions stay in scope across
+/// calls, so code can be fed in incrementally.
+pub struct Engine {
+    typechecker: TypeChecker,
+    evaluator: Evaluator,
+    /// Doc comments of the builtins and of the definitions that have been run, by name.
+    docs: BTreeMap<String, String>,
+    /// The program being run through `resume`, and the index of its next cycle.
+    running: Option<(Program, usize)>,
+    /// The steps `resume` has taken, if they're being kept, with the index of the cycle after each one's.
+    history: Option<History>,
+}
+
+/// How far a call to `Engine::resume` got.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Progress {
+    /// The program has finished running.
+    Done,
+    /// The steps ran out with more of the program left; `resume` carries on from there.
+    Pending,
+    /// A `breakpoint` ran, and `resume` carries on from after it.
+    Breakpoint,
+}
+
+impl Engine {
+    /// An engine with nothing defined. Words can be defined again, replacing what they were, as in the REPL.
+    pub fn new() -> Engine {
+        Self::with_registry(Registry::standard())
+    }
+
+    /// An engine with only the builtins in `registry`, such as chara's own with some taken out or added.
+    pub fn with_registry(registry: Registry) -> Engine {
+        let mut typechecker = TypeChecker::with_registry(&registry);
+        typechecker.set_redefinition(Redefinition::Allow);
+        let mut engine = Engine {
+            typechecker,
+            evaluator: Evaluator::new(),
+            docs: BTreeMap::new(),
+            running: None,
+            history: None,
+        };
+        engine.evaluator.limit_builtins([]);
+        for builtin in registry.into_builtins() {
+            engine.add_builtin(builtin);
+        }
+        engine
+    }
+
+    pub fn stack(&self) -> &[Value] {
+        self.evaluator.stack()
+    }
+
+    /// The type of a word that has been checked by this engine, or is built in.
+    pub fn type_of(&self, name: &str) -> Option<&Type> {
+        self.typechecker.type_of(name)
+    }
+
+    /// The type of each cycle of `program`, checked against the words this engine has without defining any of
+    /// them, as the REPL's `:type` shows.
+    pub fn infer(&self, program: &Program) -> Result<Vec<Type>, Vec<Error>> {
+        self.typechecker.clone().check(&program.cycles).map_err(|error| vec![program.locate(error)])
+    }
+
+    /// Every definition that has been run, with its type, sorted by name.
+    pub fn words(&self) -> Vec<(String, Type)> {
+        self.evaluator.definitions().into_iter()
+            .filter_map(|name| Some((name.to_string(), self.type_of(name)?.clone())))
+            .collect()
+    }
+
+    /// Every word starting with `prefix` that could be used here, with its type, sorted by name. This covers
+    /// definitions checked by this engine, builtin words, and the core combinators.
+    pub fn complete(&self, prefix: &str) -> Vec<(String, Type)> {
+        let mut words: Vec<(String, Type)> = self.typechecker.words()
+            .filter(|(name, _)| name.starts_with(prefix))
+            .map(|(name, t)| (name.to_string(), t.clone()))
+            .collect();
+        for combinator in scanner::COMBINATORS.iter().filter(|name| name.starts_with(prefix)) {
+            // Combinators are factors rather than words in the environment, so their types are worked out
+            // the same way a term's would be, with a fresh checker so their parameters start from `a`.
+            let cycle = &parser::parse(combinator).expect("combinators always parse")[0];
+            if let Ok(t) = TypeChecker::new().check_cycle(cycle) {
+                words.push((combinator.to_string(), t));
+            }
+        }
+        words.sort_by(|(a, _), (b, _)| a.cmp(b));
+        words
+    }
+
+    /// The signature of a word followed by its doc comment, if it has one.
+    pub fn help(&self, name: &str) -> Option<String> {
+        let t = self.type_of(name)?;
+        match self.docs.get(name) {
+            Some(docs) => Some(format!("{} : {}\n\n{}", name, t, docs)),
+            None => Some(format!("{} : {}", name, t)),
+        }
+    }
+
+    /// Typecheck `program` against the definitions seen so far, adding its definitions if it is well typed.
+    pub fn check(&mut self, program: &Program) -> Result<Vec<Warning>, Vec<Error>> {
+        self.typechecker.check_atomically(&program.cycles).map_err(|error| {
+            self.typechecker.take_warnings();
+            vec![error]
+        })?;
+        Ok(self.typechecker.take_warnings())
+    }
+
+    pub fn add_observer(&mut self, observer: Box<dyn evaluator::Observer>) {
+        self.evaluator.add_observer(observer);
+    }
+
+    /// Whether programs checked by this engine can define words it already has. They can unless this says otherwise.
+    pub fn set_redefinition(&mut self, redefinition: Redefinition) {
+        self.typechecker.set_redefinition(redefinition);
+    }
+
+    /// How strictly programs checked by this engine are checked.
+    pub fn set_check_config(&mut self, config: CheckConfig) {
+        self.typechecker.set_config(config);
+    }
+
+    /// Bring a type constructor that takes `arity` type arguments into scope, for the types of registered words.
+    pub fn declare_type(&mut self, name: &str, arity: usize) {
+        self.typechecker.declare_type(name, arity);
+    }
+
+    /// Only let programs checked by this engine use words with `allowed` effects, such as only pure ones for
+    /// untrusted code.
+    pub fn set_allowed_effects(&mut self, allowed: Effects) {
+        self.typechecker.set_allowed_effects(allowed);
+    }
+
+    /// Start `random-int` and `random-bool` over from `seed`, so a program using them can be run reproducibly.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.evaluator.set_seed(seed);
+    }
+
+    /// Define a word implemented in Rust, which programs are checked against as having type `t`. It should
+    /// pop the inputs and push the outputs `t` says it has; an error it returns stops evaluation there.
+    /// There's no telling what Rust does, so it's taken to do IO, unless it's registered with other effects.
+    pub fn register(&mut self, name: &str, t: Type, word: impl FnMut(&mut Vec<Value>) -> Result<(), String> + 'static) {
+        self.register_with_effects(name, t, Effects::IO, word);
+    }
+
+    /// Define a word implemented in Rust, like `register`, that has `effects`.
+    pub fn register_with_effects(&mut self, name: &str, t: Type, effects: Effects, word: impl FnMut(&mut Vec<Value>) -> Result<(), String> + 'static) {
+        self.add_builtin(Builtin::native(name, t, "", word).with_effects(effects));
+    }
+
+    /// Bring a builtin into scope, replacing any word of the same name. One without a Rust implementation has to
+    /// be one the evaluator runs itself.
+    pub fn add_builtin(&mut self, builtin: Builtin) {
+        self.typechecker.declare(&builtin.name, builtin.t);
+        self.typechecker.declare_effects(&builtin.name, builtin.effects);
+        if !builtin.doc.is_empty() {
+            self.docs.insert(builtin.name.clone(), builtin.doc);
+        }
+        match builtin.native {
+            Some(word) => self.evaluator.define_native(&builtin.name, word),
+            None => self.evaluator.allow_builtin(&builtin.name),
+        }
+    }
+
+    /// Push a Rust value onto the stack. Tuples push each of their elements.
+    pub fn push<T: IntoChara>(&mut self, value: T) {
+        value.push_onto(self.evaluator.stack_mut());
+    }
+
+    /// Pop a Rust value off the stack. If the conversion fails, the values it took are put back, so the stack
+    /// is left untouched.
+    pub fn pop_as<T: FromChara>(&mut self) -> Result<T, Error> {
+        let stack = self.evaluator.stack_mut();
+        let start = stack.len().saturating_sub(T::VALUES);
+        let taken = stack[start..].to_vec();
+        T::pop_from(stack).inspect_err(|_| {
+            stack.truncate(start);
+            stack.extend(taken);
+        })
+    }
+
+    /// Check `program`, and if it is well typed, set it up to be run a bit at a time by `resume`, so a GUI or
+    /// async server can do other work in between. Whatever was running before is abandoned.
+    pub fn start(&mut self, program: Program) -> Result<Vec<Warning>, Vec<Error>> {
+        let warnings = self.check(&program)?;
+        self.evaluator.abort();
+        self.running = Some((program, 0));
+        if let Some(history) = &mut self.history {
+            history.start(&mut self.evaluator);
+        }
+        Ok(warnings)
+    }
+
+    /// Keep the last `limit` steps `resume` takes from here on, so `back` can undo them, or stop keeping them if
+    /// `limit` is zero.
+    pub fn keep_history(&mut self, limit: usize) {
+        self.history = (limit > 0).then(|| {
+            let mut history = History::new(limit);
+            history.start(&mut self.evaluator);
+            history
+        });
+    }
+
+    /// The steps being kept, oldest first.
+    pub fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+
+    /// Undo the last step that was kept, going back to where the program was before it. Words it defined stay
+    /// defined. Returns `false` if there's no step to undo.
+    pub fn back(&mut self) -> bool {
+        let Some(next) = self.history.as_mut().and_then(|history| history.back(&mut self.evaluator)) else {
+            return false;
+        };
+        if let Some((_, position)) = &mut self.running {
+            *position = next;
+        }
+        true
+    }
+
+    /// Abandon the program given to `start`, leaving the stack as it is.
+    pub fn stop(&mut self) {
+        self.evaluator.abort();
+        self.running = None;
+    }
+
+    /// The factor the next call to `resume` will run, if it's known.
+    pub fn current_factor(&self) -> Option<&parser::Factor> {
+        self.evaluator.current_factor()
+    }
+
+    /// Run at most `steps` factors of the program given to `start`. An error abandons the rest of it.
+    pub fn resume(&mut self, steps: usize) -> Result<Progress, Error> {
+        let mut steps = steps;
+        loop {
+            if self.evaluator.depth() > 0 {
+                if steps == 0 {
+                    return Ok(Progress::Pending);
+                }
+                steps -= 1;
+                let result = match &mut self.history {
+                    Some(history) => history.step(&mut self.evaluator, self.running.as_ref().map_or(0, |(_, next)| *next)),
+                    None => self.evaluator.step(),
+                };
+                if let Err(error) = result {
+                    return Err(self.fail(error));
+                }
+                if self.evaluator.take_breakpoint() {
+                    return Ok(Progress::Breakpoint);
+                }
+                continue;
+            }
+            let Some((program, next)) = &mut self.running else {
+                return Ok(Progress::Done);
+            };
+            let Some(cycle) = program.cycles.get(*next) else {
+                self.running = None;
+                return Ok(Progress::Done);
+            };
+            *next += 1;
+            match cycle {
+                Cycle::Definition(scanner::Token { value: name, .. }, _, _, _, docs) => {
+                    if let Err(error) = self.evaluator.eval_cycle(cycle) {
+                        return Err(self.fail(error));
+                    }
+                    if let Some(docs) = docs {
+                        self.docs.insert(name.clone(), docs.clone());
+                    }
+                }
+                Cycle::Term(factors, _) => self.evaluator.push_term(factors.clone(), None),
+                Cycle::Test(_, _, _, _) | Cycle::Macro(_, _, _) => {}
+            }
+        }
+    }
+
+    /// Abandon the program given to `start` after it failed with `error`, which is located in it.
+    fn fail(&mut self, error: Error) -> Error {
+        self.evaluator.abort();
+        match self.running.take() {
+            Some((program, _)) => program.locate(error),
+            None => error,
+        }
+    }
+
+    /// Compile, check, and run `source` in this engine.
+    pub fn eval_str(&mut self, source: &str) -> Result<Vec<Warning>, Vec<Error>> {
+        let program = compile(source)?;
+        let warnings = self.check(&program)?;
+        program.run(self).map_err(|error| vec![error])?;
+        Ok(warnings)
+    }
+}
+
+/// What `Engine::snapshot` saves. The version changes whenever this does, so old snapshots are turned away
+/// rather than misread.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Snapshot {
+    version: u32,
+    typechecker: TypeChecker,
+    definitions: BTreeMap<Symbol, Vec<parser::Factor>>,
+    stack: Vec<Value>,
+    docs: BTreeMap<String, String>,
+}
+
+#[cfg(feature = "serde")]
+const SNAPSHOT_VERSION: u32 = 1;
+
+#[cfg(feature = "serde")]
+impl Engine {
+    /// The engine's definitions, their types and docs, and its stack, as bytes for `restore` to pick up from,
+    /// perhaps in another process. Native words can't be saved, so they need registering again after
+    /// restoring, and neither is a program that `start` was given.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let definitions = self.evaluator.dictionary().iter()
+            .map(|(name, factors)| (*name, factors.to_vec()))
+            .collect();
+        let snapshot = Snapshot {
+            version: SNAPSHOT_VERSION,
+            typechecker: self.typechecker.clone(),
+            definitions,
+            stack: self.stack().to_vec(),
+            docs: self.docs.clone(),
+        };
+        serde_json::to_vec(&snapshot).expect("snapshots are always serializable")
+    }
+
+    /// An engine in the state a snapshot was taken in.
+    pub fn restore(bytes: &[u8]) -> Result<Engine, Error> {
+        let snapshot: Snapshot = serde_json::from_slice(bytes).map_err(|error| Error::SnapshotError(error.to_string()))?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            let message = format!("snapshot is version {}, but only version {} can be restored", snapshot.version, SNAPSHOT_VERSION);
+            return Err(Error::SnapshotError(message));
+        }
+        let dictionary = snapshot.definitions.into_iter().map(|(name, factors)| (name, Arc::new(factors))).collect();
+        let mut evaluator = Evaluator::with_dictionary(dictionary);
+        *evaluator.stack_mut() = snapshot.stack;
+        Ok(Engine { typechecker: snapshot.typechecker, evaluator, docs: snapshot.docs, running: None, history: None })
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compile, Builtin, Edition, Effects, Engine, Progress, Registry, Type, Value};
+
+    #[test]
+    fn compiles_checks_and_runs_a_program() {
+        let program = compile("def double: (Int -> Int) = 2 *; 21 double").unwrap();
+        program.check().unwrap();
+        let mut engine = Engine::new();
+        program.run(&mut engine).unwrap();
+        assert_eq!(engine.stack(), &[Value::Int(42)]);
+    }
+
+    #[test]
+    fn reports_the_type_of_each_cycle() {
+        let program = compile("def double: (Int -> Int) = 2 *; 21 double test \"double\" { 1 double expect 2 }").unwrap();
+        let (types, warnings) = program.check_types().unwrap();
+        let types: Vec<String> = types.iter().map(Type::to_string).collect();
+        assert_eq!(types, vec!["(Int -> Int)", "( -> Int)", "( -> Int)"]);
+        assert_eq!(warnings, program.check().unwrap());
+    }
+
+    #[test]
+    fn passes_references_to_definitions_to_combinators() {
+        let mut engine = Engine::new();
+        engine.eval_str("def double: (Int -> Int) = 2 *; 3 &double keep 4 &double 2 apply-n").unwrap();
+        assert_eq!(engine.stack(), &[Value::Int(6), Value::Int(6), Value::Int(8)]);
+        let errors = compile("1 &nope keep").unwrap().check().unwrap_err();
+        assert_eq!(errors.iter().map(ToString::to_string).collect::<Vec<_>>(), vec!["1:4: type error: Unknown identifier nope"]);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn snapshots_and_restores_an_engine() {
+        let mut engine = Engine::new();
+        engine.eval_str("## Doubles.\ndef double: (Int -> Int) = 2 *; 4 double \"kept\"").unwrap();
+        let mut restored = Engine::restore(&engine.snapshot()).unwrap();
+        assert_eq!(restored.stack(), &[Value::Int(8), Value::String("kept".to_string())]);
+        assert_eq!(restored.help("double"), engine.help("double"));
+        restored.eval_str("drop double").unwrap();
+        assert_eq!(restored.stack(), &[Value::Int(16)]);
+
+        assert!(matches!(Engine::restore(b"nope"), Err(super::Error::SnapshotError(_))));
+        let old = String::from_utf8(engine.snapshot()).unwrap().replacen("\"version\":1", "\"version\":0", 1);
+        assert_eq!(
+            Engine::restore(old.as_bytes()).err().unwrap().to_string(),
+            "snapshot error: snapshot is version 0, but only version 1 can be restored",
+        );
+    }
+
+    #[test]
+    fn runs_a_program_a_few_steps_at_a_time() {
+        let mut engine = Engine::new();
+        engine.start(compile("def inc: (Int -> Int) = 1 +; 1 inc inc 10").unwrap()).unwrap();
+        assert_eq!(engine.resume(3), Ok(Progress::Pending));
+        assert_eq!(engine.stack(), &[Value::Int(1), Value::Int(1)]);
+        assert_eq!(engine.resume(3), Ok(Progress::Pending));
+        assert_eq!(engine.resume(3), Ok(Progress::Done));
+        assert_eq!(engine.stack(), &[Value::Int(3), Value::Int(10)]);
+
+        engine.start(compile("1 0 / 2").unwrap()).unwrap();
+        assert!(engine.resume(100).is_err());
+        assert_eq!(engine.resume(100), Ok(Progress::Done));
+    }
+
+    #[test]
+    fn runs_a_checked_program_from_several_threads() {
+        fn shareable<T: Send + Sync>(program: &T) -> &T {
+            program
+        }
+        let checked = compile("def f: (Int -> Int) = 2 *; 21 f def g: (Int -> Int) = f 1 +; 7 g").unwrap().into_checked().unwrap();
+        let checked = shareable(&checked);
+        std::thread::scope(|scope| {
+            let runs: Vec<_> = (0..4).map(|_| scope.spawn(|| checked.run())).collect();
+            for run in runs {
+                assert_eq!(run.join().unwrap(), Ok(vec![Value::Int(42), Value::Int(15)]));
+            }
+        });
+        assert!(compile("1 nope").unwrap().into_checked().is_err());
+    }
+
+    #[test]
+    fn runs_registered_words() {
+        let mut engine = Engine::new();
+        engine.register("halve", Type::Function(vec![Type::Int], vec![Type::Int]), |stack| match stack.pop() {
+            Some(Value::Int(i)) if i % 2 == 0 => {
+                stack.push(Value::Int(i / 2));
+                Ok(())
+            }
+            _ => Err("Can't halve an odd number".to_string()),
+        });
+        engine.eval_str("8 halve halve").unwrap();
+        assert_eq!(engine.stack(), &[Value::Int(2)]);
+        assert_eq!(engine.eval_str("3 halve").unwrap_err()[0].to_string(), "1:3: runtime error: Can't halve an odd number");
+        assert!(engine.eval_str("true halve").is_err());
+    }
+
+    #[test]
+    fn restricts_programs_to_allowed_effects() {
+        let mut engine = Engine::new();
+        engine.register("print", Type::Function(vec![Type::String], vec![]), |stack| stack.pop().map(drop).ok_or_else(|| "Stack underflow".to_string()));
+        engine.register_with_effects("double", Type::Function(vec![Type::Int], vec![Type::Int]), Effects::PURE, |stack| match stack.pop() {
+            Some(Value::Int(i)) => {
+                stack.push(Value::Int(i * 2));
+                Ok(())
+            }
+            _ => Err("Expected Int".to_string()),
+        });
+        engine.set_allowed_effects(Effects::PURE);
+        engine.eval_str("def quad: (Int -> Int) = double double; 2 quad").unwrap();
+        assert_eq!(engine.stack(), &[Value::Int(8)]);
+        let errors = engine.eval_str("def shout: (String -> ) = print; \"hi\" shout");
+        assert_eq!(errors.unwrap_err()[0].to_string(), "1:27: type error: `print` can do io, which isn't allowed here");
+        let errors = engine.eval_str("def loop: ( -> ) = loop;");
+        assert_eq!(errors.unwrap_err()[0].to_string(), "1:20: type error: `loop` can diverge, which isn't allowed here");
+    }
+
+    #[test]
+    fn runs_the_main_word() {
+        let run = |source: &str, main: &str| {
+            let mut engine = Engine::new();
+            compile(source).unwrap().run_main(&mut engine, main).map(|()| engine.stack().to_vec()).map_err(|error| error.to_string())
+        };
+        assert_eq!(run("def double: (Int -> Int) = 2 *; def main: ( -> Int) = 20 double; 1", "main"), Ok(vec![Value::Int(1), Value::Int(40)]));
+        assert_eq!(run("def start: ( -> ) = 1 drop;", "start"), Ok(vec![]));
+        assert_eq!(run("1 2 +", "main"), Ok(vec![Value::Int(3)]));
+        assert_eq!(run("def double: (Int -> Int) = 2 *;", "main"), Err("nothing to run: define a `main` word, or add top-level terms".to_string()));
+        assert_eq!(
+            run("def main: (Int -> Int) = 2 *;", "main"),
+            Err("1:11: type error: `main` is run with nothing to take as inputs, but takes Int".to_string()),
+        );
+    }
+
+    #[test]
+    fn engine_keeps_definitions_between_inputs() {
+        let mut engine = Engine::new();
+        engine.eval_str("def double: (Int -> Int) = 2 *;").unwrap();
+        engine.eval_str("21 double").unwrap();
+        assert_eq!(engine.stack(), &[Value::Int(42)]);
+    }
+
+    #[test]
+    fn engine_forgets_definitions_from_ill_typed_input() {
+        let mut engine = Engine::new();
+        assert!(engine.eval_str("def a: Int = 1; nope").is_err());
+        assert!(engine.eval_str("a").is_err());
+    }
+
+    #[test]
+    fn engine_exchanges_values_with_rust() {
+        let mut engine = Engine::new();
+        engine.push((20i64, 22i64));
+        engine.eval_str("+").unwrap();
+        assert_eq!(engine.pop_as::<i64>().unwrap(), 42);
+        engine.push(true);
+        assert!(engine.pop_as::<String>().is_err());
+        assert!(engine.pop_as::<bool>().unwrap());
+        engine.push((1i64, "a", true));
+        assert!(engine.pop_as::<(i64, i64)>().is_err());
+        assert_eq!(engine.pop_as::<(i64, String, bool)>().unwrap(), (1, "a".to_string(), true));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn round_trips_programs_and_values_through_json() {
+        let program = compile("def a: (Int -> Int) = [1 \"s\"] drop; true a").unwrap();
+        let json = serde_json::to_string(&program).unwrap();
+        assert_eq!(serde_json::from_str::<super::Program>(&json).unwrap(), program);
+        let value = Value::from(vec![1, 2]);
+        let json = serde_json::to_string(&value).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&json).unwrap(), value);
+        let t = super::Type::Function(vec![super::Type::Param(0)], vec![super::Type::Int]);
+        assert_eq!(serde_json::from_str::<super::Type>(&serde_json::to_string(&t).unwrap()).unwrap(), t);
+    }
+
+    #[test]
+    fn runs_tests_after_definitions() {
+        let program = compile("def sq: (Int -> Int) = dup *; test \"sq\" { 3 sq expect 9 } test \"bad\" { 3 sq 8 assert-eq }").unwrap();
+        program.check().unwrap();
+        let mut engine = Engine::new();
+        program.run(&mut engine).unwrap();
+        assert!(engine.stack().is_empty());
+        let outcomes = program.test(&mut engine);
+        assert_eq!(outcomes.iter().map(|outcome| outcome.name.as_str()).collect::<Vec<_>>(), vec!["sq", "bad"]);
+        assert!(outcomes[0].result.is_ok());
+        assert!(outcomes[1].result.is_err());
+    }
+
+    #[test]
+    fn engine_describes_words() {
+        let mut engine = Engine::new();
+        engine.eval_str("## Doubles a number.\ndef double: (Int -> Int) = 2 *; def quiet: (Int -> Int) = ;").unwrap();
+        assert_eq!(engine.help("double").unwrap(), "double : (Int -> Int)\n\nDoubles a number.");
+        assert_eq!(engine.help("quiet").unwrap(), "quiet : (Int -> Int)");
+        assert_eq!(engine.help("+").unwrap(), "+ : (Int, Int -> Int)\n\nAdd two numbers, wrapping around on overflow.");
+        assert_eq!(engine.help("nope"), None);
+        assert_eq!(engine.words().into_iter().map(|(name, _)| name).collect::<Vec<_>>(), vec!["double", "quiet"]);
+    }
+
+    #[test]
+    fn reads_the_edition_a_program_is_written_for() {
+        assert_eq!(compile("#lang chara/2024\n1 2 +").unwrap().edition(), Edition::E2024);
+        assert_eq!(compile("1 2 +").unwrap().edition(), Edition::default());
+        assert!(compile("#lang chara/3000\n1 2 +").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn reads_the_edition_of_a_program_read_from_a_reader() {
+        let program = super::compile_reader("\n#lang chara/2024\n1 2 +".as_bytes()).unwrap();
+        assert_eq!((program.edition(), program.cycles().len()), (Edition::E2024, 1));
+        let errors = super::compile_reader("#lang chara/3000\n1 2 +".as_bytes()).unwrap_err();
+        assert_eq!(errors[0].to_string(), "1:1: unknown edition `3000`; this version of chara knows 2024");
+        // The lines read ahead are still parsed, where they are in the source.
+        let errors = super::compile_reader("1 2\n\"x".as_bytes()).unwrap_err();
+        assert!(errors[0].to_string().starts_with("2:1: "), "{}", errors[0]);
+    }
+
+    #[test]
+    fn runs_the_builtins_in_its_registry() {
+        let mut registry = Registry::standard();
+        registry.add(Builtin::native("answer", Type::Function(vec![], vec![Type::Int]), "The answer.", |stack| {
+            stack.push(Value::Int(42));
+            Ok(())
+        }).with_effects(Effects::PURE));
+        let mut engine = Engine::with_registry(registry);
+        engine.eval_str("answer 1 +").unwrap();
+        assert_eq!(engine.stack(), &[Value::Int(43)]);
+        assert_eq!(engine.help("answer").unwrap(), "answer : ( -> Int)\n\nThe answer.");
+        let mut engine = Engine::with_registry(Registry::new());
+        assert!(engine.eval_str("1 2 +").is_err());
+        let mut registry = Registry::new();
+        registry.add(Builtin::native("+", Type::Function(vec![Type::Int, Type::Int], vec![Type::Int]), "Times.", |stack| {
+            match (stack.pop(), stack.pop()) {
+                (Some(Value::Int(b)), Some(Value::Int(a))) => stack.push(Value::Int(a * b)),
+                _ => return Err("Expected two Ints".to_string()),
+            }
+            Ok(())
+        }));
+        let mut engine = Engine::with_registry(registry);
+        engine.eval_str("2 3 +").unwrap();
+        assert_eq!(engine.stack(), &[Value::Int(6)]);
+    }
+
+    #[test]
+    fn completes_word_names() {
+        let mut engine = Engine::new();
+        engine.eval_str("def double: (Int -> Int) = 2 *;").unwrap();
+        let completions: Vec<String> = engine.complete("d").into_iter()
+            .map(|(name, t)| format!("{} : {}", name, t))
+            .collect();
+        assert_eq!(completions, vec!["double : (Int -> Int)", "drop : (a -> )", "dup : (a -> a, a)", "dynamic : (a -> Dyn)"]);
+        assert_eq!(engine.complete("an").len(), 2);
+        assert!(engine.complete("zzz").is_empty());
+    }
+
+    #[test]
+    fn reports_parse_errors() {
+        let errors = compile("def a: Int = 1").unwrap_err();
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn reports_type_errors() {
+        let program = compile("nope").unwrap();
+        assert_eq!(program.check().unwrap_err().len(), 1);
+    }
+}
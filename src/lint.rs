@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+use std::fmt;
+use crate::abstract_interpreter::AbstractInterpreter;
+use crate::error::Error;
+use crate::parser::{parse, Cycle, Factor};
+use crate::scanner::Token;
+use crate::typechecker::{Type, TypeChecker};
+
+/// Something the linter looks for. Each rule has a kebab-case name, used in configs and attributes.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum Rule {
+    /// A pair of factors that undo each other, like `dup drop` or `swap swap`.
+    RedundantPair,
+    /// A definition no other cycle uses.
+    UnusedDefinition,
+    /// A definition named after a builtin word, hiding it.
+    ShadowedBuiltin,
+    /// Quotations nested deeper than the configured maximum.
+    DeepNesting,
+    /// A top-level term that leaves values behind, where they're discarded without being looked at.
+    DiscardedValues,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 5] = [
+        Rule::RedundantPair, Rule::UnusedDefinition, Rule::ShadowedBuiltin, Rule::DeepNesting, Rule::DiscardedValues,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rule::RedundantPair => "redundant-pair",
+            Rule::UnusedDefinition => "unused-definition",
+            Rule::ShadowedBuiltin => "shadowed-builtin",
+            Rule::DeepNesting => "deep-nesting",
+            Rule::DiscardedValues => "discarded-values",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Rule> {
+        Rule::ALL.into_iter().find(|rule| rule.name() == name)
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl Level {
+    fn from_name(name: &str) -> Option<Level> {
+        match name {
+            "allow" => Some(Level::Allow),
+            "warn" => Some(Level::Warn),
+            "deny" => Some(Level::Deny),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Level::Allow => write!(f, "allow"),
+            Level::Warn => write!(f, "warning"),
+            Level::Deny => write!(f, "error"),
+        }
+    }
+}
+
+/// Which rules are reported at which level. Every rule warns unless configured otherwise.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct LintConfig {
+    levels: HashMap<Rule, Level>,
+    /// How deeply quotations may nest before `deep-nesting` reports them.
+    pub max_depth: usize,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        LintConfig { levels: HashMap::new(), max_depth: 3 }
+    }
+}
+
+impl LintConfig {
+    /// Read a config with a `rule = allow|warn|deny` or `max-depth = N` setting on each line. Lines starting
+    /// with `#` are comments.
+    pub fn parse(text: &str) -> Result<LintConfig, Error> {
+        let mut config = LintConfig::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || Error::ConfigError(format!("line {}: expected `rule = level`, got `{}`", number + 1, line));
+            let (key, value) = line.split_once('=').ok_or_else(invalid)?;
+            let (key, value) = (key.trim(), value.trim());
+            if key == "max-depth" {
+                config.max_depth = value.parse().map_err(|_| invalid())?;
+            } else {
+                let rule = Rule::from_name(key).ok_or_else(|| unknown_rule(number + 1, key))?;
+                config.set(rule, Level::from_name(value).ok_or_else(invalid)?);
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn set(&mut self, rule: Rule, level: Level) {
+        self.levels.insert(rule, level);
+    }
+
+    pub fn level(&self, rule: Rule) -> Level {
+        self.levels.get(&rule).copied().unwrap_or(Level::Warn)
+    }
+}
+
+fn unknown_rule(line: usize, name: &str) -> Error {
+    Error::ConfigError(format!("line {}: unknown lint rule `{}`", line, name))
+}
+
+/// A problem found by a rule, at the level it's configured to be reported at.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Lint {
+    pub rule: Rule,
+    pub level: Level,
+    pub message: String,
+    pub token: Token,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}[{}]: {}", self.token.line, self.token.col, self.level, self.rule.name(), self.message)
+    }
+}
+
+/// Levels set in the source with `#![allow(rule)]`-style comments for the whole file, and
+/// `#[deny(rule, ...)]`-style comments for the cycle after them.
+struct Attributes {
+    file: HashMap<Rule, Level>,
+    /// The levels set before each cycle, in the same order as the cycles.
+    cycles: Vec<HashMap<Rule, Level>>,
+}
+
+impl Attributes {
+    fn parse(source: &str, cycles: &[Cycle]) -> Result<Attributes, Error> {
+        let mut attributes = Attributes { file: HashMap::new(), cycles: vec![HashMap::new(); cycles.len()] };
+        let mut offset = 0;
+        for (number, line) in source.split_inclusive('\n').enumerate() {
+            let start = offset;
+            offset += line.len();
+            let line = line.trim();
+            let (whole_file, attribute) = match (line.strip_prefix("#!["), line.strip_prefix("#[")) {
+                (Some(attribute), _) => (true, attribute),
+                (_, Some(attribute)) => (false, attribute),
+                _ => continue,
+            };
+            let invalid = || Error::ConfigError(format!("line {}: expected `#[level(rule, ...)]`, got `{}`", number + 1, line));
+            let (level, rules) = attribute.strip_suffix(")]").and_then(|inner| inner.split_once('(')).ok_or_else(invalid)?;
+            let level = Level::from_name(level.trim()).ok_or_else(invalid)?;
+            let levels = if whole_file {
+                &mut attributes.file
+            } else {
+                match cycles.iter().position(|cycle| cycle.span().start > start) {
+                    Some(i) => &mut attributes.cycles[i],
+                    None => continue,
+                }
+            };
+            for rule in rules.split(',') {
+                levels.insert(Rule::from_name(rule.trim()).ok_or_else(|| unknown_rule(number + 1, rule.trim()))?, level);
+            }
+        }
+        Ok(attributes)
+    }
+}
+
+/// Run every rule over `source`, skipping the ones allowed by `config` or by attributes in the source. Lints
+/// are in source order.
+pub fn lint(source: &str, config: &LintConfig) -> Result<Vec<Lint>, Error> {
+    let cycles = parse(source)?;
+    let attributes = Attributes::parse(source, &cycles)?;
+    let mut found = Vec::new();
+    let builtins = TypeChecker::new();
+    let mut checker = TypeChecker::new();
+    for (i, cycle) in cycles.iter().enumerate() {
+        let mut report = |rule: Rule, message: String, token: Token| {
+            let level = attributes.cycles[i].get(&rule)
+                .or_else(|| attributes.file.get(&rule))
+                .copied()
+                .unwrap_or_else(|| config.level(rule));
+            if level != Level::Allow {
+                found.push(Lint { rule, level, message, token });
+            }
+        };
+        let body = match cycle {
+            Cycle::Definition(token, _, body, _, _) => {
+                let name = token.value.as_str();
+                if builtins.type_of(name).is_some() {
+                    report(Rule::ShadowedBuiltin, format!("`{}` hides the builtin word of the same name", name), token.clone());
+                }
+                let used = cycles.iter().enumerate().any(|(j, other)| j != i && uses(other, name));
+                if !used {
+                    report(Rule::UnusedDefinition, format!("`{}` is never used", name), token.clone());
+                }
+                body
+            }
+            Cycle::Term(term, _) => {
+                let interpreter = AbstractInterpreter::with_environment(checker.environment().clone());
+                if let (Ok(Type::Function(_, t_out)), Some(first)) = (interpreter.interpret(term), term.first()) {
+                    if !t_out.is_empty() {
+                        let types = t_out.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ");
+                        report(Rule::DiscardedValues, format!("leaves {} behind to be discarded", types), first.token());
+                    }
+                }
+                term
+            }
+            Cycle::Test(_, body, _, _) | Cycle::Macro(_, body, _) => body,
+        };
+        factor_rules(body, 1, config.max_depth, &mut report);
+        // Later terms need the definitions before them in scope; a cycle that doesn't check just isn't added.
+        let _ = checker.check_cycle(cycle);
+    }
+    found.sort_by_key(|lint| lint.token.span.start);
+    Ok(found)
+}
+
+fn factor_rules(factors: &[Factor], depth: usize, max_depth: usize, report: &mut impl FnMut(Rule, String, Token)) {
+    for pair in factors.windows(2) {
+        let redundant = match pair {
+            [Factor::Dup(_), Factor::Drop(_)] => Some("dup drop"),
+            [Factor::Swap(_), Factor::Swap(_)] => Some("swap swap"),
+            _ => None,
+        };
+        if let Some(pair_source) = redundant {
+            report(Rule::RedundantPair, format!("`{}` does nothing", pair_source), pair[0].token());
+        }
+    }
+    for factor in factors {
+        if let Factor::Quotation(inner, _) = factor {
+            if depth == max_depth + 1 {
+                report(Rule::DeepNesting, format!("quotations are nested more than {} deep", max_depth), factor.token());
+            }
+            factor_rules(inner, depth + 1, max_depth, report);
+        }
+    }
+}
+
+fn uses(cycle: &Cycle, name: &str) -> bool {
+    fn in_factors(factors: &[Factor], name: &str) -> bool {
+        factors.iter().any(|factor| match factor {
+            Factor::Identifier(used, _) => used == name,
+            Factor::Quotation(inner, _) | Factor::Set(inner, _) => in_factors(inner, name),
+            _ => false,
+        })
+    }
+    match cycle {
+        Cycle::Definition(_, _, body, _, _) => in_factors(body, name),
+        Cycle::Term(term, _) => in_factors(term, name),
+        Cycle::Macro(_, body, _) => in_factors(body, name),
+        Cycle::Test(_, body, expected, _) => in_factors(body, name) || in_factors(expected.as_deref().unwrap_or_default(), name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lint, Level, LintConfig, Rule};
+
+    fn rules(source: &str, config: &LintConfig) -> Vec<(Rule, Level, usize, usize)> {
+        lint(source, config).unwrap().into_iter()
+            .map(|lint| (lint.rule, lint.level, lint.token.line, lint.token.col))
+            .collect()
+    }
+
+    #[test]
+    fn finds_problems() {
+        let source = "def id: (Int -> Int) = dup drop swap swap;\ndef and: (Bool, Bool -> Bool) = and;\n[[[[1]]]] drop 1 id";
+        assert_eq!(rules(source, &LintConfig::default()), vec![
+            (Rule::RedundantPair, Level::Warn, 1, 24),
+            (Rule::RedundantPair, Level::Warn, 1, 33),
+            (Rule::ShadowedBuiltin, Level::Warn, 2, 5),
+            (Rule::UnusedDefinition, Level::Warn, 2, 5),
+            (Rule::DiscardedValues, Level::Warn, 3, 5),
+            (Rule::DeepNesting, Level::Warn, 3, 5),
+        ]);
+        let lint = &lint(source, &LintConfig::default()).unwrap()[2];
+        assert_eq!(lint.to_string(), "2:5: warning[shadowed-builtin]: `and` hides the builtin word of the same name");
+    }
+
+    #[test]
+    fn reads_levels_from_configs_and_attributes() {
+        let config = LintConfig::parse("# strict\nunused-definition = deny\nmax-depth = 1").unwrap();
+        let source = "#![allow(discarded-values)]\ndef a: Int = [[1]] drop 1;\n#[allow(unused-definition, deep-nesting)]\ndef b: Int = [[1]] drop 1;\n1";
+        assert_eq!(rules(source, &config), vec![
+            (Rule::UnusedDefinition, Level::Deny, 2, 5),
+            (Rule::DeepNesting, Level::Warn, 2, 16),
+        ]);
+        assert_eq!(
+            LintConfig::parse("nope = deny").unwrap_err().to_string(),
+            "config error: line 1: unknown lint rule `nope`",
+        );
+        assert!(lint("#[sometimes(redundant-pair)]\n1", &config).is_err());
+    }
+}
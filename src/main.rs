@@ -1,11 +1,742 @@
-mod error;
-mod parser;
-mod scanner;
-mod typechecker;
-mod abstract_interpreter;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{env, fs, io, process};
+use chara::{Engine, Program};
+use chara::coverage::Coverage;
+use chara::explain::Explainer;
+use chara::fuzz::Fuzzer;
+use chara::lint::{Level, LintConfig};
+use chara::package::Package;
+use chara::profiler::Profiler;
+use chara::repl::Repl;
+use chara::trace::TraceObserver;
+use chara::tutorial::Tutorial;
+use chara::typechecker::CheckConfig;
 
+const USAGE: &str = "\
+usage: chara new <dir>
+       chara build [<dir>]
+       chara [run] [--main=<word>] [--trace] [--trace-stack=N] [--trace-json=<file>] [--trace-values] [--profile] [--strict|--lenient] [--term-outputs=N] <file>
+       chara explain [--format=text|html] [--depth=N] [--main=<word>] <file>
+       chara check [--emit=ast|--emit=ast-json] [--types] [--strict|--lenient] [--term-outputs=N] <file>
+       chara test [--coverage] [--strict|--lenient] [--term-outputs=N] <file|dir>
+       chara fuzz-def [--runs=N] [--seed=N] <name> <file>
+       chara doc [--format=markdown|html] <file>
+       chara graph [--format=dot|json] <file>
+       chara lint [--config=<file>] <file>
+       chara bench [--iterations=N] [--main=<word>] <file>
+       chara repl
+       chara tutorial
+A <file> can also be the directory of a package, with a chara.toml manifest, or `-` to read standard input.";
 
 fn main() {
-    // let parser = Parser::new("[a b c]").parse().unwrap();
-    // println!("{:?}", super.parse("[a b c]").unwrap());
+    let args: Vec<String> = env::args().skip(1).collect();
+    let code = match args.first().map(String::as_str) {
+        Some("new") => new(&args[1..]),
+        Some("build") => build(&args[1..]),
+        Some("run") => run(&args[1..]),
+        Some("explain") => explain(&args[1..]),
+        Some("check") => check(&args[1..]),
+        Some("test") => test(&args[1..]),
+        Some("fuzz-def") => fuzz_def(&args[1..]),
+        Some("doc") => doc(&args[1..]),
+        Some("graph") => graph(&args[1..]),
+        Some("lint") => lint(&args[1..]),
+        Some("bench") => bench(&args[1..]),
+        Some("repl") => repl(),
+        Some("tutorial") => tutorial(),
+        Some("-h" | "--help") => {
+            println!("{}", USAGE);
+            0
+        }
+        Some(_) => run(&args),
+        None => {
+            eprintln!("{}", USAGE);
+            2
+        }
+    };
+    process::exit(code);
+}
+
+/// Split arguments into `--flags` and the single file they apply to.
+fn parse_args(args: &[String]) -> Result<(Vec<&str>, &str), i32> {
+    let (flags, files): (Vec<&str>, Vec<&str>) = args.iter()
+        .map(String::as_str)
+        .partition(|arg| arg.starts_with("--"));
+    match files[..] {
+        [file] => Ok((flags, file)),
+        _ => {
+            eprintln!("{}", USAGE);
+            Err(2)
+        }
+    }
+}
+
+/// Load the program in `path`, reading it from standard input if the path is `-`. Files are parsed as
+/// they're read, so they don't have to fit in memory as source as well as parsed.
+fn load(path: &str) -> Result<Program, i32> {
+    let program = if path == "-" {
+        chara::compile_reader(io::stdin().lock())
+    } else if Package::is_package(path) {
+        return load_package(path, false);
+    } else {
+        let file = fs::File::open(path).map_err(|err| {
+            eprintln!("{}: {}", path, err);
+            1
+        })?;
+        chara::compile_reader(io::BufReader::new(file))
+    };
+    program.map_err(|errors| {
+        errors.iter().for_each(|error| eprintln!("{}", error));
+        1
+    })
+}
+
+/// Build the package in `path`, with its test files if `tests` is set.
+fn load_package(path: &str, tests: bool) -> Result<Program, i32> {
+    let package = Package::load(path).map_err(|error| {
+        eprintln!("{}", error);
+        1
+    })?;
+    let program = if tests { package.build_tests() } else { package.build() };
+    program.map_err(|errors| {
+        errors.iter().for_each(|error| eprintln!("{}", error));
+        1
+    })
+}
+
+fn typecheck(program: &Program, config: CheckConfig) -> Result<(), i32> {
+    let (_, warnings) = program.check_with(config).map_err(|errors| {
+        errors.iter().for_each(|error| eprintln!("{}", error));
+        1
+    })?;
+    warnings.iter().for_each(|warning| eprintln!("{}", warning));
+    Ok(())
+}
+
+fn new(args: &[String]) -> i32 {
+    let [path] = args else {
+        eprintln!("{}", USAGE);
+        return 2;
+    };
+    match Package::create(path) {
+        Ok(package) => {
+            eprintln!("created package `{}` in {}", package.manifest.name, path);
+            0
+        }
+        Err(error) => {
+            eprintln!("{}", error);
+            1
+        }
+    }
+}
+
+/// Build and check the package in a directory, the current one by default.
+fn build(args: &[String]) -> i32 {
+    let path = match args {
+        [] => ".",
+        [path] if !path.starts_with("--") => path.as_str(),
+        _ => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+    let result = Package::load(path)
+        .map_err(|error| {
+            eprintln!("{}", error);
+            1
+        })
+        .and_then(|package| {
+            let program = load(path)?;
+            typecheck(&program, CheckConfig::default())?;
+            let files = package.files().map_or(0, |files| files.len());
+            eprintln!("built {} {} ({} file(s))", package.manifest.name, package.manifest.version, files);
+            Ok(())
+        });
+    result.err().unwrap_or(0)
+}
+
+fn run(args: &[String]) -> i32 {
+    let result = parse_args(args).and_then(|(flags, path)| {
+        let mut trace: Option<TraceObserver<io::Stderr>> = None;
+        let mut profiler: Option<Rc<RefCell<Profiler>>> = None;
+        #[cfg(feature = "serde")]
+        let mut json_trace: Option<chara::trace::JsonTraceObserver<fs::File>> = None;
+        #[cfg(feature = "serde")]
+        let mut trace_values = false;
+        let mut main = "main";
+        let mut config = CheckConfig::default();
+        for flag in flags {
+            match flag.split_once('=') {
+                Some(("--main", word)) => main = word,
+                _ if check_flag(flag, &mut config)? => {}
+                #[cfg(feature = "serde")]
+                Some(("--trace-json", file)) => {
+                    let file = fs::File::create(file).map_err(|err| {
+                        eprintln!("{}: {}", file, err);
+                        1
+                    })?;
+                    json_trace = Some(chara::trace::JsonTraceObserver::new(file));
+                }
+                #[cfg(feature = "serde")]
+                None if flag == "--trace-values" => trace_values = true,
+                #[cfg(not(feature = "serde"))]
+                _ if flag.starts_with("--trace-json=") || flag == "--trace-values" => {
+                    eprintln!("{} requires chara to be built with the serde feature", flag);
+                    return Err(2);
+                }
+                None if flag == "--profile" => profiler = Some(Rc::new(RefCell::new(Profiler::new()))),
+                None if flag == "--trace" => {
+                    trace.get_or_insert_with(|| TraceObserver::new(io::stderr()));
+                }
+                // How many of the values on top of the stack each line of the trace shows.
+                Some(("--trace-stack", count)) => {
+                    let count = count.parse().map_err(|_| {
+                        eprintln!("--trace-stack expects a number, got {}", count);
+                        2
+                    })?;
+                    trace.get_or_insert_with(|| TraceObserver::new(io::stderr())).max_values = count;
+                }
+                _ => {
+                    eprintln!("unknown flag {}\n{}", flag, USAGE);
+                    return Err(2);
+                }
+            }
+        }
+        let program = load(path)?;
+        typecheck(&program, config)?;
+        let mut engine = Engine::new();
+        if let Some(trace) = trace {
+            engine.add_observer(Box::new(trace));
+        }
+        #[cfg(feature = "serde")]
+        if let Some(mut json_trace) = json_trace {
+            json_trace.values = trace_values;
+            engine.add_observer(Box::new(json_trace));
+        }
+        if let Some(profiler) = &profiler {
+            engine.add_observer(Box::new(profiler.clone()));
+        }
+        let result = program.run_main(&mut engine, main);
+        if let Some(profiler) = &profiler {
+            eprint!("{}", profiler.borrow().report());
+        }
+        result.map_err(|error| {
+            eprintln!("{}", error);
+            1
+        })?;
+        for value in engine.stack() {
+            println!("{}", value);
+        }
+        Ok(())
+    });
+    result.err().unwrap_or(0)
+}
+
+/// Run a program, printing a table of the stack after every word, for showing how it works.
+fn explain(args: &[String]) -> i32 {
+    let result = parse_args(args).and_then(|(flags, path)| {
+        let explainer = Rc::new(RefCell::new(Explainer::new()));
+        let mut main = "main";
+        let mut html = false;
+        for flag in flags {
+            match flag.split_once('=') {
+                Some(("--main", word)) => main = word,
+                Some(("--format", "text")) => html = false,
+                Some(("--format", "html")) => html = true,
+                Some(("--depth", depth)) => {
+                    explainer.borrow_mut().max_depth = depth.parse().ok().filter(|&n| n > 0).ok_or_else(|| {
+                        eprintln!("--depth expects a positive number, got {}", depth);
+                        2
+                    })?;
+                }
+                _ => {
+                    eprintln!("unknown flag {}\n{}", flag, USAGE);
+                    return Err(2);
+                }
+            }
+        }
+        let program = load(path)?;
+        typecheck(&program, CheckConfig::default())?;
+        let mut engine = Engine::new();
+        engine.add_observer(Box::new(explainer.clone()));
+        // The table up to a failure is still worth showing, since it shows how the program got there.
+        let result = program.run_main(&mut engine, main);
+        if html {
+            let title = Path::new(path).file_stem().map_or(path.into(), |stem| stem.to_string_lossy());
+            print!("{}", explainer.borrow().to_html(&title));
+        } else {
+            print!("{}", explainer.borrow().to_text());
+        }
+        result.map_err(|error| {
+            eprintln!("{}", error);
+            1
+        })
+    });
+    result.err().unwrap_or(0)
+}
+
+/// Applies a flag that configures the typechecker, or returns false if it isn't one.
+fn check_flag(flag: &str, config: &mut CheckConfig) -> Result<bool, i32> {
+    let term_outputs = config.term_outputs;
+    match flag.split_once('=') {
+        None if flag == "--strict" => *config = CheckConfig { term_outputs, ..CheckConfig::strict() },
+        None if flag == "--lenient" => *config = CheckConfig { term_outputs, ..CheckConfig::lenient() },
+        Some(("--term-outputs", count)) => {
+            config.term_outputs = count.parse().map_err(|_| {
+                eprintln!("--term-outputs expects a number, got {}", count);
+                2
+            })?;
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+fn check(args: &[String]) -> i32 {
+    let result = parse_args(args).and_then(|(flags, path)| {
+        let program = load(path)?;
+        let mut types = false;
+        let mut config = CheckConfig::default();
+        for flag in flags {
+            match flag {
+                "--types" => types = true,
+                _ if check_flag(flag, &mut config)? => {}
+                "--emit=ast" => println!("{}", chara::dump::to_sexpr(program.cycles())),
+                #[cfg(feature = "serde")]
+                "--emit=ast-json" => println!("{}", chara::dump::to_json(program.cycles())),
+                #[cfg(not(feature = "serde"))]
+                "--emit=ast-json" => {
+                    eprintln!("--emit=ast-json requires chara to be built with the serde feature");
+                    return Err(2);
+                }
+                _ => {
+                    eprintln!("unknown flag {}\n{}", flag, USAGE);
+                    return Err(2);
+                }
+            }
+        }
+        if !types {
+            return typecheck(&program, config);
+        }
+        let (types, warnings) = program.check_with(config).map_err(|errors| {
+            errors.iter().for_each(|error| eprintln!("{}", error));
+            1
+        })?;
+        warnings.iter().for_each(|warning| eprintln!("{}", warning));
+        for (cycle, t) in program.cycles().iter().zip(types) {
+            println!("{} : {}", cycle.label(), t);
+        }
+        Ok(())
+    });
+    result.err().unwrap_or(0)
+}
+
+fn test(args: &[String]) -> i32 {
+    let result = parse_args(args).and_then(|(flags, path)| {
+        let mut coverage = false;
+        let mut config = CheckConfig::default();
+        for flag in flags {
+            match flag {
+                "--coverage" => coverage = true,
+                _ if check_flag(flag, &mut config)? => {}
+                _ => {
+                    eprintln!("unknown flag {}\n{}", flag, USAGE);
+                    return Err(2);
+                }
+            }
+        }
+        let (mut passed, mut failed) = (0, 0);
+        for file in test_files(path)? {
+            let program = if Package::is_package(&file) { load_package(&file, true)? } else { load(&file)? };
+            typecheck(&program, config)?;
+            let mut engine = Engine::new();
+            let observer = coverage.then(|| Rc::new(RefCell::new(Coverage::new())));
+            if let Some(observer) = &observer {
+                engine.add_observer(Box::new(observer.clone()));
+            }
+            program.run(&mut engine).map_err(|error| {
+                eprintln!("{}: {}", file, error);
+                1
+            })?;
+            for outcome in program.test(&mut engine) {
+                match outcome.result {
+                    Ok(()) => {
+                        passed += 1;
+                        eprintln!("ok   {}: {}", file, outcome.name);
+                    }
+                    Err(error) => {
+                        failed += 1;
+                        eprintln!("FAIL {}: {}\n     {}", file, outcome.name, error);
+                    }
+                }
+            }
+            if let Some(observer) = &observer {
+                report_coverage(&file, &program, &observer.borrow());
+            }
+        }
+        eprintln!("{} passed, {} failed", passed, failed);
+        if failed > 0 {
+            Err(1)
+        } else {
+            Ok(())
+        }
+    });
+    result.err().unwrap_or(0)
+}
+
+/// The file itself, or every `.ch` file directly inside it if it is a directory.
+fn test_files(path: &str) -> Result<Vec<String>, i32> {
+    if !Path::new(path).is_dir() || Package::is_package(path) {
+        return Ok(vec![path.to_string()]);
+    }
+    let entries = fs::read_dir(path).map_err(|err| {
+        eprintln!("{}: {}", path, err);
+        1
+    })?;
+    let mut files: Vec<String> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "ch"))
+        .map(|path| path.display().to_string())
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Print an lcov record for the file to stdout, and a summary of what wasn't covered to stderr.
+fn report_coverage(path: &str, program: &Program, coverage: &Coverage) {
+    print!("{}", coverage.lcov(path, program.cycles()));
+    let (covered, total) = coverage.summary(program.cycles());
+    eprintln!("coverage of {}: {}/{} factors", path, covered, total);
+    for factor in coverage.uncovered(program.cycles()) {
+        let token = factor.token();
+        eprintln!("{}:{}:{}: not covered: {}", path, token.line, token.col, chara::format::factor_to_source(factor));
+    }
+}
+
+fn fuzz_def(args: &[String]) -> i32 {
+    let (flags, positional): (Vec<&str>, Vec<&str>) = args.iter()
+        .map(String::as_str)
+        .partition(|arg| arg.starts_with("--"));
+    let [name, path] = positional[..] else {
+        eprintln!("{}", USAGE);
+        return 2;
+    };
+    let result = (|| {
+        let mut runs = 100;
+        // Seeded from the clock unless given, and always printed so a failure can be reproduced.
+        let mut seed = SystemTime::now().duration_since(UNIX_EPOCH).map_or(1, |time| time.as_nanos() as u64);
+        for flag in flags {
+            let number = |value: &str| value.parse().map_err(|_| {
+                eprintln!("{} expects a number, got {}", flag, value);
+                2
+            });
+            match flag.split_once('=') {
+                Some(("--runs", value)) => runs = number(value)? as usize,
+                Some(("--seed", value)) => seed = number(value)?,
+                _ => {
+                    eprintln!("unknown flag {}\n{}", flag, USAGE);
+                    return Err(2);
+                }
+            }
+        }
+        let program = load(path)?;
+        let mut engine = Engine::new();
+        engine.check(&program).map_err(|errors| {
+            errors.iter().for_each(|error| eprintln!("{}", error));
+            1
+        })?;
+        program.run(&mut engine).map_err(|error| {
+            eprintln!("{}", error);
+            1
+        })?;
+        let mut fuzzer = Fuzzer::new(seed);
+        fuzzer.runs = runs;
+        match fuzzer.fuzz(&mut engine, name) {
+            Ok(None) => {
+                eprintln!("{}: {} runs passed (seed {})", name, runs, seed);
+                Ok(())
+            }
+            Ok(Some(counterexample)) => {
+                let inputs: Vec<String> = counterexample.inputs.iter().map(chara::format::value_to_source).collect();
+                eprintln!("{}: failed on [{}]: {} (seed {})", name, inputs.join(" "), counterexample.problem, seed);
+                Err(1)
+            }
+            Err(error) => {
+                eprintln!("{}", error);
+                Err(1)
+            }
+        }
+    })();
+    result.err().unwrap_or(0)
+}
+
+fn doc(args: &[String]) -> i32 {
+    let result = parse_args(args).and_then(|(flags, path)| {
+        let program = load(path)?;
+        let title = Path::new(path).file_stem().map_or(path.into(), |stem| stem.to_string_lossy());
+        let mut html = false;
+        for flag in flags {
+            match flag {
+                "--format=markdown" => html = false,
+                "--format=html" => html = true,
+                _ => {
+                    eprintln!("unknown flag {}\n{}", flag, USAGE);
+                    return Err(2);
+                }
+            }
+        }
+        if html {
+            print!("{}", chara::doc::to_html(&title, program.cycles()));
+        } else {
+            print!("{}", chara::doc::to_markdown(&title, program.cycles()));
+        }
+        Ok(())
+    });
+    result.err().unwrap_or(0)
+}
+
+/// Print which definitions of a checked program use which others.
+fn graph(args: &[String]) -> i32 {
+    let result = parse_args(args).and_then(|(flags, path)| {
+        let mut json = false;
+        for flag in flags {
+            match flag {
+                "--format=dot" => json = false,
+                #[cfg(feature = "serde")]
+                "--format=json" => json = true,
+                #[cfg(not(feature = "serde"))]
+                "--format=json" => {
+                    eprintln!("--format=json requires chara to be built with the serde feature");
+                    return Err(2);
+                }
+                _ => {
+                    eprintln!("unknown flag {}\n{}", flag, USAGE);
+                    return Err(2);
+                }
+            }
+        }
+        let program = load(path)?;
+        typecheck(&program, CheckConfig::default())?;
+        let graph = chara::graph::call_graph(program.cycles());
+        if json {
+            #[cfg(feature = "serde")]
+            println!("{}", graph.to_json());
+        } else {
+            print!("{}", graph.to_dot());
+        }
+        Ok(())
+    });
+    result.err().unwrap_or(0)
+}
+
+/// Time each phase of handling a program, over several runs of it.
+fn bench(args: &[String]) -> i32 {
+    let result = parse_args(args).and_then(|(flags, path)| {
+        let mut iterations = 10;
+        let mut main = "main";
+        for flag in flags {
+            match flag.split_once('=') {
+                Some(("--main", word)) => main = word,
+                Some(("--iterations", value)) => {
+                    iterations = value.parse().ok().filter(|&n| n > 0).ok_or_else(|| {
+                        eprintln!("--iterations expects a positive number, got {}", value);
+                        2
+                    })?;
+                }
+                _ => {
+                    eprintln!("unknown flag {}\n{}", flag, USAGE);
+                    return Err(2);
+                }
+            }
+        }
+        let source = fs::read_to_string(path).map_err(|err| {
+            eprintln!("{}: {}", path, err);
+            1
+        })?;
+        let timings = chara::bench::measure(&source, main, iterations).map_err(|errors| {
+            errors.iter().for_each(|error| eprintln!("{}", error));
+            1
+        })?;
+        eprintln!("{}: {} iteration(s)", path, iterations);
+        print!("{}", timings.report());
+        Ok(())
+    });
+    result.err().unwrap_or(0)
+}
+
+fn lint(args: &[String]) -> i32 {
+    let result = parse_args(args).and_then(|(flags, path)| {
+        let mut config = LintConfig::default();
+        for flag in flags {
+            match flag.split_once('=') {
+                Some(("--config", config_path)) => {
+                    let text = fs::read_to_string(config_path).map_err(|err| {
+                        eprintln!("{}: {}", config_path, err);
+                        1
+                    })?;
+                    config = LintConfig::parse(&text).map_err(|error| {
+                        eprintln!("{}: {}", config_path, error);
+                        1
+                    })?;
+                }
+                _ => {
+                    eprintln!("unknown flag {}\n{}", flag, USAGE);
+                    return Err(2);
+                }
+            }
+        }
+        let source = fs::read_to_string(path).map_err(|err| {
+            eprintln!("{}: {}", path, err);
+            1
+        })?;
+        let lints = chara::lint::lint(&source, &config).map_err(|error| {
+            eprintln!("{}: {}", path, error);
+            1
+        })?;
+        for lint in &lints {
+            eprintln!("{}:{}", path, lint);
+        }
+        // Only denied lints fail the run, so warnings can be cleaned up gradually.
+        if lints.iter().any(|lint| lint.level == Level::Deny) {
+            Err(1)
+        } else {
+            Ok(())
+        }
+    });
+    result.err().unwrap_or(0)
+}
+
+/// Walk through the built-in lessons, reading an attempt at each from standard input.
+fn tutorial() -> i32 {
+    use std::io::{BufRead, Write};
+    let mut tutorial = Tutorial::new();
+    println!("{}", tutorial.intro());
+    let stdin = io::stdin();
+    while tutorial.lesson().is_some() {
+        print!("tutorial> ");
+        io::stdout().flush().ok();
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => println!("{}", tutorial.handle(&line)),
+            Err(err) => {
+                eprintln!("{}", err);
+                return 1;
+            }
+        }
+    }
+    0
+}
+
+fn prompt(repl: &Repl) -> &'static str {
+    if repl.is_paused() {
+        "debug> "
+    } else if repl.needs_more() {
+        "... "
+    } else {
+        "> "
+    }
+}
+
+fn print_output(output: &str) {
+    if !output.is_empty() {
+        println!("{}", output);
+    }
+}
+
+/// Completes word names in the line editor. The REPL is shared with the loop reading lines, which never
+/// holds on to it while the editor is running.
+#[cfg(feature = "readline")]
+struct WordCompleter {
+    repl: Rc<RefCell<Repl>>,
+}
+
+#[cfg(feature = "readline")]
+impl rustyline::completion::Completer for WordCompleter {
+    type Candidate = rustyline::completion::Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &rustyline::Context<'_>) -> rustyline::Result<(usize, Vec<Self::Candidate>)> {
+        let (start, words) = self.repl.borrow().complete(line, pos);
+        let candidates = words.into_iter()
+            .map(|(name, t)| rustyline::completion::Pair { display: format!("{} : {}", name, t), replacement: name })
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+#[cfg(feature = "readline")]
+impl rustyline::hint::Hinter for WordCompleter {
+    type Hint = String;
+}
+
+#[cfg(feature = "readline")]
+impl rustyline::highlight::Highlighter for WordCompleter {}
+
+#[cfg(feature = "readline")]
+impl rustyline::validate::Validator for WordCompleter {}
+
+#[cfg(feature = "readline")]
+impl rustyline::Helper for WordCompleter {}
+
+#[cfg(feature = "readline")]
+fn repl() -> i32 {
+    use rustyline::error::ReadlineError;
+    let mut editor = match rustyline::Editor::<WordCompleter, rustyline::history::DefaultHistory>::new() {
+        Ok(editor) => editor,
+        Err(err) => {
+            eprintln!("{}", err);
+            return 1;
+        }
+    };
+    let repl = Rc::new(RefCell::new(Repl::new()));
+    editor.set_helper(Some(WordCompleter { repl: repl.clone() }));
+    let history = env::var_os("HOME").map(|home| Path::new(&home).join(".chara_history"));
+    if let Some(history) = &history {
+        // There's no history the first time the REPL runs, which is fine.
+        editor.load_history(history).ok();
+    }
+    let code = loop {
+        let prompt = prompt(&repl.borrow());
+        match editor.readline(prompt) {
+            Ok(line) => {
+                editor.add_history_entry(line.as_str()).ok();
+                let output = repl.borrow_mut().handle(&line);
+                print_output(&output);
+            }
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => break 0,
+            Err(err) => {
+                eprintln!("{}", err);
+                break 1;
+            }
+        }
+    };
+    if let Some(history) = &history {
+        if let Err(err) = editor.save_history(history) {
+            eprintln!("couldn't save history to {}: {}", history.display(), err);
+        }
+    }
+    code
+}
+
+#[cfg(not(feature = "readline"))]
+fn repl() -> i32 {
+    use std::io::{BufRead, Write};
+        let mut repl = Repl::new();
+    let stdin = io::stdin();
+    loop {
+        print!("{}", prompt(&repl));
+        io::stdout().flush().ok();
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) => return 0,
+            Ok(_) => print_output(&repl.handle(&line)),
+            Err(err) => {
+                eprintln!("{}", err);
+                return 1;
+            }
+        }
+    }
 }
@@ -0,0 +1,463 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::edition::{declared, Edition};
+use crate::error::Error;
+use crate::expand::expand;
+use crate::parser::{parse, Cycle, Factor};
+use crate::scanner::{Token, PUNCTUATION};
+use crate::symbol::Symbol;
+use crate::Program;
+
+/// The name of the manifest file at the root of every package.
+pub const MANIFEST: &str = "chara.toml";
+
+const NEW_MAIN: &str = "\
+## The word `chara run` starts the program with.
+def main: ( -> String) = \"Hello, world!\";
+";
+
+const NEW_TEST: &str = "\
+test \"main greets the world\" { main expect \"Hello, world!\" }
+";
+
+/// A package's `chara.toml`. Only the small part of TOML manifests need is understood: `[package]` and
+/// `[dependencies]` sections holding strings, arrays of strings, and `{ path = "..." }` tables.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Manifest {
+    pub name: String,
+    pub version: String,
+    /// The file whose terms are run, relative to the package root. Libraries don't need one.
+    pub entry: Option<String>,
+    /// The directories, relative to the package root, whose `.ch` files make up the package.
+    pub source_dirs: Vec<String>,
+    /// The directories of `.ch` files whose tests `chara test` runs along with the package's own.
+    pub test_dirs: Vec<String>,
+    pub dependencies: Vec<Dependency>,
+    /// The edition the package is written for. Its files can say so with a `#lang` line too, but only for the same one.
+    pub edition: Edition,
+}
+
+/// Another package on the local filesystem. Its words are used as `name/word`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Dependency {
+    pub name: String,
+    /// The dependency's root, relative to the root of the package depending on it.
+    pub path: String,
+}
+
+impl Manifest {
+    pub fn parse(text: &str) -> Result<Manifest, Error> {
+        let mut name = None;
+        let mut version = None;
+        let mut manifest = Manifest {
+            name: String::new(),
+            version: String::new(),
+            entry: None,
+            source_dirs: vec!["src".to_string()],
+            test_dirs: vec!["tests".to_string()],
+            dependencies: Vec::new(),
+            edition: Edition::default(),
+        };
+        let mut section = String::new();
+        for (number, line) in text.lines().enumerate() {
+            let error = |message: &str| Error::ConfigError(format!("line {}: {}", number + 1, message));
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(header) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+                section = header.trim().to_string();
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or_else(|| error("expected `key = value`"))?;
+            let (key, value) = (key.trim(), value.trim());
+            match (section.as_str(), key) {
+                ("package", "name") => name = Some(string(value).ok_or_else(|| error("expected a string"))?),
+                ("package", "version") => version = Some(string(value).ok_or_else(|| error("expected a string"))?),
+                ("package", "entry") => manifest.entry = Some(string(value).ok_or_else(|| error("expected a string"))?),
+                ("package", "source-dirs") => {
+                    manifest.source_dirs = strings(value).ok_or_else(|| error("expected an array of strings"))?;
+                }
+                ("package", "edition") => {
+                    let edition = string(value).ok_or_else(|| error("expected a string"))?;
+                    manifest.edition = Edition::parse(&edition).ok_or_else(|| error(&format!("unknown edition `{}`", edition)))?;
+                }
+                ("package", "test-dirs") => {
+                    manifest.test_dirs = strings(value).ok_or_else(|| error("expected an array of strings"))?;
+                }
+                ("dependencies", name) => {
+                    let path = value.strip_prefix('{')
+                        .and_then(|table| table.strip_suffix('}'))
+                        .and_then(|table| table.trim().strip_prefix("path"))
+                        .and_then(|rest| rest.trim_start().strip_prefix('='))
+                        .and_then(|path| string(path.trim()))
+                        .ok_or_else(|| error("expected `{ path = \"...\" }`"))?;
+                    manifest.dependencies.push(Dependency { name: name.to_string(), path });
+                }
+                (section, key) => return Err(error(&format!("unknown key `{}` in [{}]", key, section))),
+            }
+        }
+        let missing = |key: &str| Error::ConfigError(format!("missing `{}` in [package]", key));
+        manifest.name = name.ok_or_else(|| missing("name"))?;
+        manifest.version = version.ok_or_else(|| missing("version"))?;
+        Ok(manifest)
+    }
+
+    /// The manifest as a `chara.toml`, leaving out settings that have their default values.
+    pub fn to_toml(&self) -> String {
+        let quote = |value: &str| format!("\"{}\"", value);
+        let list = |values: &[String]| format!("[{}]", values.iter().map(|value| quote(value)).collect::<Vec<_>>().join(", "));
+        let mut out = format!("[package]\nname = {}\nversion = {}\n", quote(&self.name), quote(&self.version));
+        if let Some(entry) = &self.entry {
+            out.push_str(&format!("entry = {}\n", quote(entry)));
+        }
+        if self.source_dirs != ["src"] {
+            out.push_str(&format!("source-dirs = {}\n", list(&self.source_dirs)));
+        }
+        if self.test_dirs != ["tests"] {
+            out.push_str(&format!("test-dirs = {}\n", list(&self.test_dirs)));
+        }
+        if self.edition != Edition::default() {
+            out.push_str(&format!("edition = {}\n", quote(self.edition.as_str())));
+        }
+        if !self.dependencies.is_empty() {
+            out.push_str("\n[dependencies]\n");
+            for dependency in &self.dependencies {
+                out.push_str(&format!("{} = {{ path = {} }}\n", dependency.name, quote(&dependency.path)));
+            }
+        }
+        out
+    }
+}
+
+fn string(value: &str) -> Option<String> {
+    value.strip_prefix('"')?.strip_suffix('"').map(str::to_string)
+}
+
+fn strings(value: &str) -> Option<Vec<String>> {
+    let items = value.strip_prefix('[')?.strip_suffix(']')?.trim();
+    if items.is_empty() {
+        return Some(Vec::new());
+    }
+    items.split(',').map(|item| string(item.trim())).collect()
+}
+
+/// A package: a manifest, and the directory it was found in.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Package {
+    pub root: PathBuf,
+    pub manifest: Manifest,
+}
+
+impl Package {
+    /// Read the package whose manifest is in `root`.
+    pub fn load(root: impl AsRef<Path>) -> Result<Package, Error> {
+        let root = root.as_ref().to_path_buf();
+        let path = root.join(MANIFEST);
+        let in_manifest = |error: Error| Error::InFile(path.display().to_string(), Box::new(error));
+        let text = fs::read_to_string(&path).map_err(|err| in_manifest(err.into()))?;
+        let manifest = Manifest::parse(&text).map_err(in_manifest)?;
+        Ok(Package { root, manifest })
+    }
+
+    /// Whether `path` is the root of a package.
+    pub fn is_package(path: impl AsRef<Path>) -> bool {
+        path.as_ref().join(MANIFEST).is_file()
+    }
+
+    /// Start a new package in `root`, which mustn't exist yet, named after its last component. It has an
+    /// entry point with a `main` definition, and a test of it for `chara test` to run.
+    pub fn create(root: impl AsRef<Path>) -> Result<Package, Error> {
+        let root = root.as_ref().to_path_buf();
+        if root.exists() {
+            return Err(Error::ConfigError(format!("{} already exists", root.display())));
+        }
+        let name = root.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        if name.is_empty() || name.chars().any(|c| c.is_whitespace() || PUNCTUATION.contains(&c) || "/#\"".contains(c)) {
+            return Err(Error::ConfigError(format!("`{}` can't be used as a package name", name)));
+        }
+        let manifest = Manifest {
+            name,
+            version: "0.1.0".to_string(),
+            entry: Some("src/main.ch".to_string()),
+            source_dirs: vec!["src".to_string()],
+            test_dirs: vec!["tests".to_string()],
+            dependencies: Vec::new(),
+            edition: Edition::LATEST,
+        };
+        let files = [
+            (MANIFEST, manifest.to_toml()),
+            ("src/main.ch", NEW_MAIN.to_string()),
+            ("tests/main.ch", NEW_TEST.to_string()),
+        ];
+        for (path, contents) in files {
+            let path = root.join(path);
+            let in_file = |err: std::io::Error| Error::InFile(path.display().to_string(), Box::new(err.into()));
+            fs::create_dir_all(path.parent().unwrap_or(&root)).map_err(in_file)?;
+            fs::write(&path, contents).map_err(in_file)?;
+        }
+        Ok(Package { root, manifest })
+    }
+
+    /// The package's source files in the order they're loaded: the `.ch` files directly in each source
+    /// directory, sorted by name, then the entry point. Since words have to be defined before they're used, a
+    /// file can only use words from files before it.
+    pub fn files(&self) -> Result<Vec<PathBuf>, Error> {
+        let entry = self.manifest.entry.as_ref().map(|entry| self.root.join(entry));
+        let mut files = self.files_in(&self.manifest.source_dirs, true)?;
+        files.retain(|path| Some(path) != entry.as_ref());
+        files.extend(entry);
+        Ok(files)
+    }
+
+    /// The `.ch` files in the test directories, which are loaded after the package's own files. Test
+    /// directories that don't exist are skipped.
+    pub fn test_files(&self) -> Result<Vec<PathBuf>, Error> {
+        self.files_in(&self.manifest.test_dirs, false)
+    }
+
+    fn files_in(&self, dirs: &[String], required: bool) -> Result<Vec<PathBuf>, Error> {
+        let mut files = Vec::new();
+        for dir in dirs {
+            let dir = self.root.join(dir);
+            if !required && !dir.is_dir() {
+                continue;
+            }
+            let in_dir = |err: std::io::Error| Error::InFile(dir.display().to_string(), Box::new(err.into()));
+            let mut found = Vec::new();
+            for dir_entry in fs::read_dir(&dir).map_err(in_dir)? {
+                let path = dir_entry.map_err(in_dir)?.path();
+                if path.extension().is_some_and(|extension| extension == "ch") {
+                    found.push(path);
+                }
+            }
+            found.sort();
+            files.extend(found);
+        }
+        Ok(files)
+    }
+
+    /// Everything in the package and its dependencies, as one program. Dependencies' definitions come first,
+    /// renamed to `dependency/word`, followed by the definitions and tests in the package's files, then the
+    /// whole of its entry point.
+    pub fn build(&self) -> Result<Program, Vec<Error>> {
+        self.build_with(false)
+    }
+
+    /// The package built as by `build`, followed by the definitions and tests in its test files.
+    pub fn build_tests(&self) -> Result<Program, Vec<Error>> {
+        self.build_with(true)
+    }
+
+    fn build_with(&self, tests: bool) -> Result<Program, Vec<Error>> {
+        let mut builder = Builder { cycles: Vec::new(), files: Vec::new(), building: Vec::new() };
+        builder.add(self, None, tests).map_err(|error| vec![error])?;
+        Ok(Program { cycles: builder.cycles, files: builder.files, edition: self.manifest.edition })
+    }
+}
+
+struct Builder {
+    cycles: Vec<Cycle>,
+    files: Vec<String>,
+    /// The roots of the packages being added, to catch packages that depend on themselves.
+    building: Vec<PathBuf>,
+}
+
+impl Builder {
+    /// Add a package's cycles, with its words prefixed by `namespace/` if it's a dependency.
+    fn add(&mut self, package: &Package, namespace: Option<&str>, tests: bool) -> Result<(), Error> {
+        let root = package.root.canonicalize().unwrap_or_else(|_| package.root.clone());
+        if self.building.contains(&root) {
+            return Err(Error::ConfigError(format!("package `{}` depends on itself", package.manifest.name)));
+        }
+        self.building.push(root);
+        for dependency in &package.manifest.dependencies {
+            let nested = match namespace {
+                Some(namespace) => format!("{}/{}", namespace, dependency.name),
+                None => dependency.name.clone(),
+            };
+            self.add(&Package::load(package.root.join(&dependency.path))?, Some(&nested), false)?;
+        }
+
+        let entry = package.manifest.entry.as_ref().map(|entry| package.root.join(entry));
+        let mut cycles = Vec::new();
+        let mut files = package.files()?;
+        if tests {
+            files.extend(package.test_files()?);
+        }
+        for path in files {
+            let display = path.display().to_string();
+            let in_file = |error: Error| Error::InFile(display.clone(), Box::new(error));
+            let source = fs::read_to_string(&path).map_err(|err| in_file(err.into()))?;
+            match declared(&source).map_err(in_file)? {
+                Some(edition) if edition != package.manifest.edition => {
+                    let message = format!("written for edition {}, but package `{}` is for {}", edition, package.manifest.name, package.manifest.edition);
+                    return Err(in_file(Error::ConfigError(message)));
+                }
+                _ => {}
+            }
+            let file = Some(self.files.len());
+            let is_entry = namespace.is_none() && Some(&path) == entry.as_ref();
+            // Macros are expanded a file at a time, so they're private to the file they're defined in.
+            for mut cycle in parse(&source).and_then(expand).map_err(in_file)? {
+                let keep = match cycle {
+                    Cycle::Definition(_, _, _, _, _) => true,
+                    Cycle::Macro(_, _, _) => false,
+                    Cycle::Test(_, _, _, _) => namespace.is_none(),
+                    Cycle::Term(_, _) => is_entry,
+                };
+                if keep {
+                    cycle.visit_positions_mut(&mut |token| token.span.file = file, &mut |span| span.file = file);
+                    cycles.push(cycle);
+                }
+            }
+            self.files.push(display);
+        }
+
+        if let Some(namespace) = namespace {
+            let defined: HashSet<String> = cycles.iter()
+                .filter_map(|cycle| match cycle {
+                    Cycle::Definition(Token { value: name, .. }, _, _, _, _) => Some(name.clone()),
+                    _ => None,
+                })
+                .collect();
+            let dependencies: Vec<String> = package.manifest.dependencies.iter().map(|dependency| format!("{}/", dependency.name)).collect();
+            let qualify = |name: &str| {
+                let local = defined.contains(name) || dependencies.iter().any(|prefix| name.starts_with(prefix));
+                local.then(|| format!("{}/{}", namespace, name))
+            };
+            for cycle in &mut cycles {
+                if let Cycle::Definition(Token { value: name, .. }, _, body, _, _) = cycle {
+                    if let Some(qualified) = qualify(name) {
+                        *name = qualified;
+                    }
+                    qualify_factors(body, &qualify);
+                }
+            }
+        }
+        self.cycles.extend(cycles);
+        self.building.pop();
+        Ok(())
+    }
+}
+
+fn qualify_factors(factors: &mut [Factor], qualify: &dyn Fn(&str) -> Option<String>) {
+    for factor in factors {
+        match factor {
+            Factor::Identifier(name, _) => {
+                if let Some(qualified) = qualify(name.as_str()) {
+                    *name = Symbol::intern(&qualified);
+                }
+            }
+            Factor::Quotation(inner, _) => qualify_factors(inner, qualify),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use crate::evaluator::Value;
+    use crate::Engine;
+    use super::{Dependency, Edition, Manifest, Package};
+
+    /// Write out files under a fresh temporary directory, returning its path.
+    fn write_tree(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("chara-package-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for (path, contents) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn parses_manifests() {
+        let manifest = Manifest::parse("\
+[package]
+name = \"app\"
+version = \"0.1.0\"
+entry = \"main.ch\"
+source-dirs = [\"src\", \"lib\"]
+edition = \"2024\"
+
+# Local packages only, for now.
+[dependencies]
+math = { path = \"../math\" }
+").unwrap();
+        assert_eq!(manifest.entry.as_deref(), Some("main.ch"));
+        assert_eq!(manifest.source_dirs, vec!["src", "lib"]);
+        assert_eq!(manifest.edition, Edition::E2024);
+        assert_eq!(manifest.dependencies, vec![Dependency { name: "math".to_string(), path: "../math".to_string() }]);
+        assert_eq!(Manifest::parse(&manifest.to_toml()).unwrap(), manifest);
+        assert_eq!(Manifest::parse("[package]\nname = \"x\"").unwrap_err().to_string(), "config error: missing `version` in [package]");
+        assert_eq!(
+            Manifest::parse("[package]\nedition = \"1999\"").unwrap_err().to_string(),
+            "config error: line 2: unknown edition `1999`",
+        );
+        assert_eq!(
+            Manifest::parse("[package]\nname = x").unwrap_err().to_string(),
+            "config error: line 2: expected a string",
+        );
+    }
+
+    #[test]
+    fn builds_packages_with_namespaced_dependencies() {
+        let root = write_tree("build", &[
+            ("math/chara.toml", "[package]\nname = \"math\"\nversion = \"1.0.0\"\n"),
+            ("math/src/square.ch", "def square: (Int -> Int) = dup *;\n1 square"),
+            ("math/src/twice.ch", "def fourth: (Int -> Int) = square square;"),
+            ("app/chara.toml", "[package]\nname = \"app\"\nversion = \"0.1.0\"\nentry = \"src/main.ch\"\n[dependencies]\nmath = { path = \"../math\" }\n"),
+            ("app/src/helpers.ch", "def square: (Int -> Int) = 1 +;\n100"),
+            ("app/src/main.ch", "2 math/fourth 2 square"),
+        ]);
+        let package = Package::load(root.join("app")).unwrap();
+        assert_eq!(package.files().unwrap(), vec![root.join("app/src/helpers.ch"), root.join("app/src/main.ch")]);
+        let program = package.build().unwrap();
+        program.check().unwrap();
+        let mut engine = Engine::new();
+        program.run(&mut engine).unwrap();
+        assert_eq!(engine.stack(), &[Value::Int(16), Value::Int(3)]);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn creates_new_packages() {
+        let root = write_tree("new", &[]).join("hello");
+        let package = Package::create(&root).unwrap();
+        assert_eq!(Package::load(&root).unwrap(), package);
+        assert_eq!(fs::read_to_string(root.join("chara.toml")).unwrap(), package.manifest.to_toml());
+        let program = package.build_tests().unwrap();
+        program.check().unwrap();
+        let mut engine = Engine::new();
+        program.run(&mut engine).unwrap();
+        let outcomes = program.test(&mut engine);
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(outcomes[0].result, Ok(()));
+        assert!(Package::create(&root).unwrap_err().to_string().ends_with("already exists"));
+        assert!(Package::create(root.with_file_name("a:b")).is_err());
+        fs::remove_dir_all(root.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn reports_which_file_errors_are_in() {
+        let root = write_tree("errors", &[
+            ("chara.toml", "[package]\nname = \"app\"\nversion = \"0.1.0\"\nentry = \"src/main.ch\"\n"),
+            ("src/a.ch", "def a: Int = 1;"),
+            ("src/main.ch", "a nope"),
+        ]);
+        let errors = Package::load(&root).unwrap().build().unwrap().check().unwrap_err();
+        let main = root.join("src/main.ch").display().to_string();
+        assert_eq!(errors[0].to_string(), format!("{}: 1:3: type error: Unknown identifier nope", main));
+
+        fs::write(root.join("chara.toml"), "[package]\nname = \"app\"\nversion = \"0.1.0\"\n[dependencies]\nme = { path = \".\" }\n").unwrap();
+        let errors = Package::load(&root).unwrap().build().unwrap_err();
+        assert_eq!(errors[0].to_string(), "config error: package `app` depends on itself");
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,15 +1,20 @@
-use std::fmt::Debug;
+use core::fmt::Debug;
 use crate::error::{Error};
-use crate::scanner::{scan, Token};
+use crate::symbol::Symbol;
+use crate::scanner::{scan_borrowed, BorrowedToken, Span, Token, TokenKind};
+use alloc::{boxed::Box, collections::{BTreeSet, VecDeque}, format, string::{String, ToString}, vec, vec::Vec};
 
 #[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Value {
     Integer(i64),
     Boolean(bool),
     String(String),
+    Bytes(Vec<u8>),
 }
 
 #[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Factor {
     Dup(Token),    // [A] -> [A] [A]
     Drop(Token),   // [A] [A] -> [A]
@@ -18,11 +23,27 @@ pub enum Factor {
     Cat(Token),    // [A] [B] -> [A B]
     Swap(Token),   // [A] [B] -> [B] [A]
     Ifte(Token),   // S [S -> Bool] [S -> T] [S -> F] -> T|F
+    Cond(Token),   // S [[S -> Bool] [S -> T] ... [S -> T]] -> T
+    /// The names a `let` binds, which pops a value for each, then the quotation for its body, and runs the body
+    /// with the names standing for the values. `let x y in ... end` is parsed into the body and this.
+    Let(Vec<Symbol>, Token),
+    /// A use of a name bound by an enclosing `let`.
+    Local(Symbol, Token),
     Int(Value, Token),
     Bool(Value, Token),
     String(Value, Token),
-    Identifier(String, Token),
-    Quotation(Vec<Factor>),
+    Bytes(Value, Token),
+    /// A set, with the literals of its elements in order. Sets have no syntax, so this only comes from putting
+    /// a set from the stack back into code, as `quote` does, and it's written as the words that build it.
+    Set(Vec<Factor>, Token),
+    Identifier(Symbol, Token),
+    Quotation(Vec<Factor>, Span),
+    /// A stack effect assertion like `( Int Int -- Int )`, which the typechecker checks the term up to it against.
+    /// It's held as the function type it asserts, boxed to keep factors small, and does nothing when run.
+    Assertion(Box<TypeAnnotation>),
+    /// A type ascription like `:: Int` after a factor, which the typechecker checks the value on top of the stack
+    /// against, at the first `:` of the `::`. It does nothing when run.
+    Ascription(Box<TypeAnnotation>, Token),
 }
 
 impl Factor {
@@ -35,149 +56,575 @@ impl Factor {
             Factor::Cat(token) => token.clone(),
             Factor::Swap(token) => token.clone(),
             Factor::Ifte(token) => token.clone(),
+            Factor::Cond(token) => token.clone(),
+            Factor::Let(_, token) => token.clone(),
+            Factor::Local(_, token) => token.clone(),
             Factor::Int(_, token) => token.clone(),
             Factor::Bool(_, token) => token.clone(),
             Factor::String(_, token) => token.clone(),
+            Factor::Bytes(_, token) => token.clone(),
+            Factor::Set(_, token) => token.clone(),
             Factor::Identifier(_, token) => token.clone(),
-            Factor::Quotation(factors) => factors.first().unwrap().token(),
+            Factor::Quotation(factors, _) => factors.first().map(Factor::token).unwrap_or_else(Token::unknown),
+            Factor::Assertion(annotation) => annotation.first_token().clone(),
+            Factor::Ascription(_, token) => token.clone(),
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            Factor::Quotation(_, span) => *span,
+            Factor::Assertion(annotation) => annotation.span(),
+            Factor::Ascription(annotation, token) => token.span.merge(&annotation.span()),
+            factor => factor.token().span,
         }
     }
 }
 
+/// The span covering a whole term, or an empty span if the term has no factors.
+pub fn term_span(factors: &[Factor]) -> Span {
+    match (factors.first(), factors.last()) {
+        (Some(first), Some(last)) => first.span().merge(&last.span()),
+        _ => Span::default(),
+    }
+}
+
 #[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TypeAnnotation {
     Function(Vec<TypeAnnotation>, Vec<TypeAnnotation>, Token, Token),
     Identifier(String, Token),
+    /// A type constructor applied to arguments, like `Pair(Int, a)`, with the tokens of its name and `)`.
+    Apply(String, Vec<TypeAnnotation>, Token, Token),
+    /// A definition's type with the effects it's declared to have after a `!`, like `(Int -> Int) ! io`. The
+    /// tokens are the `!` and the names of the effects.
+    Effectful(Box<TypeAnnotation>, Token, Vec<Token>),
+    /// The type of a `total def`, whose recursion the typechecker checks terminates. The token is the `total`.
+    Total(Box<TypeAnnotation>, Token),
+    /// The type of a `memo def`, whose results the evaluator keeps by its inputs. The token is the `memo`.
+    Memo(Box<TypeAnnotation>, Token),
 }
 
+impl TypeAnnotation {
+    pub fn span(&self) -> Span {
+        match self {
+            TypeAnnotation::Function(_, _, first, last) => first.span.merge(&last.span),
+            TypeAnnotation::Identifier(_, token) => token.span,
+            TypeAnnotation::Apply(_, _, name, close) => name.span.merge(&close.span),
+            TypeAnnotation::Effectful(t, bang, effects) => t.span().merge(&effects.last().unwrap_or(bang).span),
+            TypeAnnotation::Total(t, _) | TypeAnnotation::Memo(t, _) => t.span(),
+        }
+    }
+
+    /// The token the annotation starts with, which stands for its definition when one needs locating.
+    pub fn first_token(&self) -> &Token {
+        match self {
+            TypeAnnotation::Function(_, _, first, _) => first,
+            TypeAnnotation::Identifier(_, token) | TypeAnnotation::Apply(_, _, token, _) => token,
+            TypeAnnotation::Effectful(t, _, _) | TypeAnnotation::Total(t, _) | TypeAnnotation::Memo(t, _) => t.first_token(),
+        }
+    }
+
+    /// The type, without any effects, totality or memoizing declared with it.
+    pub fn without_modifiers(&self) -> &TypeAnnotation {
+        match self {
+            TypeAnnotation::Effectful(t, _, _) | TypeAnnotation::Total(t, _) | TypeAnnotation::Memo(t, _) => t.without_modifiers(),
+            t => t,
+        }
+    }
+
+    /// Whether this is the type of a `total def`.
+    pub fn is_total(&self) -> bool {
+        match self {
+            TypeAnnotation::Total(..) => true,
+            TypeAnnotation::Memo(t, _) => t.is_total(),
+            _ => false,
+        }
+    }
+
+    /// Whether this is the type of a `memo def`.
+    pub fn is_memo(&self) -> bool {
+        match self {
+            TypeAnnotation::Memo(..) => true,
+            TypeAnnotation::Total(t, _) => t.is_memo(),
+            _ => false,
+        }
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
 #[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Cycle {
-    Definition(String, TypeAnnotation, Vec<Factor>),
-    Term(Vec<Factor>),
+    /// A definition spans from `def` to the closing `;`. It starts with the token of the name it defines, and
+    /// the last field holds the `##` doc comment lines just before it, with the markers stripped.
+    Definition(Token, TypeAnnotation, Vec<Factor>, Span, Option<String>),
+    Term(Vec<Factor>, Span),
+    /// A named test, with its body and the term whose results the body's should match, if it has one.
+    /// It spans from `test` to the closing `}`.
+    Test(String, Vec<Factor>, Option<Vec<Factor>>, Span),
+    /// A macro, whose body runs when the program is expanded. It spans from `macro` to the closing `;`.
+    Macro(String, Vec<Factor>, Span),
+}
+
+impl Cycle {
+    pub fn span(&self) -> Span {
+        match self {
+            Cycle::Definition(_, _, _, span, _) => *span,
+            Cycle::Term(_, span) => *span,
+            Cycle::Test(_, _, _, span) => *span,
+            Cycle::Macro(_, _, span) => *span,
+        }
+    }
+
+    /// What the cycle is called where its type is shown: the name it defines, or a term's source.
+    pub fn label(&self) -> String {
+        match self {
+            Cycle::Definition(name, _, _, _, _) => name.value.clone(),
+            Cycle::Term(factors, _) => crate::format::term_to_source(factors),
+            Cycle::Test(name, _, _, _) => format!("test {:?}", name),
+            Cycle::Macro(name, _, _) => format!("macro {}", name),
+        }
+    }
+
+    /// Call `token` on every token in the cycle, and `span` on every span that isn't a token's, for moving
+    /// the cycle to somewhere else in a source or into another file.
+    #[cfg(feature = "std")]
+    pub(crate) fn visit_positions_mut(&mut self, token: &mut dyn FnMut(&mut Token), span: &mut dyn FnMut(&mut Span)) {
+        fn visit_factors(factors: &mut [Factor], token: &mut dyn FnMut(&mut Token), span: &mut dyn FnMut(&mut Span)) {
+            for factor in factors {
+                match factor {
+                    Factor::Quotation(inner, quotation_span) => {
+                        span(quotation_span);
+                        visit_factors(inner, token, span);
+                    }
+                    Factor::Dup(t) | Factor::Drop(t) | Factor::Quote(t) | Factor::Call(t) | Factor::Cat(t)
+                    | Factor::Swap(t) | Factor::Ifte(t) | Factor::Cond(t) | Factor::Let(_, t) | Factor::Local(_, t)
+                    | Factor::Int(_, t) | Factor::Bool(_, t) | Factor::String(_, t) | Factor::Bytes(_, t) | Factor::Set(_, t) | Factor::Identifier(_, t) => token(t),
+                    Factor::Assertion(annotation) => visit_annotation(annotation, token),
+                    Factor::Ascription(annotation, t) => {
+                        token(t);
+                        visit_annotation(annotation, token);
+                    }
+                }
+            }
+        }
+        fn visit_annotation(annotation: &mut TypeAnnotation, token: &mut dyn FnMut(&mut Token)) {
+            match annotation {
+                TypeAnnotation::Function(t_in, t_out, first, last) => {
+                    t_in.iter_mut().chain(t_out.iter_mut()).for_each(|t| visit_annotation(t, token));
+                    token(first);
+                    token(last);
+                }
+                TypeAnnotation::Identifier(_, t) => token(t),
+                TypeAnnotation::Apply(_, args, name, close) => {
+                    token(name);
+                    args.iter_mut().for_each(|t| visit_annotation(t, token));
+                    token(close);
+                }
+                TypeAnnotation::Effectful(t, bang, effects) => {
+                    visit_annotation(t, token);
+                    token(bang);
+                    effects.iter_mut().for_each(&mut *token);
+                }
+                TypeAnnotation::Total(t, modifier) | TypeAnnotation::Memo(t, modifier) => {
+                    token(modifier);
+                    visit_annotation(t, token);
+                }
+            }
+        }
+        match self {
+            Cycle::Definition(name, annotation, body, cycle_span, _) => {
+                token(name);
+                visit_annotation(annotation, token);
+                visit_factors(body, token, span);
+                span(cycle_span);
+            }
+            Cycle::Term(term, cycle_span) | Cycle::Macro(_, term, cycle_span) => {
+                visit_factors(term, token, span);
+                span(cycle_span);
+            }
+            Cycle::Test(_, body, expected, cycle_span) => {
+                visit_factors(body, token, span);
+                if let Some(expected) = expected {
+                    visit_factors(expected, token, span);
+                }
+                span(cycle_span);
+            }
+        }
+    }
 }
 
-pub struct Parser {
-    pub tokens: Vec<Token>,
+/// Descriptions of kinds of token that can appear in an expected set, as opposed to literal tokens.
+const TOKEN_CLASSES: [&str; 4] = ["identifier", "type", "factor", "string"];
+
+pub struct Parser<'src> {
+    pub tokens: Vec<BorrowedToken<'src>>,
+    /// The index of the next token to be consumed.
+    position: usize,
+    /// The index past the last token of the cycle being parsed, which the parser doesn't look beyond.
+    end: usize,
     pub cycles: Vec<Cycle>,
+    /// The constructs currently being parsed, innermost last, used to give errors some context.
+    context: Vec<String>,
+    /// How many quotations or function types the next token is inside, and how many it may be inside.
+    depth: usize,
+    max_depth: usize,
+    /// Core combinators redefined with `def override`, which are parsed as calls to those definitions from then on.
+    overridden: BTreeSet<&'src str>,
+    /// The names bound by each `let` the next token is inside, innermost last.
+    locals: Vec<Vec<&'src str>>,
 }
 
-impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Parser {
+impl<'src> Parser<'src> {
+    pub fn new(tokens: Vec<BorrowedToken<'src>>) -> Parser<'src> {
         Parser {
+            end: tokens.len(),
             tokens,
+            position: 0,
             cycles: Vec::new(),
+            context: Vec::new(),
+            depth: 0,
+            max_depth: MAX_DEPTH,
+            overridden: BTreeSet::new(),
+            locals: Vec::new(),
         }
     }
 
-    fn peek(&self) -> Option<&Token> {
-        self.tokens.get(0)
+    /// The tokens left in the cycle being parsed.
+    fn rest(&self) -> &[BorrowedToken<'src>] {
+        &self.tokens[self.position..self.end]
+    }
+
+    fn peek(&self) -> Option<&BorrowedToken<'src>> {
+        self.rest().first()
     }
 
-    fn next(&mut self) -> Option<Token> {
-        if self.tokens.len() > 0 {
-            self.tokens.drain(0..1).next()
+    fn next(&mut self) -> Option<BorrowedToken<'src>> {
+        let token = self.peek().copied()?;
+        self.position += 1;
+        Some(token)
+    }
+
+    fn context(&self) -> String {
+        self.context.last().cloned().unwrap_or_else(|| "program".to_string())
+    }
+
+    fn unexpected_token(&self, expected: &[&str], token: BorrowedToken) -> Error {
+        Error::UnexpectedToken(Self::quote_all(expected), self.context(), token.to_token())
+    }
+
+    fn unexpected_eof(&self, expected: &[&str]) -> Error {
+        Error::UnexpectedEndOfFile(Self::quote_all(expected), self.context())
+    }
+
+    /// Quote literal tokens with backticks. Token classes (like "identifier") are passed through unchanged.
+    fn quote_all(expected: &[&str]) -> Vec<String> {
+        expected.iter()
+            .map(|e| if TOKEN_CLASSES.contains(e) {
+                e.to_string()
+            } else {
+                format!("`{}`", e)
+            })
+            .collect()
+    }
+
+    /// Consume the next token, which must be one of `expected`.
+    fn expect(&mut self, expected: &[&str]) -> Result<BorrowedToken<'src>, Error> {
+        let token = self.next().ok_or_else(|| self.unexpected_eof(expected))?;
+        if expected.contains(&token.value) {
+            Ok(token)
         } else {
-            None
+            Err(self.unexpected_token(expected, token))
         }
     }
 
-    fn is_valid_identifier(token: &Token) -> bool {
-        !token.value.contains(|c| match c {
-            '{' | '}' | '(' | ')' | '[' | ']' | '.' | ',' | ';' | ':' | '"' => true,
-            _ => false,
-        })
+    /// Go a level deeper into the source for the bracket `open`, failing rather than going past the limit.
+    fn nest(&mut self, open: BorrowedToken, what: &str) -> Result<(), Error> {
+        self.depth += 1;
+        if self.depth > self.max_depth {
+            let message = format!("{} are nested more than {} deep", what, self.max_depth);
+            return Err(Error::ParseError(message, open.to_token()));
+        }
+        Ok(())
+    }
+
+    fn is_valid_identifier(token: &BorrowedToken) -> bool {
+        matches!(token.kind, TokenKind::Identifier | TokenKind::Combinator)
+    }
+
+    /// Parse the tokens the parser was made with into cycles.
+    pub fn parse(&mut self) -> Result<Vec<Cycle>, Error> {
+        let mut cycles: Vec<Cycle> = Vec::new();
+        while self.position < self.tokens.len() {
+            // Each cycle is parsed from only its own tokens, split off the way `StreamingParser` splits them.
+            let length = cycle_length(self)?;
+            self.end = self.position + length;
+            cycles.extend(self.parse_cycles()?);
+        }
+        Ok(cycles)
     }
 
-    fn parse(&mut self) -> Result<Vec<Cycle>, Error> {
+    /// Parse the cycles in the tokens up to `end`, which is usually one, but can be none if they're only doc
+    /// comments, or more if a term stops early.
+    fn parse_cycles(&mut self) -> Result<Vec<Cycle>, Error> {
         let mut cycles: Vec<Cycle> = Vec::new();
         while let Some(token) = self.peek() {
-            let cycle = if token.value == "def" {
-                self.parse_definition()
+            let cycle = if token.kind == TokenKind::DocComment {
+                let docs = self.parse_doc_comments();
+                match self.peek() {
+                    Some(token) if token.kind == TokenKind::Keyword && token.value == "def" => self.parse_definition(Some(docs))?,
+                    Some(_) if self.modified_def_follows() => self.parse_definition(Some(docs))?,
+                    // Doc comments that don't come before a definition are just comments.
+                    _ => continue,
+                }
+            } else if (token.kind == TokenKind::Keyword && token.value == "def") || self.modified_def_follows() {
+                self.parse_definition(None)?
+            } else if token.kind == TokenKind::Keyword && token.value == "test" {
+                self.parse_test()?
+            } else if token.kind == TokenKind::Keyword && token.value == "macro" {
+                self.parse_macro()?
             } else {
-                Ok(Cycle::Term(self.parse_term()?))
-            }?;
+                let term = self.parse_term()?;
+                if term.is_empty() {
+                    // Nothing could start a factor here, so we'd loop forever if we didn't bail out.
+                    let token = self.next().unwrap();
+                    return Err(self.unexpected_token(&["def", "test", "macro", "factor"], token));
+                }
+                let span = term_span(&term);
+                Cycle::Term(term, span)
+            };
             cycles.push(cycle);
         }
         Ok(cycles)
     }
 
     /// Parse a definition.
-    /// definition ::= "def" identifier ":" type "=" factor ";"
-    fn parse_definition(&mut self) -> Result<Cycle, Error> {
-        let def = self.next().ok_or(Error::UnexpectedEndOfFile(format!("Unexpected EOF, expected def")))?;
-        if def.value != "def" {
-            return Err(Error::UnexpectedToken("def".to_string(), def));
+    /// definition ::= { "total" | "memo" } "def" [ "override" ] identifier ":" type [ "!" { identifier } ] "=" factor ";"
+    ///
+    /// Core combinators can only be defined with `override`, which says that shadowing one is intended. The
+    /// combinator means the new definition from after it to the end of the source, but still means itself in
+    /// the definition's own body.
+    fn parse_definition(&mut self, docs: Option<String>) -> Result<Cycle, Error> {
+        self.context.push("definition".to_string());
+        let mut modifiers = Vec::new();
+        while self.modified_def_follows() {
+            modifiers.push(self.next().unwrap());
         }
-        let name = self.next().ok_or(Error::UnexpectedEndOfFile(format!("Unexpected EOF, expected name")))?;
-        if Self::is_valid_identifier(&name) == false {
-            return Err(Error::UnexpectedToken("identifier".to_string(), name));
+        let def = self.expect(&["def"])?;
+        // `override` is only a modifier when a name follows it, so a word can still be called `override`.
+        let overriding = self.peek().is_some_and(|token| token.value == "override")
+            && self.rest().get(1).is_some_and(|token| token.value != ":");
+        if overriding {
+            self.next();
         }
-        let colon = self.next().ok_or(Error::UnexpectedEndOfFile(format!("Unexpected EOF, expected colon")))?;
-        if colon.value != ":" {
-            return Err(Error::UnexpectedToken(":".to_string(), colon));
+        let name = self.next().ok_or_else(|| self.unexpected_eof(&["identifier"]))?;
+        if !Self::is_valid_identifier(&name) {
+            return Err(self.unexpected_token(&["identifier"], name));
         }
-        let type_ = self.parse_type()?;
-        let equals = self.next().ok_or(Error::UnexpectedEndOfFile(format!("Unexpected EOF, expected =")))?;
-        if equals.value != "=" {
-            return Err(Error::UnexpectedToken("=".to_string(), equals));
+        let combinator = name.kind == TokenKind::Combinator;
+        if combinator && !overriding && !self.overridden.contains(name.value) {
+            let message = format!("`{0}` is a core combinator, so it can't be defined without `def override {0}`", name.value);
+            return Err(Error::ParseError(message, name.to_token()));
+        }
+        if overriding && !combinator {
+            let message = format!("`{}` isn't a core combinator, so there's nothing for `override` to shadow", name.value);
+            return Err(Error::ParseError(message, name.to_token()));
+        }
+        self.context.pop();
+        self.context.push(format!("definition of `{}`", name.value));
+        self.expect(&[":"])?;
+        self.context.push(format!("type annotation of `{} {}`", def.value, name.value));
+        let mut type_ = self.parse_type()?;
+        if self.peek().is_some_and(|token| token.kind == TokenKind::Identifier && token.value == "!") {
+            let bang = self.next().unwrap().to_token();
+            let mut effects = Vec::new();
+            while self.peek().is_some_and(|token| token.kind == TokenKind::Identifier && token.value != "=") {
+                effects.push(self.next().unwrap().to_token());
+            }
+            type_ = TypeAnnotation::Effectful(Box::new(type_), bang, effects);
         }
+        for modifier in modifiers.iter().rev() {
+            type_ = match modifier.value {
+                "memo" => TypeAnnotation::Memo(Box::new(type_), modifier.to_token()),
+                _ => TypeAnnotation::Total(Box::new(type_), modifier.to_token()),
+            };
+        }
+        self.context.pop();
+        self.expect(&["="])?;
         let term = self.parse_term()?;
-        let semi = self.next().ok_or(Error::UnexpectedEndOfFile(format!("Unexpected EOF, expected ;")))?;
-        if semi.value != ";" {
-            return Err(Error::UnexpectedToken(";".to_string(), semi));
+        let semi = self.expect(&[";"])?;
+        self.context.pop();
+        if overriding {
+            self.overridden.insert(name.value);
         }
-        Ok(Cycle::Definition(name.value, type_, term))
+        let start = modifiers.first().unwrap_or(&def).span;
+        Ok(Cycle::Definition(name.to_token(), type_, term, start.merge(&semi.span), docs))
+    }
+
+    fn modified_def_follows(&self) -> bool {
+        modified_def(self.rest().iter().map(|token| (token.kind, token.value)))
+    }
+
+    /// Consume consecutive doc comments, joining their lines with the `##` markers stripped.
+    fn parse_doc_comments(&mut self) -> String {
+        let mut lines = Vec::new();
+        while self.peek().is_some_and(|token| token.kind == TokenKind::DocComment) {
+            let token = self.next().unwrap();
+            let line = &token.value[2..];
+            lines.push(line.strip_prefix(' ').unwrap_or(line).trim_end().to_string());
+        }
+        lines.join("\n")
+    }
+
+    /// Whether the doc comments coming up belong to a definition, rather than being inside a term.
+    fn docs_precede_definition(&self) -> bool {
+        let tokens = self.rest().iter().skip_while(|token| token.kind == TokenKind::DocComment);
+        match tokens.clone().next() {
+            Some(token) => (token.kind == TokenKind::Keyword && token.value == "def") || modified_def(tokens.map(|token| (token.kind, token.value))),
+            None => true,
+        }
+    }
+
+    /// Parse a test.
+    /// test ::= "test" string_literal "{" term [ "expect" term ] "}"
+    fn parse_test(&mut self) -> Result<Cycle, Error> {
+        self.context.push("test".to_string());
+        let test = self.expect(&["test"])?;
+        let name = self.next().ok_or_else(|| self.unexpected_eof(&["string"]))?;
+        if name.kind != TokenKind::String {
+            return Err(self.unexpected_token(&["string"], name));
+        }
+        let name = name.value[1..name.value.len() - 1].to_string();
+        self.context.pop();
+        self.context.push(format!("test \"{}\"", name));
+        self.expect(&["{"])?;
+        let body = self.parse_term()?;
+        let mut close = self.expect(&["expect", "}"])?;
+        let mut expected = None;
+        if close.value == "expect" {
+            expected = Some(self.parse_term()?);
+            close = self.expect(&["}"])?;
+        }
+        self.context.pop();
+        Ok(Cycle::Test(name, body, expected, test.span.merge(&close.span)))
+    }
+
+    /// Parse a macro, which takes a quotation of the factors after it in a term and leaves the quotation they
+    /// are expanded into.
+    /// macro ::= "macro" identifier "=" term ";"
+    fn parse_macro(&mut self) -> Result<Cycle, Error> {
+        self.context.push("macro".to_string());
+        let keyword = self.expect(&["macro"])?;
+        let name = self.next().ok_or_else(|| self.unexpected_eof(&["identifier"]))?;
+        if name.kind != TokenKind::Identifier {
+            return Err(self.unexpected_token(&["identifier"], name));
+        }
+        self.context.pop();
+        self.context.push(format!("macro `{}`", name.value));
+        self.expect(&["="])?;
+        let body = self.parse_term()?;
+        let semi = self.expect(&[";"])?;
+        self.context.pop();
+        Ok(Cycle::Macro(name.value.to_string(), body, keyword.span.merge(&semi.span)))
     }
 
     /// Parse a type annotation
-    /// type ::= "Int" | "Bool" | "String" | identifier | "(" type { "," type } -> type { "," type } ")"
+    /// type ::= "Int" | "Bool" | "String" | identifier | identifier "(" type { "," type } ")"
+    ///        | "(" type { "," type } -> type { "," type } ")"
+    ///
+    /// A type constructor's arguments have to follow its name without a space, since types can also be
+    /// separated by spaces in an assertion.
     fn parse_type(&mut self) -> Result<TypeAnnotation, Error> {
-        let first_token = self.next().ok_or(Error::UnexpectedEndOfFile(format!("Unexpected EOF, expected type")))?;
+        let first_token = self.next().ok_or_else(|| self.unexpected_eof(&["type"]))?;
         if first_token.value == "Int" {
-            Ok(TypeAnnotation::Identifier("Int".to_string(), first_token))
+            Ok(TypeAnnotation::Identifier("Int".to_string(), first_token.to_token()))
         } else if first_token.value == "Bool" {
-            Ok(TypeAnnotation::Identifier("Bool".to_string(), first_token))
+            Ok(TypeAnnotation::Identifier("Bool".to_string(), first_token.to_token()))
         } else if first_token.value == "String" {
-            Ok(TypeAnnotation::Identifier("String".to_string(), first_token))
+            Ok(TypeAnnotation::Identifier("String".to_string(), first_token.to_token()))
+        } else if Self::is_valid_identifier(&first_token)
+            && self.peek().is_some_and(|token| token.value == "(" && token.span.start == first_token.span.end) {
+            let open = self.next().unwrap();
+            self.nest(open, "type arguments")?;
+            let mut args = vec![self.parse_type()?];
+            while self.peek().is_some_and(|token| token.value == ",") {
+                self.next();
+                args.push(self.parse_type()?);
+            }
+            let close = self.expect(&[",", ")"])?;
+            self.depth -= 1;
+            Ok(TypeAnnotation::Apply(first_token.value.to_string(), args, first_token.to_token(), close.to_token()))
         } else if Self::is_valid_identifier(&first_token) {
-            Ok(TypeAnnotation::Identifier(first_token.value.to_string(), first_token))
+            Ok(TypeAnnotation::Identifier(first_token.value.to_string(), first_token.to_token()))
         } else if first_token.value == "(" {
+            self.nest(first_token, "function types")?;
+            // Either side can be empty, as in `( -> Int)` for a word that only pushes a value.
             let mut in_types: Vec<TypeAnnotation> = Vec::new();
-            in_types.push(self.parse_type()?);
-            while let Some(token) = self.next() {
-                if token.value == "->" {
-                    break;
-                } else if token.value == "," {
+            if self.peek().is_some_and(|token| token.value == "->") {
+                self.next();
+            } else {
+                in_types.push(self.parse_type()?);
+                while self.expect(&[",", "->"])?.value == "," {
                     in_types.push(self.parse_type()?);
-                } else {
-                    return Err(Error::UnexpectedToken(",".to_string(), token));
                 }
             }
             let mut out_types: Vec<TypeAnnotation> = Vec::new();
-            out_types.push(self.parse_type()?);
-            let mut last_token = first_token.clone();
-            while let Some(token) = self.next() {
-                if token.value == ")" {
-                    last_token = token;
-                    break;
-                } else if token.value == "," {
+            let last_token = match self.peek() {
+                Some(token) if token.value == ")" => self.next().unwrap(),
+                _ => {
                     out_types.push(self.parse_type()?);
-                } else {
-                    return Err(Error::UnexpectedToken(",".to_string(), token));
+                    loop {
+                        let token = self.expect(&[",", ")"])?;
+                        if token.value == ")" {
+                            break token;
+                        }
+                        out_types.push(self.parse_type()?);
+                    }
                 }
-                last_token = token;
-            }
-            Ok(TypeAnnotation::Function(in_types, out_types, first_token, last_token))
+            };
+            self.depth -= 1;
+            Ok(TypeAnnotation::Function(in_types, out_types, first_token.to_token(), last_token.to_token()))
         } else {
-            Err(Error::UnexpectedToken("type".to_string(), first_token))
+            Err(self.unexpected_token(&["type"], first_token))
         }
     }
 
     /// Parse a factor.
-    /// term ::= { factor }
+    /// term ::= { factor | cond | let | assertion | ascription }
     fn parse_term(&mut self) -> Result<Vec<Factor>, Error> {
         let mut factors = Vec::new();
         loop {
+            if self.modified_def_follows() {
+                break;
+            }
+            // The body of a `let` ends at the first `end` that isn't in a nested one.
+            if !self.locals.is_empty() && self.peek().is_some_and(|token| token.kind == TokenKind::Identifier && token.value == "end") {
+                break;
+            }
+            if let Some(count) = self.let_names() {
+                factors.extend(self.parse_let(count)?);
+                continue;
+            }
+            if self.peek().is_some_and(|token| token.kind == TokenKind::DocComment) {
+                if self.docs_precede_definition() {
+                    break;
+                }
+                self.parse_doc_comments();
+                continue;
+            }
+            if self.cond_follows() {
+                factors.extend(self.parse_cond()?);
+                continue;
+            }
+            if self.peek().is_some_and(|token| token.kind == TokenKind::Punct && token.value == "(") {
+                factors.push(self.parse_assertion()?);
+                continue;
+            }
+            if self.ascription_follows() {
+                factors.push(self.parse_ascription()?);
+                continue;
+            }
             let factor = self.parse_factor();
             match factor {
                 Ok(factor) => factors.push(factor),
@@ -188,54 +635,505 @@ impl Parser {
         Ok(factors)
     }
 
+    fn let_names(&self) -> Option<usize> {
+        let_names(self.rest().iter().map(|token| (token.kind, token.value)))
+    }
+
+    /// Parse a stack effect assertion, with the types below and above the `--` written the way they are in
+    /// annotations, oldest value first.
+    /// assertion ::= "(" { type } "--" { type } ")"
+    fn parse_assertion(&mut self) -> Result<Factor, Error> {
+        let open = self.next().unwrap();
+        self.context.push("stack effect assertion".to_string());
+        let mut t_in = Vec::new();
+        while self.peek().is_some_and(|token| token.value != "--" && token.value != ")") {
+            t_in.push(self.parse_type()?);
+        }
+        self.expect(&["--"])?;
+        let mut t_out = Vec::new();
+        while self.peek().is_some_and(|token| token.value != ")") {
+            t_out.push(self.parse_type()?);
+        }
+        let close = self.expect(&[")"])?;
+        self.context.pop();
+        Ok(Factor::Assertion(Box::new(TypeAnnotation::Function(t_in, t_out, open.to_token(), close.to_token()))))
+    }
+
+    fn ascription_follows(&self) -> bool {
+        let colon = |token: Option<&BorrowedToken>| token.is_some_and(|token| token.kind == TokenKind::Punct && token.value == ":");
+        colon(self.peek()) && colon(self.rest().get(1))
+    }
+
+    /// Parse a type ascription, which is a `::` and the type the value on top of the stack has there.
+    /// ascription ::= ":" ":" type
+    fn parse_ascription(&mut self) -> Result<Factor, Error> {
+        let token = self.next().unwrap();
+        self.next();
+        self.context.push("type ascription".to_string());
+        let annotation = self.parse_type()?;
+        self.context.pop();
+        Ok(Factor::Ascription(Box::new(annotation), token.to_token()))
+    }
+
+    /// Parse a `let` binding `count` names, into a quotation of its body and the `Let` that runs it.
+    /// let ::= "let" identifier { identifier } "in" term "end"
+    fn parse_let(&mut self, count: usize) -> Result<[Factor; 2], Error> {
+        let token = self.next().unwrap();
+        if count == 0 {
+            return Err(Error::ParseError("`let` has to bind at least one name".to_string(), token.to_token()));
+        }
+        let mut names = Vec::new();
+        for _ in 0..count {
+            let name = self.next().unwrap();
+            if names.contains(&name.value) || self.locals.iter().flatten().any(|local| *local == name.value) {
+                let message = format!("`{}` is already bound by a `let` here, and locals can't be shadowed", name.value);
+                return Err(Error::ParseError(message, name.to_token()));
+            }
+            names.push(name.value);
+        }
+        let open = self.next().unwrap();
+        self.nest(token, "`let`s")?;
+        self.context.push("`let`".to_string());
+        self.locals.push(names);
+        let body = self.parse_term()?;
+        let close = self.expect(&["end"])?;
+        let names = self.locals.pop().unwrap().into_iter().map(Symbol::intern).collect();
+        self.context.pop();
+        self.depth -= 1;
+        Ok([Factor::Quotation(body, open.span.merge(&close.span)), Factor::Let(names, token.to_token())])
+    }
+
+    /// Whether the next tokens are `cond` with its clauses after it, rather than a plain `cond`.
+    fn cond_follows(&self) -> bool {
+        self.peek().is_some_and(|token| token.kind == TokenKind::Combinator && token.value == "cond" && !self.overridden.contains("cond"))
+            && self.rest().get(1).is_some_and(|token| token.kind == TokenKind::Punct && token.value == "{")
+    }
+
+    /// Parse `cond` with its clauses in braces after it, into a quotation of the clauses and a plain `cond`,
+    /// which means the same.
+    /// cond ::= "cond" "{" { "[" term "]" "[" term "]" } "[" term "]" "}"
+    fn parse_cond(&mut self) -> Result<[Factor; 2], Error> {
+        let cond = self.next().unwrap();
+        let open = self.next().unwrap();
+        self.nest(open, "quotations")?;
+        self.context.push("`cond`".to_string());
+        let mut clauses = Vec::new();
+        while self.peek().is_some_and(|token| token.kind == TokenKind::Punct && token.value == "[") {
+            clauses.push(self.parse_factor()?);
+        }
+        let close = self.expect(&["[", "}"])?;
+        self.context.pop();
+        self.depth -= 1;
+        if clauses.len() % 2 == 0 {
+            let message = "`cond` takes pairs of a `[predicate]` and a `[body]`, then a `[default]`".to_string();
+            return Err(Error::ParseError(message, cond.to_token()));
+        }
+        Ok([Factor::Quotation(clauses, open.span.merge(&close.span)), Factor::Cond(cond.to_token())])
+    }
+
     /// Parse a factor.
     /// factor ::=
     ///          "[" term "]"
-    ///        | integer_literal | boolean_literal | string_literal | identifier | "(" term ")"
+    ///        | integer_literal | boolean_literal | string_literal | identifier | "&" identifier | "(" term ")"
     fn parse_factor(&mut self) -> Result<Factor, Error> {
-        let token = self.peek().ok_or(Error::EndOfTerm)?;
-        match token.value.as_str() {
-            "[" => {
-                let _brace = self.next().unwrap();
+        let token = *self.peek().ok_or(Error::EndOfTerm)?;
+        match token.kind {
+            TokenKind::Punct if token.value == "[" => {
+                let open = self.next().unwrap();
+                self.nest(open, "quotations")?;
+                self.context.push("quotation".to_string());
                 let term = self.parse_term()?;
-                let close = self.next().ok_or(Error::UnexpectedEndOfFile(format!("Unexpected EOF, expected ]")))?;
-                if close.value != "]" {
-                    return Err(Error::UnexpectedToken("]".to_string(), close));
-                }
-                Ok(Factor::Quotation(term))
-            }
-            "dup" => Ok(Factor::Dup(self.next().unwrap())),
-            "drop" => Ok(Factor::Drop(self.next().unwrap())),
-            "quote" => Ok(Factor::Quote(self.next().unwrap())),
-            "call" => Ok(Factor::Call(self.next().unwrap())),
-            "cat" => Ok(Factor::Cat(self.next().unwrap())),
-            "swap" => Ok(Factor::Swap(self.next().unwrap())),
-            "ifte" => Ok(Factor::Ifte(self.next().unwrap())),
-            _ => match token.value.parse::<i64>() {
-                Ok(i) => Ok(Factor::Int(Value::Integer(i), self.next().unwrap())),
-                Err(_) => match token.value.parse::<bool>() {
-                    Ok(b) => Ok(Factor::Bool(Value::Boolean(b), self.next().unwrap())),
-                    Err(_) => {
-                        if Self::is_valid_identifier(&token) {
-                            Ok(Factor::Identifier(token.value.to_string(), self.next().unwrap()))
-                        } else if token.value.starts_with('"') && token.value.ends_with('"') {
-                            Ok(Factor::String(Value::String(token.value.trim_matches('"').to_string()), self.next().unwrap()))
-                        } else {
-                            Err(Error::EndOfTerm)
-                        }
-                    }
-                },
+                let close = self.expect(&["]"])?;
+                self.context.pop();
+                self.depth -= 1;
+                Ok(Factor::Quotation(term, open.span.merge(&close.span)))
+            }
+            TokenKind::Combinator if self.overridden.contains(token.value) => {
+                Ok(Factor::Identifier(Symbol::intern(token.value), self.next().unwrap().to_token()))
+            }
+            TokenKind::Combinator => Ok(combinator(self.next().unwrap().to_token())),
+            TokenKind::Integer(i) => Ok(Factor::Int(Value::Integer(i), self.next().unwrap().to_token())),
+            TokenKind::Boolean(b) => Ok(Factor::Bool(Value::Boolean(b), self.next().unwrap().to_token())),
+            TokenKind::String => {
+                let token = self.next().unwrap();
+                let contents = token.value[1..token.value.len() - 1].to_string();
+                Ok(Factor::String(Value::String(contents), token.to_token()))
+            }
+            TokenKind::Bytes => bytes_literal(&self.next().unwrap().to_token()),
+            TokenKind::Identifier if token.value.len() > 1 && token.value.starts_with('&') => self.parse_reference(),
+            TokenKind::Identifier if self.locals.iter().flatten().any(|local| *local == token.value) => {
+                Ok(Factor::Local(Symbol::intern(token.value), self.next().unwrap().to_token()))
+            }
+            TokenKind::Identifier => Ok(Factor::Identifier(Symbol::intern(token.value), self.next().unwrap().to_token())),
+            TokenKind::Keyword | TokenKind::Punct | TokenKind::DocComment | TokenKind::Unknown => Err(Error::EndOfTerm),
+        }
+    }
+
+    /// Parse a `&word` reference, which is a quotation of just the word, so it can be handed to a combinator
+    /// without brackets. It's checked like any other quotation, so a reference to a word that isn't defined is a
+    /// type error before anything runs.
+    fn parse_reference(&mut self) -> Result<Factor, Error> {
+        let token = self.next().unwrap();
+        let name = &token.value[1..];
+        let word = BorrowedToken {
+            value: name,
+            kind: TokenKind::classify(name),
+            col: token.col + 1,
+            span: Span { start: token.span.start + 1, ..token.span },
+            ..token
+        };
+        let factor = match word.kind {
+            TokenKind::Combinator if !self.overridden.contains(name) => combinator(word.to_token()),
+            TokenKind::Identifier | TokenKind::Combinator if self.locals.iter().flatten().any(|local| *local == name) => {
+                Factor::Local(Symbol::intern(name), word.to_token())
             }
+            TokenKind::Identifier | TokenKind::Combinator => Factor::Identifier(Symbol::intern(name), word.to_token()),
+            _ => return Err(Error::ParseError(format!("`&` has to be followed by the name of a word, not `{}`", name), token.to_token())),
+        };
+        Ok(Factor::Quotation(vec![factor], token.span))
+    }
+}
+
+/// The factor for a combinator the scanner classified as one.
+fn combinator(token: Token) -> Factor {
+    match token.value.as_str() {
+        "dup" => Factor::Dup(token),
+        "drop" => Factor::Drop(token),
+        "quote" => Factor::Quote(token),
+        "call" => Factor::Call(token),
+        "cat" => Factor::Cat(token),
+        "swap" => Factor::Swap(token),
+        "ifte" => Factor::Ifte(token),
+        "cond" => Factor::Cond(token),
+        _ => unreachable!("the scanner only classifies known combinators"),
+    }
+}
+
+/// The factor for a bytes literal, of the UTF-8 of its text for `b"..."`, or of its pairs of hex digits for
+/// `x"..."`. It's apart from `parse_factor` to keep that function's frame small, since it recurses.
+fn bytes_literal(token: &Token) -> Result<Factor, Error> {
+    let literal = token.value.as_str();
+    let contents = &literal[2..literal.len() - 1];
+    let error = |message: &str| Error::ParseError(format!("Bytes literal {} has {}", literal, message), token.clone());
+    let bytes = if literal.starts_with('b') {
+        contents.as_bytes().to_vec()
+    } else if contents.len() % 2 == 1 {
+        return Err(error("an odd number of hex digits"));
+    } else {
+        contents.as_bytes().chunks(2)
+            .map(|pair| {
+                let pair = core::str::from_utf8(pair).ok().filter(|pair| pair.bytes().all(|digit| digit.is_ascii_hexdigit()));
+                pair.and_then(|pair| u8::from_str_radix(pair, 16).ok()).ok_or_else(|| error("something other than hex digits in it"))
+            })
+            .collect::<Result<_, _>>()?
+    };
+    Ok(Factor::Bytes(Value::Bytes(bytes), token.clone()))
+}
+
+/// How many names a `let` binds, if `tokens` start with one. `let` is only a binding when `in` follows it, after
+/// any names, so a word can still be called `let`.
+pub(crate) fn let_names<'a>(mut tokens: impl Iterator<Item = (TokenKind, &'a str)>) -> Option<usize> {
+    if tokens.next() != Some((TokenKind::Identifier, "let")) {
+        return None;
+    }
+    let mut count = 0;
+    loop {
+        match tokens.next()? {
+            (TokenKind::Identifier, "in") => return Some(count),
+            (TokenKind::Identifier, _) => count += 1,
+            _ => return None,
         }
     }
 }
 
+/// Whether `tokens` start with a `def` after modifiers, like `total def` or `memo def`. They're only modifiers
+/// before `def`, so words can still be called `total` and `memo`.
+pub(crate) fn modified_def<'a>(tokens: impl Iterator<Item = (TokenKind, &'a str)>) -> bool {
+    let mut modifiers = 0;
+    for token in tokens {
+        match token {
+            (TokenKind::Identifier, "total" | "memo") => modifiers += 1,
+            (TokenKind::Keyword, "def") => return modifiers > 0,
+            _ => return false,
+        }
+    }
+    false
+}
+
+/// Tokens to split the next cycle off the front of, from a source that may only give them as they're looked at.
+trait Lookahead {
+    /// Look at the tokens up to the `n`th from the front, or as many as there are.
+    fn look(&mut self, n: usize) -> Result<(), Error>;
+
+    /// The kind and text of the `n`th token from the front, if it's been looked at.
+    fn get(&self, n: usize) -> Option<(TokenKind, &str)>;
+
+    fn at(&mut self, n: usize) -> Result<Option<(TokenKind, &str)>, Error> {
+        self.look(n)?;
+        Ok(self.get(n))
+    }
+}
+
+impl Lookahead for Parser<'_> {
+    fn look(&mut self, _: usize) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Cycles are split before they're parsed, so this looks past the end of the one being parsed.
+    fn get(&self, n: usize) -> Option<(TokenKind, &str)> {
+        self.tokens.get(self.position + n).map(|token| (token.kind, token.value))
+    }
+}
+
+/// The tokens that have been looked at from the `n`th on.
+fn looked_at(tokens: &impl Lookahead, n: usize) -> impl Iterator<Item = (TokenKind, &str)> {
+    (n..).map_while(move |i| tokens.get(i))
+}
+
+/// How many tokens the next cycle has: a definition or macro up to its `;`, a test up to its `}`, or a term up to
+/// the next definition, test or macro, with any doc comments before it. A cycle that doesn't end where it should
+/// takes the token it goes wrong at, so parsing it fails the same way it would with every token there. Both
+/// `Parser::parse` and `StreamingParser` split cycles with this, so they can't disagree on where one ends.
+fn cycle_length(tokens: &mut impl Lookahead) -> Result<usize, Error> {
+    let docs = doc_comments_at(tokens, 0)?;
+    let close = match tokens.at(docs)? {
+        None => return Ok(docs),
+        Some((TokenKind::Keyword, "def" | "macro")) => Some(";"),
+        Some((TokenKind::Keyword, "test")) => Some("}"),
+        Some(_) => None,
+    };
+    let close = match close {
+        Some(close) => close,
+        None if modified_def_at(tokens, docs)? => ";",
+        None => return term_length(tokens, docs),
+    };
+    // Braces can nest, since `cond` has its clauses in them.
+    let mut braces = 0usize;
+    let mut n = docs;
+    while let Some(token) = tokens.at(n)? {
+        n += 1;
+        match token {
+            (TokenKind::Punct, "{") => braces += 1,
+            (TokenKind::Punct, "}") => braces = braces.saturating_sub(1),
+            _ => {}
+        }
+        if token == (TokenKind::Punct, close) && braces == 0 {
+            break;
+        }
+    }
+    Ok(n)
+}
+
+/// How many tokens a top-level term starting at the `n`th token has, with the `n` before it. It runs up to a `def`,
+/// `test` or `macro` outside any quotation, `cond`, `let` or assertion, or up to doc comments for a definition.
+fn term_length(tokens: &mut impl Lookahead, mut n: usize) -> Result<usize, Error> {
+    let mut depth = 0usize;
+    let mut lets = 0usize;
+    // Inside an assertion's parentheses, every word is a type.
+    let mut parens = 0usize;
+    while tokens.at(n)?.is_some() {
+        // A `let`'s names and `in` are taken with it, since they could look like the start of another.
+        if let Some(count) = let_names_at(tokens, n)?.filter(|_| parens == 0) {
+            n += count + 2;
+            depth += 1;
+            lets += 1;
+            continue;
+        }
+        // So is the token after a `::`, which starts the type, whatever it is.
+        if parens == 0 && ascription_at(tokens, n)? {
+            if matches!(tokens.at(n + 2)?, Some((TokenKind::Punct, "("))) {
+                depth += 1;
+                parens += 1;
+            }
+            n += 2;
+            if tokens.at(n)?.is_some() {
+                n += 1;
+            }
+            continue;
+        }
+        if depth == 0 && modified_def_at(tokens, n)? {
+            break;
+        }
+        let starts_cycle = matches!(tokens.get(n), Some((TokenKind::Keyword, "def" | "test" | "macro")));
+        if depth == 0 && starts_cycle {
+            break;
+        }
+        if depth == 0 && matches!(tokens.get(n), Some((TokenKind::DocComment, _))) {
+            let docs = doc_comments_at(tokens, n)?;
+            let definition = modified_def_at(tokens, n + docs)? || matches!(tokens.at(n + docs)?, Some((TokenKind::Keyword, "def")));
+            if !definition {
+                n += docs;
+                continue;
+            }
+            break;
+        }
+        match tokens.get(n) {
+            Some((TokenKind::Punct, "[" | "{")) => depth += 1,
+            Some((TokenKind::Punct, "]" | "}")) => depth = depth.saturating_sub(1),
+            Some((TokenKind::Punct, "(")) => {
+                depth += 1;
+                parens += 1;
+            }
+            Some((TokenKind::Punct, ")")) => {
+                depth = depth.saturating_sub(1);
+                parens = parens.saturating_sub(1);
+            }
+            Some((TokenKind::Identifier, "end")) if lets > 0 && parens == 0 => {
+                depth = depth.saturating_sub(1);
+                lets -= 1;
+            }
+            _ => {}
+        }
+        n += 1;
+        if starts_cycle {
+            break;
+        }
+    }
+    Ok(n)
+}
+
+/// How many doc comments there are in a row from the `n`th token on.
+fn doc_comments_at(tokens: &mut impl Lookahead, n: usize) -> Result<usize, Error> {
+    let mut count = 0;
+    while matches!(tokens.at(n + count)?, Some((TokenKind::DocComment, _))) {
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Whether a `def` with modifiers, like a `total def`, starts at the `n`th token.
+fn modified_def_at(tokens: &mut impl Lookahead, n: usize) -> Result<bool, Error> {
+    let mut end = n;
+    while matches!(tokens.at(end)?, Some((TokenKind::Identifier, "total" | "memo"))) {
+        end += 1;
+    }
+    Ok(modified_def(looked_at(tokens, n)))
+}
+
+/// How many names a `let` at the `n`th token binds, if there's one there.
+fn let_names_at(tokens: &mut impl Lookahead, n: usize) -> Result<Option<usize>, Error> {
+    if !matches!(tokens.at(n)?, Some((TokenKind::Identifier, "let"))) {
+        return Ok(None);
+    }
+    let mut count = 1;
+    while matches!(tokens.at(n + count)?, Some((TokenKind::Identifier, name)) if name != "in") {
+        count += 1;
+    }
+    Ok(let_names(looked_at(tokens, n)))
+}
+
+fn ascription_at(tokens: &mut impl Lookahead, n: usize) -> Result<bool, Error> {
+    let colon = |token: Option<(TokenKind, &str)>| token == Some((TokenKind::Punct, ":"));
+    Ok(colon(tokens.at(n)?) && colon(tokens.at(n + 1)?))
+}
+
+/// How deeply quotations and function types can be nested by default. Checking and evaluating are recursive
+/// in places, so without a limit, deeply nested input could run them out of stack.
+pub const MAX_DEPTH: usize = 128;
+
 pub fn parse(string: &str) -> Result<Vec<Cycle>, Error> {
-    let tokens = scan(string).unwrap();
+    parse_with_max_depth(string, MAX_DEPTH)
+}
+
+/// Parse with a limit other than `MAX_DEPTH` on how deeply quotations and function types can be nested.
+pub fn parse_with_max_depth(string: &str, max_depth: usize) -> Result<Vec<Cycle>, Error> {
+    let tokens = scan_borrowed(string)?;
     let mut parser = Parser::new(tokens);
+    parser.max_depth = max_depth;
     parser.parse()
 }
 
+/// Parse the source `reader` reads as it's scanned, a cycle at a time.
+#[cfg(feature = "std")]
+pub fn parse_reader<R: std::io::BufRead>(reader: R) -> StreamingParser<crate::scanner::TokenReader<R>> {
+    StreamingParser::new(crate::scanner::scan_reader(reader))
+}
+
+/// Parses cycles from tokens as they come, only taking as many as the next cycle needs, so the whole source
+/// never has to be scanned or in memory at once. It gives the same cycles as parsing all the tokens together
+/// would, and stops at the first error in the source, which can be a parse error before a string that isn't
+/// closed, where scanning first would find the string.
+pub struct StreamingParser<I> {
+    tokens: I,
+    /// Tokens taken from `tokens` to look ahead at, but not yet part of a cycle.
+    lookahead: VecDeque<Token>,
+    cycles: VecDeque<Cycle>,
+    overridden: BTreeSet<String>,
+    failed: bool,
+}
+
+impl<I: Iterator<Item = Result<Token, Error>>> StreamingParser<I> {
+    pub fn new(tokens: I) -> StreamingParser<I> {
+        StreamingParser { tokens, lookahead: VecDeque::new(), cycles: VecDeque::new(), overridden: BTreeSet::new(), failed: false }
+    }
+
+    /// Take the tokens of the next cycle, or none if there are no tokens left.
+    fn next_tokens(&mut self) -> Result<Vec<Token>, Error> {
+        let length = cycle_length(self)?;
+        Ok(self.lookahead.drain(..length).collect())
+    }
+
+    /// Parse one cycle's tokens, remembering any combinator it overrides for the cycles after it.
+    fn parse(&mut self, tokens: &[Token]) -> Result<Vec<Cycle>, Error> {
+        let mut parser = Parser::new(tokens.iter().map(Token::as_borrowed).collect());
+        parser.overridden = self.overridden.iter().map(String::as_str).collect();
+        let cycles = parser.parse()?;
+        let overridden = parser.overridden.iter().map(|name| name.to_string()).collect();
+        self.overridden = overridden;
+        Ok(cycles)
+    }
+}
+
+impl<I: Iterator<Item = Result<Token, Error>>> Lookahead for StreamingParser<I> {
+    fn look(&mut self, n: usize) -> Result<(), Error> {
+        while self.lookahead.len() <= n {
+            match self.tokens.next() {
+                Some(token) => self.lookahead.push_back(token?),
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn get(&self, n: usize) -> Option<(TokenKind, &str)> {
+        self.lookahead.get(n).map(|token| (token.kind, token.value.as_str()))
+    }
+}
+
+impl<I: Iterator<Item = Result<Token, Error>>> Iterator for StreamingParser<I> {
+    type Item = Result<Cycle, Error>;
+
+    fn next(&mut self) -> Option<Result<Cycle, Error>> {
+        while self.cycles.is_empty() && !self.failed {
+            let cycles = self.next_tokens().and_then(|tokens| match tokens.is_empty() {
+                true => Ok(None),
+                false => self.parse(&tokens).map(Some),
+            });
+            match cycles {
+                Ok(Some(cycles)) => self.cycles.extend(cycles),
+                Ok(None) => return None,
+                Err(error) => {
+                    self.failed = true;
+                    return Some(Err(error));
+                }
+            }
+        }
+        self.cycles.pop_front().map(Ok)
+    }
+}
+
+/// Parse a type annotation by itself, as it would be written after `def name:`.
+pub fn parse_annotation(string: &str) -> Result<TypeAnnotation, Error> {
+    let tokens = scan_borrowed(string)?;
+    let mut parser = Parser::new(tokens);
+    parser.context.push("type annotation".to_string());
+    let annotation = parser.parse_type()?;
+    match parser.next() {
+        Some(token) => Err(parser.unexpected_token(&[], token)),
+        None => Ok(annotation),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -243,7 +1141,7 @@ mod tests {
         let cycles = super::parse("1 2 +").unwrap();
         assert_eq!(cycles.len(), 1);
         match cycles[0] {
-            super::Cycle::Term(ref terms) => {
+            super::Cycle::Term(ref terms, _) => {
                 assert_eq!(terms.len(), 3);
                 match terms[0] {
                     super::Factor::Int(super::Value::Integer(1), _) => {}
@@ -267,7 +1165,7 @@ mod tests {
         let cycles = super::parse("\"Hello\"").unwrap();
         assert_eq!(cycles.len(), 1);
         match cycles[0] {
-            super::Cycle::Term(ref terms) => {
+            super::Cycle::Term(ref terms, _) => {
                 assert_eq!(terms.len(), 1);
                 match &terms[0] {
                     super::Factor::String(super::Value::String(s), _) if s == "Hello" => {}
@@ -278,6 +1176,46 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parses_bytes_in_text_or_hex() {
+        match &super::parse("b\"hi\" x\"00fF\" b\"\"").unwrap()[0] {
+            super::Cycle::Term(terms, _) => {
+                let bytes: Vec<_> = terms.iter()
+                    .map(|factor| match factor {
+                        super::Factor::Bytes(super::Value::Bytes(bytes), _) => bytes.clone(),
+                        factor => panic!("Expected Bytes, got {:?}", factor),
+                    })
+                    .collect();
+                assert_eq!(bytes, vec![b"hi".to_vec(), vec![0, 255], vec![]]);
+            }
+            cycle => panic!("Expected Term, got {:?}", cycle),
+        }
+        for (source, message) in [
+            ("1 x\"0f0\"", "1:3: Bytes literal x\"0f0\" has an odd number of hex digits"),
+            ("x\"0g\"", "1:1: Bytes literal x\"0g\" has something other than hex digits in it"),
+        ] {
+            assert_eq!(super::parse(source).unwrap_err().to_string(), message);
+        }
+    }
+
+    #[test]
+    fn parses_references_to_words_as_quotations_of_them() {
+        match &super::parse("&double &dup [&x]").unwrap()[0] {
+            super::Cycle::Term(terms, _) => {
+                let rendered: Vec<_> = terms.iter().map(crate::format::factor_to_source).collect();
+                assert_eq!(rendered, vec!["[double]", "[dup]", "[[x]]"]);
+                let super::Factor::Quotation(inner, span) = &terms[0] else {
+                    panic!("Expected Quotation, got {:?}", terms[0]);
+                };
+                assert!(matches!(&inner[..], [super::Factor::Identifier(_, token)] if token.col == 2 && token.span.start == 1));
+                assert_eq!((span.start, span.end), (0, 7));
+                assert!(matches!(&terms[1], super::Factor::Quotation(inner, _) if matches!(inner[..], [super::Factor::Dup(_)])));
+            }
+            cycle => panic!("Expected Term, got {:?}", cycle),
+        }
+        assert_eq!(super::parse("1 &2").unwrap_err().to_string(), "1:3: `&` has to be followed by the name of a word, not `2`");
+    }
+
     #[test]
     fn terminates_if_given_a_bad_definition() {
         let error = super::parse("def a: Int = 1 [");
@@ -289,8 +1227,9 @@ mod tests {
         let cycles = super::parse("def a: Int = 1;").unwrap();
         assert_eq!(cycles.len(), 1);
         match cycles[0] {
-            super::Cycle::Definition(ref name, ref annotation, ref factors) => {
-                assert_eq!(name, "a");
+            super::Cycle::Definition(ref name, ref annotation, ref factors, _, _) => {
+                assert_eq!(name.value, "a");
+                assert_eq!((name.line, name.col), (1, 5));
                 match annotation {
                     super::TypeAnnotation::Identifier(s, _) if s == "Int" => {}
                     _ => panic!("Expected Int, got {:?}", annotation),
@@ -305,13 +1244,25 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parses_function_types_with_empty_sides() {
+        let cycles = super::parse("def a: ( -> Int) = 1; def b: (Int -> ) = drop; def c: ( -> ) = 1 drop;").unwrap();
+        let sides: Vec<(usize, usize)> = cycles.iter()
+            .map(|cycle| match cycle {
+                super::Cycle::Definition(_, super::TypeAnnotation::Function(t_in, t_out, _, _), _, _, _) => (t_in.len(), t_out.len()),
+                _ => panic!("Expected a function annotation, got {:?}", cycle),
+            })
+            .collect();
+        assert_eq!(sides, vec![(0, 1), (1, 0), (0, 0)]);
+    }
+
     #[test]
     fn parses_definitions_with_function_types() {
         let cycles = super::parse("def a: (Int, String -> Int, String) = 1 drop;").unwrap();
         assert_eq!(cycles.len(), 1);
         match cycles[0] {
-            super::Cycle::Definition(ref name, ref annotation, ref factors) => {
-                assert_eq!(name, "a");
+            super::Cycle::Definition(ref name, ref annotation, ref factors, _, _) => {
+                assert_eq!(name.value, "a");
                 match annotation {
                     super::TypeAnnotation::Function(ref in_types, out_types, _, _)
                         if in_types.len() == 2 && out_types.len() == 2 => {
@@ -347,4 +1298,245 @@ mod tests {
             _ => panic!("Expected Definition, got {:?}", cycles[0]),
         }
     }
+
+    #[test]
+    fn reports_every_acceptable_token_and_the_enclosing_construct() {
+        let error = super::parse("def a: (Int x").unwrap_err();
+        match error {
+            super::Error::UnexpectedToken(expected, context, token) => {
+                assert_eq!(crate::error::describe_expected(&expected), "expected one of `,`, `->`");
+                assert_eq!(context, "type annotation of `def a`");
+                assert_eq!(token.value, "x");
+            }
+            _ => panic!("Expected UnexpectedToken, got {:?}", error),
+        }
+    }
+
+    #[test]
+    fn reports_what_was_expected_at_end_of_file() {
+        let error = super::parse("def a: Int = 1").unwrap_err();
+        assert_eq!(error, super::Error::UnexpectedEndOfFile(vec!["`;`".to_string()], "definition of `a`".to_string()));
+    }
+
+    #[test]
+    fn attaches_doc_comments_to_definitions() {
+        let cycles = super::parse("## Squares a number.\n##   Indented.\ndef sq: (Int -> Int) = dup ## not docs\n *;\n## stray\n1 sq").unwrap();
+        match &cycles[..] {
+            [super::Cycle::Definition(name, _, body, _, docs), super::Cycle::Term(term, _)] => {
+                assert_eq!(name.value, "sq");
+                assert_eq!(docs.as_deref(), Some("Squares a number.\n  Indented."));
+                assert_eq!(body.len(), 2);
+                assert_eq!(term.len(), 2);
+            }
+            cycles => panic!("Expected a definition and a term, got {:?}", cycles),
+        }
+    }
+
+    #[test]
+    fn parses_tests() {
+        let cycles = super::parse("test \"adds\" { 1 2 + expect 3 } test \"runs\" { }").unwrap();
+        match &cycles[..] {
+            [super::Cycle::Test(name, body, Some(expected), span), super::Cycle::Test(_, empty, None, _)] => {
+                assert_eq!(name, "adds");
+                assert_eq!(body.len(), 3);
+                assert_eq!(expected.len(), 1);
+                assert_eq!((span.start, span.end), (0, 30));
+                assert!(empty.is_empty());
+            }
+            cycles => panic!("Expected two tests, got {:?}", cycles),
+        }
+        let error = super::parse("test \"a\" { 1 expect 2").unwrap_err();
+        assert_eq!(error, super::Error::UnexpectedEndOfFile(vec!["`}`".to_string()], "test \"a\"".to_string()));
+    }
+
+    #[test]
+    fn limits_how_deeply_things_nest() {
+        let deep = format!("{}1{}", "[".repeat(10_000), "]".repeat(10_000));
+        let error = super::parse(&deep).unwrap_err();
+        assert_eq!(error.to_string(), "1:129: quotations are nested more than 128 deep");
+        assert!(super::parse_with_max_depth("[[1]]", 2).is_ok());
+        assert!(super::parse_with_max_depth("[[1]]", 1).is_err());
+        let error = super::parse_with_max_depth("def f: ((Int -> Int) -> ) = drop;", 1).unwrap_err();
+        assert_eq!(error.to_string(), "1:9: function types are nested more than 1 deep");
+    }
+
+    #[test]
+    fn reserves_combinators_unless_overridden() {
+        let error = super::parse("def dup: (Int -> Int) = 1 +;").unwrap_err();
+        assert_eq!(error.to_string(), "1:5: `dup` is a core combinator, so it can't be defined without `def override dup`");
+        let error = super::parse("def override sq: (Int -> Int) = dup *;").unwrap_err();
+        assert_eq!(error.to_string(), "1:14: `sq` isn't a core combinator, so there's nothing for `override` to shadow");
+        let cycles = super::parse("1 dup def override dup: (Int -> Int, Int) = dup; dup def override: Int = 1;").unwrap();
+        let factors: Vec<_> = cycles.iter()
+            .filter_map(|cycle| match cycle {
+                super::Cycle::Definition(name, _, body, _, _) => Some((name.value.as_str(), &body[0])),
+                super::Cycle::Term(term, _) => Some(("", term.last().unwrap())),
+                _ => None,
+            })
+            .map(|(name, factor)| (name, matches!(factor, super::Factor::Identifier(_, _))))
+            .collect();
+        assert_eq!(factors, vec![("", false), ("dup", false), ("", true), ("override", false)]);
+    }
+
+    #[test]
+    fn parses_cond_clauses_into_a_quotation_before_it() {
+        let cycles = super::parse("cond { [dup 0 <] [drop 1] [drop 2] }").unwrap();
+        match &cycles[0] {
+            super::Cycle::Term(factors, _) => match &factors[..] {
+                [super::Factor::Quotation(clauses, span), super::Factor::Cond(token)] => {
+                    assert_eq!(clauses.len(), 3);
+                    assert_eq!(*span, super::Span::new(5, 36));
+                    assert_eq!(token.value, "cond");
+                }
+                factors => panic!("Expected a quotation and cond, got {:?}", factors),
+            },
+            cycle => panic!("Expected Term, got {:?}", cycle),
+        }
+        let error = super::parse("cond { [true] [1] }").unwrap_err();
+        assert_eq!(error.to_string(), "1:1: `cond` takes pairs of a `[predicate]` and a `[body]`, then a `[default]`");
+    }
+
+    #[test]
+    fn parses_let_into_a_quotation_with_its_locals() {
+        let cycles = super::parse("let x y in y x [x] end").unwrap();
+        match &cycles[0] {
+            super::Cycle::Term(factors, _) => match &factors[..] {
+                [super::Factor::Quotation(body, _), super::Factor::Let(names, token)] => {
+                    assert_eq!(names.iter().map(|name| name.as_str()).collect::<Vec<_>>(), vec!["x", "y"]);
+                    assert_eq!(token.value, "let");
+                    assert!(matches!(&body[..], [super::Factor::Local(y, _), super::Factor::Local(x, _), super::Factor::Quotation(..)]
+                        if y.as_str() == "y" && x.as_str() == "x"));
+                }
+                factors => panic!("Expected a quotation and let, got {:?}", factors),
+            },
+            cycle => panic!("Expected Term, got {:?}", cycle),
+        }
+        let error = super::parse("let x in let x in x end end").unwrap_err();
+        assert_eq!(error.to_string(), "1:14: `x` is already bound by a `let` here, and locals can't be shadowed");
+        let error = super::parse("1 let in end").unwrap_err();
+        assert_eq!(error.to_string(), "1:3: `let` has to bind at least one name");
+    }
+
+    #[test]
+    fn parses_stack_effect_assertions() {
+        let cycles = super::parse("1 ( -- Int ) dup ( Int -- Int Int )").unwrap();
+        assert!(matches!(&cycles[0], super::Cycle::Term(factors, _) if matches!(&factors[..], [
+            super::Factor::Int(..),
+            super::Factor::Assertion(first),
+            super::Factor::Dup(_),
+            super::Factor::Assertion(_),
+        ] if matches!(&**first, super::TypeAnnotation::Function(t_in, t_out, _, _) if t_in.is_empty() && t_out.len() == 1))));
+        let error = super::parse("dup ( Int Int )").unwrap_err();
+        assert_eq!(error.to_string(), "1:15: expected `--` while parsing stack effect assertion, found `)`");
+    }
+
+    #[test]
+    fn parses_declared_effects() {
+        let cycles = super::parse("def f: (Int -> Int) ! io diverge = 1 +; def g: Int ! = 1;").unwrap();
+        let render = |cycle: &super::Cycle| match cycle {
+            super::Cycle::Definition(_, annotation, _, _, _) => crate::format::annotation_to_source(annotation),
+            cycle => panic!("Expected Definition, got {:?}", cycle),
+        };
+        assert_eq!(render(&cycles[0]), "(Int -> Int) ! io diverge");
+        assert_eq!(render(&cycles[1]), "Int !");
+    }
+
+    #[test]
+    fn parses_total_definitions() {
+        let cycles = super::parse("1 total def f: Int ! pure = 1; total 2").unwrap();
+        assert_eq!(cycles.len(), 3);
+        match &cycles[1] {
+            super::Cycle::Definition(name, annotation, _, span, _) => {
+                assert_eq!(name.value, "f");
+                assert_eq!(crate::format::annotation_to_source(annotation), "total Int ! pure");
+                assert_eq!(span.start, 2);
+            }
+            cycle => panic!("Expected Definition, got {:?}", cycle),
+        }
+        // `total` is a word anywhere else.
+        assert!(matches!(&cycles[2], super::Cycle::Term(factors, _) if matches!(&factors[0], super::Factor::Identifier(name, _) if name.as_str() == "total")));
+        let cycles = super::parse("memo total def g: (Int -> Int) = 1 +; memo").unwrap();
+        match &cycles[0] {
+            super::Cycle::Definition(_, annotation, _, span, _) => {
+                assert!(annotation.is_memo() && annotation.is_total());
+                assert_eq!(crate::format::annotation_to_source(annotation), "memo total (Int -> Int)");
+                assert_eq!(span.start, 0);
+            }
+            cycle => panic!("Expected Definition, got {:?}", cycle),
+        }
+        assert!(matches!(&cycles[1], super::Cycle::Term(..)));
+    }
+
+    #[test]
+    fn parses_type_constructor_applications() {
+        let cycles = super::parse("def f: (List(Pair(Int, a)) -> Int) = drop 1; ( Int (Int -> Int) -- )").unwrap();
+        match &cycles[0] {
+            super::Cycle::Definition(_, annotation, _, _, _) => {
+                assert_eq!(crate::format::annotation_to_source(annotation), "(List(Pair(Int, a)) -> Int)");
+            }
+            cycle => panic!("Expected Definition, got {:?}", cycle),
+        }
+        // With a space, a parenthesized type is another type rather than arguments.
+        assert!(matches!(&cycles[1], super::Cycle::Term(factors, _) if matches!(&factors[..], [super::Factor::Assertion(assertion)]
+            if matches!(&**assertion, super::TypeAnnotation::Function(t_in, _, _, _) if t_in.len() == 2))));
+        let error = super::parse("def f: List(Int = 1;").unwrap_err();
+        assert_eq!(error.to_string(), "1:17: expected one of `,`, `)` while parsing type annotation of `def f`, found `=`");
+    }
+
+    #[test]
+    fn parses_type_ascriptions() {
+        let cycles = super::parse("dup :: (Int -> Int) 1").unwrap();
+        assert!(matches!(&cycles[0], super::Cycle::Term(factors, _) if matches!(&factors[..], [
+            super::Factor::Dup(_),
+            super::Factor::Ascription(annotation, token),
+            super::Factor::Int(..),
+        ] if matches!(&**annotation, super::TypeAnnotation::Function(..)) && token.col == 5)));
+        let error = super::parse("1 :: ]").unwrap_err();
+        assert_eq!(error.to_string(), "1:6: expected type while parsing type ascription, found `]`");
+    }
+
+    #[test]
+    fn rejects_tokens_that_cannot_start_a_cycle() {
+        let error = super::parse("1 ]").unwrap_err();
+        match error {
+            super::Error::UnexpectedToken(expected, _, token) => {
+                assert_eq!(expected, vec!["`def`".to_string(), "`test`".to_string(), "`macro`".to_string(), "factor".to_string()]);
+                assert_eq!(token.value, "]");
+            }
+            _ => panic!("Expected UnexpectedToken, got {:?}", error),
+        }
+    }
+
+    #[test]
+    fn records_spans_of_quotations_and_cycles() {
+        let cycles = super::parse("def a: Int = [1];\n[2 3] dup").unwrap();
+        assert_eq!(cycles.len(), 2);
+        assert_eq!(cycles[0].span(), super::Span::new(0, 17));
+        assert_eq!(cycles[1].span(), super::Span::new(18, 27));
+        match &cycles[0] {
+            super::Cycle::Definition(_, annotation, factors, _, _) => {
+                assert_eq!(annotation.span(), super::Span::new(7, 10));
+                assert_eq!(factors[0].span(), super::Span::new(13, 16));
+            }
+            _ => panic!("Expected Definition, got {:?}", cycles[0]),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn parses_a_reader_a_cycle_at_a_time() {
+        let source = "## Doubles.\ndef double: (Int -> Int) = [2] call *;\ndef override dup: (Int -> Int, Int) = dup;\n\
+            1 double dup [dup] ## not a doc\n2\ntest \"doubles\" { 2 double cond { [dup 4 =] [] [drop 0] } expect 4 }\n## doc\n";
+        let cycles = super::parse_reader(source.as_bytes()).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(cycles.len(), 4);
+        assert_eq!(cycles, super::parse(source).unwrap());
+        let source = "1 2 let a b in a b end :: Int (Int -- Int)\n## doc\ntotal def f: Int = 1;\n[def]";
+        let cycles = super::parse_reader(source.as_bytes()).collect::<Result<Vec<_>, _>>();
+        assert_eq!(cycles, super::parse(source));
+        let source = "1 2\ndef f: Int = 1 def";
+        let mut cycles = super::parse_reader(source.as_bytes());
+        assert!(cycles.next().unwrap().is_ok());
+        assert_eq!(cycles.next().unwrap().err(), super::parse(source).err());
+        assert!(cycles.next().is_none());
+    }
 }
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use crate::evaluator::{Observer, Value};
+use crate::format::factor_to_source;
+use crate::parser::Factor;
+
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct ProfileEntry {
+    pub calls: u64,
+    /// Time spent in the word, including everything it called.
+    pub total: Duration,
+    /// Time spent in the word itself, excluding the words it called.
+    pub self_time: Duration,
+}
+
+/// A definition that is currently running, with the time spent so far in the words it called.
+struct Active {
+    name: String,
+    start: Instant,
+    children: Duration,
+}
+
+/// Counts invocations and accumulates wall time for every definition and builtin word that runs.
+#[derive(Default)]
+pub struct Profiler {
+    entries: HashMap<String, ProfileEntry>,
+    active: Vec<Active>,
+    /// The builtin currently running, if the factor being evaluated is one.
+    pending: Option<(String, Instant)>,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Self::default()
+    }
+
+    pub fn entries(&self) -> &HashMap<String, ProfileEntry> {
+        &self.entries
+    }
+
+    /// Entries sorted by self time, highest first, with ties broken by name for stable output.
+    pub fn sorted(&self) -> Vec<(&str, &ProfileEntry)> {
+        let mut entries: Vec<_> = self.entries.iter().map(|(name, entry)| (name.as_str(), entry)).collect();
+        entries.sort_by(|(a_name, a), (b_name, b)| b.self_time.cmp(&a.self_time).then(a_name.cmp(b_name)));
+        entries
+    }
+
+    pub fn report(&self) -> String {
+        let mut report = format!("{:<20} {:>8} {:>12} {:>12}\n", "word", "calls", "total", "self");
+        for (name, entry) in self.sorted() {
+            report.push_str(&format!(
+                "{:<20} {:>8} {:>12} {:>12}\n",
+                name, entry.calls, format_duration(entry.total), format_duration(entry.self_time),
+            ));
+        }
+        report
+    }
+
+    fn charge_parent(&mut self, elapsed: Duration) {
+        if let Some(parent) = self.active.last_mut() {
+            parent.children += elapsed;
+        }
+    }
+}
+
+pub(crate) fn format_duration(duration: Duration) -> String {
+    format!("{:.3}ms", duration.as_secs_f64() * 1000.0)
+}
+
+impl Observer for Profiler {
+    fn before_factor(&mut self, factor: &Factor, _stack: &[Value], _depth: usize) {
+        self.pending = match factor {
+            Factor::Int(_, _) | Factor::Bool(_, _) | Factor::String(_, _) | Factor::Quotation(_, _) => None,
+            word => Some((factor_to_source(word), Instant::now())),
+        };
+    }
+
+    fn after_factor(&mut self, _factor: &Factor, _stack: &[Value], _depth: usize) {
+        if let Some((name, start)) = self.pending.take() {
+            let elapsed = start.elapsed();
+            let entry = self.entries.entry(name).or_default();
+            entry.calls += 1;
+            entry.total += elapsed;
+            entry.self_time += elapsed;
+            self.charge_parent(elapsed);
+        }
+    }
+
+    fn enter_definition(&mut self, name: &str, _depth: usize) {
+        // The identifier that called the definition isn't a builtin, so it's accounted for here instead.
+        self.pending = None;
+        self.entries.entry(name.to_string()).or_default().calls += 1;
+        self.active.push(Active { name: name.to_string(), start: Instant::now(), children: Duration::ZERO });
+    }
+
+    fn exit_definition(&mut self, _name: &str, _depth: usize) {
+        if let Some(active) = self.active.pop() {
+            let elapsed = active.start.elapsed();
+            let entry = self.entries.entry(active.name).or_default();
+            entry.total += elapsed;
+            entry.self_time += elapsed.saturating_sub(active.children);
+            self.charge_parent(elapsed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::evaluator::Evaluator;
+    use crate::parser::parse;
+    use super::Profiler;
+
+    fn profile(input: &str) -> Rc<RefCell<Profiler>> {
+        let profiler = Rc::new(RefCell::new(Profiler::new()));
+        let mut evaluator = Evaluator::new();
+        evaluator.add_observer(Box::new(profiler.clone()));
+        for cycle in parse(input).unwrap() {
+            evaluator.eval_cycle(&cycle).unwrap();
+        }
+        profiler
+    }
+
+    #[test]
+    fn counts_definitions_and_builtins() {
+        let profiler = profile("def inc: (Int -> Int) = 1 +; def inc2: (Int -> Int) = inc inc; 0 inc2 inc2 dup");
+        let profiler = profiler.borrow();
+        let entries = profiler.entries();
+        assert_eq!(entries["inc2"].calls, 2);
+        assert_eq!(entries["inc"].calls, 4);
+        assert_eq!(entries["+"].calls, 4);
+        assert_eq!(entries["dup"].calls, 1);
+        assert_eq!(entries.len(), 4);
+        for entry in entries.values() {
+            assert!(entry.self_time <= entry.total);
+        }
+        assert!(entries["inc2"].total >= entries["inc"].total);
+    }
+
+    #[test]
+    fn reports_every_word() {
+        let profiler = profile("1 2 + drop");
+        let report = profiler.borrow().report();
+        assert_eq!(report.lines().count(), 3);
+        assert!(report.lines().next().unwrap().starts_with("word"));
+    }
+}
@@ -0,0 +1,185 @@
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyBytes, PyInt, PyList, PySet, PyString, PyTuple};
+use crate::evaluator::Value;
+use crate::format::value_to_source;
+use crate::parser::parse_annotation;
+use crate::typechecker::{Type, TypeChecker};
+
+/// A chara engine for Python, as `chara.Engine`. Definitions stay in scope across calls to `eval`.
+#[pyclass(name = "Engine", unsendable)]
+pub struct PyEngine {
+    engine: crate::Engine,
+}
+
+#[pymethods]
+impl PyEngine {
+    #[new]
+    fn new() -> PyEngine {
+        PyEngine { engine: crate::Engine::new() }
+    }
+
+    /// Check and run `source`, returning its warnings as strings. Errors are raised as `ValueError`s.
+    fn eval(&mut self, source: &str) -> PyResult<Vec<String>> {
+        match self.engine.eval_str(source) {
+            Ok(warnings) => Ok(warnings.iter().map(ToString::to_string).collect()),
+            Err(errors) => Err(PyValueError::new_err(errors.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n"))),
+        }
+    }
+
+    /// The values on the stack, bottom first.
+    #[getter]
+    fn stack(&self, py: Python<'_>) -> PyResult<Vec<Py<PyAny>>> {
+        self.engine.stack().iter().map(|value| to_python(py, value)).collect()
+    }
+
+    fn push(&mut self, value: &Bound<'_, PyAny>) -> PyResult<()> {
+        let value = from_python(value)?;
+        self.engine.push(value);
+        Ok(())
+    }
+
+    fn pop(&mut self, py: Python<'_>) -> PyResult<Py<PyAny>> {
+        let value = self.engine.pop_as::<Value>().map_err(|error| PyValueError::new_err(error.to_string()))?;
+        to_python(py, &value)
+    }
+
+    /// Make `function` available as the word `name`, checked as having the type `annotation`, like
+    /// `"(Int, Int -> Int)"`. It's called with the word's inputs as arguments, and returns `None` for no
+    /// outputs, the output itself for one, or a tuple of them for more.
+    fn register(&mut self, name: &str, annotation: &str, function: Py<PyAny>) -> PyResult<()> {
+        let t = parse_annotation(annotation)
+            .and_then(|annotation| TypeChecker::new().type_from_annotation(&annotation))
+            .map_err(|error| PyValueError::new_err(error.to_string()))?;
+        let (inputs, outputs) = match &t {
+            Type::Function(t_in, t_out) => (t_in.len(), t_out.len()),
+            _ => (0, 1),
+        };
+        self.engine.register(name, t, move |stack| {
+            if stack.len() < inputs {
+                return Err("Stack underflow".to_string());
+            }
+            let args = stack.split_off(stack.len() - inputs);
+            Python::attach(|py| -> PyResult<()> {
+                let args = args.iter().map(|value| to_python(py, value)).collect::<PyResult<Vec<_>>>()?;
+                let result = function.call1(py, PyTuple::new(py, args)?)?;
+                let result = result.bind(py);
+                match outputs {
+                    0 => {}
+                    1 => stack.push(from_python(result)?),
+                    _ => {
+                        let values = result.cast::<PyTuple>().map_err(|_| {
+                            PyTypeError::new_err(format!("expected a tuple of {} outputs", outputs))
+                        })?;
+                        if values.len() != outputs {
+                            return Err(PyTypeError::new_err(format!("expected {} outputs, got {}", outputs, values.len())));
+                        }
+                        for value in values.iter() {
+                            stack.push(from_python(&value)?);
+                        }
+                    }
+                }
+                Ok(())
+            })
+            .map_err(|error| error.to_string())
+        });
+        Ok(())
+    }
+}
+
+/// Ints, bools, strings, bytes and sets convert directly. Quotations of literals become lists; other quotations can't be
+/// converted, since Python has nothing to run them with.
+fn to_python(py: Python<'_>, value: &Value) -> PyResult<Py<PyAny>> {
+    Ok(match value {
+        Value::Int(i) => PyInt::new(py, *i).into_any().unbind(),
+        Value::Bool(b) => PyBool::new(py, *b).to_owned().into_any().unbind(),
+        Value::String(s) => PyString::new(py, s).into_any().unbind(),
+        Value::Bytes(bytes) => PyBytes::new(py, bytes).into_any().unbind(),
+        Value::Set(elements) => {
+            let items = elements.iter().map(|element| to_python(py, element)).collect::<PyResult<Vec<_>>>()?;
+            PySet::new(py, items)?.into_any().unbind()
+        }
+        Value::Quotation(factors) => {
+            let items = factors.iter()
+                .map(|factor| match Value::from_factor(factor) {
+                    Some(item) => to_python(py, &item),
+                    None => Err(PyTypeError::new_err(format!("{} isn't a list of values", value_to_source(value)))),
+                })
+                .collect::<PyResult<Vec<_>>>()?;
+            PyList::new(py, items)?.into_any().unbind()
+        }
+    })
+}
+
+fn from_python(object: &Bound<'_, PyAny>) -> PyResult<Value> {
+    // Bools are ints in Python, so they're looked for first.
+    if let Ok(b) = object.cast::<PyBool>() {
+        Ok(Value::Bool(b.is_true()))
+    } else if let Ok(i) = object.extract::<i64>() {
+        Ok(Value::Int(i))
+    } else if let Ok(s) = object.extract::<String>() {
+        Ok(Value::String(s))
+    } else if let Ok(bytes) = object.cast::<PyBytes>() {
+        Ok(Value::Bytes(bytes.as_bytes().to_vec()))
+    } else if let Ok(set) = object.cast::<PySet>() {
+        let mut items = set.iter().map(|item| from_python(&item)).collect::<PyResult<Vec<_>>>()?;
+        items.sort_by(Value::compare);
+        Ok(Value::Set(items))
+    } else if let Ok(list) = object.cast::<PyList>() {
+        let items = list.iter().map(|item| from_python(&item)).collect::<PyResult<Vec<_>>>()?;
+        Ok(Value::Quotation(items.iter().map(Value::to_factor).collect()))
+    } else {
+        Err(PyTypeError::new_err(format!("can't convert {} to a chara value", object.get_type().name()?)))
+    }
+}
+
+#[pymodule]
+fn chara(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyEngine>()
+}
+
+#[cfg(test)]
+mod tests {
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+    use super::PyEngine;
+
+    /// Run Python code with `Engine` in scope, returning the repr of the `result` it sets.
+    fn run(code: &str) -> String {
+        Python::initialize();
+        Python::attach(|py| {
+            let globals = PyDict::new(py);
+            globals.set_item("Engine", py.get_type::<PyEngine>()).unwrap();
+            py.run(&std::ffi::CString::new(code).unwrap(), Some(&globals), None).unwrap();
+            globals.get_item("result").unwrap().unwrap().repr().unwrap().to_string()
+        })
+    }
+
+    #[test]
+    fn evaluates_and_converts_values() {
+        assert_eq!(run("e = Engine(); e.eval('1 2 + true \"s\" [1 [2]]'); result = e.stack"), "[3, True, 's', [1, [2]]]");
+        assert_eq!(run("e = Engine(); e.push([1, False]); e.push(7); e.eval('swap'); result = (e.pop(), e.pop())"), "([1, False], 7)");
+        assert_eq!(run("\
+try:
+    Engine().eval('1 nope')
+except ValueError as error:
+    result = str(error)"), "'1:3: type error: Unknown identifier nope'");
+    }
+
+    #[test]
+    fn registers_python_functions_as_words() {
+        assert_eq!(run("\
+e = Engine()
+e.register('py-divmod', '(Int, Int -> Int, Int)', divmod)
+e.register('shout', '(String -> String)', lambda s: s.upper() + '!')
+e.eval('17 5 py-divmod \"hi\" shout')
+result = e.stack"), "[3, 2, 'HI!']");
+        assert_eq!(run("\
+e = Engine()
+e.register('fail', '(Int -> Int)', lambda i: 1 // i)
+try:
+    e.eval('0 fail')
+except ValueError as error:
+    result = str(error)"), "'1:3: runtime error: ZeroDivisionError: integer division or modulo by zero'");
+    }
+}
@@ -0,0 +1,52 @@
+/// A small xorshift generator. Fuzzing and the random words only need reproducible noise, not statistical
+/// quality.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // Zero is a fixed point of xorshift, so it's nudged away from.
+        Rng(seed.max(1))
+    }
+
+    /// A generator seeded from the time, or from a fixed seed without `std`, which has no clock to read.
+    pub fn unseeded() -> Rng {
+        #[cfg(feature = "std")]
+        let seed = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |since| since.as_nanos() as u64);
+        #[cfg(not(feature = "std"))]
+        let seed = 0;
+        Rng::new(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    /// A number from `low` to `high`, including both, which must be in order.
+    pub fn between(&mut self, low: i64, high: i64) -> i64 {
+        let range = (high as i128 - low as i128) as u128 + 1;
+        (low as i128 + (self.next_u64() as u128 % range) as i128) as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rng;
+
+    #[test]
+    fn stays_in_bounds_and_repeats_from_a_seed() {
+        let mut rng = Rng::new(7);
+        assert!((0..100).all(|_| (-2..=3).contains(&rng.between(-2, 3))));
+        rng.between(i64::MIN, i64::MAX);
+        assert_eq!(rng.between(5, 5), 5);
+        let (mut a, mut b) = (Rng::new(42), Rng::new(42));
+        assert_eq!((0..5).map(|_| a.next_u64()).collect::<Vec<_>>(), (0..5).map(|_| b.next_u64()).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,317 @@
+use crate::error::Error;
+use crate::format::factor_to_source;
+use crate::scanner::PUNCTUATION;
+use crate::typechecker::{CheckConfig, Type};
+use crate::debugger::HISTORY_LIMIT;
+use crate::{compile, Engine, Progress};
+
+const HELP: &str = "\
+Enter chara code to run it, or one of these commands:
+  help         show this message
+  help <word>  show the signature and documentation of a word
+  :load <file> bring the definitions in a file into the session
+  :reload      load every file loaded so far again, picking up changes
+  :type <code> show the type of each definition and term in some code, without running it
+The words `words` and `\"name\" see` push each definition's name and signature, and a definition's source.
+The word `breakpoint` pauses what's running, until one of these:
+  :step        run the next factor
+  :continue    run to the end, or to the next breakpoint
+  :back        undo the last step
+  :history     show the stack before each step so far, and the factor that step ran
+  :abort       stop running, leaving the stack as it is";
+
+const PAUSED: &str = "paused at a breakpoint; enter :step, :continue, :back, :history or :abort";
+
+/// An interactive session. Input is handled a line at a time, and definitions stay in scope for later lines.
+pub struct Repl {
+    engine: Engine,
+    /// Files brought in with `:load`, in the order they were first loaded.
+    loaded: Vec<String>,
+    /// Lines of input that don't make a complete program yet, like a definition missing its `;`.
+    pending: String,
+    /// Whether a program is paused at a `breakpoint`, so lines are debugger commands until it goes on.
+    paused: bool,
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        let mut engine = Engine::new();
+        // Leaving values on the stack is the point of typing an expression in, so that isn't warned about.
+        engine.set_check_config(CheckConfig::lenient());
+        engine.keep_history(HISTORY_LIMIT);
+        Repl {
+            engine,
+            loaded: Vec::new(),
+            pending: String::new(),
+            paused: false,
+        }
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// Whether the lines so far are incomplete, so the next line continues them.
+    pub fn needs_more(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Completions for the word ending at byte `pos` of `line`: where the word starts, and each word it could
+    /// be completed to, with its type.
+    pub fn complete(&self, line: &str, pos: usize) -> (usize, Vec<(String, Type)>) {
+        let start = line[..pos].rfind(|c: char| c.is_whitespace() || PUNCTUATION.contains(&c)).map_or(0, |i| i + 1);
+        (start, self.engine.complete(&line[start..pos]))
+    }
+
+    /// Whether a program is paused at a `breakpoint`.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Handle one line of input, returning what should be printed in response. Nothing is printed for a line
+    /// that leaves the input incomplete, until a later line completes it.
+    pub fn handle(&mut self, line: &str) -> String {
+        if self.paused {
+            return match line.trim() {
+                ":step" => self.run(1).unwrap_or_else(|error| error.to_string()),
+                ":continue" => self.run(usize::MAX).unwrap_or_else(|error| error.to_string()),
+                ":back" if self.engine.back() => self.show_pause(),
+                ":back" => "there's no step to go back to".to_string(),
+                ":history" => self.show_history(),
+                ":abort" => {
+                    self.engine.stop();
+                    self.paused = false;
+                    self.show_stack()
+                }
+                "help" => HELP.to_string(),
+                _ => PAUSED.to_string(),
+            };
+        }
+        if self.needs_more() {
+            let source = format!("{}\n{}", self.pending, line);
+            self.pending.clear();
+            return self.eval_or_continue(source);
+        }
+        let line = line.trim();
+        match line.split_once(char::is_whitespace) {
+            None if line == "help" => HELP.to_string(),
+            None if line == ":reload" => {
+                let files = self.loaded.clone();
+                files.iter().map(|path| self.load(path)).collect::<Vec<_>>().join("\n")
+            }
+            Some((":load", path)) => {
+                let path = path.trim().to_string();
+                let output = self.load(&path);
+                if !self.loaded.contains(&path) {
+                    self.loaded.push(path);
+                }
+                output
+            }
+            Some((":type", source)) => self.show_types(source),
+            Some(("help", word)) => {
+                let word = word.trim();
+                self.engine.help(word).unwrap_or_else(|| format!("no word named {}", word))
+            }
+            _ => self.eval_or_continue(line.to_string()),
+        }
+    }
+
+    fn eval_or_continue(&mut self, source: String) -> String {
+        if is_incomplete(&source) {
+            self.pending = source;
+            String::new()
+        } else {
+            self.eval(&source)
+        }
+    }
+
+    /// Load the definitions in a file, replacing any earlier definitions of the same words.
+    fn load(&mut self, path: &str) -> String {
+        let program = match std::fs::read_to_string(path).map_err(|err| vec![Error::from(err)]).and_then(|source| compile(&source)) {
+            Ok(program) => program.definitions(),
+            Err(errors) => return errors.iter().map(|error| format!("{}: {}", path, error)).collect::<Vec<_>>().join("\n"),
+        };
+        let result = self.engine.check(&program)
+            .and_then(|_| program.run(&mut self.engine).map_err(|error| vec![error]));
+        match result {
+            Ok(()) => format!("loaded {} definition(s) from {}", program.cycles().len(), path),
+            Err(errors) => errors.iter().map(|error| format!("{}: {}", path, error)).collect::<Vec<_>>().join("\n"),
+        }
+    }
+
+    /// The type of each cycle of `source`, checked against the session's words.
+    fn show_types(&self, source: &str) -> String {
+        let result = compile(source).and_then(|program| Ok((self.engine.infer(&program)?, program)));
+        match result {
+            Ok((types, program)) => program.cycles().iter().zip(types)
+                .map(|(cycle, t)| format!("{} : {}", cycle.label(), t))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Err(errors) => errors.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n"),
+        }
+    }
+
+    /// Run `source` until it finishes or reaches a `breakpoint`.
+    fn eval(&mut self, source: &str) -> String {
+        let result = compile(source).and_then(|program| self.engine.start(program));
+        let mut lines: Vec<String> = match result {
+            Ok(warnings) => warnings.iter().map(ToString::to_string).collect(),
+            Err(errors) => return errors.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n"),
+        };
+        // Warnings aren't worth showing next to the error that stopped the program.
+        match self.run(usize::MAX) {
+            Ok(output) => lines.push(output),
+            Err(error) => return error.to_string(),
+        }
+        lines.join("\n")
+    }
+
+    /// Run at most `steps` factors of the program that was started, showing the stack, and what's next if
+    /// that's a pause.
+    fn run(&mut self, steps: usize) -> Result<String, Error> {
+        let progress = self.engine.resume(steps);
+        self.paused = matches!(progress, Ok(Progress::Pending | Progress::Breakpoint));
+        if progress? == Progress::Done {
+            return Ok(self.show_stack());
+        }
+        Ok(self.show_pause())
+    }
+
+    /// The stack, and what's next, while paused.
+    fn show_pause(&self) -> String {
+        let next = self.engine.current_factor().map_or_else(|| "the end of a quotation".to_string(), factor_to_source);
+        format!("{}\nnext: {}", self.show_stack(), next)
+    }
+
+    fn show_stack(&self) -> String {
+        self.engine.stack().iter().map(ToString::to_string).collect::<Vec<_>>().join(" ")
+    }
+
+    /// A line for each step kept, oldest first: the stack before it, then the factor it ran.
+    fn show_history(&self) -> String {
+        let Some(history) = self.engine.history() else {
+            return String::new();
+        };
+        history.steps()
+            .map(|(factor, stack)| {
+                let stack = stack.iter().map(ToString::to_string).collect::<Vec<_>>().join(" ");
+                let factor = factor.map_or_else(|| "the end of a quotation".to_string(), factor_to_source);
+                format!("{} | {}", stack, factor)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether `source` only fails to parse because it ends too soon, like an unclosed `[` or a `def`
+/// without its `;`.
+pub fn is_incomplete(source: &str) -> bool {
+    matches!(compile(source), Err(errors) if errors.iter().any(|error| matches!(error, Error::UnexpectedEndOfFile(_, _))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Repl;
+
+    #[test]
+    fn evaluates_lines_and_shows_the_stack() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.handle("def sq: (Int -> Int) = dup *;"), "");
+        assert_eq!(repl.handle("3 sq \"s\" drop"), "9");
+        assert_eq!(repl.handle("drop drop"), "1:6: runtime error: Stack underflow");
+        assert_eq!(repl.handle("nope"), "1:1: type error: Unknown identifier nope");
+    }
+
+    #[test]
+    fn continues_incomplete_input() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.handle("def sq: (Int -> Int) ="), "");
+        assert!(repl.needs_more());
+        assert_eq!(repl.handle("  dup *;"), "");
+        assert!(!repl.needs_more());
+        assert_eq!(repl.handle("[1 2"), "");
+        assert_eq!(repl.handle("3] 4 sq"), "[1 2 3] 16");
+        assert_eq!(repl.handle("1 ]"), "1:3: expected one of `def`, `test`, `macro`, factor while parsing program, found `]`");
+        assert!(!repl.needs_more());
+    }
+
+    #[test]
+    fn completes_the_word_before_the_cursor() {
+        let mut repl = Repl::new();
+        repl.handle("def square: (Int -> Int) = dup *;");
+        let (start, words) = repl.complete("1 [squ", 6);
+        assert_eq!(start, 3);
+        assert_eq!(words.into_iter().map(|(name, _)| name).collect::<Vec<_>>(), vec!["square"]);
+        assert_eq!(repl.complete("sw 1", 2).1.len(), 1);
+    }
+
+    #[test]
+    fn loads_and_reloads_files() {
+        let path = std::env::temp_dir().join(format!("chara-repl-load-{}.ch", std::process::id()));
+        std::fs::write(&path, "def answer: Int = 41; 100").unwrap();
+        let mut repl = Repl::new();
+        let path = path.to_str().unwrap();
+        assert_eq!(repl.handle(&format!(":load {}", path)), format!("loaded 1 definition(s) from {}", path));
+        assert_eq!(repl.handle("answer"), "41");
+        std::fs::write(path, "def answer: Int = 42;").unwrap();
+        assert_eq!(repl.handle(":reload"), format!("loaded 1 definition(s) from {}", path));
+        assert_eq!(repl.handle("answer"), "41 42");
+        std::fs::remove_file(path).unwrap();
+        assert!(repl.handle(":reload").starts_with(&format!("{}: io error: ", path)));
+    }
+
+    #[test]
+    fn pauses_at_breakpoints_to_step_through() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.handle("def sq: (Int -> Int) = breakpoint dup *;"), "");
+        assert_eq!(repl.handle("3 sq 1 +"), "3\nnext: dup");
+        assert!(repl.is_paused());
+        assert_eq!(repl.handle("4"), super::PAUSED);
+        assert_eq!(repl.handle(":step"), "3 3\nnext: *");
+        assert_eq!(repl.handle(":continue"), "10");
+        assert!(!repl.is_paused());
+        repl.handle("drop 1 breakpoint 2");
+        assert_eq!(repl.handle(":abort"), "1");
+        assert_eq!(repl.handle("5"), "1 5");
+    }
+
+    #[test]
+    fn goes_back_through_the_steps_taken() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.handle("1 2 breakpoint + 10 *"), "1 2\nnext: +");
+        assert_eq!(repl.handle(":step"), "3\nnext: 10");
+        assert_eq!(repl.handle(":step"), "3 10\nnext: *");
+        assert_eq!(repl.handle(":history").lines().last(), Some("3 | 10"));
+        assert_eq!(repl.handle(":back"), "3\nnext: 10");
+        assert_eq!(repl.handle(":back"), "1 2\nnext: +");
+        assert_eq!(repl.handle(":continue"), "30");
+        assert_eq!(repl.handle("breakpoint"), "30\nnext: the end of a quotation");
+        assert_eq!(repl.handle(":back"), "30\nnext: breakpoint");
+        assert_eq!(repl.handle(":back"), "there's no step to go back to");
+    }
+
+    #[test]
+    fn shows_types_without_running() {
+        let mut repl = Repl::new();
+        repl.handle("def sq: (Int -> Int) = dup *;");
+        assert_eq!(repl.handle(":type 3 sq"), "3 sq : ( -> Int)");
+        assert_eq!(repl.handle(":type def triple: (Int -> Int) = 3 *; 2 triple sq"), "triple : (Int -> Int)\n2 triple sq : ( -> Int)");
+        assert_eq!(repl.handle(":type triple"), "1:1: type error: Unknown identifier triple");
+        assert_eq!(repl.handle("1"), "1");
+    }
+
+    #[test]
+    fn shows_help_for_words() {
+        let mut repl = Repl::new();
+        repl.handle("## Squares a number.\ndef sq: (Int -> Int) = dup *;");
+        assert_eq!(repl.handle("help sq"), "sq : (Int -> Int)\n\nSquares a number.");
+        assert_eq!(repl.handle("help nope"), "no word named nope");
+        assert!(repl.handle("help").starts_with("Enter chara code"));
+    }
+}
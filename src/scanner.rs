@@ -1,113 +1,342 @@
+use memchr::{memchr, memchr3, memchr_iter, memrchr};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_xid::UnicodeXID;
 use crate::error::Error;
+use alloc::{format, string::{String, ToString}, vec::Vec};
+#[cfg(feature = "std")]
+use alloc::collections::VecDeque;
 
+/// A region of source text, as byte offsets into the source of the file it came from.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub file: Option<usize>,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end, file: None }
+    }
+
+    /// The smallest span covering both `self` and `other`.
+    pub fn merge(&self, other: &Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+            file: self.file.or(other.file),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TokenKind {
+    Identifier,
+    /// One of the core combinators, like `dup` or `ifte`.
+    Combinator,
+    Keyword,
+    Integer(i64),
+    Boolean(bool),
+    String,
+    /// A `b"..."` literal of the bytes of its text, or an `x"..."` literal of bytes written in hex.
+    Bytes,
+    /// A single delimiter character, like `[` or `;`.
+    Punct,
+    /// A `##` comment, which documents the definition after it. Plain `#` comments aren't tokens at all.
+    DocComment,
+    Unknown,
+}
+
+pub const KEYWORDS: [&str; 4] = ["def", "test", "expect", "macro"];
+pub const COMBINATORS: [&str; 8] = ["dup", "drop", "quote", "call", "cat", "swap", "ifte", "cond"];
+pub const PUNCTUATION: [char; 10] = ['{', '}', '(', ')', '[', ']', '.', ',', ';', ':'];
+/// Characters that can be in words besides letters, digits and the like, so operators like `+` and `->` are words.
+pub const SYMBOLS: [char; 18] = ['+', '-', '*', '/', '%', '<', '>', '=', '!', '?', '&', '|', '^', '~', '@', '$', '#', '\''];
+
+impl TokenKind {
+    pub fn classify(value: &str) -> TokenKind {
+        if let Ok(i) = value.parse::<i64>() {
+            TokenKind::Integer(i)
+        } else if let Ok(b) = value.parse::<bool>() {
+            TokenKind::Boolean(b)
+        } else if value.len() > 1 && value.starts_with('"') && value.ends_with('"') {
+            TokenKind::String
+        } else if value.len() > 2 && (value.starts_with("b\"") || value.starts_with("x\"")) && value.ends_with('"') {
+            TokenKind::Bytes
+        } else if value.starts_with("##") {
+            TokenKind::DocComment
+        } else if value.len() == 1 && value.starts_with(PUNCTUATION) {
+            TokenKind::Punct
+        } else if value.is_empty() || value.contains(PUNCTUATION) || value.contains('"') || !is_word(value) {
+            TokenKind::Unknown
+        } else if KEYWORDS.contains(&value) {
+            TokenKind::Keyword
+        } else if COMBINATORS.contains(&value) {
+            TokenKind::Combinator
+        } else {
+            TokenKind::Identifier
+        }
+    }
+}
+
+/// Whether `value` can name a word: a Unicode identifier, which starts with a letter or `_` and goes on with
+/// letters, digits, marks and connectors as Unicode Standard Annex #31 defines them, with `SYMBOLS` allowed
+/// anywhere in it.
+fn is_word(value: &str) -> bool {
+    let mut chars = value.chars();
+    chars.next().is_some_and(|first| first.is_xid_start() || first == '_' || SYMBOLS.contains(&first))
+        && chars.all(|c| c.is_xid_continue() || SYMBOLS.contains(&c))
+}
+
+/// A token's position is the line it's on, counted from 1, and its column, counted from 1 in grapheme clusters.
 #[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Token {
     pub value: String,
+    pub kind: TokenKind,
     pub line: usize,
     pub col: usize,
+    pub span: Span,
 }
 
 impl Token {
+    pub fn new(value: &str, line: usize, col: usize, span: Span) -> Token {
+        Token {
+            value: value.to_string(),
+            kind: TokenKind::classify(value),
+            line,
+            col,
+            span,
+        }
+    }
+
     pub fn unknown() -> Token {
         Token {
             value: "".to_string(),
+            kind: TokenKind::Unknown,
             line: 0,
             col: 0,
+            span: Span::default(),
         }
     }
+
+    pub fn as_borrowed(&self) -> BorrowedToken<'_> {
+        BorrowedToken { value: &self.value, kind: self.kind, line: self.line, col: self.col, span: self.span }
+    }
+}
+
+/// A token that borrows its text from the source rather than owning a copy, so scanning doesn't allocate for
+/// each one. The parser works with these, and only makes a `Token` of the ones it keeps.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct BorrowedToken<'src> {
+    pub value: &'src str,
+    pub kind: TokenKind,
+    pub line: usize,
+    pub col: usize,
+    pub span: Span,
+}
+
+impl<'src> BorrowedToken<'src> {
+    pub fn new(value: &'src str, line: usize, col: usize, span: Span) -> BorrowedToken<'src> {
+        BorrowedToken { value, kind: TokenKind::classify(value), line, col, span }
+    }
+
+    pub fn to_token(&self) -> Token {
+        Token { value: self.value.to_string(), kind: self.kind, line: self.line, col: self.col, span: self.span }
+    }
+}
+
+/// How many columns `text` takes up, counting each grapheme cluster, like an `e` with a combining accent, as one.
+pub fn columns(text: &str) -> usize {
+    if text.is_ascii() {
+        text.len()
+    } else {
+        text.graphemes(true).count()
+    }
+}
+
+/// The line of `offset` in `source`, counted from 0, and its column counted from 0 in UTF-16 code units, which
+/// is how the Language Server Protocol and JavaScript count them.
+pub fn utf16_position(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source.as_bytes()[..offset];
+    let line_start = memrchr(b'\n', before).map_or(0, |newline| newline + 1);
+    (memchr_iter(b'\n', before).count(), source[line_start..offset].encode_utf16().count())
 }
 
 pub fn scan(string: &str) -> Result<Vec<Token>, Error> {
-    let mut chars = string.chars().enumerate().peekable();
+    Ok(scan_borrowed(string)?.iter().map(BorrowedToken::to_token).collect())
+}
+
+/// Scan `string` into tokens that borrow from it. This works on bytes, since everything that splits tokens is
+/// ASCII, and only counts lines and columns for where tokens start.
+pub fn scan_borrowed(string: &str) -> Result<Vec<BorrowedToken<'_>>, Error> {
+    let bytes = string.as_bytes();
+    let mut positions = Positions { source: string, offset: 0, line: 1, col: 1 };
     let mut tokens = Vec::new();
-    let mut line = 1;
-    let mut col = 1;
-    let mut token_size = 0;
-    let mut token_start = 0;
-    while let Some((index, c)) = chars.next() {
-        match c {
-            ' ' | '\t' | '\r' | '\n' => {
-                if token_size > 0 {
-                    tokens.push(Token {
-                        value: string[token_start..index].to_string(),
-                        line,
-                        col: col - token_size,
-                    });
-                    token_size = 0;
-                }
-                if c == '\n' {
-                    line += 1;
-                    col = 1;
-                } else {
-                    col += 1;
-                }
-                token_start = index + 1;
+    let mut index = 0;
+    while index < bytes.len() {
+        let start = index;
+        match bytes[index] {
+            b' ' | b'\t' | b'\r' | b'\n' => {
+                index += 1;
+                continue;
             }
-            '{' | '}' | '(' | ')' | '[' | ']' | '.' | ',' | ';' | ':' => {
-                // These characters are always tokens by themselves
-                if token_size > 0 {
-                    tokens.push(Token {
-                        value: string[token_start..index].to_string(),
-                        line,
-                        col: col - token_size,
-                    });
-                    token_size = 0;
+            // Comments run to the end of the line.
+            b'#' => {
+                index = memchr(b'\n', &bytes[index..]).map_or(bytes.len(), |end| index + end);
+                if !bytes[start..index].starts_with(b"##") {
+                    continue;
                 }
-                tokens.push(Token {
-                    value: string[index..index + 1].to_string(),
-                    line,
-                    col,
-                });
-                token_start = index + 1;
             }
-            '"' => {
-                col += 1;
-                token_size += 1;
-                while let Some((index, c)) = chars.next() {
-                    col += 1;
-                    token_size += 1;
-                    match c {
-                        '"' => {
-                            tokens.push(Token {
-                                value: string[token_start..(index+1)].to_string(),
-                                line,
-                                col: col - token_size,
-                            });
-                            token_size = 0;
-                            break;
-                        }
-                        '\n' => {
-                            return Err(Error::ParseError("Unterminated string".to_string(), Token { line, col, value: string[token_start..index].to_string() }));
-                        }
-                        '\\' => {
-                            // Whatever the escape sequence is, we just skip it at this stage.
-                            chars.next();
-                            col += 1;
-                            token_size += 1;
-                        }
-                        _ => {
-                            // Just move on to the next character
-                        }
+            // These characters are always tokens by themselves.
+            b'{' | b'}' | b'(' | b')' | b'[' | b']' | b'.' | b',' | b';' | b':' => index += 1,
+            _ => {
+                // A word runs up to the next delimiter, unless a string starts in it, in which case it ends with
+                // the string.
+                while index < bytes.len() && !is_delimiter(bytes[index]) {
+                    if bytes[index] == b'"' {
+                        index = string_end(string, index, &mut positions)?;
+                        break;
                     }
+                    index += 1;
+                }
+            }
+        }
+        let (line, col) = positions.at(start);
+        tokens.push(BorrowedToken::new(&string[start..index], line, col, Span::new(start, index)));
+    }
+    Ok(tokens)
+}
+
+fn is_delimiter(byte: u8) -> bool {
+    matches!(byte, b' ' | b'\t' | b'\r' | b'\n' | b'{' | b'}' | b'(' | b')' | b'[' | b']' | b'.' | b',' | b';' | b':')
+}
+
+/// The index just past the `"` closing the string opened at `quote`. A string has to be closed on the line it
+/// starts on, unless the line break is escaped.
+fn string_end(string: &str, quote: usize, positions: &mut Positions) -> Result<usize, Error> {
+    let bytes = string.as_bytes();
+    let mut index = quote + 1;
+    loop {
+        match memchr3(b'"', b'\\', b'\n', &bytes[index..]).map(|offset| index + offset) {
+            Some(end) if bytes[end] == b'"' => return Ok(end + 1),
+            // Whatever the escape sequence is, it's skipped at this stage.
+            Some(escape) if bytes[escape] == b'\\' => index = escape + 2,
+            Some(newline) => return Err(unterminated(string, quote, newline, "line", positions)),
+            None => return Err(unterminated(string, quote, bytes.len(), "input", positions)),
+        }
+        if index >= bytes.len() {
+            index = bytes.len();
+        }
+    }
+}
+
+/// The error for a string opened at `quote` and still open at `end`, located at the opening quote with the
+/// string so far as its token.
+fn unterminated(string: &str, quote: usize, end: usize, before: &str, positions: &mut Positions) -> Error {
+    let (line, col) = positions.at(quote);
+    let token = Token::new(&string[quote..end], line, col, Span::new(quote, end));
+    Error::ParseError(format!("Unterminated string, with no closing `\"` before the end of the {}", before), token)
+}
+
+/// Scan the source `reader` reads a line at a time, so only the line being scanned is in memory, or the lines
+/// of a string whose line breaks are escaped.
+#[cfg(feature = "std")]
+pub fn scan_reader<R: std::io::BufRead>(reader: R) -> TokenReader<R> {
+    TokenReader { reader, lines: String::new(), line: 0, offset: 0, tokens: VecDeque::new(), done: false }
+}
+
+/// The tokens of a reader's source, as `scan_reader` scans them.
+#[cfg(feature = "std")]
+pub struct TokenReader<R> {
+    reader: R,
+    /// The lines being scanned, and how many lines and bytes of the source came before them.
+    lines: String,
+    line: usize,
+    offset: usize,
+    tokens: VecDeque<Token>,
+    done: bool,
+}
+
+#[cfg(feature = "std")]
+impl<R: std::io::BufRead> TokenReader<R> {
+    /// Scan the next line, and any lines a string on it runs on into, returning false at the end of the source.
+    fn scan_lines(&mut self) -> Result<bool, Error> {
+        self.lines.clear();
+        loop {
+            let read = self.reader.read_line(&mut self.lines)?;
+            if self.lines.is_empty() {
+                return Ok(false);
+            }
+            let (line, offset) = (self.line, self.offset);
+            match scan_borrowed(&self.lines) {
+                Ok(tokens) => {
+                    self.tokens.extend(tokens.iter().map(|token| place(token.to_token(), line, offset)));
+                    break;
                 }
-                if token_size > 0 {
-                    return Err(Error::ParseError("Unterminated string".to_string(), Token { line, col, value: string[token_start..index].to_string() }));
+                // A string still open at the end of the lines has an escaped line break, so it goes on to the
+                // next line, if there is one.
+                Err(Error::ParseError(_, token)) if read > 0 && token.span.end == self.lines.len() => continue,
+                Err(Error::ParseError(message, token)) => return Err(Error::ParseError(message, place(token, line, offset))),
+                Err(error) => return Err(error),
+            }
+        }
+        self.line += memchr_iter(b'\n', self.lines.as_bytes()).count();
+        self.offset += self.lines.len();
+        Ok(true)
+    }
+}
+
+/// Move a token scanned from lines that come after `line` lines and `offset` bytes of the source to where it
+/// is in the whole source.
+#[cfg(feature = "std")]
+fn place(token: Token, line: usize, offset: usize) -> Token {
+    let span = Span::new(token.span.start + offset, token.span.end + offset);
+    Token { line: token.line + line, span, ..token }
+}
+
+#[cfg(feature = "std")]
+impl<R: std::io::BufRead> Iterator for TokenReader<R> {
+    type Item = Result<Token, Error>;
+
+    fn next(&mut self) -> Option<Result<Token, Error>> {
+        while self.tokens.is_empty() && !self.done {
+            match self.scan_lines() {
+                Ok(more) => self.done = !more,
+                Err(error) => {
+                    self.done = true;
+                    return Some(Err(error));
                 }
             }
-            _ => {
-                col += 1;
-                token_size += 1;
+        }
+        self.tokens.pop_front().map(Ok)
+    }
+}
+
+/// Works out lines and columns from byte offsets, which have to be asked for in order. Only the text since the
+/// last offset asked for is looked at, so finding every token's position takes a single pass.
+struct Positions<'a> {
+    source: &'a str,
+    offset: usize,
+    line: usize,
+    col: usize,
+}
+
+impl Positions<'_> {
+    fn at(&mut self, offset: usize) -> (usize, usize) {
+        let skipped = &self.source[self.offset..offset];
+        match memrchr(b'\n', skipped.as_bytes()) {
+            Some(last) => {
+                self.line += memchr_iter(b'\n', skipped.as_bytes()).count();
+                self.col = 1 + columns(&skipped[last + 1..]);
             }
+            None => self.col += columns(skipped),
         }
-    };
-    if token_size > 0 {
-        tokens.push(Token {
-            value: string[token_start..].to_string(),
-            line,
-            col,
-        });
+        self.offset = offset;
+        (self.line, self.col)
     }
-    Ok(tokens)
 }
 
 #[cfg(test)]
@@ -119,6 +348,15 @@ mod tests {
         assert_eq!(tokens[0].value, "\"Hello, world!\"");
     }
 
+    #[test]
+    fn borrows_token_text_from_the_source() {
+        let source = String::from("def sq: (Int -> Int) = dup *;");
+        let tokens = super::scan_borrowed(&source).unwrap();
+        assert_eq!(tokens[1].value.as_ptr(), source[4..].as_ptr());
+        let owned: Vec<super::Token> = tokens.iter().map(super::BorrowedToken::to_token).collect();
+        assert_eq!(owned, super::scan(&source).unwrap());
+    }
+
     #[test]
     fn scans_simple_string_with_escapes() {
         let tokens = super::scan("\"Hello, \\nworld!\"").unwrap();
@@ -126,6 +364,84 @@ mod tests {
         assert_eq!(tokens[0].value, "\"Hello, \\nworld!\"");
     }
 
+    #[test]
+    fn skips_comments_and_keeps_doc_comments() {
+        let tokens = super::scan("# a comment\n## Docs, with punctuation.\ndup # trailing\nx#y").unwrap();
+        let tokens: Vec<_> = tokens.iter().map(|t| (t.value.as_str(), t.kind, t.line, t.col)).collect();
+        assert_eq!(tokens, vec![
+            ("## Docs, with punctuation.", super::TokenKind::DocComment, 2, 1),
+            ("dup", super::TokenKind::Combinator, 3, 1),
+            ("x#y", super::TokenKind::Identifier, 4, 1),
+        ]);
+    }
+
+    #[test]
+    fn records_byte_spans() {
+        let tokens = super::scan("dup [1 \"a b\"]").unwrap();
+        let spans: Vec<_> = tokens.iter().map(|t| (t.span.start, t.span.end)).collect();
+        assert_eq!(spans, vec![(0, 3), (4, 5), (5, 6), (7, 12), (12, 13)]);
+    }
+
+    #[test]
+    fn records_columns() {
+        let tokens = super::scan("a: [b]\n  c").unwrap();
+        let positions: Vec<_> = tokens.iter().map(|t| (t.line, t.col)).collect();
+        assert_eq!(positions, vec![(1, 1), (1, 2), (1, 4), (1, 5), (1, 6), (2, 3)]);
+    }
+
+    #[test]
+    fn classifies_tokens() {
+        use super::TokenKind;
+        let tokens = super::scan("def -12 true \"s\" dup + ; a\"b\" b\"c\" x\"0a\"").unwrap();
+        let kinds: Vec<_> = tokens.iter().map(|t| t.kind).collect();
+        assert_eq!(kinds, vec![
+            TokenKind::Keyword,
+            TokenKind::Integer(-12),
+            TokenKind::Boolean(true),
+            TokenKind::String,
+            TokenKind::Combinator,
+            TokenKind::Identifier,
+            TokenKind::Punct,
+            TokenKind::Unknown,
+            TokenKind::Bytes,
+            TokenKind::Bytes,
+        ]);
+    }
+
+    #[test]
+    fn starts_a_new_token_after_a_string() {
+        let tokens = super::scan("a\"b c\"d \"e\\\"\"").unwrap();
+        let tokens: Vec<_> = tokens.iter().map(|t| (t.value.as_str(), t.span.start, t.col)).collect();
+        assert_eq!(tokens, vec![("a\"b c\"", 0, 1), ("d", 6, 7), ("\"e\\\"\"", 8, 9)]);
+        let Err(crate::Error::ParseError(_, token)) = super::scan("x\n \"ab\\\"") else { panic!("expected an error") };
+        assert_eq!((token.span, token.line, token.col), (super::Span::new(3, 8), 2, 2));
+    }
+
+    #[test]
+    fn locates_unterminated_strings_at_their_opening_quote() {
+        let error = super::scan("1\n  é \"oops\ndup").unwrap_err();
+        assert_eq!(error.to_string(), "2:5: Unterminated string, with no closing `\"` before the end of the line");
+        assert_eq!(error.token().map(|token| (token.value.as_str(), token.span)), Some(("\"oops", super::Span::new(7, 12))));
+        let error = super::scan("x\"a \\\n b").unwrap_err();
+        assert_eq!(error.to_string(), "1:2: Unterminated string, with no closing `\"` before the end of the input");
+    }
+
+    #[test]
+    fn scans_unicode_words_and_counts_graphemes() {
+        use super::TokenKind::{Identifier, Unknown};
+        let tokens = super::scan("café nai\u{308}ve -> 🙂 1abc\n  é").unwrap();
+        let tokens: Vec<_> = tokens.iter().map(|t| (t.value.as_str(), t.kind, t.line, t.col)).collect();
+        assert_eq!(tokens, vec![
+            ("café", Identifier, 1, 1),
+            ("nai\u{308}ve", Identifier, 1, 6),
+            ("->", Identifier, 1, 12),
+            ("🙂", Unknown, 1, 15),
+            ("1abc", Unknown, 1, 17),
+            ("é", Identifier, 2, 3),
+        ]);
+        assert_eq!(super::utf16_position("a\n🙂é x", 9), (1, 4));
+    }
+
     #[test]
     fn brackets_are_their_own_tokens() {
         let tokens = super::scan("[Hello, world!]").unwrap();
@@ -158,4 +474,14 @@ mod tests {
         assert_eq!(tokens[3].value, "world!");
         assert_eq!(tokens[4].value, "}");
     }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn scans_a_reader_a_line_at_a_time() {
+        let source = "def greeting: (-> String) = \"hello\\\n  world\";\n## doc\ngreeting\n  é";
+        let tokens = super::scan_reader(source.as_bytes()).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(tokens, super::scan(source).unwrap());
+        let error = super::scan_reader("1\n  \"open\\\n".as_bytes()).find_map(Result::err).unwrap();
+        assert_eq!(Some(error), super::scan("1\n  \"open\\\n").err());
+    }
 }
@@ -0,0 +1,140 @@
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+use alloc::{boxed::Box, collections::BTreeMap, string::String, vec::Vec};
+
+// Names are interned into one table for the whole process, so a symbol means the same name wherever it's used,
+// and comparing or looking up symbols compares integers. Interned names are never freed, which is fine for the
+// identifiers of programs but means arbitrary strings shouldn't be interned.
+
+/// An interned name, like the identifier of a word.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct Symbol(u32);
+
+impl Symbol {
+    /// The symbol for `name`, adding it to the table if it isn't there yet.
+    pub fn intern(name: &str) -> Symbol {
+        INTERNER.with(|interner| {
+            if let Some(&symbol) = interner.symbols.get(name) {
+                return symbol;
+            }
+            let name: &'static str = Box::leak(Box::from(name));
+            let symbol = Symbol(interner.names.len() as u32);
+            interner.names.push(name);
+            interner.symbols.insert(name, symbol);
+            symbol
+        })
+    }
+
+    /// The symbol for `name` if it has been interned, without interning it. A name that has never been
+    /// interned can't be the name of anything.
+    pub fn get(name: &str) -> Option<Symbol> {
+        INTERNER.with(|interner| interner.symbols.get(name).copied())
+    }
+
+    pub fn as_str(self) -> &'static str {
+        INTERNER.with(|interner| interner.names[self.0 as usize])
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Self {
+        Symbol::intern(name)
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialEq<String> for Symbol {
+    fn eq(&self, other: &String) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Symbols debug as their names, so ASTs print the same as they did with strings.
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+/// Symbols are saved as their names, since ids differ between processes.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Symbol {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Symbol {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = <alloc::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        Ok(Symbol::intern(&name))
+    }
+}
+
+struct Interner {
+    names: Vec<&'static str>,
+    symbols: BTreeMap<&'static str, Symbol>,
+}
+
+/// A spin lock, which needs nothing from an operating system. The table is only held long enough to look a
+/// name up or add one, so there's little to wait for.
+struct Lock {
+    locked: AtomicBool,
+    interner: UnsafeCell<Interner>,
+}
+
+// Safety: the interner is only reached through `with`, which holds the lock.
+unsafe impl Sync for Lock {}
+
+impl Lock {
+    fn with<T>(&self, f: impl FnOnce(&mut Interner) -> T) -> T {
+        while self.locked.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            core::hint::spin_loop();
+        }
+        // Safety: the lock is held until after `f` returns.
+        let result = f(unsafe { &mut *self.interner.get() });
+        self.locked.store(false, Ordering::Release);
+        result
+    }
+}
+
+static INTERNER: Lock = Lock {
+    locked: AtomicBool::new(false),
+    interner: UnsafeCell::new(Interner { names: Vec::new(), symbols: BTreeMap::new() }),
+};
+
+#[cfg(test)]
+mod tests {
+    use super::Symbol;
+
+    #[test]
+    fn interns_names_once() {
+        let dup = Symbol::intern("interned-twice");
+        assert_eq!(Symbol::intern("interned-twice"), dup);
+        assert_ne!(Symbol::intern("interned-once"), dup);
+        assert_eq!(dup.as_str(), "interned-twice");
+        assert_eq!(dup, "interned-twice");
+        assert_eq!(format!("{} {:?}", dup, dup), "interned-twice \"interned-twice\"");
+        assert_eq!(Symbol::get("never-interned"), None);
+        assert_eq!(Symbol::get("interned-twice"), Some(dup));
+    }
+}
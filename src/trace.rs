@@ -0,0 +1,182 @@
+use std::io::Write;
+#[cfg(feature = "serde")]
+use std::time::Instant;
+use crate::evaluator::{Observer, Value};
+use crate::format::{elide, factor_to_source};
+use crate::parser::Factor;
+
+/// Prints every evaluated factor followed by the stack afterwards, Forth-style:
+///
+/// ```text
+/// 1          -- 1
+/// 2          -- 1 2
+/// +          -- 3
+/// ```
+///
+/// Nested calls are indented by their depth. Only the top `max_values` values are shown, and long
+/// factors or values are elided to `max_width` characters.
+pub struct TraceObserver<W: Write> {
+    out: W,
+    pub max_values: usize,
+    pub max_width: usize,
+}
+
+impl<W: Write> TraceObserver<W> {
+    pub fn new(out: W) -> TraceObserver<W> {
+        TraceObserver {
+            out,
+            max_values: 8,
+            max_width: 24,
+        }
+    }
+
+    fn render_stack(&self, stack: &[Value]) -> String {
+        let shown = &stack[stack.len().saturating_sub(self.max_values)..];
+        let mut rendered: Vec<String> = shown.iter()
+            .map(|value| elide(&value.to_string(), self.max_width))
+            .collect();
+        if shown.len() < stack.len() {
+            rendered.insert(0, format!("({} more)", stack.len() - shown.len()));
+        }
+        rendered.join(" ")
+    }
+}
+
+impl<W: Write> Observer for TraceObserver<W> {
+    fn after_factor(&mut self, factor: &Factor, stack: &[Value], depth: usize) {
+        let indent = "  ".repeat(depth.saturating_sub(1));
+        let word = elide(&factor_to_source(factor), self.max_width);
+        let line = format!("{:<10} -- {}", indent + &word, self.render_stack(stack));
+        // Tracing is best effort; a closed output shouldn't stop the program being traced.
+        let _ = writeln!(self.out, "{}", line.trim_end());
+    }
+}
+
+/// Writes a JSON object per evaluated factor, one per line, for other tools to build timelines or flamegraphs
+/// from:
+///
+/// ```text
+/// {"col":5,"depth":1,"end":5,"line":1,"stack_depth":1,"start":4,"time_us":12,"word":"+"}
+/// ```
+///
+/// `time_us` is the microseconds since the observer was made, taken when the factor finished, `depth` is how
+/// many calls deep it ran and `stack_depth` how many values were on the stack afterwards. With `values` set,
+/// the values themselves are written too, top last, as `stack`.
+#[cfg(feature = "serde")]
+pub struct JsonTraceObserver<W: Write> {
+    out: W,
+    start: Instant,
+    pub values: bool,
+}
+
+#[cfg(feature = "serde")]
+impl<W: Write> JsonTraceObserver<W> {
+    pub fn new(out: W) -> JsonTraceObserver<W> {
+        JsonTraceObserver {
+            out,
+            start: Instant::now(),
+            values: false,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<W: Write> Observer for JsonTraceObserver<W> {
+    fn after_factor(&mut self, factor: &Factor, stack: &[Value], depth: usize) {
+        let token = factor.token();
+        let mut event = serde_json::json!({
+            "time_us": self.start.elapsed().as_micros() as u64,
+            "word": factor_to_source(factor),
+            "line": token.line,
+            "col": token.col,
+            "start": token.span.start,
+            "end": token.span.end,
+            "depth": depth,
+            "stack_depth": stack.len(),
+        });
+        if self.values {
+            event["stack"] = stack.iter().map(ToString::to_string).collect();
+        }
+        // Like the text trace, this is best effort.
+        let _ = writeln!(self.out, "{}", event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::Write;
+    use std::rc::Rc;
+    use crate::evaluator::Evaluator;
+    use crate::parser::parse;
+    use super::TraceObserver;
+    #[cfg(feature = "serde")]
+    use super::JsonTraceObserver;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn trace(input: &str, configure: impl FnOnce(&mut TraceObserver<SharedBuffer>)) -> String {
+        let buffer = SharedBuffer::default();
+        let mut observer = TraceObserver::new(buffer.clone());
+        configure(&mut observer);
+        run(input, Box::new(observer), buffer)
+    }
+
+    fn run(input: &str, observer: Box<dyn crate::evaluator::Observer>, buffer: SharedBuffer) -> String {
+        let mut evaluator = Evaluator::new();
+        evaluator.add_observer(observer);
+        for cycle in parse(input).unwrap() {
+            evaluator.eval_cycle(&cycle).unwrap();
+        }
+        let output = buffer.0.borrow().clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn prints_the_stack_after_every_word() {
+        assert_eq!(trace("1 [2 +] call", |_| {}), "\
+1          -- 1
+[2 +]      -- 1 [2 +]
+call       -- 1
+  2        -- 1 2
+  +        -- 3
+");
+    }
+
+    #[test]
+    fn limits_and_elides_the_stack() {
+        let output = trace("1 2 3 \"a long string\"", |observer| {
+            observer.max_values = 2;
+            observer.max_width = 6;
+        });
+        assert_eq!(output.lines().last().unwrap(), "\"a lo…     -- (2 more) 3 \"a lo…");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn writes_an_event_per_factor() {
+        let buffer = SharedBuffer::default();
+        let mut observer = JsonTraceObserver::new(buffer.clone());
+        observer.values = true;
+        let output = run("1 [2 +] call", Box::new(observer), buffer);
+        let events: Vec<serde_json::Value> = output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(events.len(), 5);
+        let mut last = events[4].clone();
+        assert!(last["time_us"].is_u64());
+        last.as_object_mut().unwrap().remove("time_us");
+        assert_eq!(last, serde_json::json!({
+            "word": "+", "line": 1, "col": 6, "start": 5, "end": 6, "depth": 2, "stack_depth": 1, "stack": ["3"],
+        }));
+    }
+}
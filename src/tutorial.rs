@@ -0,0 +1,229 @@
+use crate::abstract_interpreter::AbstractInterpreter;
+use crate::error::Error;
+use crate::parser::Cycle;
+use crate::typechecker::{Type, TypeChecker};
+use std::collections::BTreeMap;
+use crate::{compile, Engine, Progress, Value};
+
+/// A lesson: what to read, and a task for the learner to write a term for.
+pub struct Lesson {
+    pub title: &'static str,
+    pub text: &'static str,
+    /// The stack effect the term has to have, as its type is shown.
+    pub effect: &'static str,
+    /// Inputs the term is run on, and the values it should leave for them, both as source.
+    pub cases: &'static [(&'static str, &'static str)],
+    pub hint: &'static str,
+}
+
+/// The lessons `chara tutorial` walks through, in order.
+pub const LESSONS: &[Lesson] = &[
+    Lesson {
+        title: "Pushing values",
+        text: "Chara keeps its values on a stack. A number pushes itself onto the top of it.\n\
+               Leave 1 on the stack, with 2 on top of it.",
+        effect: "( -> Int, Int)",
+        cases: &[("", "1 2")],
+        hint: "Type the numbers in the order they go onto the stack, separated by a space.",
+    },
+    Lesson {
+        title: "Words",
+        text: "A word takes its inputs from the top of the stack and pushes its results back.\n\
+               With 3 and 4 on the stack, add them together.",
+        effect: "(Int, Int -> Int)",
+        cases: &[("3 4", "7"), ("10 -2", "8")],
+        hint: "`+` takes two numbers and pushes their sum.",
+    },
+    Lesson {
+        title: "Shuffling the stack",
+        text: "`-` subtracts the top value from the one under it. `swap` exchanges the top two values.\n\
+               With 5 and 2 on the stack, subtract the 5 from the 2.",
+        effect: "(Int, Int -> Int)",
+        cases: &[("5 2", "-3"), ("1 8", "7")],
+        hint: "Swap the values first, so the 5 is on top.",
+    },
+    Lesson {
+        title: "Copying values",
+        text: "`dup` pushes a copy of the top value, and `*` multiplies.\n\
+               Square the number on the stack.",
+        effect: "(Int -> Int)",
+        cases: &[("6", "36"), ("-3", "9")],
+        hint: "Copy the number, so there are two of it to multiply.",
+    },
+    Lesson {
+        title: "Comparing",
+        text: "`<` pushes whether the value under the top one is less than it, as true or false.\n\
+               Leave whether the number on the stack is less than 10.",
+        effect: "(Int -> Bool)",
+        cases: &[("9", "true"), ("10", "false")],
+        hint: "Push the 10 first, so it's on top to compare with.",
+    },
+    Lesson {
+        title: "Choosing",
+        text: "Code in brackets is a quotation: it's pushed without running, for a word to run later.\n\
+               `[condition] [then] [else] ifte` runs the condition on a copy of the stack, then runs one\n\
+               of the other two quotations depending on whether it left true.\n\
+               Make the number on the stack positive, leaving it alone if it already is.",
+        effect: "(Int -> Int)",
+        cases: &[("-4", "4"), ("3", "3"), ("0", "0")],
+        hint: "`0 <` checks for a negative number, and `0 swap -` negates one.",
+    },
+];
+
+/// How many steps a term gets to finish in, so one that never does can't hang the tutorial.
+const STEP_LIMIT: usize = 100_000;
+
+/// Check that `input` has a lesson's stack effect and leaves the right values for each of its cases,
+/// explaining what's wrong if not.
+pub fn check(lesson: &Lesson, input: &str) -> Result<(), String> {
+    let program = compile(input).map_err(|errors| describe(&errors))?;
+    let [Cycle::Term(factors, _)] = program.cycles() else {
+        return Err("That should be a single term, without any definitions.".to_string());
+    };
+    // Type errors are reported by running it. Terms the abstract interpreter can't follow are left to their
+    // results to judge.
+    let checker = TypeChecker::new();
+    let wanted = parse_type(&checker, lesson.effect);
+    if let Ok(effect) = AbstractInterpreter::with_environment(checker.environment().clone()).interpret(factors) {
+        if !instance(&effect, &wanted, &mut BTreeMap::new()) {
+            return Err(format!("That has the stack effect {}, but it should have {}.", effect, lesson.effect));
+        }
+    }
+    for (inputs, expected) in lesson.cases {
+        // The input goes on a line of its own, so errors in it point at the column the learner typed.
+        let actual = run(&mut Engine::new(), &format!("{}\n{}", inputs, input))?;
+        let expected = run(&mut Engine::new(), expected).expect("lessons' expected values run");
+        if actual != expected {
+            let on = if inputs.is_empty() { String::new() } else { format!(" on {}", inputs) };
+            return Err(format!("Run{}, that leaves [{}], but it should leave [{}].", on, show(&actual), show(&expected)));
+        }
+    }
+    Ok(())
+}
+
+fn run(engine: &mut Engine, source: &str) -> Result<Vec<Value>, String> {
+    let program = compile(source).map_err(|errors| describe(&errors))?;
+    engine.start(program).map_err(|errors| describe(&errors))?;
+    match engine.resume(STEP_LIMIT).map_err(|error| error.to_string())? {
+        Progress::Done => Ok(engine.stack().to_vec()),
+        _ => Err("That didn't finish.".to_string()),
+    }
+}
+
+/// The type a lesson's effect is written as.
+fn parse_type(checker: &TypeChecker, effect: &str) -> Type {
+    let source = format!("def answer: {} = ;", effect);
+    match compile(&source).expect("lessons' effects parse").cycles() {
+        [Cycle::Definition(_, annotation, _, _, _)] => checker.type_from_annotation(annotation).expect("lessons' effects are types"),
+        _ => unreachable!(),
+    }
+}
+
+/// Whether `specific` is `general` with its parameters filled in, consistently with `bound` so far. A term with a
+/// more general effect than a lesson asks for still does what it asks.
+fn instance(general: &Type, specific: &Type, bound: &mut BTreeMap<usize, Type>) -> bool {
+    let all = |general: &[Type], specific: &[Type], bound: &mut BTreeMap<usize, Type>| {
+        general.len() == specific.len() && general.iter().zip(specific).all(|(general, specific)| instance(general, specific, bound))
+    };
+    match (general, specific) {
+        (Type::Param(n), _) => bound.entry(*n).or_insert_with(|| specific.clone()) == specific,
+        (Type::Set(general), Type::Set(specific)) => instance(general, specific, bound),
+        (Type::Named(name, general), Type::Named(other, specific)) => name == other && all(general, specific, bound),
+        (Type::Function(general_in, general_out), Type::Function(specific_in, specific_out)) => {
+            all(general_in, specific_in, bound) && all(general_out, specific_out, bound)
+        }
+        _ => general == specific,
+    }
+}
+
+fn describe(errors: &[Error]) -> String {
+    errors.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n")
+}
+
+fn show(stack: &[Value]) -> String {
+    stack.iter().map(ToString::to_string).collect::<Vec<_>>().join(" ")
+}
+
+/// Progress through the lessons, a line of input at a time.
+#[derive(Default)]
+pub struct Tutorial {
+    lesson: usize,
+}
+
+impl Tutorial {
+    pub fn new() -> Tutorial {
+        Self::default()
+    }
+
+    /// The lesson being worked on, or `None` once they're all done.
+    pub fn lesson(&self) -> Option<&'static Lesson> {
+        LESSONS.get(self.lesson)
+    }
+
+    /// What to show for the lesson being worked on.
+    pub fn intro(&self) -> String {
+        match self.lesson() {
+            Some(lesson) => format!(
+                "Lesson {} of {}: {}\n\n{}\nIts stack effect is {}.",
+                self.lesson + 1, LESSONS.len(), lesson.title, lesson.text, lesson.effect,
+            ),
+            None => "That's every lesson. Run `chara repl` to keep experimenting.".to_string(),
+        }
+    }
+
+    /// Handle one line of input: an attempt at the lesson, `:hint`, or `:skip` to move on without one.
+    pub fn handle(&mut self, line: &str) -> String {
+        let Some(lesson) = self.lesson() else {
+            return self.intro();
+        };
+        match line.trim() {
+            ":hint" => lesson.hint.to_string(),
+            ":skip" => {
+                self.lesson += 1;
+                self.intro()
+            }
+            input => match check(lesson, input) {
+                Ok(()) => {
+                    self.lesson += 1;
+                    format!("Correct!\n\n{}", self.intro())
+                }
+                Err(problem) => format!("{}\nTry again, or enter :hint or :skip.", problem),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, Tutorial, LESSONS};
+
+    #[test]
+    fn every_lesson_can_be_passed() {
+        let answers = ["1 2", "+", "swap -", "dup *", "10 <", "[0 <] [0 swap -] [] ifte"];
+        assert_eq!(answers.len(), LESSONS.len());
+        for (lesson, answer) in LESSONS.iter().zip(answers) {
+            assert_eq!(check(lesson, answer), Ok(()), "{}", lesson.title);
+        }
+    }
+
+    #[test]
+    fn explains_wrong_answers() {
+        assert_eq!(check(&LESSONS[2], "-"), Err("Run on 5 2, that leaves [3], but it should leave [-3].".to_string()));
+        assert_eq!(check(&LESSONS[1], "drop"), Err("That has the stack effect (a -> ), but it should have (Int, Int -> Int).".to_string()));
+        assert_eq!(check(&LESSONS[1], "def x: Int = 1;"), Err("That should be a single term, without any definitions.".to_string()));
+        assert_eq!(check(&LESSONS[5], "drop 4"), Err("Run on 3, that leaves [4], but it should leave [3].".to_string()));
+    }
+
+    #[test]
+    fn moves_through_the_lessons() {
+        let mut tutorial = Tutorial::new();
+        assert!(tutorial.intro().starts_with("Lesson 1 of 6: Pushing values"));
+        assert!(tutorial.handle("2 1").ends_with("Try again, or enter :hint or :skip."));
+        assert_eq!(tutorial.handle(":hint"), LESSONS[0].hint);
+        assert!(tutorial.handle("1 2").starts_with("Correct!\n\nLesson 2 of 6"));
+        for _ in 1..LESSONS.len() {
+            tutorial.handle(":skip");
+        }
+        assert!(tutorial.lesson().is_none());
+    }
+}
@@ -1,47 +1,309 @@
-use std::collections::HashMap;
-use crate::error::Error;
-use crate::parser::{Cycle, Factor, TypeAnnotation};
+use alloc::{borrow::Cow, boxed::Box, collections::BTreeMap, format, string::{String, ToString}, sync::Arc, vec, vec::Vec};
+use core::{fmt, iter};
+use rpds::RedBlackTreeMapSync;
+use crate::abstract_interpreter::{AbstractInterpreter, StackDepth};
+use crate::builtins::Registry;
+use crate::error::{Error, Warning};
+use crate::format::{annotation_to_source, factor_to_source, term_to_source};
+use crate::parser::{self, Cycle, Factor, TypeAnnotation};
+use crate::scanner::Token;
+use crate::symbol::Symbol;
 
 #[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Type {
     Param(usize),
     Int,
     Bool,
     String,
+    Bytes,
+    Set(Box<Type>),
+    /// A value whose type is only known when it's run. It can be used as any type, and any value can be made
+    /// one, so code using it opts out of checking, and `:: Type` coercions out of it are checked at runtime.
+    Dyn,
+    /// A declared type, applied to as many types as its constructor takes.
+    Named(String, Vec<Type>),
     Function(Vec<Type>, Vec<Type>),
 }
 
-struct TypeChecker {
-    environment: HashMap<String, Type>,
+/// Types are displayed in annotation syntax, with parameters written as lowercase letters.
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Param(n) if *n < 26 => write!(f, "{}", (b'a' + *n as u8) as char),
+            Type::Param(n) => write!(f, "t{}", n),
+            Type::Int => write!(f, "Int"),
+            Type::Bool => write!(f, "Bool"),
+            Type::String => write!(f, "String"),
+            Type::Bytes => write!(f, "Bytes"),
+            Type::Set(element) => write!(f, "Set({})", element),
+            Type::Dyn => write!(f, "Dyn"),
+            Type::Named(name, args) if args.is_empty() => write!(f, "{}", name),
+            Type::Named(name, args) => write!(f, "{}({})", name, args.iter().map(Type::to_string).collect::<Vec<_>>().join(", ")),
+            Type::Function(t_in, t_out) => {
+                let render = |types: &[Type]| types.iter().map(Type::to_string).collect::<Vec<_>>().join(", ");
+                match (t_in.is_empty(), t_out.is_empty()) {
+                    (true, true) => write!(f, "( -> )"),
+                    (true, false) => write!(f, "( -> {})", render(t_out)),
+                    (false, true) => write!(f, "({} -> )", render(t_in)),
+                    (false, false) => write!(f, "({} -> {})", render(t_in), render(t_out)),
+                }
+            }
+        }
+    }
+}
+
+/// What running a word can do besides change the stack: do IO, give different results from the same inputs, or
+/// not terminate. A word has the effects of every word it uses, and a word that calls itself can diverge.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Effects(u8);
+
+impl Effects {
+    pub const PURE: Effects = Effects(0);
+    pub const IO: Effects = Effects(1);
+    pub const NONDETERMINISM: Effects = Effects(2);
+    pub const DIVERGENCE: Effects = Effects(4);
+    pub const ALL: Effects = Effects(7);
+
+    /// Each effect with the name it's written with after a `!`, and how it's described in errors.
+    const NAMES: [(Effects, &'static str, &'static str); 3] = [
+        (Effects::IO, "io", "do io"),
+        (Effects::NONDETERMINISM, "nondet", "be nondeterministic"),
+        (Effects::DIVERGENCE, "diverge", "diverge"),
+    ];
+
+    /// The effect written as `name`, where `pure` is none.
+    pub fn from_name(name: &str) -> Option<Effects> {
+        match name {
+            "pure" => Some(Effects::PURE),
+            name => Self::NAMES.iter().find(|(_, written, _)| *written == name).map(|(effects, _, _)| *effects),
+        }
+    }
+
+    pub fn union(self, other: Effects) -> Effects {
+        Effects(self.0 | other.0)
+    }
+
+    pub fn intersection(self, other: Effects) -> Effects {
+        Effects(self.0 & other.0)
+    }
+
+    pub fn contains(self, other: Effects) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_pure(self) -> bool {
+        self == Effects::PURE
+    }
+
+    fn describe(self) -> String {
+        Self::NAMES.iter().filter(|(effects, _, _)| self.contains(*effects)).map(|(_, _, description)| *description).collect::<Vec<_>>().join(" and ")
+    }
+}
+
+/// Effects are displayed as they're written after a `!`.
+impl fmt::Display for Effects {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_pure() {
+            return write!(f, "pure");
+        }
+        let names = Self::NAMES.iter().filter(|(effects, _, _)| self.contains(*effects)).map(|(_, name, _)| *name);
+        write!(f, "{}", names.collect::<Vec<_>>().join(" "))
+    }
+}
+
+/// Programs with fewer cycles than this are checked in order, since threads would cost more than they save.
+#[cfg(feature = "parallel")]
+const PARALLEL_CYCLES: usize = 64;
+
+/// The type of each word in scope. It's a persistent map, so a copy shares all but what has changed since it
+/// was made, and types are shared rather than copied each time a word is used.
+pub type Environment = RedBlackTreeMapSync<Symbol, Arc<Type>>;
+
+/// What checking a definition of a word that's already defined does.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Redefinition {
+    /// Reject the new definition, pointing at both. In a file, a word defined twice is almost always a mistake.
+    #[default]
+    Error,
+    /// Replace the earlier definition, as the REPL does so words can be fixed and tried again.
+    Allow,
+}
+
+/// What checking a top-level term that leaves values on the stack does.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Leftovers {
+    /// Nothing, as in the REPL, where leaving a value is how it's shown.
+    Allow,
+    /// Warn about them, since a program's values are usually meant to be used.
+    #[default]
+    Warn,
+    /// Reject the term.
+    Error,
+}
+
+/// How strictly programs are checked. The default suits most programs; `strict` suits library code, and
+/// `lenient` quick scripting.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CheckConfig {
+    pub redefinition: Redefinition,
+    /// Whether a definition can have the name of a builtin or declared word, replacing it.
+    pub shadowing: bool,
+    pub leftovers: Leftovers,
+    /// How many values a top-level term can leave on the stack before `leftovers` says what's done about it.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub term_outputs: usize,
+    /// Whether a definition's signature can use `Dyn`, which leaves what it takes or leaves unchecked until it runs.
+    pub dynamic_signatures: bool,
+}
+
+impl CheckConfig {
+    /// Words can't be defined over, not even builtins, terms can't leave values behind, and every definition's
+    /// signature has to be checkable.
+    pub fn strict() -> CheckConfig {
+        CheckConfig { redefinition: Redefinition::Error, shadowing: false, leftovers: Leftovers::Error, term_outputs: 0, dynamic_signatures: false }
+    }
+
+    /// Words can be defined again, and terms can leave values behind without a warning, as in the REPL.
+    pub fn lenient() -> CheckConfig {
+        CheckConfig { redefinition: Redefinition::Allow, shadowing: true, leftovers: Leftovers::Allow, term_outputs: 0, dynamic_signatures: true }
+    }
+}
+
+impl Default for CheckConfig {
+    fn default() -> Self {
+        CheckConfig { redefinition: Redefinition::Error, shadowing: true, leftovers: Leftovers::Warn, term_outputs: 0, dynamic_signatures: true }
+    }
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TypeChecker {
+    environment: Environment,
+    /// Where each word defined in chara was defined, by the token of its name. Builtin and
+    /// declared words aren't in it, so they can be defined over.
+    #[cfg_attr(feature = "serde", serde(default))]
+    definitions: RedBlackTreeMapSync<Symbol, Token>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    config: CheckConfig,
+    /// The effects of each word that has any. Builtins are pure but for the random words and `force`, which
+    /// can be given an infinite sequence, so otherwise only definitions and declared words are here.
+    #[cfg_attr(feature = "serde", serde(default))]
+    effects: RedBlackTreeMapSync<Symbol, Effects>,
+    /// The effects programs may not use words with.
+    #[cfg_attr(feature = "serde", serde(default))]
+    forbidden_effects: Effects,
+    /// How many type arguments each declared type constructor takes.
+    #[cfg_attr(feature = "serde", serde(default))]
+    type_constructors: RedBlackTreeMapSync<Symbol, usize>,
     param_count: usize,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    warnings: Vec<Warning>,
 }
 
 impl TypeChecker {
-    fn new() -> Self {
-        let mut environment: HashMap<String, Type> = HashMap::new();
-        environment.insert("+".to_string(), Type::Function(vec![Type::Int, Type::Int], vec![Type::Int]));
-        environment.insert("-".to_string(), Type::Function(vec![Type::Int, Type::Int], vec![Type::Int]));
-        environment.insert("*".to_string(), Type::Function(vec![Type::Int, Type::Int], vec![Type::Int]));
-        environment.insert("/".to_string(), Type::Function(vec![Type::Int, Type::Int], vec![Type::Int]));
-        environment.insert("<".to_string(), Type::Function(vec![Type::Int, Type::Int], vec![Type::Bool]));
-        environment.insert(">".to_string(), Type::Function(vec![Type::Int, Type::Int], vec![Type::Bool]));
-        environment.insert("=".to_string(), Type::Function(vec![Type::Int, Type::Int], vec![Type::Bool]));
-        environment.insert("not".to_string(), Type::Function(vec![Type::Bool], vec![Type::Bool]));
-        environment.insert("and".to_string(), Type::Function(vec![Type::Bool, Type::Bool], vec![Type::Bool]));
-        environment.insert("or".to_string(), Type::Function(vec![Type::Bool, Type::Bool], vec![Type::Bool]));
+    pub fn new() -> Self {
+        Self::with_registry(&Registry::standard())
+    }
+
+    /// A checker for programs using the builtins in `registry`, rather than all of chara's.
+    pub fn with_registry(registry: &Registry) -> Self {
         Self {
-            environment,
+            environment: registry.environment(),
+            definitions: RedBlackTreeMapSync::new_sync(),
+            config: CheckConfig::default(),
+            effects: registry.effects(),
+            forbidden_effects: Effects::PURE,
+            type_constructors: [(Symbol::intern("Seq"), 1), (Symbol::intern("Set"), 1), (Symbol::intern("Coroutine"), 2)].into_iter().collect(),
             param_count: 0,
+            warnings: Vec::new(),
         }
     }
 
+    /// The type of a word, as declared by its definition or built in.
+    pub fn type_of(&self, name: &str) -> Option<&Type> {
+        self.environment.get(&Symbol::get(name)?).map(|t| &**t)
+    }
+
+    /// Bring a word into scope with the given type, without checking a body for it.
+    pub fn declare(&mut self, name: &str, t: Type) {
+        self.environment.insert_mut(Symbol::intern(name), Arc::new(t));
+    }
+
+    /// Bring a type constructor that takes `arity` type arguments into scope, so annotations can use it, like
+    /// `List(Int)` for one that takes one.
+    pub fn declare_type(&mut self, name: &str, arity: usize) {
+        self.type_constructors.insert_mut(Symbol::intern(name), arity);
+    }
+
+    /// What running a word can do besides change the stack, as its definition declared or was found to.
+    pub fn effects_of(&self, name: &str) -> Effects {
+        Symbol::get(name).and_then(|name| self.effects.get(&name).copied()).unwrap_or_default()
+    }
+
+    /// Give a declared word effects, which words using it then have too.
+    pub fn declare_effects(&mut self, name: &str, effects: Effects) {
+        self.effects.insert_mut(Symbol::intern(name), effects);
+    }
+
+    /// Only let programs use words with `allowed` effects, so untrusted code can be kept to pure words. A
+    /// definition that calls itself can diverge, so it isn't pure either.
+    pub fn set_allowed_effects(&mut self, allowed: Effects) {
+        self.forbidden_effects = Effects(Effects::ALL.0 & !allowed.0);
+    }
+
+    /// The types of the words in scope. Cloning it is cheap, since copies share the map.
+    pub fn environment(&self) -> &Environment {
+        &self.environment
+    }
+
+    /// Every word in the environment with its type, in no particular order.
+    pub fn words(&self) -> impl Iterator<Item = (&str, &Type)> {
+        self.environment.iter().map(|(name, t)| (name.as_str(), &**t))
+    }
+
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// Remove and return the warnings collected so far, so a long-lived checker can report them per input.
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        core::mem::take(&mut self.warnings)
+    }
+
+    /// Check `cycles`, leaving the environment as it was before if any of them fails.
+    pub fn check_atomically(&mut self, cycles: &[Cycle]) -> Result<Vec<Type>, Error> {
+        let (environment, definitions, effects) = (self.environment.clone(), self.definitions.clone(), self.effects.clone());
+        let result = self.check(cycles);
+        if result.is_err() {
+            (self.environment, self.definitions, self.effects) = (environment, definitions, effects);
+        }
+        result
+    }
+
+    pub fn set_redefinition(&mut self, redefinition: Redefinition) {
+        self.config.redefinition = redefinition;
+    }
+
+    pub fn config(&self) -> CheckConfig {
+        self.config
+    }
+
+    pub fn set_config(&mut self, config: CheckConfig) {
+        self.config = config;
+    }
+
     fn new_param(&mut self) -> Type {
         let parameter_count = self.param_count;
         self.param_count += 1;
         Type::Param(parameter_count)
     }
 
-    fn type_from_annotation(&self, annotation: &TypeAnnotation) -> Result<Type, Error> {
+    pub fn type_from_annotation(&self, annotation: &TypeAnnotation) -> Result<Type, Error> {
         match annotation {
             TypeAnnotation::Function(in_types, out_types, token, _) => {
                 let (in_types, in_type_errors): (Vec<_>, Vec<_>) =
@@ -54,7 +316,7 @@ impl TypeChecker {
                         .map(|t| self.type_from_annotation(t))
                         .partition(Result::is_ok);
                 let out_types: Vec<_> = out_types.into_iter().map(Result::unwrap).collect();
-                if in_type_errors.len() > 0 || out_type_errors.len() > 0 {
+                if !in_type_errors.is_empty() || !out_type_errors.is_empty() {
                     return Err(Error::TypeError("Error in function type".to_string(), token.clone(), ));
                 }
                 Ok(Type::Function(in_types, out_types))
@@ -62,54 +324,485 @@ impl TypeChecker {
             TypeAnnotation::Identifier(name, _) if name == "Int" => Ok(Type::Int),
             TypeAnnotation::Identifier(name, _) if name == "Bool" => Ok(Type::Bool),
             TypeAnnotation::Identifier(name, _) if name == "String" => Ok(Type::String),
-            TypeAnnotation::Identifier(name, token) => Err(Error::TypeError(format!("Unknown type {}", name), token.clone())),
+            TypeAnnotation::Identifier(name, _) if name == "Bytes" => Ok(Type::Bytes),
+            TypeAnnotation::Identifier(name, _) if name == "Dyn" => Ok(Type::Dyn),
+            // Parameters are written as they're displayed.
+            TypeAnnotation::Identifier(name, _) if name.len() == 1 && name.as_bytes()[0].is_ascii_lowercase() => {
+                Ok(Type::Param((name.as_bytes()[0] - b'a') as usize))
+            }
+            TypeAnnotation::Identifier(name, token) => self.apply_constructor(name, Vec::new(), token),
+            TypeAnnotation::Apply(name, args, token, _) => {
+                let args = args.iter().map(|t| self.type_from_annotation(t)).collect::<Result<Vec<_>, _>>()?;
+                self.apply_constructor(name, args, token)
+            }
+            TypeAnnotation::Effectful(t, _, _) | TypeAnnotation::Total(t, _) | TypeAnnotation::Memo(t, _) => self.type_from_annotation(t),
         }
     }
 
-    pub fn check(&mut self, cycles: &Vec<Cycle>) -> Result<(), Error> {
-        for cycle in cycles {
-            self.check_cycle(cycle)?;
+    /// The type constructor `name` applied to `args`, if it's declared to take that many.
+    fn apply_constructor(&self, name: &str, args: Vec<Type>, token: &Token) -> Result<Type, Error> {
+        let arity = *Symbol::get(name).and_then(|name| self.type_constructors.get(&name))
+            .ok_or_else(|| Error::TypeError(format!("Unknown type {}", name), token.clone()))?;
+        if args.len() != arity {
+            let plural = |n: usize| if n == 1 { "" } else { "s" };
+            let message = format!("`{}` takes {} type argument{}, but is given {}", name, arity, plural(arity), args.len());
+            return Err(Error::TypeError(message, token.clone()));
+        }
+        match name {
+            // An embedder can declare `Set` again with another arity, and then it's just a name.
+            "Set" => match <[Type; 1]>::try_from(args) {
+                Ok([element]) => Ok(Type::Set(Box::new(element))),
+                Err(args) => Ok(Type::Named(name.to_string(), args)),
+            },
+            name => Ok(Type::Named(name.to_string(), args)),
         }
-        Ok(())
     }
 
+    /// Check `cycles` in order, returning the type inferred for each of them, so it can be shown without inferring
+    /// it again.
+    pub fn check(&mut self, cycles: &[Cycle]) -> Result<Vec<Type>, Error> {
+        #[cfg(feature = "parallel")]
+        if cycles.len() >= PARALLEL_CYCLES && self.independent(cycles) {
+            return self.check_in_parallel(cycles);
+        }
+        cycles.iter().map(|cycle| self.check_cycle(cycle)).collect()
+    }
+
+    /// Check one cycle, returning the type inferred for it: the effect of a term, or of the body of a definition,
+    /// test or macro.
     pub fn check_cycle(&mut self, cycle: &Cycle) -> Result<Type, Error> {
-        let t = match cycle {
-            Cycle::Definition(name, annotation, factors) => {
-                self.check_definition(name, &self.type_from_annotation(annotation)?, factors)?
+        if let Cycle::Definition(token, annotation, _, _, _) = cycle {
+            let symbol = Symbol::intern(&token.value);
+            if let (Redefinition::Error, Some(first)) = (self.config.redefinition, self.definitions.get(&symbol)) {
+                return Err(Error::DuplicateDefinition(token.value.clone(), first.clone(), token.clone()));
+            }
+            let t = self.type_from_annotation(annotation)?;
+            self.check_signature(&token.value, annotation.first_token(), &t)?;
+            self.environment.insert_mut(symbol, Arc::new(t));
+            self.definitions.insert_mut(symbol, token.clone());
+        }
+        let t = self.check_body(cycle)?;
+        self.check_effects(cycle)?;
+        Ok(normalize(&t))
+    }
+
+    /// Check a definition's signature against what the config allows: whether it can replace a word that isn't
+    /// defined in chara, and whether its type can use `Dyn`.
+    fn check_signature(&self, name: &str, site: &Token, t: &Type) -> Result<(), Error> {
+        let symbol = Symbol::intern(name);
+        if !self.config.shadowing && self.environment.contains_key(&symbol) && !self.definitions.contains_key(&symbol) {
+            return Err(Error::TypeError(format!("`{}` is already a builtin word, so it can't be defined here", name), site.clone()));
+        }
+        if !self.config.dynamic_signatures && mentions_dyn(t) {
+            return Err(Error::TypeError(format!("`{}` has `Dyn` in its signature, so it can't be checked", name), site.clone()));
+        }
+        Ok(())
+    }
+
+    /// Check that the words a cycle uses only have the effects allowed, and the ones its definition declares if it
+    /// declares any, and record the effects of a definition: the ones it declares, or else the ones of its words.
+    fn check_effects(&mut self, cycle: &Cycle) -> Result<(), Error> {
+        fn words<'a>(factors: &'a [Factor], found: &mut Vec<(Symbol, &'a Token)>) {
+            for factor in factors {
+                match factor {
+                    Factor::Identifier(name, token) => found.push((*name, token)),
+                    Factor::Quotation(inner, _) => words(inner, found),
+                    _ => {}
+                }
             }
-            Cycle::Term(factors) => {
-                self.check_term(factors)?
+        }
+        let mut found = Vec::new();
+        let (mut total, mut memo) = (false, false);
+        let (defining, declared) = match cycle {
+            Cycle::Definition(Token { value: name, .. }, annotation, body, _, _) => {
+                if annotation.is_total() {
+                    self.check_total(name, body, false)?;
+                    total = true;
+                }
+                memo = annotation.is_memo();
+                words(body, &mut found);
+                (Some((name, Symbol::intern(name))), self.declared_effects(annotation)?)
+            }
+            Cycle::Term(body, _) | Cycle::Macro(_, body, _) => {
+                words(body, &mut found);
+                (None, None)
+            }
+            Cycle::Test(_, body, expected, _) => {
+                words(body, &mut found);
+                words(expected.as_deref().unwrap_or_default(), &mut found);
+                (None, None)
             }
         };
-        Ok(t)
+        let mut inferred = Effects::PURE;
+        for (word, token) in found {
+            let effects = match defining {
+                Some((_, name)) if name == word && total => Effects::PURE,
+                Some((_, name)) if name == word => Effects::DIVERGENCE,
+                _ => self.effects.get(&word).copied().unwrap_or_default(),
+            };
+            let forbidden = effects.intersection(self.forbidden_effects);
+            if !forbidden.is_pure() {
+                let message = format!("`{}` can {}, which isn't allowed here", word, forbidden.describe());
+                return Err(Error::TypeError(message, token.clone()));
+            }
+            // A cached result is only the same as running the definition again if it's pure, but a definition that
+            // diverges never has a result to cache.
+            let impure = Effects(effects.0 & !Effects::DIVERGENCE.0);
+            if let (Some((name, _)), true, false) = (defining, memo, impure.is_pure()) {
+                let message = format!("`{}` is memoized, so it has to be pure, but `{}` can {}", name, word, impure.describe());
+                return Err(Error::TypeError(message, token.clone()));
+            }
+            if let (Some((name, _)), Some(declared)) = (defining, declared) {
+                if !declared.contains(effects) {
+                    let undeclared = Effects(effects.0 & !declared.0);
+                    let message = format!("`{}` is declared `! {}`, but `{}` can {}", name, declared, word, undeclared.describe());
+                    return Err(Error::TypeError(message, token.clone()));
+                }
+            }
+            inferred = inferred.union(effects);
+        }
+        if let Some((_, name)) = defining {
+            self.effects.insert_mut(name, declared.unwrap_or(inferred));
+        }
+        Ok(())
     }
 
-    fn check_definition(&mut self, name: &str, annotation: &Type, factors: &Vec<Factor>) -> Result<Type, Error> {
-        self.environment.insert(name.to_string(), annotation.clone());
-        self.check_term(factors)
+    /// Check that a `total def` of `name` terminates, as far as it can be told from `factors`, which are its body
+    /// or part of it, in a branch of an `ifte` or `cond` if `guarded`. It can only use words that terminate, and
+    /// only call itself in a branch, after making a number smaller with `-` or `/` by a literal, which is taken to
+    /// be what the branch's condition counts down.
+    fn check_total(&self, name: &str, factors: &[Factor], guarded: bool) -> Result<(), Error> {
+        let error = |message: String, token: &Token| Err(Error::TypeError(message, token.clone()));
+        for (index, factor) in factors.iter().enumerate() {
+            match factor {
+                Factor::Identifier(word, token) if word.as_str() == name => {
+                    if !guarded {
+                        return error(format!("`{}` calls itself outside of any `ifte` or `cond` branch, so it can't be total", name), token);
+                    }
+                    let decreases = factors[..index].windows(2).any(|pair| match pair {
+                        [Factor::Int(parser::Value::Integer(n), _), Factor::Identifier(op, _)] => {
+                            (op.as_str() == "-" && *n > 0) || (op.as_str() == "/" && *n > 1)
+                        }
+                        _ => false,
+                    });
+                    if !decreases {
+                        return error(format!("`{}` calls itself without first making a number smaller, like with `1 -`, so it can't be total", name), token);
+                    }
+                }
+                Factor::Identifier(word, token) if self.effects.get(word).is_some_and(|effects| effects.contains(Effects::DIVERGENCE)) => {
+                    return error(format!("`{}` might not terminate, so `{}` can't be total", word, name), token);
+                }
+                Factor::Quotation(inner, _) => {
+                    let branch = matches!(
+                        (factors.get(index + 1), factors.get(index + 2)),
+                        (Some(Factor::Ifte(_) | Factor::Cond(_)), _) | (Some(Factor::Quotation(..)), Some(Factor::Ifte(_)))
+                    ) || matches!(factors.get(index + 1), Some(Factor::Identifier(word, _)) if matches!(word.as_str(), "if" | "unless"));
+                    self.check_total(name, inner, guarded || branch)?;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// The effects a definition's annotation declares, if it declares any.
+    fn declared_effects(&self, annotation: &TypeAnnotation) -> Result<Option<Effects>, Error> {
+        let names = match annotation {
+            TypeAnnotation::Total(t, _) | TypeAnnotation::Memo(t, _) => return self.declared_effects(t),
+            TypeAnnotation::Effectful(_, _, names) => names,
+            _ => return Ok(None),
+        };
+        names.iter()
+            .try_fold(Effects::PURE, |declared, name| match Effects::from_name(&name.value) {
+                Some(effects) => Ok(declared.union(effects)),
+                None => Err(Error::TypeError(format!("Unknown effect {}", name.value), name.clone())),
+            })
+            .map(Some)
+    }
+
+    /// Check the factors of a cycle. A definition's own signature has to be in the environment already.
+    fn check_body(&mut self, cycle: &Cycle) -> Result<Type, Error> {
+        match cycle {
+            Cycle::Definition(Token { value: name, .. }, _, factors, _, _) => {
+                self.warn_definition_underflow(name, factors);
+                self.check_term(factors)
+            }
+            Cycle::Macro(_, factors, _) => self.check_term(factors),
+            Cycle::Term(factors, _) => {
+                let t = self.check_term(factors)?;
+                self.warn_unbalanced_term(factors)?;
+                Ok(t)
+            }
+            // Tests are expected to leave values behind for their expectation, so they aren't warned about.
+            Cycle::Test(_, body, expected, _) => {
+                if let Some(expected) = expected {
+                    self.check_term(expected)?;
+                }
+                self.check_term(body)
+            }
+        }
+    }
+
+    /// Whether checking `cycles` against all of their signatures at once gives the same results as checking
+    /// them in order, which it does unless a word is defined twice or used before its definition.
+    #[cfg(feature = "parallel")]
+    fn independent(&self, cycles: &[Cycle]) -> bool {
+        let mut defined_at = BTreeMap::new();
+        for (index, cycle) in cycles.iter().enumerate() {
+            if let Cycle::Definition(Token { value: name, .. }, _, _, _, _) = cycle {
+                let name = Symbol::intern(name);
+                if self.environment.contains_key(&name) || defined_at.insert(name, index).is_some() {
+                    return false;
+                }
+            }
+        }
+        fn defined_in_time(factors: &[Factor], defined_at: &BTreeMap<Symbol, usize>, index: usize) -> bool {
+            factors.iter().all(|factor| match factor {
+                Factor::Identifier(name, _) => defined_at.get(name).is_none_or(|&defined| defined <= index),
+                Factor::Quotation(term, _) => defined_in_time(term, defined_at, index),
+                _ => true,
+            })
+        }
+        cycles.iter().enumerate().all(|(index, cycle)| match cycle {
+            Cycle::Definition(_, _, factors, _, _) | Cycle::Term(factors, _) | Cycle::Macro(_, factors, _) => {
+                defined_in_time(factors, &defined_at, index)
+            }
+            Cycle::Test(_, body, expected, _) => {
+                defined_in_time(body, &defined_at, index)
+                    && expected.as_ref().is_none_or(|expected| defined_in_time(expected, &defined_at, index))
+            }
+        })
+    }
+
+    /// Check `cycles` on several threads. Bodies only read the environment, so once every signature is in it
+    /// they can be checked in any order, and their results are then taken in program order: the warnings, the
+    /// error, and the environment left behind are the same as checking them one at a time.
+    #[cfg(feature = "parallel")]
+    fn check_in_parallel(&mut self, cycles: &[Cycle]) -> Result<Vec<Type>, Error> {
+        use rayon::prelude::*;
+        type Signature<'a> = Option<(Symbol, &'a Token, Result<Type, Error>)>;
+        fn declare(checker: &mut TypeChecker, signatures: &[Signature]) {
+            for (name, token, t) in signatures.iter().flatten() {
+                if let Ok(t) = t {
+                    checker.environment.insert_mut(*name, Arc::new(t.clone()));
+                    checker.definitions.insert_mut(*name, Token::clone(token));
+                }
+            }
+        }
+        let before = (self.environment.clone(), self.definitions.clone());
+        let signatures: Vec<Signature> = cycles.iter()
+            .map(|cycle| match cycle {
+                Cycle::Definition(token, annotation, _, _, _) => {
+                    let t = self.type_from_annotation(annotation)
+                        .and_then(|t| self.check_signature(&token.value, annotation.first_token(), &t).map(|_| t));
+                    Some((Symbol::intern(&token.value), token, t))
+                }
+                _ => None,
+            })
+            .collect();
+        declare(self, &signatures);
+        let checker = &*self;
+        let results: Vec<Result<(Type, Vec<Warning>), Error>> = cycles.par_iter().zip(&signatures)
+            .map(|(cycle, signature)| {
+                if let Some((_, _, Err(error))) = signature {
+                    return Err(error.clone());
+                }
+                let mut checker = TypeChecker {
+                    environment: checker.environment.clone(),
+                    definitions: checker.definitions.clone(),
+                    config: checker.config,
+                    effects: checker.effects.clone(),
+                    forbidden_effects: checker.forbidden_effects,
+                    type_constructors: checker.type_constructors.clone(),
+                    param_count: checker.param_count,
+                    warnings: Vec::new(),
+                };
+                let t = checker.check_body(cycle)?;
+                Ok((normalize(&t), checker.warnings))
+            })
+            .collect();
+        // Effects depend on the effects of the words defined before, so they're inferred in order.
+        let mut types = Vec::new();
+        for (index, (result, cycle)) in results.into_iter().zip(cycles).enumerate() {
+            match result.and_then(|checked| self.check_effects(cycle).map(|_| checked)) {
+                Ok((t, warnings)) => {
+                    types.push(t);
+                    self.warnings.extend(warnings);
+                }
+                Err(error) => {
+                    // Only the definitions up to the failing cycle would have been seen, with its own if its
+                    // signature was well formed.
+                    (self.environment, self.definitions) = before;
+                    declare(self, &signatures[..=index]);
+                    return Err(error);
+                }
+            }
+        }
+        Ok(types)
+    }
+
+    /// Record a warning if a definition's body can take more values from the stack than its signature says
+    /// it takes. Only how many values each factor takes and leaves is followed, so this works even for a body
+    /// whose types don't check.
+    fn warn_definition_underflow(&mut self, name: &str, factors: &[Factor]) {
+        let declared = match self.environment.get(&Symbol::intern(name)).map(|t| &**t) {
+            Some(Type::Function(t_in, _)) => t_in.len(),
+            _ => 0,
+        };
+        let interpreter = AbstractInterpreter::with_environment(self.environment.clone());
+        if let Some(StackDepth { lowest, deepest: Some(token), .. }) = interpreter.stack_depth(factors) {
+            let needed = lowest.unsigned_abs();
+            if needed > declared {
+                self.warnings.push(Warning::DefinitionUnderflow(name.to_string(), declared, needed, token));
+            }
+        }
     }
 
-    fn check_term(&mut self, factors: &Vec<Factor>) -> Result<Type, Error> {
+    /// Run the abstract interpreter over a top-level term and record a warning if it would underflow
+    /// or leaves more values behind than expected, or fail if the config doesn't allow leaving them. Terms the
+    /// interpreter can't handle are skipped.
+    fn warn_unbalanced_term(&mut self, factors: &[Factor]) -> Result<(), Error> {
+        let Some(token) = factors.first().map(Factor::token) else {
+            return Ok(());
+        };
+        let interpreter = AbstractInterpreter::with_environment(self.environment.clone());
+        if let Ok(Type::Function(t_in, t_out)) = interpreter.interpret(factors) {
+            if !t_in.is_empty() {
+                self.warnings.push(Warning::StackUnderflow(t_in, token.clone()));
+            }
+            if t_out.len() > self.config.term_outputs {
+                match self.config.leftovers {
+                    Leftovers::Allow => {}
+                    Leftovers::Warn => self.warnings.push(Warning::LeftoverValues(t_out, token)),
+                    Leftovers::Error => {
+                        let types = t_out.iter().map(Type::to_string).collect::<Vec<_>>().join(", ");
+                        let message = format!("term leaves {} value(s) on the stack: {}", t_out.len(), types);
+                        return Err(Error::TypeError(message, token));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The type of a term. Values it moves onto the retain stack have to be moved back off it before it ends,
+    /// so each definition and quotation leaves the retain stack as it found it.
+    fn check_term(&mut self, factors: &[Factor]) -> Result<Type, Error> {
         let mut in_stack: Vec<Type> = Vec::new();
         let mut out_stack: Vec<Type> = Vec::new();
-        for factor in factors {
-            let t = self.check_factor(factor)?;
-            match t {
-                Type::Param(_) => out_stack.push(t),
-                Type::Int => out_stack.push(t),
-                Type::Bool => out_stack.push(t),
-                Type::String => out_stack.push(t),
+        let mut retained: Vec<(Type, &Token)> = Vec::new();
+        let mut factors = factors.iter().peekable();
+        while let Some(factor) = factors.next() {
+            let t = match (factor, factors.peek().copied()) {
+                (Factor::Identifier(name, token), _) if is_retain_word(name) && !self.definitions.contains_key(name) => {
+                    if name.as_str() == ">r" {
+                        let t = out_stack.pop().unwrap_or_else(|| {
+                            let t = self.new_param();
+                            in_stack.push(t.clone());
+                            t
+                        });
+                        retained.push((t, token));
+                    } else {
+                        let (t, _) = retained.last().cloned().ok_or_else(|| {
+                            Error::TypeError(format!("`{}` has no value on the retain stack to take here", name), token.clone())
+                        })?;
+                        if name.as_str() == "r>" {
+                            retained.pop();
+                        }
+                        out_stack.push(t);
+                    }
+                    continue;
+                }
+                (Factor::Assertion(annotation), _) => {
+                    self.check_assertion(annotation, &mut in_stack, &mut out_stack)?;
+                    continue;
+                }
+                (Factor::Ascription(annotation, token), _) => {
+                    self.check_ascription(annotation, token, &mut in_stack, &mut out_stack)?;
+                    continue;
+                }
+                // What `call` and `cat` do depends on the quotations they're given, so they can only be checked when
+                // those quotations' types are known from the stack.
+                (Factor::Call(token), _) => match out_stack.pop() {
+                    Some(t @ Type::Function(..)) => Cow::Owned(t),
+                    t => return Err(Self::unknown_quotation("call", t, token)),
+                },
+                (Factor::Cat(token), _) => match (out_stack.pop(), out_stack.pop()) {
+                    (Some(Type::Function(second_in, second_out)), Some(Type::Function(first_in, first_out))) => {
+                        let (mut joined_in, mut joined_out) = (Vec::new(), Vec::new());
+                        Self::concat_function(&mut joined_in, &mut joined_out, &first_in, &first_out);
+                        Self::concat_function(&mut joined_in, &mut joined_out, &second_in, &second_out);
+                        Cow::Owned(Type::Function(vec![], vec![Type::Function(joined_in, joined_out)]))
+                    }
+                    (Some(Type::Function(..)), t) | (t, _) => return Err(Self::unknown_quotation("cat", t, token)),
+                },
+                (Factor::Quotation(clauses, _), Some(Factor::Cond(token))) => {
+                    factors.next();
+                    Cow::Owned(self.check_cond(clauses, token)?)
+                }
+                (Factor::Quotation(body, _), Some(Factor::Let(names, _))) => {
+                    factors.next();
+                    Cow::Owned(self.check_let(body, names)?)
+                }
+                (Factor::Quotation(body, _), Some(Factor::Identifier(name, token)))
+                    if matches!(name.as_str(), "if" | "unless") && !self.definitions.contains_key(name) => {
+                    factors.next();
+                    Cow::Owned(self.check_if(body, name.as_str(), token)?)
+                }
+                (Factor::Quotation(body, _), Some(Factor::Int(parser::Value::Integer(count), _))) if self.builtin_follows(factors.clone(), "apply-n") => {
+                    factors.next();
+                    let Some(Factor::Identifier(_, token)) = factors.next() else {
+                        unreachable!()
+                    };
+                    let t = self.check_term(body)?;
+                    Cow::Owned(self.check_apply_n(t, &format!("`[{}]`", term_to_source(body)), *count, token)?)
+                }
+                (Factor::Int(parser::Value::Integer(count), _), Some(Factor::Identifier(name, token)))
+                    if name.as_str() == "apply-n" && !self.definitions.contains_key(name) && matches!(out_stack.last(), Some(Type::Function(..))) => {
+                    factors.next();
+                    let t = out_stack.pop().expect("matched above");
+                    Cow::Owned(self.check_apply_n(t, "it", *count, token)?)
+                }
+                (Factor::Identifier(name, token), _) if name.as_str() == "apply-n" && !self.definitions.contains_key(name) => {
+                    let message = "`apply-n` can only be checked with its count written out just before it, after a quotation whose type is known";
+                    return Err(Error::TypeError(message.to_string(), token.clone()));
+                }
+                (Factor::Identifier(name, token), _) if name.as_str() == "compose" && !self.definitions.contains_key(name) => {
+                    Cow::Owned(Self::check_compose(out_stack.pop(), token)?)
+                }
+                (Factor::Quotation(body, _), Some(Factor::Quotation(handler, _))) if self.builtin_follows(factors.clone(), "try") => {
+                    factors.next();
+                    let Some(Factor::Identifier(_, token)) = factors.next() else {
+                        unreachable!()
+                    };
+                    Cow::Owned(self.check_try(body, handler, token)?)
+                }
+                // A quotation is a value, whose type is the effect of running it.
+                (Factor::Quotation(body, _), _) => {
+                    let t = self.check_term(body)?;
+                    out_stack.push(t);
+                    continue;
+                }
+                _ => self.check_factor(factor)?,
+            };
+            match &*t {
                 Type::Function(t_in, t_out) => {
                     Self::concat_function(&mut in_stack, &mut out_stack, t_in, t_out);
                 }
+                t => out_stack.push(t.clone()),
             }
         }
+        if let Some((_, token)) = retained.first() {
+            let message = "`>r` moves a value onto the retain stack that isn't moved back with `r>` before the end of the term";
+            return Err(Error::TypeError(message.to_string(), Token::clone(token)));
+        }
         Ok(Type::Function(in_stack, out_stack))
     }
 
-    fn check_factor(&mut self, factor: &Factor) -> Result<Type, Error> {
-        match factor {
+    /// The type of a factor. Words' types are borrowed from the environment rather than copied out of it, unless
+    /// they have parameters to renumber.
+    fn check_factor(&mut self, factor: &Factor) -> Result<Cow<'_, Type>, Error> {
+        let t = match factor {
             Factor::Dup(_) => {
                 let t = self.new_param();
                 Ok(Type::Function(vec![t.clone()], vec![t.clone(), t]))
@@ -122,11 +815,12 @@ impl TypeChecker {
                 let t = self.new_param();
                 Ok(Type::Function(vec![t.clone()], vec![Type::Function(vec![], vec![t])]))
             },
-            Factor::Call(_) => {
-                unimplemented!()
+            // Their stack effects depend on the quotations they're given, so `check_term` checks them against the stack.
+            Factor::Call(token) => {
+                Err(Error::TypeError("`call` can only be checked in a term, against the quotation it calls".to_string(), token.clone()))
             },
-            Factor::Cat(_) => {
-                unimplemented!()
+            Factor::Cat(token) => {
+                Err(Error::TypeError("`cat` can only be checked in a term, against the quotations it joins".to_string(), token.clone()))
             },
             Factor::Swap(_) => {
                 let a = self.new_param();
@@ -142,32 +836,289 @@ impl TypeChecker {
                 let t_output = vec![t_out];
                 Ok(Type::Function(t_input, t_output))
             },
+            Factor::Cond(token) => {
+                let message = "`cond` can only be checked with its clauses written out just before it";
+                Err(Error::TypeError(message.to_string(), token.clone()))
+            },
+            Factor::Let(_, token) => {
+                Err(Error::TypeError("`let` can only be checked with its body just before it".to_string(), token.clone()))
+            },
             Factor::Int(_, _) => Ok(Type::Function(vec![], vec![Type::Int])),
             Factor::Bool(_, _) => Ok(Type::Function(vec![], vec![Type::Bool])),
             Factor::String(_, _) => Ok(Type::Function(vec![], vec![Type::String])),
+            Factor::Bytes(_, _) => Ok(Type::Function(vec![], vec![Type::Bytes])),
+            // A set's elements all have the same type, so the first of them gives it.
+            Factor::Set(elements, _) => {
+                let element = match elements.first() {
+                    Some(first) => self.check_factor(first).map(|t| match &*t {
+                        Type::Function(_, t_out) => t_out.last().cloned().unwrap_or(Type::Dyn),
+                        t => t.clone(),
+                    })?,
+                    None => self.new_param(),
+                };
+                Ok(Type::Function(vec![], vec![Type::Set(Box::new(element))]))
+            }
             Factor::Identifier(name, token) => {
-                if !self.environment.contains_key(name) {
-                    return Err(Error::TypeError(format!("Unknown identifier {}", name), token.clone()));
-                }
-                Ok(self.environment[name].clone())
+                return match self.environment.get(name) {
+                    Some(t) => Ok(instantiate(t, &mut self.param_count)),
+                    None => Err(Error::TypeError(format!("Unknown identifier {}", name), token.clone())),
+                };
             }
-            Factor::Quotation(term) => {
+            // A local's type is the one it was bound with in this term, so its parameters are the term's own.
+            Factor::Local(name, token) => {
+                return match self.environment.get(name) {
+                    Some(t) => Ok(Cow::Borrowed(t)),
+                    None => Err(Error::TypeError(format!("Unknown identifier {}", name), token.clone())),
+                };
+            }
+            Factor::Quotation(term, _) => {
                 self.check_term(term)
             }
+            // Assertions and ascriptions are checked against the factors before them by `check_term`, and have no
+            // effect themselves.
+            Factor::Assertion(_) | Factor::Ascription(..) => Ok(Type::Function(vec![], vec![])),
+        };
+        t.map(Cow::Owned)
+    }
+
+    /// The error for `word` being given `t`, which isn't known to be a quotation, or nothing if the stack was empty.
+    fn unknown_quotation(word: &str, t: Option<Type>, token: &Token) -> Error {
+        let message = match t {
+            Some(t) => format!("`{}` needs a quotation whose type is known here, but got {}", word, t),
+            None => format!("`{}` needs a quotation whose type is known here, but the stack is empty", word),
+        };
+        Error::TypeError(message, token.clone())
+    }
+
+    /// The type of a `cond` with the clauses `clauses`, which is the stack effect every body has to share.
+    /// Predicates only run against a copy of the stack, so all they need to do is leave a `Bool` on top of it.
+    fn check_cond(&mut self, clauses: &[Factor], token: &Token) -> Result<Type, Error> {
+        let error = |message: String| Error::TypeError(message, token.clone());
+        let clauses = clauses.iter()
+            .map(|clause| match clause {
+                Factor::Quotation(factors, _) => Some(factors),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .filter(|clauses| clauses.len() % 2 == 1)
+            .ok_or_else(|| error("`cond` takes pairs of a `[predicate]` and a `[body]`, then a `[default]`".to_string()))?;
+        let (default, pairs) = clauses.split_last().unwrap();
+        let mut bodies = Vec::new();
+        for pair in pairs.chunks(2) {
+            if let Type::Function(_, t_out) = self.check_term(pair[0])? {
+                if let Some(t) = t_out.last().filter(|t| !matches!(t, Type::Bool | Type::Param(_))) {
+                    let message = format!("The predicate `[{}]` of a `cond` has to leave a Bool, but leaves {}",
+                        term_to_source(pair[0]), t);
+                    return Err(error(message));
+                }
+            }
+            bodies.push(pair[1]);
         }
+        bodies.push(default);
+        let effect = normalize(&self.check_term(bodies[0])?);
+        for body in &bodies[1..] {
+            let other = normalize(&self.check_term(body)?);
+            if other != effect {
+                let message = format!("Every body of a `cond` has to have the same stack effect, but `[{}]` is {} and `[{}]` is {}",
+                    term_to_source(bodies[0]), effect, term_to_source(body), other);
+                return Err(error(message));
+            }
+        }
+        Ok(effect)
+    }
+
+    /// Check that a term that has taken `in_stack`, listed from the top of the stack down, and left `out_stack`
+    /// has the effect a stack effect assertion says it does. Values further down than the term goes are taken
+    /// from below it, so they're pinned as its inputs, which it leaves where they were. Values further down than
+    /// the assertion goes can be anything, so they're left as they are on both sides.
+    fn check_assertion(&mut self, annotation: &TypeAnnotation, in_stack: &mut Vec<Type>, out_stack: &mut Vec<Type>) -> Result<(), Error> {
+        let Type::Function(mut asserted_in, mut asserted_out) = self.type_from_annotation(annotation)? else {
+            unreachable!("assertions are parsed as function types")
+        };
+        let actual = normalize(&Type::Function(in_stack.iter().rev().cloned().collect(), out_stack.clone()));
+        // The assertion lists values from the bottom of the stack up.
+        let below = asserted_in.len().saturating_sub(in_stack.len());
+        let (mut actual_in, mut actual_out) = (asserted_in[..below].to_vec(), asserted_in[..below].to_vec());
+        actual_in.extend(in_stack.iter().rev().cloned());
+        actual_out.extend(out_stack.iter().cloned());
+        let unasserted = actual_in[..actual_in.len() - asserted_in.len()].to_vec();
+        asserted_in.splice(0..0, unasserted.iter().cloned());
+        asserted_out.splice(0..0, unasserted);
+        if !generalizes(&Type::Function(actual_in, actual_out), &Type::Function(asserted_in.clone(), asserted_out), &mut BTreeMap::new()) {
+            let Type::Function(t_in, t_out) = actual else {
+                unreachable!()
+            };
+            let render = |types: &[Type]| types.iter().map(|t| format!("{} ", t)).collect::<String>();
+            let message = format!("The term up to here has the stack effect ( {}-- {}), not the asserted {}",
+                render(&t_in), render(&t_out), factor_to_source(&Factor::Assertion(Box::new(annotation.clone()))));
+            return Err(Error::TypeError(message, annotation.first_token().clone()));
+        }
+        in_stack.extend(asserted_in[..below].iter().rev().cloned());
+        out_stack.splice(0..0, asserted_in[..below].iter().cloned());
+        Ok(())
+    }
+
+    /// Check that the value on top of `out_stack` has the type an ascription gives it, or could have it, in which
+    /// case the parameters standing for it are pinned to that type everywhere in the term so far, and the value
+    /// has the ascribed type from then on, which coerces it into or out of `Dyn`. A term that has left nothing
+    /// has no value to ascribe a type to.
+    fn check_ascription(&mut self, annotation: &TypeAnnotation, token: &Token, in_stack: &mut [Type], out_stack: &mut [Type]) -> Result<(), Error> {
+        let ascribed = self.type_from_annotation(annotation)?;
+        let Some(top) = out_stack.last() else {
+            let message = format!("There's no value on the stack here to ascribe {} to", annotation_to_source(annotation));
+            return Err(Error::TypeError(message, token.clone()));
+        };
+        let mut bindings = BTreeMap::new();
+        if !generalizes(top, &ascribed, &mut bindings) {
+            let message = format!("The value on top of the stack here is {}, not the ascribed {}", normalize(top), annotation_to_source(annotation));
+            return Err(Error::TypeError(message, token.clone()));
+        }
+        for t in in_stack.iter_mut().chain(out_stack.iter_mut()) {
+            *t = substitute(t, &bindings);
+        }
+        *out_stack.last_mut().expect("checked above") = ascribed;
+        Ok(())
     }
 
-    fn concat_function(in_stack: &mut Vec<Type>, out_stack: &mut Vec<Type>, t_in: Vec<Type>, mut t_out: Vec<Type>) {
-        for t_expected in t_in.into_iter().rev() {
-            if out_stack.len() == 0 {
-                in_stack.push(t_expected);
+    /// Whether the factors after a quotation are one more factor and then the builtin `word`, like the handler
+    /// before a `try`.
+    fn builtin_follows<'a>(&self, mut factors: impl Iterator<Item = &'a Factor>, word: &str) -> bool {
+        factors.next();
+        matches!(factors.next(), Some(Factor::Identifier(name, _)) if name.as_str() == word && !self.definitions.contains_key(name))
+    }
+
+    /// The type of an `apply-n` of a quotation of type `t`, shown in errors as `shown`, to `count` values, which
+    /// takes each of them in turn, so the quotation has to take exactly one.
+    fn check_apply_n(&mut self, t: Type, shown: &str, count: i64, token: &Token) -> Result<Type, Error> {
+        let Type::Function(t_in, t_out) = t else {
+            unreachable!()
+        };
+        let [t_value] = &t_in[..] else {
+            let message = format!("The quotation given to `apply-n` has to take one value, but {} is {}",
+                shown, normalize(&Type::Function(t_in, t_out)));
+            return Err(Error::TypeError(message, token.clone()));
+        };
+        let count = usize::try_from(count)
+            .map_err(|_| Error::TypeError(format!("`apply-n` can't apply a quotation to {} values", count), token.clone()))?;
+        Ok(normalize(&Type::Function(vec![t_value.clone(); count], vec![t_out; count].concat())))
+    }
+
+    /// The type of a `compose` of `list`, the type of the value on top of the stack, which has to be a list of
+    /// quotations whose types are known, like one written out. The quotation it leaves runs each of them in turn.
+    fn check_compose(list: Option<Type>, token: &Token) -> Result<Type, Error> {
+        let quotations = match &list {
+            Some(Type::Function(t_in, elements)) if t_in.is_empty() => elements.iter()
+                .map(|element| match element {
+                    Type::Function(t_in, t_out) => Some((t_in, t_out)),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>(),
+            _ => None,
+        };
+        let Some(quotations) = quotations else {
+            let message = match list {
+                Some(t) => format!("`compose` needs a list of quotations whose types are known here, but got {}", t),
+                None => "`compose` needs a list of quotations whose types are known here, but the stack is empty".to_string(),
+            };
+            return Err(Error::TypeError(message, token.clone()));
+        };
+        let (mut joined_in, mut joined_out) = (Vec::new(), Vec::new());
+        for (t_in, t_out) in quotations {
+            Self::concat_function(&mut joined_in, &mut joined_out, t_in, t_out);
+        }
+        Ok(Type::Function(vec![], vec![Type::Function(joined_in, joined_out)]))
+    }
+
+    /// The type of a `try` of `body` and `handler`, which is the body's stack effect. The handler runs on the
+    /// stack the body started with, with the thrown value on top, so once it has taken that value it has to
+    /// have the body's effect, or a more general one.
+    fn check_try(&mut self, body: &[Factor], handler: &[Factor], token: &Token) -> Result<Type, Error> {
+        let (effect, handler_effect) = (self.check_term(body)?, self.check_term(handler)?);
+        let (Type::Function(mut body_in, mut body_out), Type::Function(mut handler_in, mut handler_out)) =
+            (effect.clone(), handler_effect.clone()) else {
+            unreachable!()
+        };
+        // A handler that doesn't take the thrown value leaves it below what it pushes.
+        if handler_in.is_empty() {
+            handler_out.insert(0, self.new_param());
+        } else {
+            handler_in.remove(0);
+        }
+        // Whichever side takes fewer values leaves the rest as they are, so it's compared with the other side's
+        // types for them. Inputs are listed from the top of the stack down, so values from further down go at
+        // their end.
+        for t in body_in.get(handler_in.len()..).unwrap_or_default().to_vec() {
+            handler_in.push(t.clone());
+            handler_out.insert(0, t);
+        }
+        for t in handler_in.get(body_in.len()..).unwrap_or_default().to_vec() {
+            body_in.push(t.clone());
+            body_out.insert(0, t);
+        }
+        let (padded, handled) = (normalize(&Type::Function(body_in, body_out)), normalize(&Type::Function(handler_in, handler_out)));
+        if !generalizes(&handled, &padded, &mut BTreeMap::new()) {
+            let message = format!("The handler of a `try` has to have the stack effect of its body once it takes the thrown value, but `[{}]` is {} and `[{}]` is {}",
+                term_to_source(body), normalize(&effect), term_to_source(handler), normalize(&handler_effect));
+            return Err(Error::TypeError(message, token.clone()));
+        }
+        Ok(padded)
+    }
+
+    /// The type of an `if` or `unless` of `body`, which takes a `Bool` from the top of the stack and the body's
+    /// inputs from under it. The body might not run, so it has to leave as many values as it takes, each of a
+    /// type the value it replaces could have. A parameter on either side takes the type on the other.
+    fn check_if(&mut self, body: &[Factor], name: &str, token: &Token) -> Result<Type, Error> {
+        let Type::Function(t_in, t_out) = self.check_term(body)? else {
+            unreachable!()
+        };
+        let compatible = |a: &Type, b: &Type| a == b || [a, b].iter().any(|t| matches!(t, Type::Param(_) | Type::Dyn));
+        // Inputs are listed from the top of the stack down, and outputs from the bottom up.
+        if t_in.len() != t_out.len() || !t_in.iter().rev().zip(&t_out).all(|(a, b)| compatible(a, b)) {
+            let message = format!("The body of `{}` has to leave the stack as it found it, but `[{}]` is {}",
+                name, term_to_source(body), normalize(&Type::Function(t_in, t_out)));
+            return Err(Error::TypeError(message, token.clone()));
+        }
+        let kept: Vec<Type> = t_in.iter().rev().zip(t_out)
+            .map(|(t_in, t_out)| if matches!(t_out, Type::Param(_)) { t_in.clone() } else { t_out })
+            .collect();
+        let t_in = iter::once(Type::Bool).chain(kept.iter().rev().cloned()).collect();
+        Ok(normalize(&Type::Function(t_in, kept)))
+    }
+
+    /// The type of a `let` binding `names` around `body`, which takes the values to bind from the top of the
+    /// stack, and whatever the body takes from below them. A local's type is a parameter, like any value's that
+    /// isn't known.
+    fn check_let(&mut self, body: &[Factor], names: &[Symbol]) -> Result<Type, Error> {
+        let outside = self.environment.clone();
+        let mut bound = Vec::new();
+        for name in names {
+            let t = self.new_param();
+            self.environment.insert_mut(*name, Arc::new(t.clone()));
+            bound.push(t);
+        }
+        let t = self.check_term(body);
+        self.environment = outside;
+        match t? {
+            Type::Function(mut t_in, t_out) => {
+                t_in.extend(bound);
+                Ok(Type::Function(t_in, t_out))
+            }
+            t => Ok(t),
+        }
+    }
+
+    fn concat_function(in_stack: &mut Vec<Type>, out_stack: &mut Vec<Type>, t_in: &[Type], t_out: &[Type]) {
+        let mut t_out = t_out.to_vec();
+        for t_expected in t_in.iter().rev() {
+            if out_stack.is_empty() {
+                in_stack.push(t_expected.clone());
             } else {
                 let t_actual = out_stack.pop().unwrap();
                 if let Type::Param(n_expected) = t_expected {
                     if let Type::Param(n_actual) = t_actual {
                         t_out.iter_mut().for_each(|el| {
                             match el {
-                                Type::Param(n) if n == &n_expected => {
+                                Type::Param(n) if n == n_expected => {
                                     *el = Type::Param(n_actual);
                                 },
                                 _ => {},
@@ -177,15 +1128,163 @@ impl TypeChecker {
                 }
             }
         }
-        out_stack.extend(t_out.into_iter());
+        out_stack.extend(t_out);
+    }
+}
+
+/// Whether `t` is `Dyn` or made from it.
+fn mentions_dyn(t: &Type) -> bool {
+    match t {
+        Type::Dyn => true,
+        Type::Function(t_in, t_out) => t_in.iter().chain(t_out).any(mentions_dyn),
+        Type::Named(_, args) => args.iter().any(mentions_dyn),
+        Type::Set(element) => mentions_dyn(element),
+        _ => false,
+    }
+}
+
+/// `t` with its parameters numbered from 0 in the order they first appear, so types that only differ in which
+/// parameters they were given are equal.
+pub(crate) fn normalize(t: &Type) -> Type {
+    fn renumber(t: &Type, seen: &mut Vec<usize>) -> Type {
+        match t {
+            Type::Param(n) => match seen.iter().position(|m| m == n) {
+                Some(index) => Type::Param(index),
+                None => {
+                    seen.push(*n);
+                    Type::Param(seen.len() - 1)
+                }
+            },
+            Type::Function(t_in, t_out) => {
+                let t_in = t_in.iter().map(|t| renumber(t, seen)).collect();
+                Type::Function(t_in, t_out.iter().map(|t| renumber(t, seen)).collect())
+            }
+            Type::Named(name, args) => Type::Named(name.clone(), args.iter().map(|t| renumber(t, seen)).collect()),
+            Type::Set(element) => Type::Set(Box::new(renumber(element, seen))),
+            t => t.clone(),
+        }
+    }
+    renumber(t, &mut Vec::new())
+}
+
+/// Whether `general` becomes `specific` when its parameters are replaced, the same way as in `bindings`. `Dyn`
+/// matches anything, either way around.
+pub(crate) fn generalizes(general: &Type, specific: &Type, bindings: &mut BTreeMap<usize, Type>) -> bool {
+    match (general, specific) {
+        (Type::Param(n), _) => match bindings.get(n) {
+            Some(t) => t == specific,
+            None => {
+                bindings.insert(*n, specific.clone());
+                true
+            }
+        },
+        (Type::Function(general_in, general_out), Type::Function(specific_in, specific_out)) => {
+            general_in.len() == specific_in.len() && general_out.len() == specific_out.len()
+                && general_in.iter().chain(general_out).zip(specific_in.iter().chain(specific_out))
+                    .all(|(general, specific)| generalizes(general, specific, bindings))
+        }
+        (Type::Named(general_name, general_args), Type::Named(specific_name, specific_args)) => {
+            general_name == specific_name && general_args.len() == specific_args.len()
+                && general_args.iter().zip(specific_args).all(|(general, specific)| generalizes(general, specific, bindings))
+        }
+        (Type::Set(general), Type::Set(specific)) => generalizes(general, specific, bindings),
+        (Type::Dyn, _) | (_, Type::Dyn) => true,
+        (general, specific) => general == specific,
+    }
+}
+
+/// `t` with the parameters in `bindings` replaced by what they're bound to.
+fn substitute(t: &Type, bindings: &BTreeMap<usize, Type>) -> Type {
+    match t {
+        Type::Param(n) => bindings.get(n).cloned().unwrap_or_else(|| t.clone()),
+        Type::Function(t_in, t_out) => Type::Function(
+            t_in.iter().map(|t| substitute(t, bindings)).collect(),
+            t_out.iter().map(|t| substitute(t, bindings)).collect(),
+        ),
+        Type::Named(name, args) => Type::Named(name.clone(), args.iter().map(|t| substitute(t, bindings)).collect()),
+        Type::Set(element) => Type::Set(Box::new(substitute(element, bindings))),
+        t => t.clone(),
+    }
+}
+
+/// `t` with its parameters renumbered from `*next` on, moving `*next` past them, so a word's parameters can't be
+/// mistaken for the fresh ones of the term it's used in. A type without parameters is borrowed as it is.
+pub(crate) fn instantiate<'a>(t: &'a Type, next: &mut usize) -> Cow<'a, Type> {
+    fn highest(t: &Type) -> Option<usize> {
+        match t {
+            Type::Param(n) => Some(*n),
+            Type::Function(t_in, t_out) => t_in.iter().chain(t_out).filter_map(highest).max(),
+            Type::Named(_, args) => args.iter().filter_map(highest).max(),
+            Type::Set(element) => highest(element),
+            _ => None,
+        }
     }
+    let Some(highest) = highest(t) else {
+        return Cow::Borrowed(t);
+    };
+    let bindings = (0..=highest).map(|n| (n, Type::Param(*next + n))).collect();
+    *next += highest + 1;
+    Cow::Owned(substitute(t, &bindings))
+}
+
+impl Default for TypeChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether `name` is one of the builtin words that move values between the stack and the retain stack.
+pub(crate) fn is_retain_word(name: &Symbol) -> bool {
+    matches!(name.as_str(), ">r" | "r>" | "r@")
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::error::{Error, Warning};
     use crate::parser::parse;
     use super::{Type};
 
+    #[test]
+    fn shares_the_environment_between_copies() {
+        let mut typechecker = super::TypeChecker::new();
+        typechecker.check(&parse("def sq: (Int -> Int) = dup *;").unwrap()).unwrap();
+        let copy = typechecker.clone();
+        assert_eq!(typechecker.environment(), copy.environment());
+        typechecker.declare("cube", Type::Function(vec![Type::Int], vec![Type::Int]));
+        assert!(copy.type_of("cube").is_none());
+        assert!(std::ptr::eq(typechecker.type_of("sq").unwrap(), copy.type_of("sq").unwrap()));
+    }
+
+    #[test]
+    fn checks_large_programs_the_same_as_in_order() {
+        let mut source = String::from("def f0: (Int -> Int) = 1 +;\n");
+        for i in 1..100 {
+            source += &format!("def f{}: (Int -> Int) = f{} 1 +;\n{} f{} 2\ntest \"t{}\" {{ 1 f{} expect {} }}\n", i, i - 1, i, i, i, i, i + 2);
+        }
+        let broken = source.replace("def f50: (Int -> Int)", "def f50: (Nope -> Int)").replace("f70 2", "f70 nope");
+        for source in [source, broken] {
+            let cycles = parse(&source).unwrap();
+            let mut in_order = super::TypeChecker::new();
+            let expected: Result<Vec<_>, _> = cycles.iter().map(|cycle| in_order.check_cycle(cycle)).collect();
+            let mut typechecker = super::TypeChecker::new();
+            assert_eq!(typechecker.check(&cycles), expected);
+            assert_eq!(typechecker.warnings(), in_order.warnings());
+            assert_eq!(typechecker.environment(), in_order.environment());
+        }
+    }
+
+    #[test]
+    fn rejects_duplicate_definitions_unless_allowed() {
+        let input = parse("def f: (Int -> Int) = 1 +;\ndef g: Int = 1;\ndef f: (Int -> Bool) = 1 >;").unwrap();
+        let mut typechecker = super::TypeChecker::new();
+        let error = typechecker.check_atomically(&input).unwrap_err();
+        assert_eq!(error.to_string(), "3:5: `f` is already defined, at 1:5");
+        assert!(typechecker.type_of("g").is_none());
+        typechecker.set_redefinition(super::Redefinition::Allow);
+        typechecker.check(&input).unwrap();
+        assert_eq!(typechecker.type_of("f"), Some(&Type::Function(vec![Type::Int], vec![Type::Bool])));
+    }
+
     #[test]
     fn recognizes_unknown_identifiers() {
         let input = parse("[a b c]").unwrap();
@@ -200,9 +1299,300 @@ mod tests {
         }
     }
 
+    #[test]
+    fn checks_that_cond_bodies_share_a_stack_effect() {
+        let mut typechecker = super::TypeChecker::new();
+        let sign = "def sign: (Int -> String) = cond { [dup 0 <] [drop \"-\"] [dup 0 =] [drop \"0\"] [drop \"+\"] };";
+        typechecker.check(&parse(sign).unwrap()).unwrap();
+        let mismatched = parse("def f: (Int -> Int) = cond { [dup 0 <] [drop \"-\"] [1 +] };").unwrap();
+        match typechecker.check(&mismatched).unwrap_err() {
+            super::Error::TypeError(message, token) => {
+                assert_eq!(message, "Every body of a `cond` has to have the same stack effect, but `[drop \"-\"]` is (a -> String) and `[1 +]` is (Int -> Int)");
+                assert_eq!(token.value, "cond");
+            }
+            error => panic!("Expected TypeError, got {:?}", error),
+        }
+        assert!(typechecker.check(&parse("1 cond { [\"yes\"] [1 +] [2 +] }").unwrap()).is_err());
+    }
+
+    #[test]
+    fn checks_definitions_using_let() {
+        let mut typechecker = super::TypeChecker::new();
+        let source = "def hyp2: (Int, Int -> Int) = let a b in a a * b b * + end;\ndef swap2: (Int, String -> String, Int) = let n s in s n end;";
+        typechecker.check(&parse(source).unwrap()).unwrap();
+        // Locals go out of scope at the `end`.
+        let error = typechecker.check(&parse("def f: (Int -> Int) = let x in x end x;").unwrap()).unwrap_err();
+        assert_eq!(error.to_string(), "1:38: type error: Unknown identifier x");
+    }
+
+    #[test]
+    fn checks_that_retained_values_are_moved_back() {
+        let mut typechecker = super::TypeChecker::new();
+        let source = "def under: (Int, Int, Int -> Int, Int) = >r + r>;\ndef f: (Int -> Int) = >r 1 [>r r> 1 +] drop r@ r> +;";
+        typechecker.check(&parse(source).unwrap()).unwrap();
+        for (source, message, column) in [
+            ("def g: (Int -> Int) = >r 1;", "`>r` moves a value onto the retain stack that isn't moved back with `r>` before the end of the term", 23),
+            ("def h: (Int -> Int) = >r [r>] drop r>;", "`r>` has no value on the retain stack to take here", 27),
+        ] {
+            match typechecker.check(&parse(source).unwrap()).unwrap_err() {
+                super::Error::TypeError(actual, token) => {
+                    assert_eq!(actual, message);
+                    assert_eq!(token.col, column);
+                }
+                error => panic!("Expected TypeError, got {:?}", error),
+            }
+        }
+        // A definition of one of the words replaces it.
+        typechecker.check(&parse("def r@: ( -> Int) = 1;\ndef one: ( -> Int) = r@;").unwrap()).unwrap();
+    }
+
+    #[test]
+    fn checks_that_try_handlers_match_their_bodies() {
+        let mut typechecker = super::TypeChecker::new();
+        let source = "def safe-div: (Int, Int -> Int) = [/] [drop drop drop 0] try;\ndef g: ( -> Int) = [1 throw 0] [drop 1] try;";
+        typechecker.check(&parse(source).unwrap()).unwrap();
+        match typechecker.check(&parse("def h: (Int -> Int) = [1 +] [drop \"failed\"] try;").unwrap()).unwrap_err() {
+            super::Error::TypeError(message, token) => {
+                assert_eq!(message, "The handler of a `try` has to have the stack effect of its body once it takes the thrown value, but `[1 +]` is (Int -> Int) and `[drop \"failed\"]` is (a -> String)");
+                assert_eq!(token.value, "try");
+            }
+            error => panic!("Expected TypeError, got {:?}", error),
+        }
+        match typechecker.check(&parse("1 2 [+] [3] try").unwrap()).unwrap_err() {
+            super::Error::TypeError(message, _) => assert_eq!(message, "The handler of a `try` has to have the stack effect of its body once it takes the thrown value, but `[+]` is (Int, Int -> Int) and `[3]` is ( -> Int)"),
+            error => panic!("Expected TypeError, got {:?}", error),
+        }
+    }
+
+    #[test]
+    fn gives_each_use_of_a_word_its_own_parameters() {
+        let mut typechecker = super::TypeChecker::new();
+        let t = typechecker.check_cycle(&parse("drop hash").unwrap()[0]).unwrap();
+        assert_eq!(t.to_string(), "(a, b -> Int)");
+    }
+
+    #[test]
+    fn checks_that_if_bodies_are_balanced() {
+        let mut typechecker = super::TypeChecker::new();
+        let t = typechecker.check_cycle(&parse("[0 swap -] if").unwrap()[0]).unwrap();
+        assert_eq!(t.to_string(), "(Int, Bool -> Int)");
+        let t = typechecker.check_cycle(&parse("[] unless").unwrap()[0]).unwrap();
+        assert_eq!(t.to_string(), "(Bool -> )");
+        for (source, message) in [
+            ("def f: (Int, Bool -> ) = [drop] if;", "The body of `if` has to leave the stack as it found it, but `[drop]` is (a -> )"),
+            ("def g: (Bool -> Int) = [1] unless;", "The body of `unless` has to leave the stack as it found it, but `[1]` is ( -> Int)"),
+        ] {
+            match typechecker.check(&parse(source).unwrap()).unwrap_err() {
+                super::Error::TypeError(actual, token) => {
+                    assert_eq!(actual, message);
+                    assert!(token.value == "if" || token.value == "unless");
+                }
+                error => panic!("Expected TypeError, got {:?}", error),
+            }
+        }
+    }
+
+    #[test]
+    fn checks_apply_n_with_its_count() {
+        let mut typechecker = super::TypeChecker::new();
+        let t = typechecker.check_cycle(&parse("[1 +] 3 apply-n").unwrap()[0]).unwrap();
+        assert_eq!(t.to_string(), "(Int, Int, Int -> Int, Int, Int)");
+        // The quotation can come from anywhere its type is known, but the count has to be written out.
+        let types = typechecker.check(&parse("def inc: ( -> (Int -> Int)) = [1 +];\n3 4 inc 2 apply-n").unwrap()).unwrap();
+        assert_eq!(types[1].to_string(), "( -> Int, Int)");
+        for (source, message) in [
+            ("def f: (Int, Int -> ) = [+] 2 apply-n;", "The quotation given to `apply-n` has to take one value, but `[+]` is (Int, Int -> Int)"),
+            ("def g: (Int -> ) = [drop] -1 apply-n;", "`apply-n` can't apply a quotation to -1 values"),
+            ("def h: (Int, Int -> ) = 2 [drop] swap apply-n;", "`apply-n` can only be checked with its count written out just before it, after a quotation whose type is known"),
+            ("def add: ( -> (Int, Int -> Int)) = [+];\ndef k: (Int, Int -> ) = add 2 apply-n;", "The quotation given to `apply-n` has to take one value, but it is (Int, Int -> Int)"),
+        ] {
+            match typechecker.check(&parse(source).unwrap()).unwrap_err() {
+                super::Error::TypeError(actual, token) => {
+                    assert_eq!(actual, message);
+                    assert_eq!(token.value, "apply-n");
+                }
+                error => panic!("Expected TypeError, got {:?}", error),
+            }
+        }
+    }
+
+    #[test]
+    fn checks_stack_effect_assertions_against_the_term_before_them() {
+        let mut typechecker = super::TypeChecker::new();
+        let source = "def f: (Int, Int -> Int) = + ( Int Int -- Int ) dup * ( Int Int -- Int );\ndef g: (Int -> Int, Int) = ( -- ) dup ( Int -- Int Int );";
+        typechecker.check(&parse(source).unwrap()).unwrap();
+        match typechecker.check(&parse("def h: (Int -> Int, String) = dup 1 + ( Int -- Int Int ) \"x\" ( -- Int );").unwrap()).unwrap_err() {
+            super::Error::TypeError(message, token) => {
+                assert_eq!(message, "The term up to here has the stack effect ( a -- a Int String ), not the asserted ( -- Int )");
+                assert_eq!(token.col, 62);
+            }
+            error => panic!("Expected TypeError, got {:?}", error),
+        }
+        let t = typechecker.check_cycle(&parse("( Int -- Int )").unwrap()[0]).unwrap();
+        assert_eq!(t.to_string(), "(Int -> Int)");
+        let t = typechecker.check_cycle(&parse("1 ( Bool -- Bool Int )").unwrap()[0]).unwrap();
+        assert_eq!(t.to_string(), "(Bool -> Bool, Int)");
+        assert!(typechecker.check(&parse("def f: (String -> String) = ( Int -- Int );").unwrap()).is_err());
+    }
+
+    #[test]
+    fn pins_the_top_of_the_stack_to_ascribed_types() {
+        let mut typechecker = super::TypeChecker::new();
+        let t = typechecker.check_cycle(&parse("dup :: Int").unwrap()[0]).unwrap();
+        assert_eq!(t.to_string(), "(Int -> Int, Int)");
+        match typechecker.check(&parse("def f: ( -> Int) = 1 :: Bool;").unwrap()).unwrap_err() {
+            super::Error::TypeError(message, token) => {
+                assert_eq!(message, "The value on top of the stack here is Int, not the ascribed Bool");
+                assert_eq!(token.col, 22);
+            }
+            error => panic!("Expected TypeError, got {:?}", error),
+        }
+        match typechecker.check(&parse(":: String 1").unwrap()).unwrap_err() {
+            super::Error::TypeError(message, token) => {
+                assert_eq!(message, "There's no value on the stack here to ascribe String to");
+                assert_eq!(token.col, 1);
+            }
+            error => panic!("Expected TypeError, got {:?}", error),
+        }
+    }
+
+    #[test]
+    fn lets_dyn_values_be_used_as_any_type() {
+        let mut typechecker = super::TypeChecker::new();
+        let source = "def parse: (String -> Dyn) = dynamic;\ndef f: (String -> Int) = parse :: Int 1 +;\ndef g: (Dyn -> String) = type-of;";
+        typechecker.check(&parse(source).unwrap()).unwrap();
+        let t = typechecker.check_cycle(&parse("1 :: Dyn dynamic :: Bool").unwrap()[0]).unwrap();
+        assert_eq!(t.to_string(), "( -> Bool)");
+        assert_eq!(typechecker.type_of("parse").unwrap().to_string(), "(String -> Dyn)");
+    }
+
+    #[test]
+    fn checks_bytes_apart_from_strings() {
+        let mut typechecker = super::TypeChecker::new();
+        let source = "def header: (Bytes -> Bytes) = 0 4 bytes-slice;
+def magic: ( -> String) = x\"7f454c46\" header bytes->string;";
+        typechecker.check(&parse(source).unwrap()).unwrap();
+        assert_eq!(typechecker.type_of("header").unwrap().to_string(), "(Bytes -> Bytes)");
+        let error = typechecker.check(&parse("def f: ( -> Bytes) = \"text\" :: Bytes;").unwrap()).unwrap_err();
+        assert_eq!(error.to_string(), "1:29: type error: The value on top of the stack here is String, not the ascribed Bytes");
+    }
+
+    #[test]
+    fn infers_and_checks_declared_effects() {
+        let mut typechecker = super::TypeChecker::new();
+        typechecker.declare("print", super::Type::Function(vec![super::Type::String], vec![]));
+        typechecker.declare_effects("print", super::Effects::IO);
+        let source = "def greet: ( -> ) = \"hi\" [print] keep drop;\ndef count: (Int -> Int) = 1 - count;\ndef sq: (Int -> Int) ! pure = dup *;\ndef both: ( -> ) ! io diverge = greet;";
+        typechecker.check(&parse(source).unwrap()).unwrap();
+        assert_eq!(typechecker.effects_of("greet"), super::Effects::IO);
+        assert_eq!(typechecker.effects_of("count"), super::Effects::DIVERGENCE);
+        assert!(typechecker.effects_of("sq").is_pure());
+        assert_eq!(typechecker.effects_of("both").to_string(), "io diverge");
+        assert_eq!(typechecker.effects_of("random-int"), super::Effects::NONDETERMINISM);
+        for (source, message) in [
+            ("def f: ( -> ) ! pure = greet;", "1:24: type error: `f` is declared `! pure`, but `greet` can do io"),
+            ("def g: (Int -> Int) ! nondet = count;", "1:32: type error: `g` is declared `! nondet`, but `count` can diverge"),
+            ("def h: ( -> ) ! loud = ;", "1:17: type error: Unknown effect loud"),
+        ] {
+            assert_eq!(typechecker.check(&parse(source).unwrap()).unwrap_err().to_string(), message);
+        }
+    }
+
+    #[test]
+    fn checks_that_total_definitions_terminate() {
+        let mut typechecker = super::TypeChecker::new();
+        let source = "total def countdown: (Int -> Int) = [dup 0 =] [] [1 - countdown] ifte;\ndef loop: (Int -> Int) = loop;\ntotal def halve: (Int -> Int) ! pure = countdown 2 /;";
+        typechecker.check(&parse(source).unwrap()).unwrap();
+        assert!(typechecker.effects_of("countdown").is_pure());
+        for (source, message) in [
+            ("total def f: (Int -> Int) = 1 - f;", "1:33: type error: `f` calls itself outside of any `ifte` or `cond` branch, so it can't be total"),
+            ("total def g: (Int -> Int) = [dup 0 =] [] [1 + g] ifte;", "1:47: type error: `g` calls itself without first making a number smaller, like with `1 -`, so it can't be total"),
+            ("total def h: (Int -> Int) = 1 + loop;", "1:33: type error: `loop` might not terminate, so `h` can't be total"),
+        ] {
+            assert_eq!(typechecker.check(&parse(source).unwrap()).unwrap_err().to_string(), message);
+        }
+    }
+
+    #[test]
+    fn memoized_definitions_have_to_be_pure() {
+        let mut typechecker = super::TypeChecker::new();
+        typechecker.check(&parse("memo def forever: (Int -> Int) = forever;").unwrap()).unwrap();
+        let error = typechecker.check(&parse("memo def roll: (Int -> Int) = 6 random-int;").unwrap()).unwrap_err();
+        assert_eq!(error.to_string(), "1:33: type error: `roll` is memoized, so it has to be pure, but `random-int` can be nondeterministic");
+    }
+
+    #[test]
+    fn types_lazy_sequences() {
+        let mut typechecker = super::TypeChecker::new();
+        let source = "def naturals: ( -> Seq(Int)) = 0 [1 +] iterate;\ndef some: ( -> ( -> )) = naturals 3 take force;";
+        typechecker.check(&parse(source).unwrap()).unwrap();
+        assert_eq!(typechecker.type_of("take-while").unwrap().to_string(), "(Seq(a), (a -> Bool) -> Seq(a))");
+        assert_eq!(typechecker.effects_of("force"), super::Effects::DIVERGENCE);
+    }
+
+    #[test]
+    fn types_coroutines_by_what_they_take_and_yield() {
+        let mut typechecker = super::TypeChecker::new();
+        let source = "def doubler: ( -> Coroutine(Int, Int)) = [2 * yield] coroutine;\ndef first: ( -> Int) = doubler 4 resume drop swap drop;";
+        typechecker.check(&parse(source).unwrap()).unwrap();
+        let error = typechecker.check(&parse("doubler :: Coroutine(String, Int)").unwrap()).unwrap_err().to_string();
+        assert_eq!(error, "1:9: type error: The value on top of the stack here is Coroutine(Int, Int), not the ascribed Coroutine(String, Int)");
+    }
+
+    #[test]
+    fn types_continuations_as_quotations() {
+        let typechecker = super::TypeChecker::new();
+        assert_eq!(typechecker.type_of("shift").unwrap().to_string(), "(((a -> b) -> b) -> a)");
+    }
+
+    #[test]
+    fn types_sets_by_their_elements() {
+        let mut typechecker = super::TypeChecker::new();
+        let source = "def primes: ( -> Set(Int)) = set-new 2 set-add 3 set-add;\ndef has: (Int -> Bool) = primes swap set-contains;";
+        typechecker.check(&parse(source).unwrap()).unwrap();
+        assert_eq!(typechecker.type_of("primes").unwrap().to_string(), "( -> Set(Int))");
+        for (source, message) in [
+            ("primes :: Set(String)", "1:8: type error: The value on top of the stack here is Set(Int), not the ascribed Set(String)"),
+            ("primes :: Set", "1:11: type error: `Set` takes 1 type argument, but is given 0"),
+        ] {
+            assert_eq!(typechecker.check(&parse(source).unwrap()).unwrap_err().to_string(), message);
+        }
+    }
+
+    #[test]
+    fn checks_declared_type_constructors() {
+        use super::Type::{Named, Param};
+        let mut typechecker = super::TypeChecker::new();
+        typechecker.declare_type("List", 1);
+        typechecker.declare_type("Pair", 2);
+        typechecker.declare("pair", super::Type::Function(vec![Param(0), Param(1)], vec![Named("Pair".to_string(), vec![Param(0), Param(1)])]));
+        assert_eq!(typechecker.type_of("pair").unwrap().to_string(), "(a, b -> Pair(a, b))");
+        let source = "def f: ( -> Pair(Int, String)) = 1 \"x\" pair :: Pair(Int, String);\ndef g: (List(a) -> List(a)) = ;";
+        typechecker.check(&parse(source).unwrap()).unwrap();
+        assert_eq!(typechecker.type_of("g").unwrap().to_string(), "(List(a) -> List(a))");
+        for (source, message) in [
+            ("1 2 pair :: List(Int)", "1:10: type error: The value on top of the stack here is Pair(a, b), not the ascribed List(Int)"),
+            ("1 2 pair :: Pair(Int)", "1:13: type error: `Pair` takes 2 type arguments, but is given 1"),
+            ("[] :: List", "1:7: type error: `List` takes 1 type argument, but is given 0"),
+            ("[] :: Tree(Int)", "1:7: type error: Unknown type Tree"),
+        ] {
+            assert_eq!(typechecker.check(&parse(source).unwrap()).unwrap_err().to_string(), message);
+        }
+    }
+
+    #[test]
+    fn checks_definitions_using_application_combinators() {
+        let mut typechecker = super::TypeChecker::new();
+        let source = "def f: (Int -> Int, Bool) = [1 +] [0 =] bi;\ndef g: (Int, Int -> String, String) = [drop \"x\"] both;";
+        typechecker.check(&parse(source).unwrap()).unwrap();
+        assert_eq!(typechecker.type_of("tri").unwrap().to_string(), "(a, (a -> b), (a -> c), (a -> d) -> b, c, d)");
+        assert_eq!(typechecker.type_of("keep").unwrap().to_string(), "(a, (a -> b) -> b, a)");
+        assert_eq!(typechecker.type_of("curry").unwrap().to_string(), "(a, (a -> b) -> ( -> b))");
+    }
+
     #[test]
     fn allows_known_identifiers() {
-        let input = parse("[dup drop dup]").unwrap();
+        let input = parse("[dup drop dup] call").unwrap();
         let mut typechecker = super::TypeChecker::new();
         typechecker.check(&input).unwrap();
     }
@@ -224,7 +1614,7 @@ mod tests {
 
     #[test]
     fn gets_correct_param_types() {
-        let input = parse("[dup drop dup]").unwrap();
+        let input = parse("[dup drop dup] call").unwrap();
         let mut typechecker = super::TypeChecker::new();
         let t = typechecker.check_cycle(&input[0]).unwrap();
         match t {
@@ -241,7 +1631,7 @@ mod tests {
 
     #[test]
     fn gets_correct_param_types_complicated() {
-        let input = parse("[ifte dup drop]").unwrap();
+        let input = parse("[ifte dup drop] call").unwrap();
         let mut typechecker = super::TypeChecker::new();
         let t = typechecker.check_cycle(&input[0]).unwrap();
         match t {
@@ -256,4 +1646,121 @@ mod tests {
             _ => panic!("Expected Function"),
         }
     }
+
+    #[test]
+    fn warns_about_underflowing_terms() {
+        let input = parse("1 + drop").unwrap();
+        let mut typechecker = super::TypeChecker::new();
+        typechecker.check(&input).unwrap();
+        match typechecker.warnings() {
+            [Warning::StackUnderflow(t_in, token)] => {
+                assert_eq!(t_in, &vec![Type::Int]);
+                assert_eq!(token.value, "1");
+            }
+            warnings => panic!("Expected StackUnderflow, got {:?}", warnings),
+        }
+        let mut typechecker = super::TypeChecker::new();
+        typechecker.check(&parse("let x y in x y - end").unwrap()).unwrap();
+        match typechecker.warnings() {
+            [Warning::StackUnderflow(t_in, token), Warning::LeftoverValues(..)] => {
+                assert_eq!(t_in, &vec![Type::Int, Type::Int]);
+                assert_eq!(token.value, "x");
+            }
+            warnings => panic!("Expected StackUnderflow, got {:?}", warnings),
+        }
+    }
+
+    #[test]
+    fn warns_about_definitions_taking_more_than_they_declare() {
+        let input = parse("def f: (Int -> Int) = 1 swap - +;\ndef g: (Int, Int -> Int) = [true] [+] [-] ifte;\ndef h: (Int -> Int) = let x y in x y - end;").unwrap();
+        let mut typechecker = super::TypeChecker::new();
+        typechecker.check(&input).unwrap();
+        match typechecker.warnings() {
+            [warning @ Warning::DefinitionUnderflow(_, 1, 2, token), Warning::DefinitionUnderflow(_, 1, 2, bound)] => {
+                assert_eq!(token.value, "+");
+                assert_eq!(warning.to_string(), "1:32: warning: `f` is declared to take 1 value(s), but can take 2 from the stack by here");
+                assert_eq!(bound.value, "let");
+            }
+            warnings => panic!("Expected DefinitionUnderflow, got {:?}", warnings),
+        }
+    }
+
+    #[test]
+    fn checks_call_and_cat_against_the_quotations_on_the_stack() {
+        let term_type = |source: &str| {
+            let mut typechecker = super::TypeChecker::new();
+            typechecker.check(&parse(source).unwrap()).map(|types| super::normalize(types.last().unwrap()))
+        };
+        assert_eq!(term_type("[1] call"), Ok(Type::Function(vec![], vec![Type::Int])));
+        assert_eq!(term_type("def sq: (Int -> Int) = dup *; 3 &sq call"), Ok(Type::Function(vec![], vec![Type::Int])));
+        assert_eq!(term_type("[1 2] [3] cat call"), Ok(Type::Function(vec![], vec![Type::Int, Type::Int, Type::Int])));
+        let joined = Type::Function(vec![Type::Int], vec![Type::Int]);
+        assert_eq!(term_type("[1 +] [2 *] cat"), Ok(Type::Function(vec![], vec![joined.clone()])));
+        assert_eq!(term_type("[[1 +] [2 *]] compose"), Ok(Type::Function(vec![], vec![joined])));
+        assert_eq!(term_type("3 [[1 +] [2 *]] compose call"), Ok(Type::Function(vec![], vec![Type::Int])));
+        assert_eq!(term_type("[] compose call"), Ok(Type::Function(vec![], vec![])));
+    }
+
+    #[test]
+    fn reports_calling_what_isnt_a_known_quotation() {
+        for (input, word, message) in [
+            ("1 call", "call", "`call` needs a quotation whose type is known here, but got Int"),
+            ("call", "call", "`call` needs a quotation whose type is known here, but the stack is empty"),
+            ("1 [2] cat", "cat", "`cat` needs a quotation whose type is known here, but got Int"),
+            ("[[1] 2] compose", "compose", "`compose` needs a list of quotations whose types are known here, but got ( -> ( -> Int), Int)"),
+        ] {
+            let mut typechecker = super::TypeChecker::new();
+            match typechecker.check(&parse(input).unwrap()) {
+                Err(Error::TypeError(actual, token)) => {
+                    assert_eq!(actual, message);
+                    assert_eq!(token.value, word);
+                }
+                result => panic!("Expected TypeError, got {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn warns_about_leftover_values() {
+        let input = parse("1 2").unwrap();
+        let mut typechecker = super::TypeChecker::new();
+        typechecker.check(&input).unwrap();
+        match typechecker.warnings() {
+            [Warning::LeftoverValues(t_out, _)] => assert_eq!(t_out, &vec![Type::Int, Type::Int]),
+            warnings => panic!("Expected LeftoverValues, got {:?}", warnings),
+        }
+    }
+
+    #[test]
+    fn checks_as_strictly_as_configured() {
+        let check = |config: super::CheckConfig, source: &str| {
+            let mut typechecker = super::TypeChecker::new();
+            typechecker.set_config(config);
+            typechecker.check(&parse(source).unwrap()).map(|_| typechecker.warnings().len())
+        };
+        let (strict, lenient, default) = (super::CheckConfig::strict(), super::CheckConfig::lenient(), super::CheckConfig::default());
+        assert_eq!(check(default, "1 2"), Ok(1));
+        assert_eq!(check(lenient, "1 2"), Ok(0));
+        assert_eq!(check(strict, "1 2").unwrap_err().to_string(), "1:1: type error: term leaves 2 value(s) on the stack: Int, Int");
+        let shadowing = "def hash: (Int -> Int) = ;";
+        assert_eq!(check(default, shadowing), Ok(0));
+        assert_eq!(check(strict, shadowing).unwrap_err().to_string(), "1:11: type error: `hash` is already a builtin word, so it can't be defined here");
+        let dynamic = "def parse: (String -> Dyn) = dynamic;";
+        assert_eq!(check(default, dynamic), Ok(0));
+        assert_eq!(check(strict, dynamic).unwrap_err().to_string(), "1:12: type error: `parse` has `Dyn` in its signature, so it can't be checked");
+        let twice = "def f: ( -> Int) = 1; def f: ( -> Int) = 2;";
+        assert!(matches!(check(default, twice), Err(Error::DuplicateDefinition(..))));
+        assert_eq!(check(lenient, twice), Ok(0));
+        let one_output = super::CheckConfig { term_outputs: 1, ..strict };
+        assert_eq!(check(one_output, "1 2 +"), Ok(0));
+        assert_eq!(check(one_output, "1 2").unwrap_err().to_string(), "1:1: type error: term leaves 2 value(s) on the stack: Int, Int");
+    }
+
+    #[test]
+    fn does_not_warn_about_balanced_terms() {
+        let input = parse("1 2 + drop").unwrap();
+        let mut typechecker = super::TypeChecker::new();
+        typechecker.check(&input).unwrap();
+        assert!(typechecker.warnings().is_empty());
+    }
 }
\ No newline at end of file
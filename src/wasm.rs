@@ -0,0 +1,102 @@
+use serde_json::{json, Value as Json};
+use wasm_bindgen::prelude::*;
+use crate::error::{Error, Warning};
+use crate::format::value_to_source;
+use crate::scanner::Token;
+use crate::Engine;
+
+// Each binding takes a whole source and returns a JSON object, so the page never has to deal with Rust types.
+// Problems are listed under `diagnostics`, each with a `severity`, a `message`, and where it is if it's known.
+
+/// `{ tokens: [...], diagnostics }`, with the tokens as serialized by the `serde` feature.
+#[wasm_bindgen]
+pub fn scan(source: &str) -> String {
+    match crate::scanner::scan(source) {
+        Ok(tokens) => json!({ "tokens": tokens, "diagnostics": [] }),
+        Err(error) => json!({ "tokens": [], "diagnostics": [error_diagnostic(&error)] }),
+    }.to_string()
+}
+
+/// `{ ast: [...], diagnostics }`, with the cycles as `chara check --emit=ast-json` prints them.
+#[wasm_bindgen]
+pub fn parse(source: &str) -> String {
+    match crate::parser::parse(source) {
+        Ok(cycles) => json!({ "ast": cycles, "diagnostics": [] }),
+        Err(error) => json!({ "ast": [], "diagnostics": [error_diagnostic(&error)] }),
+    }.to_string()
+}
+
+/// `{ diagnostics }`, with the warnings of a well-typed program or the errors of one that isn't.
+#[wasm_bindgen]
+pub fn check(source: &str) -> String {
+    let diagnostics = match crate::compile(source).and_then(|program| program.check()) {
+        Ok(warnings) => warnings.iter().map(warning_diagnostic).collect::<Vec<_>>(),
+        Err(errors) => errors.iter().map(error_diagnostic).collect(),
+    };
+    json!({ "diagnostics": diagnostics }).to_string()
+}
+
+/// `{ stack: [...], output, diagnostics }`, checking and running the source in a fresh engine. The stack is
+/// each value as source, and `output` is what `chara repl` would print for it.
+#[wasm_bindgen]
+pub fn eval(source: &str) -> String {
+    let mut engine = Engine::new();
+    let diagnostics = match engine.eval_str(source) {
+        Ok(warnings) => warnings.iter().map(warning_diagnostic).collect::<Vec<_>>(),
+        Err(errors) => errors.iter().map(error_diagnostic).collect(),
+    };
+    let stack: Vec<String> = engine.stack().iter().map(value_to_source).collect();
+    json!({ "stack": stack, "output": stack.join(" "), "diagnostics": diagnostics }).to_string()
+}
+
+fn error_diagnostic(error: &Error) -> Json {
+    diagnostic("error", error.to_string(), error.token())
+}
+
+fn warning_diagnostic(warning: &Warning) -> Json {
+    diagnostic("warning", warning.to_string(), Some(warning.token()))
+}
+
+fn diagnostic(severity: &str, message: String, token: Option<&Token>) -> Json {
+    let mut diagnostic = json!({ "severity": severity, "message": message });
+    if let Some(token) = token {
+        diagnostic["line"] = json!(token.line);
+        diagnostic["col"] = json!(token.col);
+        diagnostic["start"] = json!(token.span.start);
+        diagnostic["end"] = json!(token.span.end);
+    }
+    diagnostic
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    fn call(binding: fn(&str) -> String, source: &str) -> Value {
+        serde_json::from_str(&binding(source)).unwrap()
+    }
+
+    #[test]
+    fn evaluates_with_diagnostics() {
+        let result = call(super::eval, "1 2 + \"s\"");
+        assert_eq!(result["stack"], json!(["3", "\"s\""]));
+        assert_eq!(result["output"], "3 \"s\"");
+        assert_eq!(result["diagnostics"][0]["severity"], "warning");
+
+        let result = call(super::check, "1 nope");
+        assert_eq!(result["diagnostics"], json!([{
+            "severity": "error",
+            "message": "1:3: type error: Unknown identifier nope",
+            "line": 1, "col": 3, "start": 2, "end": 6,
+        }]));
+    }
+
+    #[test]
+    fn scans_and_parses() {
+        assert_eq!(call(super::scan, "1 dup")["tokens"].as_array().unwrap().len(), 2);
+        assert_eq!(call(super::parse, "def a: Int = 1;")["ast"].as_array().unwrap().len(), 1);
+        let result = call(super::parse, "[1");
+        assert_eq!(result["diagnostics"][0]["message"], "unexpected end of file: expected `]` while parsing quotation");
+        assert!(result["diagnostics"][0].get("line").is_none());
+    }
+}